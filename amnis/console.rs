@@ -0,0 +1,189 @@
+use std::{path::PathBuf, str::FromStr};
+
+pub const HELP: &str = "\
+Commands:
+  help                        Shows this text
+  set <PARAM> <VALUE>         Sets a simulation parameter, the value is in the RON syntax
+  get [PARAM]                 Prints a simulation parameter or all of them
+  pause                       Pauses the simulation
+  resume                      Resumes the simulation
+  step <N>                    Runs N steps and pauses, the next commands wait for them
+  rg                          Regenerates the particles
+  dump <FILE>                 Writes the particles as CSV
+  shot <FILE>                 Writes the next frame as PNG
+  camera orbit <YAW> <PITCH>  Rotates the camera around its center, in degrees
+  camera zoom <DELTA>         Changes the distance from the camera to its center
+  camera reset                Moves the camera to the initial position
+  load <SCENE>                Loads the parameters and the sources of a RON scene
+  stats                       Prints the statistics of the particles
+  run <SCRIPT>                Executes the commands of a file, one per line, `#` starts a comment
+  size <W> <H>                Sets the size of the rendered frames
+  rec <DIR> [FPS]             Records the frames as PNGs, and as a Y4M video if FPS is set
+  stop                        Stops the recording
+  cap <N>                     Captures N frames with RenderDoc
+  prof <FILE>                 Writes the GPU timings as CSV
+  die                         Exits";
+
+#[cfg(test)]
+mod test {
+  use super::Command;
+
+  #[test]
+  fn parse() {
+    assert_eq!(
+      Command::parse("set  h 0.05").unwrap(),
+      Some(Command::Set {
+        param: "h".into(),
+        value: "0.05".into()
+      })
+    );
+    assert_eq!(Command::parse("get").unwrap(), Some(Command::Get(None)));
+    assert_eq!(Command::parse("step 20").unwrap(), Some(Command::Step(20)));
+    assert_eq!(
+      Command::parse("camera orbit 45 -10").unwrap(),
+      Some(Command::Orbit {
+        yaw: 45.0,
+        pitch: -10.0
+      })
+    );
+    assert_eq!(
+      Command::parse("rec out 30").unwrap(),
+      Some(Command::Record {
+        dir: "out".into(),
+        video_fps: Some(30)
+      })
+    );
+    assert_eq!(Command::parse("   ").unwrap(), None);
+    assert_eq!(Command::parse("# step 1").unwrap(), None);
+  }
+
+  #[test]
+  fn malformed() {
+    assert_eq!(
+      Command::parse("step").unwrap_err(),
+      "Usage: step <N>    Runs N steps and pauses, the next commands wait for them"
+    );
+    assert_eq!(
+      Command::parse("step x").unwrap_err(),
+      "Invalid count of steps `x`"
+    );
+    assert!(Command::parse("size 0 10").is_err());
+    assert!(Command::parse("camera spin").is_err());
+    assert_eq!(
+      Command::parse("jump").unwrap_err(),
+      "Unknown command `jump`, see `help`"
+    );
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+  Help,
+  Set {
+    param: String,
+    value: String,
+  },
+  Get(Option<String>),
+  Pause,
+  Resume,
+  Step(u32),
+  Regen,
+  Dump(PathBuf),
+  Shot(PathBuf),
+  /// Angles in degrees
+  Orbit {
+    yaw: f32,
+    pitch: f32,
+  },
+  Zoom(f32),
+  ResetCamera,
+  Load(PathBuf),
+  Stats,
+  Run(PathBuf),
+  Size([u32; 2]),
+  Record {
+    dir: PathBuf,
+    video_fps: Option<u32>,
+  },
+  StopRecording,
+  Capture(u32),
+  Profile(PathBuf),
+  Die,
+}
+
+impl Command {
+  /// Parses a line of the console or a script, `None` if it's blank or a comment
+  pub fn parse(line: &str) -> Result<Option<Self>, String> {
+    let line = line.trim();
+    if line.starts_with('#') {
+      return Ok(None);
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.as_slice() {
+      [] => return Ok(None),
+      ["help"] => Command::Help,
+      ["set", param, value] => Command::Set {
+        param: param.to_string(),
+        value: value.to_string(),
+      },
+      ["get"] => Command::Get(None),
+      ["get", param] => Command::Get(Some(param.to_string())),
+      ["pause"] => Command::Pause,
+      ["resume"] => Command::Resume,
+      ["step", n] => Command::Step(arg("count of steps", n)?),
+      ["rg"] => Command::Regen,
+      ["dump", file] => Command::Dump(file.into()),
+      ["shot", file] => Command::Shot(file.into()),
+      ["camera", "orbit", yaw, pitch] => Command::Orbit {
+        yaw: arg("angle", yaw)?,
+        pitch: arg("angle", pitch)?,
+      },
+      ["camera", "zoom", delta] => Command::Zoom(arg("distance", delta)?),
+      ["camera", "reset"] => Command::ResetCamera,
+      ["load", file] => Command::Load(file.into()),
+      ["stats"] => Command::Stats,
+      ["run", file] => Command::Run(file.into()),
+      ["size", w, h] => match (arg("width", w)?, arg("height", h)?) {
+        (0, _) | (_, 0) => return Err("The size must not be zero".into()),
+        (w, h) => Command::Size([w, h]),
+      },
+      ["rec", dir] => Command::Record {
+        dir: dir.into(),
+        video_fps: None,
+      },
+      ["rec", dir, fps] => Command::Record {
+        dir: dir.into(),
+        video_fps: Some(arg("frame rate", fps)?),
+      },
+      ["stop"] => Command::StopRecording,
+      ["cap", n] => Command::Capture(arg("count of captures", n)?),
+      ["prof", file] => Command::Profile(file.into()),
+      ["die"] => Command::Die,
+      [name, ..] => {
+        return Err(match usage(name) {
+          Some(usage) => format!("Usage: {usage}"),
+          None => format!("Unknown command `{name}`, see `help`"),
+        })
+      }
+    };
+    Ok(Some(command))
+  }
+}
+
+fn arg<T: FromStr>(what: &str, word: &str) -> Result<T, String> {
+  word.parse().map_err(|_| format!("Invalid {what} `{word}`"))
+}
+
+/// Lines of [`HELP`] describing the command `name`
+fn usage(name: &str) -> Option<String> {
+  let lines: Vec<String> = HELP
+    .lines()
+    .map(str::trim)
+    .filter(|line| line.split_whitespace().next() == Some(name))
+    .map(|line| match line.split_once("  ") {
+      Some((syntax, description)) => format!("{syntax}    {}", description.trim()),
+      None => line.to_owned(),
+    })
+    .collect();
+  (!lines.is_empty()).then(|| lines.join("\n"))
+}
@@ -1,17 +1,31 @@
-use std::time::Instant;
+use std::{
+  collections::VecDeque,
+  fs,
+  io::{self, Write},
+  path::Path,
+  time::Instant,
+};
 
+use console::{Command, HELP};
 use egui::{PaintCallbackInfo, Pos2, Rect};
 use egui_wgpu::{CallbackTrait, ScreenDescriptor, WgpuSetup};
 use limne::{
+  export::write_particles_csv,
   render::{
     camera::OrbitCameraController,
-    state::StateCallback,
+    capture::{FrameReader, Recorder},
+    state::{PersistentState, RecordCommand, StateCallback},
     targets::simulation::SimulationParams,
     texture_provider::{TextureProvider, TextureProviderDescriptor},
   },
+  scene::Scene,
+  stats::Stats,
   *,
 };
 
+mod console;
+
+#[cfg(feature = "renderdoc")]
 pub mod renderdoc {
   use std::{
     os::raw::c_void,
@@ -62,11 +76,25 @@ pub mod renderdoc {
   }
 }
 
+/// Stands in for the RenderDoc API when the `renderdoc` feature is disabled
+#[cfg(not(feature = "renderdoc"))]
+pub mod renderdoc {
+  pub struct Api;
+
+  impl Api {
+    pub fn new() -> Option<Self> {
+      None
+    }
+    pub fn start_frame_capture(&self) {}
+    pub fn end_frame_capture(&self) {}
+  }
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
   env_logger::init();
   let WgpuSetup::Existing(egui_wgpu::WgpuSetupExisting { device, queue, .. }) =
-    create_wgpu_setup().await
+    create_wgpu_setup().await.unwrap_or_else(|e| panic!("{e}"))
   else {
     unreachable!()
   };
@@ -79,75 +107,191 @@ async fn main() {
   let begin = time;
 
   let mut params = SimulationParams::default();
-  const SIZE: [u32; 2] = [1024, 1024];
-  const SIZE_VEC: egui::Vec2 = egui::Vec2 {
-    x: SIZE[0] as f32,
-    y: SIZE[1] as f32,
-  };
-  let viewport = Rect::from_min_max(Pos2::ZERO, SIZE_VEC.to_pos2());
-  let cam = OrbitCameraController::default()
-    .rotate_radians(egui::Vec2 {
-      x: std::f32::consts::PI / 4.,
-      y: 0.0,
-    })
-    .get_camera();
-  let target_tex = TextureProvider::new(
+  let mut size = [1024, 1024];
+  let mut record = None;
+  let mut controller = initial_camera();
+  let mut target_tex = TextureProvider::new(
     &device,
     TextureProviderDescriptor {
       label: Some("Target texture".to_string()),
       size: wgpu::Extent3d {
-        width: SIZE[0],
-        height: SIZE[1],
+        width: size[0],
+        height: size[1],
         depth_or_array_layers: 1,
       },
       mip_level_count: 1,
       sample_count: 1,
       dimension: wgpu::TextureDimension::D2,
       format,
-      usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+      usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
       view_formats: vec![],
     },
   );
 
-  let api = renderdoc::Api::new().expect("failed to create Renderdoc api");
+  let api = renderdoc::Api::new();
+  if api.is_none() {
+    log::warn!("RenderDoc is not available, the frames are not captured");
+  }
   let (tx, mut input) = tokio::sync::mpsc::channel(1);
-  tokio::spawn(async move {
-    loop {
-      let mut input = String::new();
-      std::io::stdin().read_line(&mut input).unwrap();
-      tx.send(input.clone()).await.unwrap();
-      if input.trim() == "die" {
+  // Not a task, so that it doesn't keep the runtime alive
+  std::thread::spawn(move || {
+    for line in io::stdin().lines() {
+      let Ok(line) = line else { break };
+      if tx.blocking_send(line).is_err() {
         break;
       }
     }
   });
+  // Lines of the scripts being run, they go before the console input
+  let mut script: VecDeque<String> = std::env::args()
+    .skip(1)
+    .map(|path| format!("run {path}"))
+    .collect();
+  let mut steps_left = 0;
+  let mut shot = None;
   let mut capture_count = 0;
-  loop {
-    if let Ok(s) = input.try_recv() {
-      match s.trim().split(' ').collect::<Vec<_>>().as_slice() {
-        ["rg"] => params.regen_particles = true,
-        ["cap", count] => {
-          capture_count = count.parse().unwrap_or(0);
+  'frames: loop {
+    while steps_left == 0 {
+      let Some(line) = script.pop_front().or_else(|| input.try_recv().ok()) else {
+        break;
+      };
+      let command = match Command::parse(&line) {
+        Ok(Some(command)) => command,
+        Ok(None) => continue,
+        Err(e) => {
+          log::error!("{e}");
+          continue;
+        }
+      };
+      let state = callback_res.get_mut::<PersistentState>().unwrap();
+      match command {
+        Command::Help => println!("{HELP}"),
+        Command::Set { param, value } => {
+          if let Err(e) = params.set(&param, &value) {
+            log::error!("{e}");
+          }
+        }
+        Command::Get(Some(param)) => match params.get(&param) {
+          Some(value) => println!("{param} = {value}"),
+          None => log::error!("Unknown parameter `{param}`"),
+        },
+        Command::Get(None) => {
+          for name in SimulationParams::FIELDS {
+            println!("{name} = {}", params.get(name).unwrap());
+          }
+        }
+        Command::Pause => params.paused = true,
+        Command::Resume => params.paused = false,
+        Command::Step(n) => {
+          steps_left = n;
+          params.paused = n == 0;
+        }
+        Command::Regen => params.regen_particles = true,
+        Command::Dump(path) => {
+          let particles = state.simulation().read_particles(&device, &queue);
+          match write_to(&path, |w| write_particles_csv(w, &particles)) {
+            Ok(()) => log::info!("Particles written to {}", path.display()),
+            Err(e) => log::error!("Unable to write {}: {e}", path.display()),
+          }
+        }
+        Command::Shot(path) => shot = Some(path),
+        Command::Orbit { yaw, pitch } => {
+          controller.rotate_radians(egui::vec2(yaw.to_radians(), pitch.to_radians()));
         }
-        ["die"] => break,
-        _ => (),
+        Command::Zoom(delta) => {
+          controller.move_radius(delta);
+        }
+        Command::ResetCamera => controller = initial_camera(),
+        Command::Load(path) => match Scene::load(&path) {
+          Ok(scene) => {
+            let sim = state.simulation();
+            if (sim.count(), sim.storage()) != (scene.count, scene.storage) {
+              state.stop_recording(&device);
+              *state = PersistentState::create_with(
+                &device,
+                &format,
+                &queue,
+                scene.count,
+                scene.storage,
+                false,
+              );
+            }
+            state
+              .simulation_mut()
+              .set_sources(scene.emitters, scene.sinks);
+            params = scene.params;
+            params.regen_particles = true;
+          }
+          Err(e) => log::error!("Unable to load {}: {e}", path.display()),
+        },
+        Command::Stats => {
+          let particles = state.simulation().read_particles(&device, &queue);
+          println!("{:#?}", Stats::compute(&particles, params.m0));
+        }
+        Command::Run(path) => match fs::read_to_string(&path) {
+          Ok(text) => text
+            .lines()
+            .rev()
+            .for_each(|line| script.push_front(line.to_owned())),
+          Err(e) => log::error!("Unable to read {}: {e}", path.display()),
+        },
+        Command::Size([w, h]) => {
+          size = [w, h];
+          target_tex.resize(
+            &device,
+            wgpu::Extent3d {
+              width: w,
+              height: h,
+              depth_or_array_layers: 1,
+            },
+          );
+        }
+        Command::Record { dir, video_fps } => match Recorder::new(&dir, video_fps) {
+          Ok(recorder) => record = Some(RecordCommand::Start(recorder)),
+          Err(e) => log::error!("Unable to record to {}: {e}", dir.display()),
+        },
+        Command::StopRecording => record = Some(RecordCommand::Stop),
+        Command::Capture(count) => match api {
+          Some(_) => capture_count = count,
+          None => log::warn!("RenderDoc is not available, `cap` is ignored"),
+        },
+        Command::Profile(path) => match state.profiler() {
+          Some(profiler) => match write_to(&path, |w| profiler.write_csv(w)) {
+            Ok(()) => log::info!("GPU timings written to {}", path.display()),
+            Err(e) => log::error!("Unable to write {}: {e}", path.display()),
+          },
+          None => log::warn!("GPU profiling is not supported"),
+        },
+        Command::Die => break 'frames,
       }
     }
     let t_now = Instant::now();
     let dt = (t_now - time).as_secs_f32();
     time = t_now;
+    let size_vec = egui::vec2(size[0] as f32, size[1] as f32);
+    let viewport = Rect::from_min_max(Pos2::ZERO, size_vec.to_pos2());
     let sc = StateCallback {
       dt,
       time: (t_now - begin).as_secs_f32(),
       params: params,
-      camera: cam,
-      size: SIZE_VEC,
-      new_blur: egui::mutex::Mutex::new(None)
+      camera: controller.get_camera(),
+      size: size_vec,
+      new_blur: egui::mutex::Mutex::new(None),
+      new_sources: egui::mutex::Mutex::new(None),
+      new_material: egui::mutex::Mutex::new(None),
+      new_environment: egui::mutex::Mutex::new(None),
+      layers: Default::default(),
+      particle_view: None,
+      surface: None,
+      record: egui::mutex::Mutex::new(record.take()),
     };
+    params.regen_particles = false;
 
     if capture_count > 0 {
       log::info!("Remaining captures: {capture_count}, fps={:.1}", 1./dt);
-      api.start_frame_capture();
+      if let Some(api) = &api {
+        api.start_frame_capture();
+      }
     }
 
     let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -158,7 +302,7 @@ async fn main() {
       &device,
       &queue,
       &ScreenDescriptor {
-        size_in_pixels: SIZE,
+        size_in_pixels: size,
         pixels_per_point: 1.0,
       },
       &mut encoder,
@@ -187,21 +331,72 @@ async fn main() {
         viewport: viewport,
         clip_rect: viewport,
         pixels_per_point: 1.0,
-        screen_size_px: SIZE,
+        screen_size_px: size,
       },
       &mut pass,
       &callback_res,
     );
     std::mem::drop(pass);
+    let mut shot_reader = match shot
+      .as_ref()
+      .map(|_| FrameReader::new(&device, target_tex.tex().size(), format))
+    {
+      Some(Ok(mut reader)) => {
+        reader.copy(&mut encoder, target_tex.tex());
+        Some(reader)
+      }
+      Some(Err(e)) => {
+        log::error!("Unable to take the screenshot: {e}");
+        shot = None;
+        None
+      }
+      None => None,
+    };
     queue.submit(
       a.into_iter()
         .chain(b)
         .chain(std::iter::once(encoder.finish())),
     );
+    if let (Some(path), Some(frame)) = (
+      shot.take(),
+      shot_reader.as_mut().and_then(|r| r.read(&device)),
+    ) {
+      match frame.save_png(&path) {
+        Ok(()) => log::info!("Frame written to {}", path.display()),
+        Err(e) => log::error!("Unable to write {}: {e}", path.display()),
+      }
+    }
     if capture_count > 0 {
-      api.end_frame_capture();
+      if let Some(api) = &api {
+        api.end_frame_capture();
+      }
       capture_count -= 1;
     }
+    if steps_left > 0 {
+      steps_left -= 1;
+      params.paused = steps_left == 0;
+    }
+  }
+  if let Some(state) = callback_res.get_mut::<PersistentState>() {
+    state.stop_recording(&device);
   }
   log::info!("Exit.");
 }
+
+fn initial_camera() -> OrbitCameraController {
+  let mut controller = OrbitCameraController::default();
+  controller.rotate_radians(egui::Vec2 {
+    x: std::f32::consts::PI / 4.,
+    y: 0.0,
+  });
+  controller
+}
+
+fn write_to(
+  path: &Path,
+  f: impl FnOnce(&mut io::BufWriter<fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+  let mut w = io::BufWriter::new(fs::File::create(path)?);
+  f(&mut w)?;
+  w.flush()
+}
@@ -10,6 +10,9 @@ void *create_renderdoc_api() {
   if (mod) {
     pRENDERDOC_GetAPI RENDERDOC_GetAPI =
         (pRENDERDOC_GetAPI)dlsym(mod, "RENDERDOC_GetAPI");
+    if (RENDERDOC_GetAPI == NULL) {
+      return NULL;
+    }
     RenderdocApi *out = malloc(sizeof(RenderdocApi));
     int ret = RENDERDOC_GetAPI(kRequestedVersion, (void **)&out);
 
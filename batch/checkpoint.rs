@@ -0,0 +1,134 @@
+use std::{
+  fs,
+  io::{self, Read, Write},
+  path::Path,
+};
+
+use cgmath::{Point3, Vector3};
+use limne::solvers::sph_solver_gpu::Particle;
+
+const MAGIC: &[u8; 8] = b"LIMNECK1";
+/// Position, density and velocity
+const PARTICLE_FLOATS: usize = 7;
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use limne::solvers::sph_solver_gpu::Particle;
+
+  use super::Checkpoint;
+
+  #[test]
+  fn roundtrip() {
+    let particles = (0..4)
+      .map(|i| {
+        let mut p = Particle::default();
+        p.pos = Point3::new(i as f32, 1.0, -2.0);
+        p.density = 1000.0 + i as f32;
+        p.velocity = Vector3::new(0.0, -(i as f32), 0.5);
+        p
+      })
+      .collect();
+    let ckpt = Checkpoint {
+      step: 1234,
+      time: 5.5,
+      particles,
+    };
+    let mut bytes = Vec::new();
+    ckpt.write_to(&mut bytes).unwrap();
+    let read = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+    assert_eq!((read.step, read.time), (ckpt.step, ckpt.time));
+    for (a, b) in read.particles.iter().zip(&ckpt.particles) {
+      assert_eq!(
+        (a.pos, a.density, a.velocity),
+        (b.pos, b.density, b.velocity)
+      );
+    }
+    assert!(Checkpoint::read_from(&mut &bytes[1..]).is_err());
+  }
+}
+
+/// State of a batch run the simulation can be resumed from.
+///
+/// The file starts with [`MAGIC`], the step, the time and the count of the particles,
+/// followed by [`PARTICLE_FLOATS`] floats per particle, all little endian.
+#[derive(Debug)]
+pub struct Checkpoint {
+  pub step: u64,
+  pub time: f32,
+  /// Including the dead particles
+  pub particles: Vec<Particle>,
+}
+
+impl Checkpoint {
+  pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&self.step.to_le_bytes())?;
+    w.write_all(&self.time.to_le_bytes())?;
+    w.write_all(&(self.particles.len() as u32).to_le_bytes())?;
+    for p in &self.particles {
+      let floats: [f32; PARTICLE_FLOATS] = [
+        p.pos.x,
+        p.pos.y,
+        p.pos.z,
+        p.density,
+        p.velocity.x,
+        p.velocity.y,
+        p.velocity.z,
+      ];
+      for f in floats {
+        w.write_all(&f.to_le_bytes())?;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "not a checkpoint",
+      ));
+    }
+    let mut step = [0; 8];
+    let mut time = [0; 4];
+    let mut count = [0; 4];
+    r.read_exact(&mut step)?;
+    r.read_exact(&mut time)?;
+    r.read_exact(&mut count)?;
+    let count = u32::from_le_bytes(count) as usize;
+    let mut bytes = vec![0; count * PARTICLE_FLOATS * size_of::<f32>()];
+    r.read_exact(&mut bytes)?;
+    let particles = bytes
+      .chunks_exact(PARTICLE_FLOATS * size_of::<f32>())
+      .map(|b| {
+        let f: Vec<f32> = b
+          .chunks_exact(size_of::<f32>())
+          .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+          .collect();
+        let mut p = Particle::default();
+        p.pos = Point3::new(f[0], f[1], f[2]);
+        p.density = f[3];
+        p.velocity = Vector3::new(f[4], f[5], f[6]);
+        p
+      })
+      .collect();
+    Ok(Self {
+      step: u64::from_le_bytes(step),
+      time: f32::from_le_bytes(time),
+      particles,
+    })
+  }
+
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    let mut w = io::BufWriter::new(fs::File::create(path)?);
+    self.write_to(&mut w)?;
+    w.flush()
+  }
+
+  pub fn load(path: &Path) -> io::Result<Self> {
+    Self::read_from(&mut io::BufReader::new(fs::File::open(path)?))
+  }
+}
@@ -0,0 +1,266 @@
+use std::{
+  error::Error,
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  process::ExitCode,
+};
+
+use egui_wgpu::{WgpuSetup, WgpuSetupExisting};
+use limne::{
+  create_wgpu_setup_with,
+  export::{write_mesh, write_particles_csv, MeshFormat},
+  render::state::PersistentState,
+  scene::Scene,
+  solvers::{
+    sph_solver_gpu::Particle,
+    surface::{extract_cpu, Mesh, SurfaceGrid, SurfaceSettings},
+  },
+  stats::{self, Stats},
+};
+
+use checkpoint::Checkpoint;
+
+mod checkpoint;
+
+const USAGE: &str = "\
+Usage: limne-batch <SCENE> [OPTIONS]
+
+Runs the simulation of the RON scene without a window.
+
+Options:
+  --steps <N>             Count of the steps to run [default: 1000]
+  --dt <SECONDS>          Time step [default: 0.001]
+  --out <DIR>             Output directory [default: batch-out]
+  --stats-every <N>       Steps between the lines of `stats.csv` [default: 10]
+  --check-every <N>       Steps between the checks for non-finite particles [default: 10]
+  --export-every <N>      Steps between the `particles-<step>.csv` exports, 0 disables [default: 0]
+  --checkpoint-every <N>  Steps between the `checkpoint-<step>.bin` files, 0 disables [default: 0]
+  --mesh-every <N>        Steps between the `surface-<step>.<ext>` meshes, 0 disables [default: 0]
+  --mesh-format <FORMAT>  Format of the meshes, `obj` or `ply` [default: obj]
+  --mesh-resolution <N>   Cells of the mesh grid along the width of the tank [default: 64]
+  --resume <CHECKPOINT>   Continue from a checkpoint instead of generating the particles
+  --fallback              Use the fallback adapter, usually a CPU implementation
+
+Exit status: 0 on success, 1 on errors, 2 on invalid arguments, 3 if NaNs appeared.";
+
+/// The particles are never rendered, so the format doesn't matter
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Steps submitted before waiting for the GPU to finish them
+const SYNC_EVERY: u64 = 16;
+
+struct Options {
+  scene: PathBuf,
+  steps: u64,
+  dt: f32,
+  out: PathBuf,
+  stats_every: u64,
+  check_every: u64,
+  export_every: u64,
+  checkpoint_every: u64,
+  mesh_every: u64,
+  mesh_format: MeshFormat,
+  surface: SurfaceSettings,
+  resume: Option<PathBuf>,
+  fallback: bool,
+}
+
+impl Options {
+  fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+    let mut scene = None;
+    let mut opts = Options {
+      scene: PathBuf::new(),
+      steps: 1000,
+      dt: 1e-3,
+      out: PathBuf::from("batch-out"),
+      stats_every: 10,
+      check_every: 10,
+      export_every: 0,
+      checkpoint_every: 0,
+      mesh_every: 0,
+      mesh_format: MeshFormat::Obj,
+      surface: SurfaceSettings::default(),
+      resume: None,
+      fallback: false,
+    };
+    while let Some(arg) = args.next() {
+      let mut value = || args.next().ok_or(format!("`{arg}` requires a value"));
+      match arg.as_str() {
+        "--steps" => opts.steps = parse_value(&arg, value()?)?,
+        "--dt" => opts.dt = parse_value(&arg, value()?)?,
+        "--out" => opts.out = value()?.into(),
+        "--stats-every" => opts.stats_every = parse_value(&arg, value()?)?,
+        "--check-every" => {
+          opts.check_every = parse_value(&arg, value()?)?;
+          if opts.check_every == 0 {
+            return Err("`--check-every` must be positive".to_owned());
+          }
+        }
+        "--export-every" => opts.export_every = parse_value(&arg, value()?)?,
+        "--checkpoint-every" => opts.checkpoint_every = parse_value(&arg, value()?)?,
+        "--mesh-every" => opts.mesh_every = parse_value(&arg, value()?)?,
+        "--mesh-format" => opts.mesh_format = parse_value(&arg, value()?)?,
+        "--mesh-resolution" => opts.surface.resolution = parse_value(&arg, value()?)?,
+        "--resume" => opts.resume = Some(value()?.into()),
+        "--fallback" => opts.fallback = true,
+        _ if arg.starts_with('-') => return Err(format!("Unknown option `{arg}`")),
+        _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+        _ => return Err(format!("Unexpected argument `{arg}`")),
+      }
+    }
+    opts.scene = scene.ok_or("The scene is not specified")?;
+    Ok(opts)
+  }
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: String) -> Result<T, String> {
+  value
+    .parse()
+    .map_err(|_| format!("Invalid value of `{option}`: {value}"))
+}
+
+fn export_particles(path: &Path, particles: &[Particle]) -> io::Result<()> {
+  let mut w = io::BufWriter::new(fs::File::create(path)?);
+  write_particles_csv(&mut w, particles)?;
+  w.flush()
+}
+
+fn export_mesh(path: &Path, mesh: &Mesh, format: MeshFormat) -> io::Result<()> {
+  let mut w = io::BufWriter::new(fs::File::create(path)?);
+  write_mesh(&mut w, mesh, format)?;
+  w.flush()
+}
+
+/// Returns `false` if NaNs appeared
+async fn run(opts: &Options) -> Result<bool, Box<dyn Error>> {
+  let scene = Scene::load(&opts.scene)?;
+  let WgpuSetup::Existing(WgpuSetupExisting { device, queue, .. }) =
+    create_wgpu_setup_with(opts.fallback).await?
+  else {
+    unreachable!()
+  };
+  let mut state =
+    PersistentState::create_with(&device, &FORMAT, &queue, scene.count, scene.storage, true);
+  state
+    .simulation_mut()
+    .set_sources(scene.emitters.clone(), scene.sinks.clone());
+  fs::create_dir_all(&opts.out)?;
+
+  let mut params = scene.params;
+  // The time is computed from the steps since `start`, so the rounding errors don't add up
+  let (mut start, mut start_time) = (0, 0.0);
+  match &opts.resume {
+    Some(path) => {
+      let ckpt = Checkpoint::load(path)?;
+      log::info!("Resuming from the step {}", ckpt.step);
+      (start, start_time) = (ckpt.step, ckpt.time);
+      state
+        .simulation_mut()
+        .load_particles(&device, &queue, &ckpt.particles);
+    }
+    None => params.regen_particles = true,
+  }
+
+  let stats_path = opts.out.join("stats.csv");
+  // Appended to when resuming
+  let new_stats = opts.resume.is_none() || !stats_path.exists();
+  let mut stats_csv = io::BufWriter::new(
+    fs::OpenOptions::new()
+      .create(true)
+      .write(true)
+      .append(!new_stats)
+      .truncate(new_stats)
+      .open(&stats_path)?,
+  );
+  if new_stats {
+    writeln!(stats_csv, "{}", stats::CSV_HEADER)?;
+  }
+
+  let time_at = |step: u64| (start_time as f64 + (step - start) as f64 * opts.dt as f64) as f32;
+  let (mut step, end) = (start, start + opts.steps);
+  while step < end {
+    state.step(&device, &queue, &params, time_at(step), opts.dt);
+    params.regen_particles = false;
+    step += 1;
+    let time = time_at(step);
+    // Otherwise the submitted steps may queue up without a limit
+    if step % SYNC_EVERY == 0 {
+      device.poll(wgpu::Maintain::Wait);
+    } else {
+      device.poll(wgpu::Maintain::Poll);
+    }
+
+    let due = |every: u64| every != 0 && (step % every == 0 || step == end);
+    if !due(opts.check_every)
+      && !due(opts.stats_every)
+      && !due(opts.export_every)
+      && !due(opts.checkpoint_every)
+      && !due(opts.mesh_every)
+    {
+      continue;
+    }
+    let particles = state.simulation().read_particles(&device, &queue);
+    let stats = Stats::compute(&particles, params.m0);
+    if due(opts.stats_every) {
+      stats.write_csv(&mut stats_csv, step, time)?;
+      log::info!("Step {step}/{end}: {} particles", stats.alive);
+    }
+    if stats.non_finite > 0 {
+      stats_csv.flush()?;
+      log::error!(
+        "{} particles are not finite at the step {step}",
+        stats.non_finite
+      );
+      return Ok(false);
+    }
+    if due(opts.export_every) {
+      export_particles(
+        &opts.out.join(format!("particles-{step:06}.csv")),
+        &particles,
+      )?;
+    }
+    if due(opts.mesh_every) {
+      // There is no renderer, the surface is extracted on the CPU
+      let grid = SurfaceGrid::new(params.w, params.h, opts.surface.resolution);
+      let mesh = extract_cpu(&particles, &grid, &opts.surface).welded();
+      export_mesh(
+        &opts.out.join(format!(
+          "surface-{step:06}.{}",
+          opts.mesh_format.extension()
+        )),
+        &mesh,
+        opts.mesh_format,
+      )?;
+    }
+    if due(opts.checkpoint_every) {
+      Checkpoint {
+        step,
+        time,
+        particles,
+      }
+      .save(&opts.out.join(format!("checkpoint-{step:06}.bin")))?;
+    }
+  }
+  stats_csv.flush()?;
+  Ok(true)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+  env_logger::init();
+  let opts = match Options::parse(std::env::args().skip(1)) {
+    Ok(opts) => opts,
+    Err(e) => {
+      eprintln!("{e}\n\n{USAGE}");
+      return ExitCode::from(2);
+    }
+  };
+  match run(&opts).await {
+    Ok(true) => ExitCode::SUCCESS,
+    Ok(false) => ExitCode::from(3),
+    Err(e) => {
+      log::error!("{e}");
+      ExitCode::FAILURE
+    }
+  }
+}
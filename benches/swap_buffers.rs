@@ -0,0 +1,76 @@
+//! Measures the copy between the particle buffers that the ping-pong design avoids,
+//! in isolation from the solver. Both variants submit an encoder per step and wait for it,
+//! so the difference of the times is the cost of the copy.
+//!
+//! Run with `cargo bench --bench swap_buffers`.
+use std::time::{Duration, Instant};
+
+use egui_wgpu::{WgpuSetup, WgpuSetupExisting};
+use limne::{
+  create_wgpu_setup_with,
+  render::swapchain::{SwapBuffers, SwapBuffersDescriptor},
+  solvers::{
+    sph_solver_gpu::Particle,
+    storage::{ParticleData, StorageLayout},
+  },
+};
+
+const COUNTS: [usize; 3] = [16_384, 65_536, 262_144];
+const STEPS: u32 = 1000;
+const WARMUP: u32 = 50;
+
+/// Runs `steps` submissions swapping the `particles` and returns the time spent per step
+fn run(
+  device: &wgpu::Device,
+  queue: &wgpu::Queue,
+  particles: &mut SwapBuffers<ParticleData>,
+  copy: bool,
+  steps: u32,
+) -> Duration {
+  let start = Instant::now();
+  for _ in 0..steps {
+    let mut encoder = device.create_command_encoder(&Default::default());
+    if copy {
+      particles.swap(&mut encoder);
+    } else {
+      particles.flip();
+    }
+    queue.submit([encoder.finish()]);
+    device.poll(wgpu::Maintain::Wait);
+  }
+  start.elapsed() / steps
+}
+
+#[tokio::main]
+async fn main() {
+  let Ok(WgpuSetup::Existing(WgpuSetupExisting { device, queue, .. })) =
+    create_wgpu_setup_with(false).await
+  else {
+    eprintln!("No GPU adapter available, skipping the benchmark");
+    return;
+  };
+  println!("{:>10} {:>12} {:>12}", "particles", "copy, µs", "flip, µs");
+  for count in COUNTS {
+    let mut particles = SwapBuffers::init_with(
+      ParticleData::new(StorageLayout::default(), &vec![Particle::default(); count]),
+      &device,
+      SwapBuffersDescriptor {
+        usage: wgpu::BufferUsages::STORAGE
+          | wgpu::BufferUsages::COPY_SRC
+          | wgpu::BufferUsages::COPY_DST,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+      },
+    );
+    run(&device, &queue, &mut particles, true, WARMUP);
+    let copy = run(&device, &queue, &mut particles, true, STEPS);
+    let flip = run(&device, &queue, &mut particles, false, STEPS);
+    println!(
+      "{:>10} {:>12.1} {:>12.1}",
+      count,
+      copy.as_secs_f64() * 1e6,
+      flip.as_secs_f64() * 1e6,
+    );
+  }
+}
@@ -1,4 +1,5 @@
 fn main() {
+    #[cfg(feature = "renderdoc")]
     cc::Build::new()
         .std("c11")
         .file("amnis/renderdoc.c")
@@ -0,0 +1,111 @@
+use std::{
+  io::{self, Write},
+  str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::solvers::{emitters::DEAD_POS, sph_solver_gpu::Particle, surface::Mesh};
+
+/// Writes the alive particles as the `x,y,z,density,vx,vy,vz` lines
+pub fn write_particles_csv(w: &mut impl Write, particles: &[Particle]) -> io::Result<()> {
+  writeln!(w, "x,y,z,density,vx,vy,vz")?;
+  for p in particles.iter().filter(|p| p.pos.x != DEAD_POS) {
+    writeln!(
+      w,
+      "{},{},{},{},{},{},{}",
+      p.pos.x, p.pos.y, p.pos.z, p.density, p.velocity.x, p.velocity.y, p.velocity.z
+    )?;
+  }
+  Ok(())
+}
+
+/// File formats of the exported surface meshes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshFormat {
+  #[default]
+  Obj,
+  /// Binary little-endian
+  Ply,
+}
+
+impl MeshFormat {
+  pub const ALL: [MeshFormat; 2] = [MeshFormat::Obj, MeshFormat::Ply];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      MeshFormat::Obj => "OBJ",
+      MeshFormat::Ply => "PLY",
+    }
+  }
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      MeshFormat::Obj => "obj",
+      MeshFormat::Ply => "ply",
+    }
+  }
+}
+
+impl FromStr for MeshFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Self::ALL
+      .into_iter()
+      .find(|f| f.extension().eq_ignore_ascii_case(s))
+      .ok_or_else(|| format!("Unknown mesh format `{s}`"))
+  }
+}
+
+pub fn write_mesh(w: &mut impl Write, mesh: &Mesh, format: MeshFormat) -> io::Result<()> {
+  match format {
+    MeshFormat::Obj => write_mesh_obj(w, mesh),
+    MeshFormat::Ply => write_mesh_ply(w, mesh),
+  }
+}
+
+/// Writes the vertices with their normals and the faces indexing both
+pub fn write_mesh_obj(w: &mut impl Write, mesh: &Mesh) -> io::Result<()> {
+  for [x, y, z] in &mesh.positions {
+    writeln!(w, "v {x} {y} {z}")?;
+  }
+  for [x, y, z] in &mesh.normals {
+    writeln!(w, "vn {x} {y} {z}")?;
+  }
+  // The indices of OBJ start at 1
+  for t in mesh.indices.chunks_exact(3) {
+    let [a, b, c] = [t[0] + 1, t[1] + 1, t[2] + 1];
+    writeln!(w, "f {a}//{a} {b}//{b} {c}//{c}")?;
+  }
+  Ok(())
+}
+
+/// Writes the vertices as `x y z nx ny nz` floats and the triangles as lists of `int` indices
+pub fn write_mesh_ply(w: &mut impl Write, mesh: &Mesh) -> io::Result<()> {
+  write!(
+    w,
+    "ply\n\
+     format binary_little_endian 1.0\n\
+     element vertex {}\n\
+     property float x\nproperty float y\nproperty float z\n\
+     property float nx\nproperty float ny\nproperty float nz\n\
+     element face {}\n\
+     property list uchar int vertex_indices\n\
+     end_header\n",
+    mesh.positions.len(),
+    mesh.triangles()
+  )?;
+  for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+    for c in p.iter().chain(n) {
+      w.write_all(&c.to_le_bytes())?;
+    }
+  }
+  for t in mesh.indices.chunks_exact(3) {
+    w.write_all(&[3])?;
+    for &i in t {
+      w.write_all(&(i as i32).to_le_bytes())?;
+    }
+  }
+  Ok(())
+}
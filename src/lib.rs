@@ -4,10 +4,9 @@
 #![feature(more_float_constants)]
 #![feature(more_qualified_paths)]
 
-use solvers::{
-  bitonic_sorter::{LOCAL_ARRAY_SIZE, LOCAL_PASS_SIZE},
-  sph_solver_gpu::Particle,
-};
+use std::fmt;
+
+use solvers::bitonic_sorter::{SortMode, LOCAL_PASS_SIZE};
 use wgpu::Features;
 #[macro_export]
 macro_rules! with {
@@ -27,30 +26,70 @@ macro_rules! with {
       }
   };
 }
+pub mod export;
+pub mod remote;
 pub mod render;
+pub mod scene;
 pub mod solvers;
+pub mod stats;
 
-pub async fn create_wgpu_setup() -> egui_wgpu::WgpuSetup {
-  let required_limits = wgpu::Limits {
-    max_bind_groups: 5,
-    max_compute_invocations_per_workgroup: LOCAL_PASS_SIZE,
-    max_compute_workgroup_size_x: LOCAL_PASS_SIZE,
-    max_compute_workgroup_storage_size: LOCAL_ARRAY_SIZE * size_of::<Particle>() as u32,
-    max_push_constant_size: 8,
-    ..Default::default()
-  };
-  let required_features = Features::VERTEX_WRITABLE_STORAGE
-    | Features::POLYGON_MODE_LINE
-    | Features::PUSH_CONSTANTS
-    | Features::ADDRESS_MODE_CLAMP_TO_BORDER
-    | Features::DEPTH_CLIP_CONTROL;
+/// Error of [`create_wgpu_setup`]
+#[derive(Debug)]
+pub enum SetupError {
+  NoAdapter,
+  /// The adapter lacks the features or the limits the simulation can't do without
+  Unsupported {
+    adapter: String,
+    missing_features: Features,
+    /// Descriptions of the limits that are too low
+    missing_limits: Vec<String>,
+  },
+  Device(wgpu::RequestDeviceError),
+}
 
-  log::info!("Required workgroup size: {LOCAL_PASS_SIZE}");
-  log::info!(
-    "Required local storage: {}",
-    required_limits.max_compute_workgroup_storage_size
-  );
+impl fmt::Display for SetupError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SetupError::NoAdapter => write!(f, "No suitable graphics adapter found"),
+      SetupError::Unsupported {
+        adapter,
+        missing_features,
+        missing_limits,
+      } => {
+        write!(f, "The adapter {adapter} is unsupported.")?;
+        if !missing_features.is_empty() {
+          write!(f, "\nMissing features: {missing_features:?}")?;
+        }
+        for limit in missing_limits {
+          write!(f, "\nInsufficient limit: {limit}")?;
+        }
+        Ok(())
+      }
+      SetupError::Device(e) => write!(f, "Unable to create a device: {e}"),
+    }
+  }
+}
+
+impl std::error::Error for SetupError {}
+
+/// Features the simulation requires
+const REQUIRED_FEATURES: Features = Features::empty();
+/// Features enabled if the adapter supports them, otherwise a fallback is used,
+/// see the warnings logged by [`create_wgpu_setup`]
+const OPTIONAL_FEATURES: Features = Features::PUSH_CONSTANTS
+  .union(Features::POLYGON_MODE_LINE)
+  .union(Features::TIMESTAMP_QUERY)
+  .union(Features::ADDRESS_MODE_CLAMP_TO_BORDER)
+  .union(Features::DEPTH_CLIP_CONTROL);
 
+pub async fn create_wgpu_setup() -> Result<egui_wgpu::WgpuSetup, SetupError> {
+  create_wgpu_setup_with(false).await
+}
+
+/// Requests the fallback adapter, usually a CPU implementation, if `force_fallback_adapter` is set
+pub async fn create_wgpu_setup_with(
+  force_fallback_adapter: bool,
+) -> Result<egui_wgpu::WgpuSetup, SetupError> {
   let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
     backends: wgpu::Backends::all(),
     ..Default::default()
@@ -60,31 +99,98 @@ pub async fn create_wgpu_setup() -> egui_wgpu::WgpuSetup {
     .request_adapter(&wgpu::RequestAdapterOptions {
       compatible_surface: None,
       power_preference: wgpu::PowerPreference::HighPerformance,
-      force_fallback_adapter: false,
+      force_fallback_adapter,
     })
     .await
-    .expect("Unable to create an adapter");
+    .ok_or(SetupError::NoAdapter)?;
 
   log::info!("Backend: {}", adapter.get_info().backend);
   log::info!("Adapter: {}", adapter.get_info().name);
   log::debug!("Adapter's limits:\n{:#?}", adapter.limits());
 
+  let adapter_limits = adapter.limits();
+  let optional_features = adapter.features() & OPTIONAL_FEATURES;
+  let local_pass_size = SortMode::default().local_pass_size(&adapter_limits);
+  let required_limits = wgpu::Limits {
+    max_bind_groups: 5,
+    max_compute_invocations_per_workgroup: local_pass_size
+      .max(wgpu::Limits::default().max_compute_invocations_per_workgroup),
+    max_compute_workgroup_size_x: local_pass_size
+      .max(wgpu::Limits::default().max_compute_workgroup_size_x),
+    max_compute_workgroup_storage_size: SortMode::default()
+      .workgroup_storage_size(local_pass_size)
+      .max(wgpu::Limits::default().max_compute_workgroup_storage_size),
+    max_push_constant_size: if optional_features.contains(Features::PUSH_CONSTANTS) {
+      8
+    } else {
+      0
+    },
+    ..Default::default()
+  };
+
+  let missing_features = REQUIRED_FEATURES - adapter.features();
+  let mut missing_limits = Vec::new();
+  required_limits.check_limits_with_fail_fn(&adapter_limits, false, |name, required, allowed| {
+    missing_limits.push(format!("{name} is {allowed}, {required} is required"))
+  });
+  if !missing_features.is_empty() || !missing_limits.is_empty() {
+    return Err(SetupError::Unsupported {
+      adapter: adapter.get_info().name,
+      missing_features,
+      missing_limits,
+    });
+  }
+
+  if local_pass_size < LOCAL_PASS_SIZE {
+    log::warn!("Workgroups of {LOCAL_PASS_SIZE} invocations are unsupported, the sorter uses {local_pass_size}");
+  }
+  for (feature, fallback) in [
+    (
+      Features::PUSH_CONSTANTS,
+      "the pass parameters are passed in uniform buffers",
+    ),
+    (
+      Features::POLYGON_MODE_LINE,
+      "the gizmo outlines are not drawn",
+    ),
+    (Features::TIMESTAMP_QUERY, "GPU profiling is disabled"),
+    (
+      Features::ADDRESS_MODE_CLAMP_TO_BORDER,
+      "the textures are clamped to the edge",
+    ),
+    (
+      Features::DEPTH_CLIP_CONTROL,
+      "the depth of the smoothed fluid is clipped",
+    ),
+  ] {
+    if !optional_features.contains(feature) {
+      log::warn!("{feature:?} is unsupported, {fallback}");
+    }
+  }
+  log::info!("Workgroup size: {local_pass_size}");
+  log::info!(
+    "Local storage: {}",
+    required_limits.max_compute_workgroup_storage_size
+  );
+
   let (device, queue) = adapter
     .request_device(
       &wgpu::DeviceDescriptor {
-        required_features,
+        required_features: REQUIRED_FEATURES | optional_features,
         required_limits,
         ..Default::default()
       },
       None,
     )
     .await
-    .expect("Your hardware is unsupported");
+    .map_err(SetupError::Device)?;
 
-  egui_wgpu::WgpuSetup::Existing(egui_wgpu::WgpuSetupExisting {
-    instance,
-    adapter,
-    device,
-    queue,
-  })
+  Ok(egui_wgpu::WgpuSetup::Existing(
+    egui_wgpu::WgpuSetupExisting {
+      instance,
+      adapter,
+      device,
+      queue,
+    },
+  ))
 }
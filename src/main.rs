@@ -2,25 +2,70 @@ use eframe::{AppCreator, NativeOptions};
 use egui_wgpu::WgpuConfiguration;
 
 use limne::create_wgpu_setup;
+use limne::remote::{ListenAddr, RemoteServer};
 use limne::render::application::App;
 
-fn make_app_creator<'a>() -> AppCreator<'a> {
-  Box::new(|cc| Ok(Box::new(App::new(cc))))
+const USAGE: &str = "\
+Usage: limne [OPTIONS]
+
+Options:
+  --listen <ADDR>       Accept the remote control clients on a loopback TCP address
+  --listen-unix <PATH>  Accept the remote control clients on a Unix socket";
+
+fn make_app_creator<'a>(remote: Option<RemoteServer>) -> AppCreator<'a> {
+  Box::new(move |cc| Ok(Box::new(App::new(cc, remote))))
+}
+
+fn parse_listen_addr(mut args: impl Iterator<Item = String>) -> Result<Option<ListenAddr>, String> {
+  let mut addr = None;
+  while let Some(arg) = args.next() {
+    let value = args.next().ok_or(format!("`{arg}` requires a value"));
+    addr = Some(match arg.as_str() {
+      "--listen" => ListenAddr::Tcp(
+        value?
+          .parse()
+          .map_err(|e| format!("Invalid value of `{arg}`: {e}"))?,
+      ),
+      #[cfg(unix)]
+      "--listen-unix" => ListenAddr::Unix(value?.into()),
+      _ => return Err(format!("Unknown option `{arg}`")),
+    });
+  }
+  Ok(addr)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), eframe::Error> {
   env_logger::init();
+  let remote = match parse_listen_addr(std::env::args().skip(1)) {
+    Ok(addr) => addr.map(|addr| {
+      RemoteServer::start(&addr).unwrap_or_else(|e| {
+        log::error!("Unable to start the remote control server: {e}");
+        std::process::exit(1);
+      })
+    }),
+    Err(e) => {
+      eprintln!("{e}\n\n{USAGE}");
+      std::process::exit(2);
+    }
+  };
+  let wgpu_setup = match create_wgpu_setup().await {
+    Ok(setup) => setup,
+    Err(e) => {
+      log::error!("{e}");
+      std::process::exit(1);
+    }
+  };
   let opts = NativeOptions {
     hardware_acceleration: eframe::HardwareAcceleration::Required,
     renderer: eframe::Renderer::Wgpu,
     run_and_return: false,
     centered: true,
     wgpu_options: WgpuConfiguration {
-      wgpu_setup: create_wgpu_setup().await,
+      wgpu_setup,
       ..Default::default()
     },
     ..Default::default()
   };
-  eframe::run_native("m0sni.limne", opts, make_app_creator())
+  eframe::run_native("m0sni.limne", opts, make_app_creator(remote))
 }
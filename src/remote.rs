@@ -0,0 +1,571 @@
+use std::{
+  collections::HashMap,
+  io::{self, BufRead, BufReader, Read, Write},
+  net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+use egui::mutex::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[cfg(test)]
+mod test {
+  use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+  };
+
+  use serde_json::{json, Value};
+
+  use super::{Incoming, ListenAddr, RemoteServer, Request};
+
+  /// Waits for a request to arrive
+  fn poll_one(server: &RemoteServer) -> Incoming {
+    let start = Instant::now();
+    loop {
+      if let Some(incoming) = server.poll().pop() {
+        return incoming;
+      }
+      assert!(start.elapsed() < Duration::from_secs(5), "No request");
+      std::thread::sleep(Duration::from_millis(5));
+    }
+  }
+
+  fn read_json(reader: &mut impl BufRead) -> Value {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+  }
+
+  #[test]
+  fn tcp_roundtrip() {
+    let server = RemoteServer::start(&ListenAddr::Tcp("127.0.0.1:0".parse().unwrap())).unwrap();
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    writeln!(
+      stream,
+      r#"{{"id": 7, "cmd": "set", "param": "h", "value": 0.05}}"#
+    )
+    .unwrap();
+    let incoming = poll_one(&server);
+    assert_eq!(incoming.id, Some(json!(7)));
+    assert_eq!(
+      incoming.request,
+      Request::Set {
+        param: "h".into(),
+        value: json!(0.05)
+      }
+    );
+    server.reply(&incoming, Ok(Value::Null));
+    assert_eq!(read_json(&mut reader), json!({"id": 7, "ok": true}));
+
+    writeln!(stream, r#"{{"cmd": "jump"}}"#).unwrap();
+    let error = read_json(&mut reader);
+    assert_eq!(error["ok"], json!(false));
+    assert!(error["error"].as_str().unwrap().contains("jump"));
+
+    writeln!(stream, r#"{{"cmd": "subscribe", "every": 2}}"#).unwrap();
+    // Handled by the server
+    let start = Instant::now();
+    while server.stats_subscribers(0).is_empty() {
+      assert!(server.poll().is_empty());
+      assert!(start.elapsed() < Duration::from_secs(5), "No request");
+      std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(read_json(&mut reader), json!({"ok": true}));
+    assert!(server.stats_subscribers(3).is_empty());
+    assert_eq!(server.stats_subscribers(4), [incoming.client]);
+    server.send_event(incoming.client, "stats", json!({"frame": 4}));
+    assert_eq!(
+      read_json(&mut reader),
+      json!({"event": "stats", "frame": 4})
+    );
+  }
+
+  #[test]
+  fn drop_disconnects() {
+    let server = RemoteServer::start(&ListenAddr::Tcp("127.0.0.1:0".parse().unwrap())).unwrap();
+    let addr = server.local_addr().unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream);
+    writeln!(reader.get_mut(), r#"{{"cmd": "pause"}}"#).unwrap();
+    poll_one(&server);
+    drop(server);
+    let mut line = String::new();
+    assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+    assert!(TcpStream::connect(addr).is_err());
+  }
+
+  #[test]
+  fn non_loopback_is_refused() {
+    assert!(RemoteServer::start(&ListenAddr::Tcp("0.0.0.0:0".parse().unwrap())).is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_roundtrip() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("limne-test-{}.sock", std::process::id()));
+    let server = RemoteServer::start(&ListenAddr::Unix(path.clone())).unwrap();
+    let mut stream = UnixStream::connect(&path).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    writeln!(stream, r#"{{"cmd": "step", "n": 3}}"#).unwrap();
+    let incoming = poll_one(&server);
+    assert_eq!(incoming.request, Request::Step { n: 3 });
+    server.reply(&incoming, Err("busy".into()));
+    assert_eq!(
+      read_json(&mut reader),
+      json!({"ok": false, "error": "busy"})
+    );
+    drop(server);
+    assert!(!path.exists());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn stale_socket_is_replaced() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("limne-stale-{}.sock", std::process::id()));
+    // Left behind like by a crashed run
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let server = RemoteServer::start(&ListenAddr::Unix(path.clone())).unwrap();
+    assert!(RemoteServer::start(&ListenAddr::Unix(path.clone())).is_err());
+    drop(server);
+    assert!(!path.exists());
+  }
+}
+
+/// Where [`RemoteServer`] listens
+#[derive(Clone, Debug)]
+pub enum ListenAddr {
+  /// Only the loopback addresses are allowed
+  Tcp(SocketAddr),
+  #[cfg(unix)]
+  Unix(PathBuf),
+}
+
+/// A command of the remote control protocol.
+///
+/// Every line sent by a client is a JSON object with the `cmd` field and the fields of the command,
+/// and an optional `id` copied to the response. The response is
+/// `{"id": ..., "ok": true, "result": ...}` or `{"id": ..., "ok": false, "error": "..."}`.
+/// Events are sent as `{"event": "<name>", ...}` lines.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+  /// Sets a field of [`SimulationParams`](crate::render::targets::simulation::SimulationParams),
+  /// the `value` is in the form returned by `get`, e.g. `"Hilbert"` for the sort key
+  Set {
+    param: String,
+    value: Value,
+  },
+  /// A parameter or all of them if `param` is not set
+  Get {
+    #[serde(default)]
+    param: Option<String>,
+  },
+  Pause,
+  Resume,
+  /// Runs `n` steps and pauses, the `steps_done` event is sent after them
+  Step {
+    n: u32,
+  },
+  /// Rotates the camera around its center, the angles are in degrees
+  Orbit {
+    yaw: f32,
+    pitch: f32,
+  },
+  /// Changes the distance from the camera to its center
+  Zoom {
+    delta: f32,
+  },
+  ResetCamera,
+  /// Alive particles as the `[x, y, z, density, vx, vy, vz]` arrays
+  Snapshot,
+  /// Statistics of the particles
+  Stats,
+  /// Sends the `stats` event every `every` frames, 0 stops it.
+  /// Handled by the server itself.
+  Subscribe {
+    every: u32,
+  },
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+  #[serde(default)]
+  id: Option<Value>,
+  #[serde(flatten)]
+  request: Request,
+}
+
+pub type ClientId = u64;
+
+/// A request received from a client
+#[derive(Debug)]
+pub struct Incoming {
+  pub client: ClientId,
+  pub id: Option<Value>,
+  pub request: Request,
+}
+
+/// Messages queued for a client, it is dropped when it falls this far behind
+const QUEUE_LEN: usize = 256;
+/// A client not accepting a message for this long is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest pause of the accept loop after consecutive errors
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+struct Client {
+  /// Lines sent by the writer thread of the client
+  queue: mpsc::SyncSender<String>,
+  /// Period of the `stats` events in frames, 0 if they are not sent
+  stats_every: u32,
+}
+
+type Clients = Arc<Mutex<HashMap<ClientId, Client>>>;
+
+/// Server of a JSON-lines protocol steering a running simulation, see [`Request`].
+///
+/// The connections are served by background threads, the requests are taken by
+/// [`RemoteServer::poll`] on every frame.
+pub struct RemoteServer {
+  clients: Clients,
+  incoming: mpsc::Receiver<Incoming>,
+  local_addr: Option<SocketAddr>,
+  /// Removed when the server is dropped
+  socket_path: Option<PathBuf>,
+  /// Tells the accept thread to end at the next connection
+  stop: Arc<AtomicBool>,
+  accept_thread: Option<JoinHandle<()>>,
+}
+
+impl RemoteServer {
+  pub fn start(addr: &ListenAddr) -> io::Result<Self> {
+    let clients = Clients::default();
+    let (tx, incoming) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut server = Self {
+      clients: clients.clone(),
+      incoming,
+      local_addr: None,
+      socket_path: None,
+      stop: stop.clone(),
+      accept_thread: None,
+    };
+    match addr {
+      ListenAddr::Tcp(addr) => {
+        if !addr.ip().is_loopback() {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{addr} is not a loopback address"),
+          ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        server.local_addr = Some(listener.local_addr()?);
+        log::info!("Remote control listens on {}", listener.local_addr()?);
+        server.accept_thread = Some(accept(clients, tx, stop, move || {
+          listener.accept().map(|(s, _)| s)
+        }));
+      }
+      #[cfg(unix)]
+      ListenAddr::Unix(path) => {
+        remove_stale_socket(path)?;
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        server.socket_path = Some(path.clone());
+        log::info!("Remote control listens on {}", path.display());
+        server.accept_thread = Some(accept(clients, tx, stop, move || {
+          listener.accept().map(|(s, _)| s)
+        }));
+      }
+    }
+    Ok(server)
+  }
+
+  /// Address of the TCP listener, `None` for the Unix sockets
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    self.local_addr
+  }
+
+  /// Takes the requests received since the last call
+  pub fn poll(&self) -> Vec<Incoming> {
+    self
+      .incoming
+      .try_iter()
+      .filter_map(|incoming| match incoming.request {
+        Request::Subscribe { every } => {
+          if let Some(client) = self.clients.lock().get_mut(&incoming.client) {
+            client.stats_every = every;
+          }
+          self.reply(&incoming, Ok(Value::Null));
+          None
+        }
+        _ => Some(incoming),
+      })
+      .collect()
+  }
+
+  /// Sends the response to the `incoming` request, the `null` result is omitted
+  pub fn reply(&self, incoming: &Incoming, result: Result<Value, String>) {
+    let mut response = match result {
+      Ok(Value::Null) => json!({"ok": true}),
+      Ok(result) => json!({"ok": true, "result": result}),
+      Err(error) => json!({"ok": false, "error": error}),
+    };
+    if let Some(id) = &incoming.id {
+      response["id"] = id.clone();
+    }
+    send(&self.clients, incoming.client, &response);
+  }
+
+  /// Clients subscribed to the `stats` event of the `frame`
+  pub fn stats_subscribers(&self, frame: u64) -> Vec<ClientId> {
+    self
+      .clients
+      .lock()
+      .iter()
+      .filter(|(_, c)| c.stats_every != 0 && frame.is_multiple_of(c.stats_every as u64))
+      .map(|(id, _)| *id)
+      .collect()
+  }
+
+  /// Sends the `event` with the fields of the `data` object
+  pub fn send_event(&self, client: ClientId, event: &str, data: Value) {
+    send(&self.clients, client, &event_message(event, data));
+  }
+
+  /// Sends the `event` to all the clients
+  pub fn broadcast(&self, event: &str, data: Value) {
+    let message = event_message(event, data);
+    let clients: Vec<ClientId> = self.clients.lock().keys().copied().collect();
+    for client in clients {
+      send(&self.clients, client, &message);
+    }
+  }
+}
+
+impl Drop for RemoteServer {
+  /// Stops accepting the connections and disconnects the clients
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::Relaxed);
+    // The accept thread is blocked until a connection arrives
+    let woken = match (&self.local_addr, &self.socket_path) {
+      (Some(addr), _) => TcpStream::connect(addr).is_ok(),
+      #[cfg(unix)]
+      (_, Some(path)) => std::os::unix::net::UnixStream::connect(path).is_ok(),
+      _ => false,
+    };
+    if let Some(thread) = self.accept_thread.take() {
+      if woken {
+        let _ = thread.join();
+      } else {
+        log::warn!("Unable to wake the remote control accept thread, leaving it running");
+      }
+    }
+    // The writer threads end when their queues are dropped and close the connections
+    self.clients.lock().clear();
+    if let Some(path) = &self.socket_path {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+fn event_message(event: &str, data: Value) -> Value {
+  let mut message = json!({ "event": event });
+  if let Value::Object(fields) = data {
+    message.as_object_mut().unwrap().extend(fields);
+  }
+  message
+}
+
+/// Removes the socket left at `path` by a server that didn't shut down.
+/// Fails if a server still listens on it or if it is not a socket.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+  use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+  let Ok(metadata) = std::fs::symlink_metadata(path) else {
+    return Ok(());
+  };
+  if !metadata.file_type().is_socket() {
+    return Err(io::Error::new(
+      io::ErrorKind::AlreadyExists,
+      format!("{} exists and is not a socket", path.display()),
+    ));
+  }
+  if UnixStream::connect(path).is_ok() {
+    return Err(io::Error::new(
+      io::ErrorKind::AddrInUse,
+      format!("Another server listens on {}", path.display()),
+    ));
+  }
+  log::info!("Removing the stale socket {}", path.display());
+  std::fs::remove_file(path)
+}
+
+/// Queues the `message` line for the writer thread of the client.
+/// The client is dropped if it has stopped reading.
+fn send(clients: &Clients, client: ClientId, message: &Value) {
+  let mut clients = clients.lock();
+  let Some(c) = clients.get(&client) else {
+    return;
+  };
+  match c.queue.try_send(message.to_string()) {
+    Ok(()) => {}
+    Err(mpsc::TrySendError::Full(_)) => {
+      log::warn!("Remote control client {client} doesn't read the responses, dropping it");
+      clients.remove(&client);
+    }
+    Err(mpsc::TrySendError::Disconnected(_)) => {
+      clients.remove(&client);
+    }
+  }
+}
+
+/// A connection of a client
+trait Stream: Read + Write + Send + Sized + 'static {
+  fn duplicate(&self) -> io::Result<Self>;
+  fn set_timeout(&self, timeout: Duration) -> io::Result<()>;
+  /// Closes both directions, unblocking the threads reading and writing the connection
+  fn close(&self);
+}
+
+impl Stream for TcpStream {
+  fn duplicate(&self) -> io::Result<Self> {
+    self.try_clone()
+  }
+
+  fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+    self.set_write_timeout(Some(timeout))
+  }
+
+  fn close(&self) {
+    let _ = self.shutdown(Shutdown::Both);
+  }
+}
+
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+  fn duplicate(&self) -> io::Result<Self> {
+    self.try_clone()
+  }
+
+  fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+    self.set_write_timeout(Some(timeout))
+  }
+
+  fn close(&self) {
+    let _ = self.shutdown(Shutdown::Both);
+  }
+}
+
+/// Accepts the connections on a separate thread, backing off while accepting fails.
+/// The thread ends at the first connection after `stop` is set.
+fn accept<S: Stream>(
+  clients: Clients,
+  tx: mpsc::Sender<Incoming>,
+  stop: Arc<AtomicBool>,
+  mut next: impl FnMut() -> io::Result<S> + Send + 'static,
+) -> JoinHandle<()> {
+  thread::spawn(move || {
+    let mut backoff = Duration::ZERO;
+    let mut client = 0;
+    while !stop.load(Ordering::Relaxed) {
+      let stream = match next() {
+        Ok(_) if stop.load(Ordering::Relaxed) => break,
+        Ok(stream) => stream,
+        Err(e) => {
+          // Running out of descriptors persists until some connections are closed
+          backoff = (backoff * 2).clamp(Duration::from_millis(10), MAX_ACCEPT_BACKOFF);
+          log::warn!("Unable to accept a remote control connection, retrying in {backoff:?}: {e}");
+          thread::sleep(backoff);
+          continue;
+        }
+      };
+      backoff = Duration::ZERO;
+      if let Err(e) = serve(&clients, &tx, client, stream) {
+        log::warn!("Unable to serve a remote control connection: {e}");
+      }
+      client += 1;
+    }
+  })
+}
+
+/// Reads the requests of a client and writes the messages queued for it on separate threads
+fn serve<S: Stream>(
+  clients: &Clients,
+  tx: &mpsc::Sender<Incoming>,
+  client: ClientId,
+  stream: S,
+) -> io::Result<()> {
+  let mut writer = stream.duplicate()?;
+  writer.set_timeout(WRITE_TIMEOUT)?;
+  let (queue, messages) = mpsc::sync_channel::<String>(QUEUE_LEN);
+  log::info!("Remote control client {client} is connected");
+  clients.lock().insert(
+    client,
+    Client {
+      queue,
+      stats_every: 0,
+    },
+  );
+
+  let writer_clients = clients.clone();
+  thread::spawn(move || {
+    // Ends when the client is dropped
+    for message in messages {
+      let written = writeln!(writer, "{message}").and_then(|()| writer.flush());
+      if let Err(e) = written {
+        log::info!("Remote control client {client} is disconnected: {e}");
+        writer_clients.lock().remove(&client);
+        break;
+      }
+    }
+    writer.close();
+  });
+
+  let (clients, tx) = (clients.clone(), tx.clone());
+  let reader = stream;
+  thread::spawn(move || {
+    for line in BufReader::new(reader).lines() {
+      let Ok(line) = line else {
+        break;
+      };
+      if line.trim().is_empty() {
+        continue;
+      }
+      match serde_json::from_str::<Envelope>(&line) {
+        Ok(Envelope { id, request }) => {
+          if tx
+            .send(Incoming {
+              client,
+              id,
+              request,
+            })
+            .is_err()
+          {
+            break;
+          }
+        }
+        Err(e) => {
+          let error = json!({"ok": false, "error": format!("Invalid request: {e}")});
+          send(&clients, client, &error);
+        }
+      }
+    }
+    clients.lock().remove(&client);
+  });
+  Ok(())
+}
@@ -1,15 +1,43 @@
 use crate::render::state::*;
-use cgmath::{num_traits::zero, InnerSpace, Vector2, Zero};
+use cgmath::{num_traits::zero, InnerSpace, Point3, Vector2, Vector3, Zero};
 use eframe::CreationContext;
 use egui::mutex::Mutex;
 use egui::{Grid, Key, Rect, Sense};
-use std::{f32::consts::PI, time::Instant};
+use egui_wgpu::RenderState;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  collections::BTreeMap,
+  f32::consts::PI,
+  fs,
+  io::{self, Write},
+  path::Path,
+  sync::Arc,
+  time::Instant,
+};
 
 use super::{
-  blur::{Blur, GaussianBlur},
+  blur::{BilateralBlur, Blur, CurvatureFlow, DepthFilter, GaussianBlur, NarrowRangeBlur},
   camera::OrbitCameraController,
-  targets::simulation::SimulationParams,
+  capture::Recorder,
+  colormap::Colormap,
+  environment::EnvironmentMap,
+  material::FluidMaterial,
+  profiler::{GpuProfiler, HISTORY_LEN},
+  targets::{
+    particle_view::{ParticleAttribute, ParticleViewSettings},
+    simulation::SimulationParams,
+    tank::SceneLayers,
+  },
 };
+use crate::export::{write_mesh, MeshFormat};
+use crate::remote::{RemoteServer, Request};
+use crate::solvers::emitters::{Emitter, Sink, DEAD_POS};
+use crate::solvers::sort_keys::SortKey;
+use crate::solvers::sph_solver_gpu::Particle;
+use crate::solvers::storage::StorageLayout;
+use crate::solvers::surface::SurfaceSettings;
+use crate::stats::Stats;
 
 pub struct App {
   time_factor: f32,
@@ -23,6 +51,101 @@ pub struct App {
   fixed_dt: bool,
   dt: f32,
   gauss: GaussianBlur,
+  depth_filter: DepthFilter,
+  /// See [`crate::render::blur::SmootherParams::depth_threshold`]
+  depth_threshold: f32,
+  flow: CurvatureFlow,
+  material: FluidMaterial,
+  /// Path of the loaded environment map, the procedural one if empty
+  environment: String,
+  /// Path typed in the settings, becomes `environment` once the map is loaded
+  environment_input: String,
+  /// Layout of the particle buffers, the simulation is recreated when it changes
+  storage: StorageLayout,
+  layers: SceneLayers,
+  /// Draw the particles coloured by `particle_view` instead of the fluid
+  show_particles: bool,
+  particle_view: ParticleViewSettings,
+  /// Draw the surface extracted with `surface` instead of the fluid
+  show_surface: bool,
+  surface: SurfaceSettings,
+  emitters: Vec<Emitter>,
+  sinks: Vec<Sink>,
+  /// `None` if the timestamp queries are not supported
+  profiler: Option<Arc<GpuProfiler>>,
+  /// The frames are rendered at `record_size` and written to `record_dir`
+  recording: bool,
+  record_dir: String,
+  record_size: [u32; 2],
+  /// Write the `video.y4m` in addition to the PNGs
+  record_video: bool,
+  record_fps: u32,
+  /// The meshes of the surface are written to `mesh_dir`
+  mesh_dir: String,
+  mesh_format: MeshFormat,
+  /// Export the mesh after every frame
+  export_meshes: bool,
+  render_state: RenderState,
+  remote: Option<RemoteServer>,
+  /// Steps requested by the remote control left to run
+  steps_left: u32,
+  frame: u64,
+  presets: BTreeMap<String, SimulationParams>,
+  /// Name typed in the presets editor
+  preset_name: String,
+}
+
+/// Settings of the [`App`] kept in the eframe storage between the runs
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct SavedState {
+  params: SimulationParams,
+  gauss: GaussianBlur,
+  depth_filter: DepthFilter,
+  depth_threshold: f32,
+  flow: CurvatureFlow,
+  material: FluidMaterial,
+  environment: String,
+  storage: StorageLayout,
+  layers: SceneLayers,
+  show_particles: bool,
+  particle_view: ParticleViewSettings,
+  show_surface: bool,
+  surface: SurfaceSettings,
+  mesh_format: MeshFormat,
+  time_factor: f32,
+  rho_from_h: bool,
+  fixed_dt: bool,
+  dt: f32,
+  camera: OrbitCameraController,
+  presets: BTreeMap<String, SimulationParams>,
+}
+
+impl Default for SavedState {
+  fn default() -> Self {
+    Self {
+      params: Default::default(),
+      gauss: Default::default(),
+      depth_filter: DepthFilter::Gaussian,
+      depth_threshold: 0.02,
+      flow: Default::default(),
+      material: Default::default(),
+      environment: String::new(),
+      storage: Default::default(),
+      layers: Default::default(),
+      show_particles: false,
+      particle_view: Default::default(),
+      show_surface: false,
+      surface: Default::default(),
+      mesh_format: MeshFormat::Obj,
+      time_factor: 1.0,
+      rho_from_h: false,
+      fixed_dt: false,
+      dt: 0.0,
+      camera: Default::default(),
+      presets: BTreeMap::new(),
+    }
+  }
 }
 
 const K_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0e10;
@@ -34,7 +157,12 @@ impl eframe::App for App {
     let time = Instant::now();
     let mut dt = time - self.time;
     let mut new_blur: Option<Box<dyn Blur + Send + Sync + 'static>> = None;
+    let mut new_sources = None;
+    let mut new_material = None;
+    let mut new_environment = None;
+    let mut record = None;
     self.time = time;
+    self.handle_remote();
 
     egui::SidePanel::left("simulation_props").show(ctx, |ui| {
       log::trace!("left: {}", ui.available_size());
@@ -76,6 +204,30 @@ impl eframe::App for App {
         ui.add(egui::Slider::new(&mut self.params.w, 0.0f32..=2.0f32));
         ui.end_row();
 
+        ui.label("Sort key");
+        egui::ComboBox::from_id_salt("sort_key")
+          .selected_text(self.params.sort_key.name())
+          .show_ui(ui, |ui| {
+            for key in SortKey::ALL {
+              ui.selectable_value(&mut self.params.sort_key, key, key.name());
+            }
+          });
+        ui.end_row();
+
+        ui.label("Storage");
+        let mut storage = self.storage;
+        egui::ComboBox::from_id_salt("storage")
+          .selected_text(storage.name())
+          .show_ui(ui, |ui| {
+            for layout in StorageLayout::ALL {
+              ui.selectable_value(&mut storage, layout, layout.name());
+            }
+          });
+        if storage != self.storage {
+          self.set_storage(storage);
+        }
+        ui.end_row();
+
         ui.label("t factor");
         ui.add(egui::Slider::new(&mut self.time_factor, 0.0..=1.0));
         ui.end_row();
@@ -102,6 +254,34 @@ impl eframe::App for App {
         ui.add(egui::Slider::new(&mut self.params.ttr, 0.0f32..=10.0));
         ui.end_row();
 
+        if self.material_ui(ui) {
+          new_material = Some(self.material);
+        }
+
+        ui.label("Environment");
+        ui.horizontal(|ui| {
+          ui.text_edit_singleline(&mut self.environment_input)
+            .on_hover_text("Equirectangular .hdr or .png map, the procedural sky if empty");
+          if ui.button("Load").clicked() {
+            new_environment = self.load_environment(&self.environment_input);
+            if new_environment.is_some() {
+              self.environment = self.environment_input.clone();
+            }
+          }
+        });
+        ui.end_row();
+
+        ui.label("Scene");
+        ui.horizontal(|ui| {
+          ui.checkbox(&mut self.layers.ground, "Ground");
+          ui.checkbox(&mut self.layers.walls, "Walls");
+          ui.checkbox(&mut self.layers.gizmo, "Axes");
+        });
+        ui.end_row();
+
+        self.particle_view_ui(ui);
+        self.surface_ui(ui);
+
         ui.separator();
         ui.end_row();
 
@@ -110,16 +290,45 @@ impl eframe::App for App {
 
         ui.separator();
         ui.end_row();
-        ui.label("Gaussian blur");
+        ui.label("Smoothing");
         ui.end_row();
 
-        ui.label("σ");
-        ui.add(egui::Slider::new(&mut self.gauss.s, 0.0..=15.0));
+        ui.label("Filter");
+        egui::ComboBox::from_id_salt("depth_filter")
+          .selected_text(self.depth_filter.name())
+          .show_ui(ui, |ui| {
+            for filter in DepthFilter::ALL {
+              ui.selectable_value(&mut self.depth_filter, filter, filter.name());
+            }
+          });
         ui.end_row();
 
-        ui.label("Side");
-        ui.add(egui::Slider::new(&mut self.gauss.side, 0..=64));
-        ui.end_row();
+        if self.depth_filter == DepthFilter::CurvatureFlow {
+          ui.label("Iterations");
+          ui.add(egui::Slider::new(&mut self.flow.iterations, 1..=200));
+          ui.end_row();
+
+          ui.label("Time step");
+          ui.add(egui::Slider::new(&mut self.flow.dt, 1e-5..=1e-2).logarithmic(true));
+          ui.end_row();
+        } else {
+          ui.label("σ");
+          ui.add(egui::Slider::new(&mut self.gauss.s, 0.0..=15.0));
+          ui.end_row();
+
+          ui.label("Side");
+          ui.add(egui::Slider::new(&mut self.gauss.side, 0..=64));
+          ui.end_row();
+        }
+
+        if matches!(
+          self.depth_filter,
+          DepthFilter::Bilateral | DepthFilter::NarrowRange
+        ) {
+          ui.label("Depth threshold");
+          ui.add(egui::Slider::new(&mut self.depth_threshold, 0.001..=0.5).logarithmic(true));
+          ui.end_row();
+        }
 
         let apply_button = ui.button(if self.immediate_blur {
           "Un-auto-apply"
@@ -131,10 +340,24 @@ impl eframe::App for App {
           self.immediate_blur = !self.immediate_blur;
         }
         if self.immediate_blur || apply_button.clicked() {
-          new_blur = Some(Box::new(self.gauss));
+          new_blur = Some(self.blur());
         }
       });
       self.params.regen_particles = ui.button("Regen positions").clicked();
+      self.params.clear_particles = ui.button("Remove all particles").clicked();
+      egui::CollapsingHeader::new("Presets").show(ui, |ui| self.presets_ui(ui));
+      egui::CollapsingHeader::new("Sources & sinks").show(ui, |ui| {
+        if self.sources_ui(ui) {
+          new_sources = Some((self.emitters.clone(), self.sinks.clone()));
+        }
+      });
+      egui::CollapsingHeader::new("Recording").show(ui, |ui| {
+        record = self.recording_ui(ui);
+      });
+      egui::CollapsingHeader::new("Mesh export").show(ui, |ui| self.mesh_export_ui(ui));
+      if let Some(profiler) = &self.profiler {
+        egui::CollapsingHeader::new("GPU profiler").show(ui, |ui| profiler_ui(ui, profiler));
+      }
 
       ui.label(format!(
         "Viewport size: {}x{}",
@@ -209,41 +432,874 @@ Looks at: ({:.1}, {:.1}, {:.1})\nr={:.1}",
             time: (time - self.startup_time).as_secs_f32(),
             params: self.params,
             camera: self.controller.get_camera(),
-            size: rect.size(),
+            size: if self.recording {
+              egui::vec2(self.record_size[0] as f32, self.record_size[1] as f32)
+            } else {
+              rect.size()
+            },
             new_blur: Mutex::new(new_blur),
+            new_sources: Mutex::new(new_sources),
+            new_material: Mutex::new(new_material),
+            new_environment: Mutex::new(new_environment),
+            layers: self.layers,
+            particle_view: self.show_particles.then_some(self.particle_view),
+            surface: self.show_surface.then_some(self.surface),
+            record: Mutex::new(record),
           },
         ));
         self.viewport_rect = rect;
       });
     });
+    self.frame_finished();
     ctx.request_repaint();
   }
-  fn save(&mut self, _storage: &mut dyn eframe::Storage) {
-    eprintln!("SAVE!");
+  fn save(&mut self, storage: &mut dyn eframe::Storage) {
+    let saved = SavedState {
+      params: self.params,
+      gauss: self.gauss,
+      depth_filter: self.depth_filter,
+      depth_threshold: self.depth_threshold,
+      flow: self.flow,
+      material: self.material,
+      environment: self.environment.clone(),
+      storage: self.storage,
+      layers: self.layers,
+      show_particles: self.show_particles,
+      particle_view: self.particle_view,
+      show_surface: self.show_surface,
+      surface: self.surface,
+      mesh_format: self.mesh_format,
+      time_factor: self.time_factor,
+      rho_from_h: self.rho_from_h,
+      fixed_dt: self.fixed_dt,
+      dt: self.dt,
+      camera: self.controller.clone(),
+      presets: self.presets.clone(),
+    };
+    eframe::set_value(storage, eframe::APP_KEY, &saved);
   }
 }
 
 impl App {
-  pub fn new(cc: &CreationContext<'_>) -> Self {
+  /// `remote` is served on every frame
+  pub fn new(cc: &CreationContext<'_>, remote: Option<RemoteServer>) -> Self {
     let wgpu_render_state = cc.wgpu_render_state.as_ref().unwrap();
-    let state = PersistentState::create_egui(wgpu_render_state);
-    wgpu_render_state
-      .renderer
-      .write()
-      .callback_resources
-      .insert(state);
-    Self {
-      time_factor: 1.0,
+    let saved: SavedState = cc
+      .storage
+      .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+      .unwrap_or_default();
+    let mut state = PersistentState::create_egui(wgpu_render_state, saved.storage);
+    let profiler = state.profiler().cloned();
+    let mut app = Self {
+      time_factor: saved.time_factor,
       time: Instant::now(),
       startup_time: Instant::now(),
-      rho_from_h: false,
+      rho_from_h: saved.rho_from_h,
       // Just a random rectangle
       viewport_rect: Rect::everything_above(0.0),
-      params: Default::default(),
-      controller: Default::default(),
-      gauss: Default::default(),
+      params: SimulationParams {
+        regen_particles: false,
+        clear_particles: false,
+        ..saved.params
+      },
+      controller: saved.camera,
+      gauss: saved.gauss,
+      depth_filter: saved.depth_filter,
+      depth_threshold: saved.depth_threshold,
+      flow: saved.flow,
+      material: saved.material,
+      environment_input: saved.environment.clone(),
+      environment: saved.environment,
+      storage: saved.storage,
+      layers: saved.layers,
+      show_particles: saved.show_particles,
+      particle_view: saved.particle_view,
+      show_surface: saved.show_surface,
+      surface: saved.surface,
       immediate_blur: false,
-      fixed_dt: false, dt: 0.0
+      fixed_dt: saved.fixed_dt,
+      dt: saved.dt,
+      emitters: Vec::new(),
+      sinks: Vec::new(),
+      profiler,
+      recording: false,
+      record_dir: "frames".to_owned(),
+      record_size: [1280, 720],
+      record_video: false,
+      record_fps: 30,
+      mesh_dir: "meshes".to_owned(),
+      mesh_format: saved.mesh_format,
+      export_meshes: false,
+      render_state: wgpu_render_state.clone(),
+      remote,
+      steps_left: 0,
+      frame: 0,
+      presets: saved.presets,
+      preset_name: String::new(),
+    };
+    app.configure_state(&mut state);
+    wgpu_render_state
+      .renderer
+      .write()
+      .callback_resources
+      .insert(state);
+    app
+  }
+
+  /// Applies the renderer settings of the UI to a newly created `state`
+  fn configure_state(&mut self, state: &mut PersistentState) {
+    let RenderState { device, queue, .. } = &self.render_state;
+    state.simulation_mut().set_blur(self.blur(), device, queue);
+    state.simulation_mut().set_material(self.material, queue);
+    if !self.environment.is_empty() {
+      match self.load_environment(&self.environment) {
+        Some(env) => state
+          .simulation_mut()
+          .set_environment(&env, device, queue)
+          .expect("the size of the environment map is checked by `load_environment`"),
+        // Not loaded on the next start either
+        None => self.environment.clear(),
+      }
+    }
+  }
+
+  /// Recreates the simulation with the particles stored in the `storage` layout.
+  /// The particles are regenerated.
+  fn set_storage(&mut self, storage: StorageLayout) {
+    let render_state = self.render_state.clone();
+    let RenderState {
+      device,
+      queue,
+      target_format,
+      renderer,
+      ..
+    } = &render_state;
+    let mut renderer = renderer.write();
+    let old = renderer
+      .callback_resources
+      .get_mut::<PersistentState>()
+      .unwrap();
+    old.stop_recording(device);
+    self.recording = false;
+    let mut state = PersistentState::create_with(
+      device,
+      target_format,
+      queue,
+      old.simulation().count(),
+      storage,
+      false,
+    );
+    state
+      .simulation_mut()
+      .set_sources(self.emitters.clone(), self.sinks.clone());
+    self.storage = storage;
+    self.profiler = state.profiler().cloned();
+    self.configure_state(&mut state);
+    renderer.callback_resources.insert(state);
+  }
+
+  /// Rows of the fluid material in the settings grid, returns whether it changed
+  fn material_ui(&mut self, ui: &mut egui::Ui) -> bool {
+    let old = self.material;
+    let m = &mut self.material;
+    ui.label("Material");
+    let preset = FluidMaterial::PRESETS
+      .iter()
+      .find(|(_, preset)| preset == m)
+      .map_or("Custom", |(name, _)| name);
+    egui::ComboBox::from_id_salt("fluid_material")
+      .selected_text(preset)
+      .show_ui(ui, |ui| {
+        for (name, preset) in FluidMaterial::PRESETS {
+          ui.selectable_value(m, preset, name);
+        }
+      });
+    ui.end_row();
+
+    ui.label("Absorption");
+    ui.horizontal(|ui| {
+      for a in &mut m.absorption {
+        ui.add(egui::DragValue::new(a).speed(0.01).range(0.0..=100.0));
+      }
+    });
+    ui.end_row();
+
+    ui.label("IOR");
+    ui.add(egui::Slider::new(&mut m.ior, 1.0..=2.5));
+    ui.end_row();
+
+    ui.label("Refraction");
+    ui.add(egui::Slider::new(&mut m.refraction, 0.0..=0.2));
+    ui.end_row();
+
+    ui.label("Specular");
+    ui.horizontal(|ui| {
+      ui.color_edit_button_rgb(&mut m.specular);
+      ui.add(egui::Slider::new(&mut m.shininess, 1.0..=1000.0).logarithmic(true));
+    });
+    ui.end_row();
+
+    ui.label("Light");
+    ui.horizontal(|ui| {
+      ui.color_edit_button_rgb(&mut m.light_color);
+      for d in &mut m.light_dir {
+        ui.add(egui::DragValue::new(d).speed(0.01).range(-1.0..=1.0));
+      }
+    });
+    ui.end_row();
+
+    ui.label("Ambient");
+    ui.color_edit_button_rgb(&mut m.ambient);
+    ui.end_row();
+
+    // A zero direction would be normalized to NaNs
+    if m.light_dir == [0.0; 3] {
+      m.light_dir = old.light_dir;
+    }
+    self.material != old
+  }
+
+  /// Rows of the particle debug view in the settings grid
+  fn particle_view_ui(&mut self, ui: &mut egui::Ui) {
+    ui.label("Particles");
+    ui.checkbox(&mut self.show_particles, "Show instead of the fluid");
+    ui.end_row();
+    if !self.show_particles {
+      return;
+    }
+    let v = &mut self.particle_view;
+
+    ui.label("Attribute");
+    let old = v.attribute;
+    egui::ComboBox::from_id_salt("particle_attribute")
+      .selected_text(v.attribute.name())
+      .show_ui(ui, |ui| {
+        for attribute in ParticleAttribute::ALL {
+          ui.selectable_value(&mut v.attribute, attribute, attribute.name());
+        }
+      });
+    ui.end_row();
+
+    ui.label("Colormap");
+    egui::ComboBox::from_id_salt("particle_colormap")
+      .selected_text(v.colormap.name())
+      .show_ui(ui, |ui| {
+        for colormap in Colormap::ALL {
+          ui.selectable_value(&mut v.colormap, colormap, colormap.name());
+        }
+      });
+    ui.end_row();
+
+    ui.label("Range");
+    let mut reset = v.attribute != old;
+    ui.horizontal(|ui| {
+      let speed = 0.01 * (v.range[1] - v.range[0]).abs().max(1e-3);
+      ui.add(egui::DragValue::new(&mut v.range[0]).speed(speed));
+      ui.add(egui::DragValue::new(&mut v.range[1]).speed(speed));
+      reset |= ui.button("Reset").clicked();
+    });
+    ui.end_row();
+    if reset {
+      let count = self.particle_capacity();
+      let v = &mut self.particle_view;
+      v.range = v.attribute.default_range(&self.params, count);
+    }
+    let v = &mut self.particle_view;
+
+    ui.label("Size");
+    ui.add(egui::Slider::new(&mut v.size, 0.05..=2.0).suffix(" h"));
+    ui.end_row();
+
+    ui.label("");
+    colormap_legend(ui, v.colormap, v.range);
+    ui.end_row();
+  }
+
+  /// Rows of the surface mesh in the settings grid
+  fn surface_ui(&mut self, ui: &mut egui::Ui) {
+    ui.label("Surface");
+    ui.checkbox(&mut self.show_surface, "Mesh instead of the fluid");
+    ui.end_row();
+    if !self.show_surface {
+      return;
+    }
+
+    ui.label("Resolution");
+    ui.add(egui::Slider::new(&mut self.surface.resolution, 8..=256));
+    ui.end_row();
+
+    ui.label("Iso level");
+    ui.add(egui::Slider::new(&mut self.surface.iso, 0.05..=2.0));
+    ui.end_row();
+  }
+
+  /// `None` if the file of the map can't be read or the map doesn't fit into a texture
+  fn load_environment(&self, path: &str) -> Option<EnvironmentMap> {
+    if path.is_empty() {
+      return Some(EnvironmentMap::procedural());
+    }
+    let max_dimension = self.render_state.device.limits().max_texture_dimension_2d;
+    match EnvironmentMap::load(Path::new(path))
+      .and_then(|env| env.check_size(max_dimension).map(|()| env))
+    {
+      Ok(env) => Some(env),
+      Err(e) => {
+        log::error!("Unable to load the environment map {path}: {e}");
+        None
+      }
+    }
+  }
+
+  /// The smoother set up in the UI
+  fn blur(&self) -> Box<dyn Blur + Send + Sync> {
+    match self.depth_filter {
+      DepthFilter::Gaussian => Box::new(self.gauss),
+      DepthFilter::Bilateral => Box::new(BilateralBlur {
+        gauss: self.gauss,
+        depth_sigma: self.depth_threshold,
+      }),
+      DepthFilter::NarrowRange => Box::new(NarrowRangeBlur {
+        gauss: self.gauss,
+        threshold: self.depth_threshold,
+      }),
+      DepthFilter::CurvatureFlow => Box::new(self.flow),
     }
   }
+
+  /// Draws the named presets of the simulation parameters
+  fn presets_ui(&mut self, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+      ui.text_edit_singleline(&mut self.preset_name);
+      let name = self.preset_name.trim();
+      if ui
+        .add_enabled(!name.is_empty(), egui::Button::new("Save"))
+        .clicked()
+      {
+        self.presets.insert(name.to_owned(), self.params);
+      }
+    });
+    let mut deleted = None;
+    Grid::new("presets_grid").show(ui, |ui| {
+      for (name, params) in &self.presets {
+        ui.label(name);
+        if ui.button("Load").clicked() {
+          // Loading a preset doesn't pause or resume the simulation
+          self.params = SimulationParams {
+            paused: self.params.paused,
+            regen_particles: false,
+            clear_particles: false,
+            ..*params
+          };
+          self.preset_name.clone_from(name);
+        }
+        if ui.button("Delete").clicked() {
+          deleted = Some(name.clone());
+        }
+        ui.end_row();
+      }
+    });
+    if let Some(name) = deleted {
+      self.presets.remove(&name);
+    }
+  }
+
+  /// Executes the requests of the remote control clients
+  fn handle_remote(&mut self) {
+    let Some(remote) = self.remote.take() else {
+      return;
+    };
+    for incoming in remote.poll() {
+      let result = self.execute_remote(&incoming.request);
+      remote.reply(&incoming, result);
+    }
+    self.remote = Some(remote);
+  }
+
+  fn execute_remote(&mut self, request: &Request) -> Result<Value, String> {
+    match request {
+      Request::Set { param, value } => {
+        // Parsed like the `get` response to keep both directions in JSON
+        let mut params = serde_json::to_value(self.params).map_err(|e| e.to_string())?;
+        let field = params
+          .get_mut(param)
+          .ok_or_else(|| format!("Unknown parameter `{param}`"))?;
+        *field = value.clone();
+        let params: SimulationParams =
+          serde_json::from_value(params).map_err(|e| format!("Invalid value `{value}`: {e}"))?;
+        // The flags are not serialized
+        self.params = SimulationParams {
+          regen_particles: self.params.regen_particles,
+          clear_particles: self.params.clear_particles,
+          ..params
+        };
+      }
+      Request::Get { param } => {
+        let params = serde_json::to_value(self.params).map_err(|e| e.to_string())?;
+        return match param {
+          Some(param) => params
+            .get(param)
+            .cloned()
+            .ok_or_else(|| format!("Unknown parameter `{param}`")),
+          None => Ok(params),
+        };
+      }
+      Request::Pause => self.params.paused = true,
+      Request::Resume => self.params.paused = false,
+      Request::Step { n } => {
+        self.steps_left = *n;
+        self.params.paused = *n == 0;
+      }
+      Request::Orbit { yaw, pitch } => {
+        self
+          .controller
+          .rotate_radians(egui::vec2(yaw.to_radians(), pitch.to_radians()));
+      }
+      Request::Zoom { delta } => {
+        self.controller.move_radius(*delta);
+      }
+      Request::ResetCamera => {
+        self.controller.reset();
+      }
+      Request::Snapshot => {
+        let particles: Vec<[f32; 7]> = self
+          .read_particles()
+          .iter()
+          .filter(|p| p.pos.x != DEAD_POS)
+          .map(|p| {
+            [
+              p.pos.x,
+              p.pos.y,
+              p.pos.z,
+              p.density,
+              p.velocity.x,
+              p.velocity.y,
+              p.velocity.z,
+            ]
+          })
+          .collect();
+        return Ok(json!(particles));
+      }
+      Request::Stats => {
+        let stats = Stats::compute(&self.read_particles(), self.params.m0);
+        return serde_json::to_value(stats).map_err(|e| e.to_string());
+      }
+      Request::Subscribe { .. } => unreachable!("Handled by the server"),
+    }
+    Ok(Value::Null)
+  }
+
+  /// Counts the remote steps down, exports the mesh and sends the remote control events
+  fn frame_finished(&mut self) {
+    self.frame += 1;
+    if self.export_meshes {
+      self.export_mesh();
+    }
+    let Some(remote) = &self.remote else {
+      return;
+    };
+    if self.steps_left > 0 {
+      self.steps_left -= 1;
+      if self.steps_left == 0 {
+        self.params.paused = true;
+        remote.broadcast("steps_done", json!({ "frame": self.frame }));
+      }
+    }
+    let subscribers = remote.stats_subscribers(self.frame);
+    if !subscribers.is_empty() {
+      let stats = Stats::compute(&self.read_particles(), self.params.m0);
+      let data = json!({
+        "frame": self.frame,
+        "time": (self.time - self.startup_time).as_secs_f32(),
+        "stats": stats,
+      });
+      for client in subscribers {
+        remote.send_event(client, "stats", data.clone());
+      }
+    }
+  }
+
+  /// Reads the particles back from the GPU, the dead ones are included
+  fn read_particles(&self) -> Vec<Particle> {
+    let RenderState {
+      device,
+      queue,
+      renderer,
+      ..
+    } = &self.render_state;
+    let renderer = renderer.read();
+    let state = renderer
+      .callback_resources
+      .get::<PersistentState>()
+      .unwrap();
+    state.simulation().read_particles(device, queue)
+  }
+
+  /// Writes the surface of the fluid to `mesh_dir`, named after the frame
+  fn export_mesh(&self) {
+    let path = Path::new(&self.mesh_dir).join(format!(
+      "surface-{:06}.{}",
+      self.frame,
+      self.mesh_format.extension()
+    ));
+    let RenderState {
+      device,
+      queue,
+      renderer,
+      ..
+    } = &self.render_state;
+    let renderer = renderer.read();
+    let state = renderer
+      .callback_resources
+      .get::<PersistentState>()
+      .unwrap();
+    let mesh = state
+      .simulation()
+      .extract_surface(device, queue, &self.surface);
+    let written = fs::create_dir_all(&self.mesh_dir).and_then(|()| {
+      let mut w = io::BufWriter::new(fs::File::create(&path)?);
+      write_mesh(&mut w, &mesh, self.mesh_format)?;
+      w.flush()
+    });
+    match written {
+      Ok(()) => log::debug!(
+        "{} triangles written to {}",
+        mesh.triangles(),
+        path.display()
+      ),
+      Err(e) => log::error!("Unable to write {}: {e}", path.display()),
+    }
+  }
+
+  /// Draws the settings of the mesh export, the meshes are extracted with the surface settings
+  fn mesh_export_ui(&mut self, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+      ui.label("Directory");
+      ui.text_edit_singleline(&mut self.mesh_dir);
+    });
+    ui.horizontal(|ui| {
+      ui.label("Format");
+      egui::ComboBox::from_id_salt("mesh_format")
+        .selected_text(self.mesh_format.name())
+        .show_ui(ui, |ui| {
+          for format in MeshFormat::ALL {
+            ui.selectable_value(&mut self.mesh_format, format, format.name());
+          }
+        });
+    });
+    ui.horizontal(|ui| {
+      if ui.button("Export").clicked() {
+        self.export_mesh();
+      }
+      ui.toggle_value(&mut self.export_meshes, "Every frame");
+    });
+  }
+
+  /// Capacity of the particle buffers
+  fn particle_capacity(&self) -> usize {
+    let renderer = self.render_state.renderer.read();
+    let state = renderer
+      .callback_resources
+      .get::<PersistentState>()
+      .unwrap();
+    state.simulation().count()
+  }
+
+  /// Draws the capture settings and the "Record" toggle.
+  /// Returns the command if the toggle was switched.
+  fn recording_ui(&mut self, ui: &mut egui::Ui) -> Option<RecordCommand> {
+    ui.add_enabled_ui(!self.recording, |ui| {
+      ui.horizontal(|ui| {
+        ui.label("Directory");
+        ui.text_edit_singleline(&mut self.record_dir);
+      });
+      ui.horizontal(|ui| {
+        ui.label("Size");
+        ui.add(egui::DragValue::new(&mut self.record_size[0]).range(1..=8192));
+        ui.label("×");
+        ui.add(egui::DragValue::new(&mut self.record_size[1]).range(1..=8192));
+      });
+      ui.horizontal(|ui| {
+        ui.checkbox(&mut self.record_video, "Y4M video");
+        if self.record_video {
+          ui.add(
+            egui::DragValue::new(&mut self.record_fps)
+              .range(1..=240)
+              .suffix(" fps"),
+          );
+        }
+      });
+    });
+    if !ui.toggle_value(&mut self.recording, "Record").changed() {
+      return None;
+    }
+    if !self.recording {
+      return Some(RecordCommand::Stop);
+    }
+    let video_fps = self.record_video.then_some(self.record_fps);
+    match Recorder::new(&self.record_dir, video_fps) {
+      Ok(recorder) => Some(RecordCommand::Start(recorder)),
+      Err(e) => {
+        log::error!("Unable to record to {}: {e}", self.record_dir);
+        self.recording = false;
+        None
+      }
+    }
+  }
+
+  /// Draws the editor of particle emitters and sinks.
+  /// Returns `true` if any of them was changed.
+  fn sources_ui(&mut self, ui: &mut egui::Ui) -> bool {
+    let old = (self.emitters.clone(), self.sinks.clone());
+    let w = self.params.w;
+    ui.horizontal(|ui| {
+      if ui.button("Faucet").clicked() {
+        self.emitters = vec![Emitter::Nozzle {
+          pos: Point3::new(0., 0.8, 0.),
+          dir: -Vector3::unit_y(),
+          radius: 0.03,
+          rate: 2000.,
+          speed: 1.,
+        }];
+        self.sinks.clear();
+      }
+      if ui.button("Fountain").clicked() {
+        self.emitters = vec![Emitter::Nozzle {
+          pos: Point3::new(0., 0.02, 0.),
+          dir: Vector3::unit_y(),
+          radius: 0.02,
+          rate: 2000.,
+          speed: 3.,
+        }];
+        self.sinks = vec![Sink::Box {
+          min: Point3::new(-w, -1., -w),
+          max: Point3::new(w, 0.01, w),
+        }];
+      }
+      if ui.button("Outflow").clicked() {
+        self.emitters.clear();
+        self.sinks = vec![Sink::Box {
+          min: Point3::new(0.75 * w, -1., -w),
+          max: Point3::new(w, 0.05, w),
+        }];
+      }
+    });
+    ui.horizontal(|ui| {
+      if ui.button("+ Nozzle").clicked() {
+        self.emitters.push(Emitter::Nozzle {
+          pos: Point3::new(0., 0.5, 0.),
+          dir: -Vector3::unit_y(),
+          radius: 0.03,
+          rate: 1000.,
+          speed: 1.,
+        });
+      }
+      if ui.button("+ Box source").clicked() {
+        self.emitters.push(Emitter::Box {
+          min: Point3::new(-0.05, 0.4, -0.05),
+          max: Point3::new(0.05, 0.5, 0.05),
+          rate: 1000.,
+          velocity: Vector3::zero(),
+        });
+      }
+      if ui.button("+ Sink").clicked() {
+        self.sinks.push(Sink::Sphere {
+          center: Point3::new(0., 0., 0.),
+          radius: 0.05,
+        });
+      }
+    });
+
+    let mut removed = None;
+    for (i, e) in self.emitters.iter_mut().enumerate() {
+      ui.separator();
+      match e {
+        Emitter::Nozzle {
+          pos,
+          dir,
+          radius,
+          rate,
+          speed,
+        } => {
+          ui.label(format!("Nozzle #{i}"));
+          drag_point(ui, "Position", pos);
+          drag_vector(ui, "Direction", dir);
+          drag_scalar(ui, "Radius", radius, 0.001);
+          drag_scalar(ui, "Speed", speed, 0.01);
+          drag_scalar(ui, "Rate", rate, 10.);
+        }
+        Emitter::Box {
+          min,
+          max,
+          rate,
+          velocity,
+        } => {
+          ui.label(format!("Box source #{i}"));
+          drag_point(ui, "Min", min);
+          drag_point(ui, "Max", max);
+          drag_vector(ui, "Velocity", velocity);
+          drag_scalar(ui, "Rate", rate, 10.);
+        }
+      }
+      if ui.button("Remove").clicked() {
+        removed = Some(i);
+      }
+    }
+    if let Some(i) = removed {
+      self.emitters.remove(i);
+    }
+
+    let mut removed = None;
+    for (i, s) in self.sinks.iter_mut().enumerate() {
+      ui.separator();
+      ui.label(format!("Sink #{i}"));
+      match s {
+        Sink::Box { min, max } => {
+          drag_point(ui, "Min", min);
+          drag_point(ui, "Max", max);
+        }
+        Sink::Sphere { center, radius } => {
+          drag_point(ui, "Center", center);
+          drag_scalar(ui, "Radius", radius, 0.001);
+        }
+      }
+      if ui.button("Remove").clicked() {
+        removed = Some(i);
+      }
+    }
+    if let Some(i) = removed {
+      self.sinks.remove(i);
+    }
+
+    old != (self.emitters.clone(), self.sinks.clone())
+  }
+}
+
+/// Draws the table of the pass timings and their graph over the last frames
+fn profiler_ui(ui: &mut egui::Ui, profiler: &GpuProfiler) {
+  let mut enabled = profiler.is_enabled();
+  if ui.checkbox(&mut enabled, "Enabled").changed() {
+    profiler.set_enabled(enabled);
+  }
+  let history = profiler.history();
+  let averages = profiler.averages();
+  let color = |i: usize| {
+    egui::Color32::from(egui::epaint::Hsva::new(
+      i as f32 / averages.len() as f32,
+      0.8,
+      0.9,
+      1.0,
+    ))
+  };
+
+  Grid::new("profiler_grid").striped(true).show(ui, |ui| {
+    ui.label("Pass");
+    ui.label("Last, ms");
+    ui.label("Average, ms");
+    ui.end_row();
+    let latest = history.last().map(Vec::as_slice).unwrap_or_default();
+    for (i, (label, avg)) in averages.iter().enumerate() {
+      ui.colored_label(color(i), *label);
+      let last = latest.iter().find(|(l, _)| l == label).map_or(0.0, |t| t.1);
+      ui.label(format!("{last:.3}"));
+      ui.label(format!("{avg:.3}"));
+      ui.end_row();
+    }
+    ui.label("Total");
+    ui.label(format!("{:.3}", latest.iter().map(|t| t.1).sum::<f32>()));
+    ui.label(format!("{:.3}", averages.iter().map(|t| t.1).sum::<f32>()));
+    ui.end_row();
+  });
+
+  let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 100.0), Sense::hover());
+  let painter = ui.painter_at(rect);
+  painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+  let max = history
+    .iter()
+    .flatten()
+    .map(|t| t.1)
+    .fold(f32::EPSILON, f32::max);
+  let dx = rect.width() / (HISTORY_LEN - 1) as f32;
+  for (i, (label, _)) in averages.iter().enumerate() {
+    let points = history
+      .iter()
+      .enumerate()
+      .filter_map(|(frame, timings)| {
+        let ms = timings.iter().find(|(l, _)| l == label)?.1;
+        Some(egui::pos2(
+          rect.left() + frame as f32 * dx,
+          rect.bottom() - ms / max * rect.height(),
+        ))
+      })
+      .collect();
+    painter.add(egui::Shape::line(
+      points,
+      egui::Stroke::new(1.0_f32, color(i)),
+    ));
+  }
+  painter.text(
+    rect.left_top(),
+    egui::Align2::LEFT_TOP,
+    format!("{max:.3} ms"),
+    egui::FontId::monospace(10.0),
+    ui.visuals().text_color(),
+  );
+}
+
+/// The gradient of the `colormap` with the values at its ends and in the middle
+fn colormap_legend(ui: &mut egui::Ui, colormap: Colormap, [min, max]: [f32; 2]) {
+  const STEPS: usize = 64;
+  let (rect, _) = ui.allocate_exact_size(egui::vec2(200.0, 28.0), Sense::hover());
+  let painter = ui.painter_at(rect);
+  let bar = rect.with_max_y(rect.top() + 12.0);
+  let dx = bar.width() / STEPS as f32;
+  for i in 0..STEPS {
+    let [r, g, b] = colormap
+      .sample((i as f32 + 0.5) / STEPS as f32)
+      .map(|c| (c * 255.0) as u8);
+    let x = bar.left() + i as f32 * dx;
+    painter.rect_filled(
+      Rect::from_x_y_ranges(x..=x + dx + 0.5, bar.y_range()),
+      0.0,
+      egui::Color32::from_rgb(r, g, b),
+    );
+  }
+  let font = egui::FontId::monospace(10.0);
+  let color = ui.visuals().text_color();
+  for (align, x, value) in [
+    (egui::Align2::LEFT_TOP, bar.left(), min),
+    (egui::Align2::CENTER_TOP, bar.center().x, 0.5 * (min + max)),
+    (egui::Align2::RIGHT_TOP, bar.right(), max),
+  ] {
+    painter.text(
+      egui::pos2(x, bar.bottom() + 2.0),
+      align,
+      format!("{value:.3}"),
+      font.clone(),
+      color,
+    );
+  }
+}
+
+fn drag_scalar(ui: &mut egui::Ui, label: &str, val: &mut f32, speed: f32) {
+  ui.horizontal(|ui| {
+    ui.label(label);
+    ui.add(egui::DragValue::new(val).speed(speed).range(0.0..=f32::MAX));
+  });
+}
+
+fn drag_xyz(ui: &mut egui::Ui, label: &str, x: &mut f32, y: &mut f32, z: &mut f32) {
+  ui.horizontal(|ui| {
+    ui.label(label);
+    ui.add(egui::DragValue::new(x).speed(0.01).prefix("x: "));
+    ui.add(egui::DragValue::new(y).speed(0.01).prefix("y: "));
+    ui.add(egui::DragValue::new(z).speed(0.01).prefix("z: "));
+  });
+}
+
+fn drag_point(ui: &mut egui::Ui, label: &str, p: &mut Point3<f32>) {
+  drag_xyz(ui, label, &mut p.x, &mut p.y, &mut p.z);
+}
+
+fn drag_vector(ui: &mut egui::Ui, label: &str, v: &mut Vector3<f32>) {
+  drag_xyz(ui, label, &mut v.x, &mut v.y, &mut v.z);
 }
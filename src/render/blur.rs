@@ -1,11 +1,165 @@
 use cgmath::{ElementWise, InnerSpace, Vector2};
-use core::f32;
+use core::{f32, slice};
+use serde::{Deserialize, Serialize};
+
+use super::AsBuffer;
 
 pub trait Blur {
   #[must_use]
   fn full_kernel(&self) -> Vec<f32>;
   #[must_use]
   fn down_right_kernel(&self) -> Vec<f32>;
+  /// The filter the kernel is applied with
+  #[must_use]
+  fn smoother_params(&self) -> SmootherParams {
+    SmootherParams::default()
+  }
+  /// Whether the blur can be applied as a horizontal and a vertical pass
+  #[must_use]
+  fn is_separable(&self) -> bool {
+    false
+  }
+  /// Replaces the kernel smoother if set
+  #[must_use]
+  fn curvature_flow(&self) -> Option<CurvatureFlow> {
+    None
+  }
+  /// Kernel uploaded to the smoother, [`separate_kernel`] if [`Self::is_separable`]
+  #[must_use]
+  fn smoother_kernel(&self) -> Vec<f32> {
+    if self.is_separable() {
+      separate_kernel(&self.down_right_kernel())
+    } else {
+      self.full_kernel()
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Blur, GaussianBlur};
+  use cgmath::Vector2;
+
+  #[test]
+  fn separable_gauss() {
+    let gauss = GaussianBlur {
+      side: 5,
+      ..Default::default()
+    };
+    assert!(gauss.is_separable());
+    let kernel = gauss.smoother_kernel();
+    assert_eq!(kernel.len(), 2 * 11);
+    for half in kernel.chunks_exact(11) {
+      assert!((half.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+      assert!(half.iter().zip(half.iter().rev()).all(|(a, b)| a == b));
+      assert!(half[5] > half[4] && half[4] > half[0]);
+    }
+  }
+
+  #[test]
+  fn separated_equals_full() {
+    let gauss = GaussianBlur {
+      side: 4,
+      dh: Vector2::new(1.0, 0.5),
+      ..Default::default()
+    };
+    let full = gauss.full_kernel();
+    let separated = gauss.smoother_kernel();
+    let len = 2 * gauss.side + 1;
+    assert_eq!(full.len(), len * len);
+    let (horizontal, vertical) = separated.split_at(len);
+    // The first index of the full kernel is vertical
+    for (y, v) in vertical.iter().enumerate() {
+      for (x, h) in horizontal.iter().enumerate() {
+        assert!((full[y * len + x] - v * h).abs() < 1e-6);
+      }
+    }
+  }
+}
+
+/// Splits an `n × n` [`Blur::down_right_kernel`] into the 1D horizontal and vertical kernels
+/// of the length `2 * n - 1`, concatenated.
+/// The down-right kernel of a separable blur is the outer product of their halves.
+pub fn separate_kernel(down_right: &[f32]) -> Vec<f32> {
+  let side = (down_right.len() as f32).sqrt() as usize;
+  // The second index is horizontal, see `GaussianBlur::full_kernel`
+  let horizontal = (0..side).map(|i| down_right[i]);
+  let vertical = (0..side).map(|i| down_right[i * side]);
+  [horizontal.collect::<Vec<_>>(), vertical.collect()]
+    .into_iter()
+    .flat_map(|half| {
+      let mirrored: Vec<f32> = half.iter().rev().chain(&half[1..]).copied().collect();
+      let norm: f32 = mirrored.iter().sum();
+      mirrored.into_iter().map(move |x| x / norm)
+    })
+    .collect()
+}
+
+/// Weighting of the depth samples by the smoother.
+/// This enum **must** be kept the same as the `FILTER_*` constants of the smoother shader.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DepthFilter {
+  /// Plain convolution with the kernel
+  #[default]
+  Gaussian = 0,
+  /// The weights also fall off with the depth difference from the center
+  Bilateral = 1,
+  /// The narrow-range filter of Truong & Yuksel: the samples behind the range are ignored,
+  /// the ones in front of it are clamped to it
+  NarrowRange = 2,
+  /// [`CurvatureFlow`], the kernel smoother isn't run
+  CurvatureFlow = 3,
+}
+
+impl DepthFilter {
+  pub const ALL: [DepthFilter; 4] = [
+    DepthFilter::Gaussian,
+    DepthFilter::Bilateral,
+    DepthFilter::NarrowRange,
+    DepthFilter::CurvatureFlow,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      DepthFilter::Gaussian => "Gaussian",
+      DepthFilter::Bilateral => "Bilateral",
+      DepthFilter::NarrowRange => "Narrow-range",
+      DepthFilter::CurvatureFlow => "Curvature flow",
+    }
+  }
+}
+
+/// Uniform of the smoother shader
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SmootherParams {
+  pub filter: DepthFilter,
+  /// σ of the depth differences of [`DepthFilter::Bilateral`]
+  /// or the half-width of the range of [`DepthFilter::NarrowRange`], in the view space
+  pub depth_threshold: f32,
+  _pad: [u32; 2],
+}
+
+impl SmootherParams {
+  pub fn new(filter: DepthFilter, depth_threshold: f32) -> Self {
+    Self {
+      filter,
+      depth_threshold,
+      _pad: [0; 2],
+    }
+  }
+}
+
+impl AsBuffer for SmootherParams {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<SmootherParams>(),
+      )
+    }
+  }
 }
 
 #[inline(always)]
@@ -31,7 +185,7 @@ pub fn trace_matrix(m: impl Iterator<Item = impl Iterator<Item = f32>>) {
   log::trace!("Matrix sum={sum:.5}");
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct GaussianBlur {
   pub s: f32,
   pub side: usize,
@@ -49,11 +203,17 @@ impl Default for GaussianBlur {
 }
 
 impl Blur for GaussianBlur {
+  /// The empty kernel can't be separated
+  fn is_separable(&self) -> bool {
+    self.side > 0
+  }
+
+  /// The quarter of [`Self::full_kernel`] from its center, `side + 1` per axis
   fn down_right_kernel(&self) -> Vec<f32> {
-    let mut out = vec![vec![0.; self.side]; self.side];
+    let mut out = vec![vec![0.; self.side + 1]; self.side + 1];
     let mut norm = 0.0;
-    for x in 0..self.side {
-      for y in 0..self.side {
+    for x in 0..self.side + 1 {
+      for y in 0..self.side + 1 {
         let l = scaled_len(x, y, self.dh);
         let w = 1. / f32::consts::TAU / self.s * self.s * f32::exp(-(l / self.s).powi(2) / 2.);
         out[x][y] = w;
@@ -94,3 +254,86 @@ impl Blur for GaussianBlur {
     out.into_iter().flatten().map(|x| x / norm).collect()
   }
 }
+
+/// Gaussian kernel with [`DepthFilter::Bilateral`], doesn't blur across the silhouettes
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct BilateralBlur {
+  pub gauss: GaussianBlur,
+  pub depth_sigma: f32,
+}
+
+impl Blur for BilateralBlur {
+  fn full_kernel(&self) -> Vec<f32> {
+    self.gauss.full_kernel()
+  }
+
+  fn down_right_kernel(&self) -> Vec<f32> {
+    self.gauss.down_right_kernel()
+  }
+
+  fn smoother_params(&self) -> SmootherParams {
+    SmootherParams::new(DepthFilter::Bilateral, self.depth_sigma)
+  }
+}
+
+/// Gaussian kernel with [`DepthFilter::NarrowRange`], keeps the separate blobs apart
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct NarrowRangeBlur {
+  pub gauss: GaussianBlur,
+  pub threshold: f32,
+}
+
+impl Blur for NarrowRangeBlur {
+  fn full_kernel(&self) -> Vec<f32> {
+    self.gauss.full_kernel()
+  }
+
+  fn down_right_kernel(&self) -> Vec<f32> {
+    self.gauss.down_right_kernel()
+  }
+
+  fn smoother_params(&self) -> SmootherParams {
+    SmootherParams::new(DepthFilter::NarrowRange, self.threshold)
+  }
+
+  /// The range is applied per pass, as in the paper
+  fn is_separable(&self) -> bool {
+    self.gauss.is_separable()
+  }
+}
+
+/// Screen-space curvature flow of van der Laan et al.: the depth is moved along its mean curvature
+/// for a number of iterations, then the normals are reconstructed from it
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvatureFlow {
+  pub iterations: u32,
+  /// Time step of an iteration
+  pub dt: f32,
+}
+
+impl Default for CurvatureFlow {
+  fn default() -> Self {
+    Self {
+      iterations: 40,
+      dt: 1e-3,
+    }
+  }
+}
+
+impl Blur for CurvatureFlow {
+  fn full_kernel(&self) -> Vec<f32> {
+    vec![1.0]
+  }
+
+  fn down_right_kernel(&self) -> Vec<f32> {
+    vec![1.0]
+  }
+
+  fn smoother_params(&self) -> SmootherParams {
+    SmootherParams::new(DepthFilter::CurvatureFlow, 0.0)
+  }
+
+  fn curvature_flow(&self) -> Option<CurvatureFlow> {
+    Some(*self)
+  }
+}
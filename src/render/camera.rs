@@ -2,7 +2,9 @@ use cgmath::{
   EuclideanSpace, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation, Rotation3, Vector2,
   Vector3,
 };
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OrbitCameraController {
   center: Point3<f32>,
   right: Vector3<f32>,
@@ -0,0 +1,293 @@
+use std::{
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+
+use wgpu::{Buffer, BufferDescriptor, BufferUsages, Extent3d, Texture, TextureFormat};
+
+#[cfg(test)]
+mod test {
+  use super::{padded_bytes_per_row, unpad, Frame, Y4mWriter};
+
+  #[test]
+  fn unpad_bgra() {
+    let (width, height) = (3, 2);
+    let pitch = padded_bytes_per_row(width) as usize;
+    assert_eq!(pitch, 256);
+    let mut data = vec![0xee; pitch * height as usize];
+    for y in 0..height as usize {
+      for x in 0..width as usize {
+        let i = y * pitch + 4 * x;
+        data[i..i + 4].copy_from_slice(&[x as u8, y as u8, 7, 255]);
+      }
+    }
+    let rgba = unpad(&data, width, height, pitch as u32, true);
+    assert_eq!(rgba.len(), (4 * width * height) as usize);
+    assert_eq!(rgba[..8], [7, 0, 0, 255, 7, 0, 1, 255]);
+    assert_eq!(rgba[12..16], [7, 1, 0, 255]);
+    let rgba = unpad(&data, width, height, pitch as u32, false);
+    assert_eq!(rgba[20..24], [2, 1, 7, 255]);
+  }
+
+  #[test]
+  fn y4m_stream() {
+    let frame = Frame {
+      width: 2,
+      height: 1,
+      rgba: vec![255, 255, 255, 255, 0, 0, 0, 255],
+    };
+    let mut out = Vec::new();
+    let mut y4m = Y4mWriter::new(&mut out, 2, 1, 30).unwrap();
+    y4m.write_frame(&frame).unwrap();
+    y4m.write_frame(&frame).unwrap();
+    let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444\n";
+    assert_eq!(out[..header.len()], header[..]);
+    let frame_len = b"FRAME\n".len() + 3 * 2;
+    assert_eq!(out.len(), header.len() + 2 * frame_len);
+    // Studio range luma, neutral chroma
+    assert_eq!(
+      out[header.len()..header.len() + frame_len],
+      *b"FRAME\n\xeb\x10\x80\x80\x80\x80"
+    );
+  }
+}
+
+/// Pixels of a captured frame, RGBA rows from the top without padding
+#[derive(Clone)]
+pub struct Frame {
+  pub width: u32,
+  pub height: u32,
+  pub rgba: Vec<u8>,
+}
+
+impl Frame {
+  pub fn save_png(&self, path: &Path) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+      io::BufWriter::new(fs::File::create(path)?),
+      self.width,
+      self.height,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer
+      .write_image_data(&self.rgba)
+      .map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+  }
+}
+
+/// Row pitch of a `width` pixels wide texture copied to a buffer
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+  (4 * width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// Removes the row padding and swaps the red and blue channels if `bgra` is set
+fn unpad(data: &[u8], width: u32, height: u32, padded_bytes_per_row: u32, bgra: bool) -> Vec<u8> {
+  let row = 4 * width as usize;
+  let mut rgba = Vec::with_capacity(row * height as usize);
+  for line in data
+    .chunks_exact(padded_bytes_per_row as usize)
+    .take(height as usize)
+  {
+    rgba.extend_from_slice(&line[..row]);
+  }
+  if bgra {
+    rgba.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+  }
+  rgba
+}
+
+/// Copies a rendered texture to a buffer and reads it back as a [`Frame`].
+///
+/// Only the 8-bit RGBA and BGRA textures with [`wgpu::TextureUsages::COPY_SRC`] are supported,
+/// [`Self::new`] fails for the other formats.
+pub struct FrameReader {
+  buf: Buffer,
+  size: Extent3d,
+  padded_bytes_per_row: u32,
+  bgra: bool,
+  /// The copy is recorded and not read yet
+  pending: bool,
+}
+
+impl FrameReader {
+  pub fn new(device: &wgpu::Device, size: Extent3d, format: TextureFormat) -> io::Result<Self> {
+    let bgra = match format {
+      TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+      TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+      _ => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          format!("unable to capture the textures of the format {format:?}"),
+        ))
+      }
+    };
+    let padded_bytes_per_row = padded_bytes_per_row(size.width);
+    let buf = device.create_buffer(&BufferDescriptor {
+      label: Some("FrameReader"),
+      size: padded_bytes_per_row as u64 * size.height as u64,
+      usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    Ok(Self {
+      buf,
+      size,
+      padded_bytes_per_row,
+      bgra,
+      pending: false,
+    })
+  }
+
+  pub fn size(&self) -> Extent3d {
+    self.size
+  }
+
+  /// Whether a copy is recorded and not read yet
+  pub fn is_pending(&self) -> bool {
+    self.pending
+  }
+
+  /// Records the copy of the `texture` of the size of the reader
+  pub fn copy(&mut self, encoder: &mut wgpu::CommandEncoder, texture: &Texture) {
+    encoder.copy_texture_to_buffer(
+      texture.as_image_copy(),
+      wgpu::TexelCopyBufferInfo {
+        buffer: &self.buf,
+        layout: wgpu::TexelCopyBufferLayout {
+          offset: 0,
+          bytes_per_row: Some(self.padded_bytes_per_row),
+          rows_per_image: Some(self.size.height),
+        },
+      },
+      self.size,
+    );
+    self.pending = true;
+  }
+
+  /// Waits for the copy recorded by [`Self::copy`] and reads the frame.
+  /// The encoder of the copy must be submitted. Returns `None` if there is no copy.
+  pub fn read(&mut self, device: &wgpu::Device) -> Option<Frame> {
+    if !std::mem::take(&mut self.pending) {
+      return None;
+    }
+    let slice = self.buf.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |r| {
+      r.expect("Unable to map the captured frame")
+    });
+    device.poll(wgpu::Maintain::Wait);
+    let rgba = unpad(
+      &slice.get_mapped_range(),
+      self.size.width,
+      self.size.height,
+      self.padded_bytes_per_row,
+      self.bgra,
+    );
+    self.buf.unmap();
+    Some(Frame {
+      width: self.size.width,
+      height: self.size.height,
+      rgba,
+    })
+  }
+}
+
+/// Writes the frames as an uncompressed YUV4MPEG2 stream with the full chroma resolution.
+/// It's accepted by `ffmpeg` and most video players.
+pub struct Y4mWriter<W: Write> {
+  w: W,
+  width: u32,
+  height: u32,
+}
+
+impl<W: Write> Y4mWriter<W> {
+  pub fn new(mut w: W, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+    writeln!(w, "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444")?;
+    Ok(Self { w, width, height })
+  }
+
+  /// Converts the frame to the BT.601 studio range, the alpha is ignored
+  pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+    if (frame.width, frame.height) != (self.width, self.height) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the frame size differs from the one of the stream",
+      ));
+    }
+    let pixels = (self.width * self.height) as usize;
+    let mut planes = vec![0u8; 3 * pixels];
+    for (i, px) in frame.rgba.chunks_exact(4).enumerate() {
+      let [r, g, b] = [px[0], px[1], px[2]].map(f32::from);
+      planes[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+      planes[pixels + i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+      planes[2 * pixels + i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+    self.w.write_all(b"FRAME\n")?;
+    self.w.write_all(&planes)
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.w.flush()
+  }
+}
+
+/// Writes the captured frames to a directory as `frame-<number>.png`
+/// and optionally as the `video.y4m` stream.
+pub struct Recorder {
+  dir: PathBuf,
+  /// Frame rate of the video, `None` if it's not written
+  video_fps: Option<u32>,
+  video: Option<Y4mWriter<io::BufWriter<fs::File>>>,
+  frames: u32,
+}
+
+impl Recorder {
+  /// Creates the `dir` if it doesn't exist
+  pub fn new(dir: impl Into<PathBuf>, video_fps: Option<u32>) -> io::Result<Self> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    Ok(Self {
+      dir,
+      video_fps,
+      video: None,
+      frames: 0,
+    })
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Count of the frames written
+  pub fn frames(&self) -> u32 {
+    self.frames
+  }
+
+  pub fn write(&mut self, frame: &Frame) -> io::Result<()> {
+    frame.save_png(&self.dir.join(format!("frame-{:06}.png", self.frames)))?;
+    if let Some(fps) = self.video_fps {
+      let video = match &mut self.video {
+        Some(video) => video,
+        // The size is known only now
+        None => self.video.insert(Y4mWriter::new(
+          io::BufWriter::new(fs::File::create(self.dir.join("video.y4m"))?),
+          frame.width,
+          frame.height,
+          fps,
+        )?),
+      };
+      video.write_frame(frame)?;
+    }
+    self.frames += 1;
+    Ok(())
+  }
+
+  /// Flushes the video
+  pub fn finish(mut self) -> io::Result<()> {
+    match &mut self.video {
+      Some(video) => video.flush(),
+      None => Ok(()),
+    }
+  }
+}
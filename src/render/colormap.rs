@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of the entries of [`Colormap::lut`]
+pub const LUT_SIZE: usize = 256;
+
+#[cfg(test)]
+mod test {
+  use super::Colormap;
+
+  fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+    a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.02)
+  }
+
+  #[test]
+  fn endpoints() {
+    assert!(close(Colormap::Viridis.sample(0.0), [0.267, 0.005, 0.329]));
+    assert!(close(Colormap::Viridis.sample(1.0), [0.993, 0.906, 0.144]));
+    assert!(close(Colormap::Plasma.sample(0.0), [0.050, 0.030, 0.528]));
+    assert!(close(Colormap::Plasma.sample(1.0), [0.940, 0.975, 0.131]));
+    assert!(close(Colormap::CoolWarm.sample(0.0), [0.230, 0.299, 0.754]));
+    assert!(close(Colormap::CoolWarm.sample(0.5), [0.865, 0.865, 0.865]));
+    assert!(close(Colormap::CoolWarm.sample(1.0), [0.706, 0.016, 0.150]));
+  }
+
+  #[test]
+  fn clamped_and_in_gamut() {
+    for map in Colormap::ALL {
+      assert_eq!(map.sample(-1.0), map.sample(0.0));
+      assert_eq!(map.sample(2.0), map.sample(1.0));
+      assert_eq!(map.sample(f32::NAN), map.sample(0.0));
+      let lut = map.lut();
+      assert!(lut.iter().flatten().all(|c| (0.0..=1.0).contains(c)));
+      assert_eq!(lut[0][3], 1.0);
+    }
+  }
+}
+
+/// Maps the scalars in `0..=1` to colours
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Colormap {
+  /// Perceptually uniform, dark blue to yellow
+  #[default]
+  Viridis,
+  /// Perceptually uniform, dark blue to yellow through magenta
+  Plasma,
+  /// Diverging, blue to red through grey. Meant for the values around a center, like the pressure
+  CoolWarm,
+}
+
+/// Coefficients of the polynomial fits of the matplotlib colormaps, from the lowest degree
+#[rustfmt::skip]
+const VIRIDIS: [[f32; 3]; 7] = [
+  [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+  [0.105_093_04, 1.404_613_5, 1.384_590_1],
+  [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+  [-4.634_230_6, -5.799_101, -19.332_441],
+  [6.228_27, 14.179_933, 56.690_55],
+  [4.776_385, -13.745_146, -65.353_03],
+  [-5.435_456, 4.645_852_6, 26.312_435],
+];
+#[rustfmt::skip]
+const PLASMA: [[f32; 3]; 7] = [
+  [0.058_732_344, 0.023_336_709, 0.543_340_2],
+  [2.176_514_6, 0.238_383_42, 0.753_960_45],
+  [-2.689_460_5, -7.455_851, 3.110_8],
+  [6.130_348, 42.346_188, -28.518_855],
+  [-11.107_436, -82.666_31, 60.139_847],
+  [10.023_066, 71.413_62, -54.072_186],
+  [-3.658_713_8, -22.931_534, 18.191_908],
+];
+/// Moreland's diverging map sampled at the equal steps
+const COOL_WARM: [[f32; 3]; 9] = [
+  [0.230, 0.299, 0.754],
+  [0.353, 0.472, 0.893],
+  [0.486, 0.631, 0.977],
+  [0.619, 0.744, 0.998],
+  [0.865, 0.865, 0.865],
+  [0.958, 0.769, 0.678],
+  [0.958, 0.604, 0.482],
+  [0.870, 0.393, 0.301],
+  [0.706, 0.016, 0.150],
+];
+
+fn polynomial(c: &[[f32; 3]], t: f32) -> [f32; 3] {
+  [0, 1, 2].map(|i| c.iter().rev().fold(0.0, |acc, c| acc * t + c[i]))
+}
+
+fn piecewise_linear(points: &[[f32; 3]], t: f32) -> [f32; 3] {
+  let x = t * (points.len() - 1) as f32;
+  let i = (x as usize).min(points.len() - 2);
+  let f = x - i as f32;
+  [0, 1, 2].map(|c| points[i][c] + (points[i + 1][c] - points[i][c]) * f)
+}
+
+impl Colormap {
+  pub const ALL: [Colormap; 3] = [Colormap::Viridis, Colormap::Plasma, Colormap::CoolWarm];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Colormap::Viridis => "Viridis",
+      Colormap::Plasma => "Plasma",
+      Colormap::CoolWarm => "Cool-warm",
+    }
+  }
+
+  /// RGB of `t`, clamped to `0..=1`. NaN is mapped like 0.
+  pub fn sample(self, t: f32) -> [f32; 3] {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    let color = match self {
+      Colormap::Viridis => polynomial(&VIRIDIS, t),
+      Colormap::Plasma => polynomial(&PLASMA, t),
+      Colormap::CoolWarm => piecewise_linear(&COOL_WARM, t),
+    };
+    color.map(|c| c.clamp(0.0, 1.0))
+  }
+
+  /// The colours at the equal steps from 0 to 1, opaque. Sampled by the shaders.
+  pub fn lut(self) -> [[f32; 4]; LUT_SIZE] {
+    std::array::from_fn(|i| {
+      let [r, g, b] = self.sample(i as f32 / (LUT_SIZE - 1) as f32);
+      [r, g, b, 1.0]
+    })
+  }
+}
@@ -0,0 +1,297 @@
+use std::{
+  f32::consts::PI,
+  fs,
+  io::{self, BufRead},
+  path::Path,
+};
+
+const ZENITH: [f32; 3] = [0.2, 0.4, 0.9];
+const HORIZON: [f32; 3] = [0.9, 0.92, 0.95];
+const GROUND: [f32; 3] = [0.3, 0.27, 0.24];
+
+#[cfg(test)]
+mod test {
+  use super::{f16_bits, read_hdr, EnvironmentMap};
+
+  #[test]
+  fn half_floats() {
+    assert_eq!(f16_bits(0.0), 0);
+    assert_eq!(f16_bits(1.0), 0x3c00);
+    assert_eq!(f16_bits(-2.0), 0xc000);
+    assert_eq!(f16_bits(0.5), 0x3800);
+    assert_eq!(f16_bits(65504.0), 0x7bff);
+    assert_eq!(f16_bits(1e6), 0x7c00);
+    assert_eq!(f16_bits(1e-8), 0);
+  }
+
+  #[test]
+  fn hdr_flat_and_rle() {
+    let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+    // A flat scanline
+    for _ in 0..8 {
+      file.extend_from_slice(&[128, 64, 0, 129]);
+    }
+    // An RLE scanline: a run of 8 in each channel but the green one, which is literal
+    file.extend_from_slice(&[2, 2, 0, 8]);
+    file.extend_from_slice(&[128 + 8, 64]);
+    file.extend_from_slice(&[8, 1, 2, 3, 4, 5, 6, 7, 8]);
+    file.extend_from_slice(&[128 + 8, 0]);
+    file.extend_from_slice(&[128 + 8, 130]);
+    let env = read_hdr(&mut file.as_slice()).unwrap();
+    assert_eq!((env.width, env.height), (8, 2));
+    assert_eq!(env.rgba[..4], [1.0, 0.5, 0.0, 1.0]);
+    assert_eq!(env.rgba[8 * 4..8 * 4 + 4], [1.0, 1.0 / 64.0, 0.0, 1.0]);
+    assert_eq!(env.rgba[15 * 4 + 1], 8.0 / 64.0);
+    assert!(read_hdr(&mut &file[1..]).is_err());
+    assert!(read_hdr(&mut &file[..file.len() - 1]).is_err());
+  }
+
+  #[test]
+  fn size_limits() {
+    let env = EnvironmentMap::procedural();
+    assert!(env.check_size(env.width).is_ok());
+    assert!(env.check_size(env.width - 1).is_err());
+    let empty = EnvironmentMap {
+      width: 0,
+      height: 0,
+      rgba: vec![],
+    };
+    assert!(empty.check_size(8192).is_err());
+  }
+
+  #[test]
+  fn procedural_sky() {
+    let env = EnvironmentMap::procedural();
+    assert_eq!(env.rgba.len(), (4 * env.width * env.height) as usize);
+    // Brighter and bluer at the zenith than the ground
+    let top = &env.rgba[..4];
+    let bottom = &env.rgba[env.rgba.len() - 4..];
+    assert!(top[2] > top[0] && top[2] > bottom[2]);
+  }
+}
+
+/// Equirectangular map of the light coming from the directions, reflected by the fluid.
+///
+/// The rows go from the zenith (+Y) to the nadir, the columns around the Y axis starting at -X.
+#[derive(Clone)]
+pub struct EnvironmentMap {
+  pub width: u32,
+  pub height: u32,
+  /// Linear RGBA
+  pub rgba: Vec<f32>,
+}
+
+impl Default for EnvironmentMap {
+  fn default() -> Self {
+    Self::procedural()
+  }
+}
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Rounds to the nearest half float, the subnormals are flushed to zero
+fn f16_bits(x: f32) -> u16 {
+  let bits = x.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exp = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+  let mantissa = bits & 0x7f_ffff;
+  if x.is_nan() {
+    return sign | 0x7e00;
+  }
+  if exp >= 0x1f {
+    return sign | 0x7c00;
+  }
+  if exp <= 0 {
+    return sign;
+  }
+  // A carry of the rounding goes to the exponent, up to the infinity
+  let half = ((exp as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+  sign | half as u16
+}
+
+/// Reads the RGBE pixels of the Radiance HDR format, flat or with the "new" run-length encoding.
+/// Only the usual `-Y <H> +X <W>` orientation is supported.
+fn read_hdr(r: &mut impl BufRead) -> io::Result<EnvironmentMap> {
+  let mut line = String::new();
+  r.read_line(&mut line)?;
+  if !line.starts_with("#?") {
+    return Err(invalid("not a Radiance HDR file"));
+  }
+  loop {
+    line.clear();
+    if r.read_line(&mut line)? == 0 {
+      return Err(invalid("the HDR header is not terminated"));
+    }
+    match line.trim_end() {
+      "" => break,
+      format if format.starts_with("FORMAT=") && format != "FORMAT=32-bit_rle_rgbe" => {
+        return Err(invalid("only the RGBE pixels are supported"))
+      }
+      _ => {}
+    }
+  }
+  line.clear();
+  r.read_line(&mut line)?;
+  let (height, width) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+    ["-Y", h, "+X", w] => (h.parse::<u32>(), w.parse::<u32>()),
+    _ => return Err(invalid("unsupported HDR orientation")),
+  };
+  let (Ok(height), Ok(width)) = (height, width) else {
+    return Err(invalid("invalid HDR resolution"));
+  };
+
+  let w = width as usize;
+  let mut rgba = Vec::with_capacity(4 * w * height as usize);
+  let mut scanline = vec![0u8; 4 * w];
+  for _ in 0..height {
+    let mut start = [0u8; 4];
+    r.read_exact(&mut start)?;
+    let rle = (8..0x8000).contains(&w) && start[..2] == [2, 2] && start[2] & 0x80 == 0;
+    if !rle {
+      scanline[..4].copy_from_slice(&start);
+      r.read_exact(&mut scanline[4..])?;
+    } else {
+      if usize::from(u16::from_be_bytes([start[2], start[3]])) != w {
+        return Err(invalid("invalid HDR scanline length"));
+      }
+      // The channels are encoded one after another
+      for channel in 0..4 {
+        let mut x = 0;
+        while x < w {
+          let mut count = [0u8; 1];
+          r.read_exact(&mut count)?;
+          let (run, count) = match count[0] {
+            c if c > 128 => (true, usize::from(c - 128)),
+            c => (false, usize::from(c)),
+          };
+          if count == 0 || x + count > w {
+            return Err(invalid("invalid HDR run length"));
+          }
+          let mut values = [0u8; 128];
+          r.read_exact(&mut values[..if run { 1 } else { count }])?;
+          for i in 0..count {
+            scanline[4 * (x + i) + channel] = values[if run { 0 } else { i }];
+          }
+          x += count;
+        }
+      }
+    }
+    for px in scanline.chunks_exact(4) {
+      let scale = match px[3] {
+        0 => 0.0,
+        e => 2f32.powi(i32::from(e) - (128 + 8)),
+      };
+      rgba.extend_from_slice(&[px[0], px[1], px[2]].map(|m| f32::from(m) * scale));
+      rgba.push(1.0);
+    }
+  }
+  Ok(EnvironmentMap {
+    width,
+    height,
+    rgba,
+  })
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+  let c = f32::from(c) / 255.0;
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+fn read_png(r: impl io::Read) -> io::Result<EnvironmentMap> {
+  let mut decoder = png::Decoder::new(r);
+  decoder.set_transformations(png::Transformations::normalize_to_color8());
+  let mut reader = decoder.read_info().map_err(io::Error::other)?;
+  let mut buf = vec![0; reader.output_buffer_size()];
+  let info = reader.next_frame(&mut buf).map_err(io::Error::other)?;
+  let channels = info.color_type.samples();
+  let mut rgba = Vec::with_capacity(4 * (info.width * info.height) as usize);
+  for px in buf[..info.buffer_size()].chunks_exact(channels) {
+    let (color, alpha) = match *px {
+      [l] => ([l; 3], 255),
+      [l, a] => ([l; 3], a),
+      [r, g, b] => ([r, g, b], 255),
+      [r, g, b, a] => ([r, g, b], a),
+      _ => unreachable!(),
+    };
+    rgba.extend_from_slice(&color.map(srgb_to_linear));
+    rgba.push(f32::from(alpha) / 255.0);
+  }
+  Ok(EnvironmentMap {
+    width: info.width,
+    height: info.height,
+    rgba,
+  })
+}
+
+impl EnvironmentMap {
+  /// Sky fading into the horizon above the ground
+  pub fn procedural() -> Self {
+    let (width, height) = (256, 128);
+    let mut rgba = Vec::with_capacity(4 * width * height);
+    for y in 0..height {
+      let up = ((y as f32 + 0.5) / height as f32 * PI).cos();
+      let (from, to) = if up >= 0.0 {
+        (HORIZON, ZENITH)
+      } else {
+        (HORIZON, GROUND)
+      };
+      let t = up.abs().sqrt();
+      let color = [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t);
+      for _ in 0..width {
+        rgba.extend_from_slice(&color);
+        rgba.push(1.0);
+      }
+    }
+    Self {
+      width: width as u32,
+      height: height as u32,
+      rgba,
+    }
+  }
+
+  /// Reads a Radiance HDR (`.hdr`) or a PNG file, the PNG is in sRGB
+  pub fn load(path: &Path) -> io::Result<Self> {
+    let mut r = io::BufReader::new(fs::File::open(path)?);
+    let extension = path
+      .extension()
+      .and_then(|e| e.to_str())
+      .unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+      "hdr" => read_hdr(&mut r),
+      "png" => read_png(r),
+      _ => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "only the .hdr and .png environment maps are supported",
+      )),
+    }
+  }
+
+  /// Fails if the map is empty or a side is longer than `max_dimension`,
+  /// see [`wgpu::Limits::max_texture_dimension_2d`]
+  pub fn check_size(&self, max_dimension: u32) -> io::Result<()> {
+    if self.width == 0 || self.height == 0 {
+      return Err(invalid("the environment map is empty"));
+    }
+    if self.width.max(self.height) > max_dimension {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "the environment map is {}x{}, the sides are limited to {max_dimension} on this device",
+          self.width, self.height
+        ),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Data of a [`wgpu::TextureFormat::Rgba16Float`] texture
+  pub fn half_floats(&self) -> Vec<u16> {
+    self.rgba.iter().map(|&x| f16_bits(x)).collect()
+  }
+}
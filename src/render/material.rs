@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod test {
+  use super::FluidMaterial;
+
+  #[test]
+  fn uniform_layout() {
+    let u = FluidMaterial::HONEY.uniform();
+    assert_eq!(u[..4], [0.3, 1.2, 4.0, 1.5]);
+    assert_eq!(u[7], FluidMaterial::HONEY.shininess);
+    assert_eq!(u[11], FluidMaterial::HONEY.refraction);
+    assert_eq!(u[12..15], FluidMaterial::HONEY.light_color);
+    assert_eq!([u[15], u[19]], [0.0; 2]);
+  }
+}
+
+/// Optical properties of the fluid and the light shading it, the uniform of the merger shader
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FluidMaterial {
+  /// Beer–Lambert absorption coefficients of the red, green and blue light per unit of thickness
+  pub absorption: [f32; 3],
+  /// Index of refraction, gives the Fresnel reflectance
+  pub ior: f32,
+  pub specular: [f32; 3],
+  /// Exponent of the Blinn-Phong highlight
+  pub shininess: f32,
+  /// Direction towards the light in the world space, doesn't have to be normalized
+  pub light_dir: [f32; 3],
+  /// Screen-space offset of the background seen through the fluid per unit of thickness
+  pub refraction: f32,
+  pub light_color: [f32; 3],
+  /// Colour scattered by the fluid where it's thick, the background shows through the thin parts
+  pub ambient: [f32; 3],
+}
+
+impl Default for FluidMaterial {
+  fn default() -> Self {
+    Self::WATER
+  }
+}
+
+impl FluidMaterial {
+  pub const WATER: FluidMaterial = FluidMaterial {
+    absorption: [1.2, 1.0, 0.6],
+    ior: 1.33,
+    specular: [1.0, 1.0, 1.0],
+    shininess: 200.0,
+    light_dir: [0.3, 1.0, 0.4],
+    refraction: 0.02,
+    light_color: [1.0, 1.0, 1.0],
+    ambient: [0.07, 0.075, 1.0],
+  };
+  pub const MILK: FluidMaterial = FluidMaterial {
+    absorption: [8.0, 8.0, 8.0],
+    ior: 1.35,
+    specular: [0.4, 0.4, 0.4],
+    shininess: 40.0,
+    ambient: [0.95, 0.94, 0.9],
+    ..Self::WATER
+  };
+  pub const HONEY: FluidMaterial = FluidMaterial {
+    absorption: [0.3, 1.2, 4.0],
+    ior: 1.5,
+    specular: [0.9, 0.8, 0.6],
+    shininess: 120.0,
+    refraction: 0.03,
+    ambient: [0.8, 0.45, 0.05],
+    ..Self::WATER
+  };
+  pub const LAVA: FluidMaterial = FluidMaterial {
+    absorption: [6.0, 6.0, 6.0],
+    ior: 1.6,
+    specular: [1.0, 0.6, 0.2],
+    shininess: 20.0,
+    light_color: [1.0, 0.9, 0.8],
+    ambient: [1.0, 0.3, 0.02],
+    ..Self::WATER
+  };
+
+  pub const PRESETS: [(&'static str, FluidMaterial); 4] = [
+    ("Water", Self::WATER),
+    ("Milk", Self::MILK),
+    ("Honey", Self::HONEY),
+    ("Lava", Self::LAVA),
+  ];
+
+  /// The `FluidMaterial` struct of the merger shader, the `vec3f`s are padded to 16 bytes
+  pub fn uniform(&self) -> [f32; 20] {
+    let [a, s, l, c, m] = [
+      self.absorption,
+      self.specular,
+      self.light_dir,
+      self.light_color,
+      self.ambient,
+    ];
+    [
+      a[0],
+      a[1],
+      a[2],
+      self.ior,
+      s[0],
+      s[1],
+      s[2],
+      self.shininess,
+      l[0],
+      l[1],
+      l[2],
+      self.refraction,
+      c[0],
+      c[1],
+      c[2],
+      0.0,
+      m[0],
+      m[1],
+      m[2],
+      0.0,
+    ]
+  }
+}
@@ -5,6 +5,11 @@ use cgmath::{Matrix, Matrix4};
 pub mod application;
 pub mod blur;
 pub mod camera;
+pub mod capture;
+pub mod colormap;
+pub mod environment;
+pub mod material;
+pub mod profiler;
 pub mod render_target;
 pub mod state;
 pub mod swapchain;
@@ -33,6 +38,24 @@ impl<const N: usize> AsBuffer for [u16; N] {
   }
 }
 
+impl<const N: usize> AsBuffer for [u32; N] {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe { slice::from_raw_parts(self.as_ptr().cast(), N * std::mem::size_of::<u32>()) }
+  }
+}
+
+impl AsBuffer for &[u16] {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe { slice::from_raw_parts(self.as_ptr().cast(), std::mem::size_of_val(*self)) }
+  }
+}
+
+impl AsBuffer for &[i32] {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe { slice::from_raw_parts(self.as_ptr().cast(), std::mem::size_of_val(*self)) }
+  }
+}
+
 impl AsBuffer for &[f32] {
   fn as_bytes_buffer(&self) -> &[u8] {
     unsafe {
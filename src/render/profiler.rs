@@ -0,0 +1,292 @@
+use std::{
+  collections::VecDeque,
+  io,
+  sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+  },
+};
+
+use egui::mutex::Mutex;
+use wgpu::{
+  Buffer, BufferDescriptor, BufferUsages, ComputePassTimestampWrites, QuerySet, QuerySetDescriptor,
+  RenderPassTimestampWrites,
+};
+
+/// Maximum count of the passes timed in a frame
+pub const MAX_SCOPES: u32 = 32;
+/// Count of the frames kept in the history
+pub const HISTORY_LEN: usize = 240;
+/// Count of the frames which timestamps may be read back at the same time
+const READBACK_COUNT: usize = 3;
+
+const MAP_PENDING: u8 = 0;
+const MAP_OK: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+/// Durations of the passes of a frame in milliseconds, in the order they were recorded
+pub type FrameTimings = Vec<(&'static str, f32)>;
+
+enum Stage {
+  Free,
+  /// The timestamps are copied to the buffer by a command buffer that is not submitted yet
+  Copied {
+    frame: u64,
+    labels: Vec<&'static str>,
+  },
+  Mapping {
+    frame: u64,
+    labels: Vec<&'static str>,
+    status: Arc<AtomicU8>,
+  },
+}
+
+struct Readback {
+  buf: Buffer,
+  stage: Stage,
+}
+
+struct Inner {
+  /// Labels of the passes of the frame being recorded
+  labels: Vec<&'static str>,
+  readbacks: Vec<Readback>,
+  history: VecDeque<FrameTimings>,
+  frame: u64,
+}
+
+/// Measures the GPU time of the passes with timestamp queries.
+///
+/// Every frame goes as follows: [`GpuProfiler::begin_frame`], the passes taking their
+/// timestamp writes from [`GpuProfiler::compute_writes`] or [`GpuProfiler::render_writes`],
+/// then [`GpuProfiler::resolve`] on the encoder submitted last. The results are read back
+/// asynchronously and appear in the history a few frames later.
+pub struct GpuProfiler {
+  query_set: QuerySet,
+  resolve_buf: Buffer,
+  /// Nanoseconds per timestamp tick
+  period: f32,
+  enabled: AtomicBool,
+  inner: Mutex<Inner>,
+}
+
+impl GpuProfiler {
+  /// Returns `None` if [`wgpu::Features::TIMESTAMP_QUERY`] is not enabled on the `device`
+  pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+    if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+      log::info!("Timestamp queries are not supported, GPU profiling is disabled");
+      return None;
+    }
+    let size = 2 * MAX_SCOPES as u64 * size_of::<u64>() as u64;
+    let query_set = device.create_query_set(&QuerySetDescriptor {
+      label: Some("GpuProfiler"),
+      ty: wgpu::QueryType::Timestamp,
+      count: 2 * MAX_SCOPES,
+    });
+    let resolve_buf = device.create_buffer(&BufferDescriptor {
+      label: Some("GpuProfiler resolve"),
+      size,
+      usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+      mapped_at_creation: false,
+    });
+    let readbacks = (0..READBACK_COUNT)
+      .map(|_| Readback {
+        buf: device.create_buffer(&BufferDescriptor {
+          label: Some("GpuProfiler readback"),
+          size,
+          usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+          mapped_at_creation: false,
+        }),
+        stage: Stage::Free,
+      })
+      .collect();
+    Some(Self {
+      query_set,
+      resolve_buf,
+      period: queue.get_timestamp_period(),
+      enabled: AtomicBool::new(true),
+      inner: Mutex::new(Inner {
+        labels: Vec::new(),
+        readbacks,
+        history: VecDeque::with_capacity(HISTORY_LEN),
+        frame: 0,
+      }),
+    })
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::Relaxed)
+  }
+
+  pub fn set_enabled(&self, enabled: bool) {
+    self.enabled.store(enabled, Ordering::Relaxed);
+  }
+
+  /// Collects the timestamps of the previous frames and starts a new one
+  pub fn begin_frame(&self, device: &wgpu::Device) {
+    device.poll(wgpu::Maintain::Poll);
+    let mut inner = self.inner.lock();
+    inner.labels.clear();
+    let mut finished = Vec::new();
+    for rb in inner.readbacks.iter_mut() {
+      match std::mem::replace(&mut rb.stage, Stage::Free) {
+        Stage::Free => {}
+        // Submitted since then, so the buffer may be mapped
+        Stage::Copied { frame, labels } => {
+          let status = Arc::new(AtomicU8::new(MAP_PENDING));
+          let callback_status = status.clone();
+          rb.buf.slice(..).map_async(wgpu::MapMode::Read, move |r| {
+            let s = if r.is_ok() { MAP_OK } else { MAP_FAILED };
+            callback_status.store(s, Ordering::Release);
+          });
+          rb.stage = Stage::Mapping {
+            frame,
+            labels,
+            status,
+          };
+        }
+        Stage::Mapping {
+          frame,
+          labels,
+          status,
+        } => match status.load(Ordering::Acquire) {
+          MAP_PENDING => {
+            rb.stage = Stage::Mapping {
+              frame,
+              labels,
+              status,
+            }
+          }
+          MAP_OK => {
+            finished.push((frame, self.read_timings(&rb.buf, &labels)));
+            rb.buf.unmap();
+          }
+          _ => log::warn!("Unable to read back the timestamps of the frame {frame}"),
+        },
+      }
+    }
+    finished.sort_by_key(|(frame, _)| *frame);
+    for (_, timings) in finished {
+      if inner.history.len() == HISTORY_LEN {
+        inner.history.pop_front();
+      }
+      inner.history.push_back(timings);
+    }
+  }
+
+  fn read_timings(&self, buf: &Buffer, labels: &[&'static str]) -> FrameTimings {
+    let view = buf.slice(..).get_mapped_range();
+    let ticks: Vec<u64> = view
+      .chunks_exact(size_of::<u64>())
+      .take(2 * labels.len())
+      .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+      .collect();
+    labels
+      .iter()
+      .zip(ticks.chunks_exact(2))
+      .map(|(label, t)| {
+        (
+          *label,
+          t[1].saturating_sub(t[0]) as f32 * self.period * 1e-6,
+        )
+      })
+      .collect()
+  }
+
+  /// Allocates the pair of queries of a pass
+  fn allocate(&self, label: &'static str) -> Option<u32> {
+    if !self.is_enabled() {
+      return None;
+    }
+    let mut inner = self.inner.lock();
+    let i = inner.labels.len() as u32;
+    if i == MAX_SCOPES {
+      return None;
+    }
+    inner.labels.push(label);
+    Some(i)
+  }
+
+  /// Timestamp writes measuring a compute pass, `None` if the profiler is disabled
+  pub fn compute_writes(&self, label: &'static str) -> Option<ComputePassTimestampWrites<'_>> {
+    let i = self.allocate(label)?;
+    Some(ComputePassTimestampWrites {
+      query_set: &self.query_set,
+      beginning_of_pass_write_index: Some(2 * i),
+      end_of_pass_write_index: Some(2 * i + 1),
+    })
+  }
+
+  /// Timestamp writes measuring a render pass, `None` if the profiler is disabled
+  pub fn render_writes(&self, label: &'static str) -> Option<RenderPassTimestampWrites<'_>> {
+    let i = self.allocate(label)?;
+    Some(RenderPassTimestampWrites {
+      query_set: &self.query_set,
+      beginning_of_pass_write_index: Some(2 * i),
+      end_of_pass_write_index: Some(2 * i + 1),
+    })
+  }
+
+  /// Copies the timestamps of the frame to a readback buffer.
+  /// The frame is skipped if all of them are still in use.
+  pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+    let mut inner = self.inner.lock();
+    let labels = std::mem::take(&mut inner.labels);
+    if labels.is_empty() {
+      return;
+    }
+    inner.frame += 1;
+    let frame = inner.frame;
+    let Some(rb) = inner
+      .readbacks
+      .iter_mut()
+      .find(|rb| matches!(rb.stage, Stage::Free))
+    else {
+      return;
+    };
+    let count = 2 * labels.len() as u32;
+    encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buf, 0);
+    encoder.copy_buffer_to_buffer(
+      &self.resolve_buf,
+      0,
+      &rb.buf,
+      0,
+      count as u64 * size_of::<u64>() as u64,
+    );
+    rb.stage = Stage::Copied { frame, labels };
+  }
+
+  /// Timings of the last [`HISTORY_LEN`] frames read back, the oldest first
+  pub fn history(&self) -> Vec<FrameTimings> {
+    self.inner.lock().history.iter().cloned().collect()
+  }
+
+  /// Average duration of every pass over the history, in the order of the latest frame
+  pub fn averages(&self) -> Vec<(&'static str, f32)> {
+    let inner = self.inner.lock();
+    let Some(latest) = inner.history.back() else {
+      return Vec::new();
+    };
+    latest
+      .iter()
+      .map(|(label, _)| {
+        let (sum, n) = inner
+          .history
+          .iter()
+          .flat_map(|frame| frame.iter().filter(|(l, _)| l == label))
+          .fold((0.0, 0), |(sum, n), (_, ms)| (sum + ms, n + 1));
+        (*label, sum / n as f32)
+      })
+      .collect()
+  }
+
+  /// Writes the history as the `frame,pass,ms` lines
+  pub fn write_csv(&self, w: &mut impl io::Write) -> io::Result<()> {
+    writeln!(w, "frame,pass,ms")?;
+    for (i, frame) in self.history().iter().enumerate() {
+      for (label, ms) in frame {
+        writeln!(w, "{i},{label},{ms}")?;
+      }
+    }
+    Ok(())
+  }
+}
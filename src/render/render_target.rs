@@ -39,6 +39,7 @@ pub trait RenderTarget<'a> {
   fn resized(
     &mut self,
     _device: &wgpu::Device,
+    _queue: &wgpu::Queue,
     _new_size: egui::Vec2,
     _resources: &'a Self::UpdateResources,
     _format: TextureFormat,
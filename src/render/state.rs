@@ -1,8 +1,8 @@
 use bindings::{GLOBAL_BIND_LOC, GLOBAL_BIND_SIZE};
-use cgmath::{Deg, Matrix4};
+use cgmath::{Deg, Matrix4, SquareMatrix};
 use egui::mutex::Mutex;
 use egui_wgpu::{CallbackTrait, RenderState};
-use std::num::NonZero;
+use std::{num::NonZero, sync::Arc};
 use wgpu::{
   BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
   BindGroupLayoutEntry, Buffer, BufferBinding, BufferDescriptor, BufferUsages, Color,
@@ -10,18 +10,31 @@ use wgpu::{
   RenderPassDescriptor, ShaderStages, StencilState, TextureFormat, TextureUsages,
 };
 
-use crate::render::{
-  render_target::RenderTarget,
-  targets::{
-    gizmo::GizmoResources,
-    show_texture::{TextureDrawerInitRes, TextureDrawerResources},
-    simulation::*,
+use crate::{
+  render::{
+    render_target::RenderTarget,
+    targets::{
+      gizmo::GizmoResources,
+      particle_view::ParticleViewSettings,
+      show_texture::{TextureDrawerInitRes, TextureDrawerResources},
+      simulation::*,
+      tank::{SceneLayers, Tank, TankResources},
+    },
+    texture_provider::TextureProviderDescriptor,
+  },
+  solvers::{
+    emitters::{Emitter, Sink},
+    storage::StorageLayout,
+    surface::SurfaceSettings,
   },
-  texture_provider::TextureProviderDescriptor,
 };
 
 use super::{
   blur::Blur,
+  capture::{FrameReader, Recorder},
+  environment::EnvironmentMap,
+  material::FluidMaterial,
+  profiler::GpuProfiler,
   targets::{gizmo::Gizmo, show_texture::TextureDrawer},
   texture_provider::TextureProvider,
   AsBuffer,
@@ -39,7 +52,13 @@ pub struct PersistentState {
   target_texture: TextureProvider,
   depth_state: wgpu::DepthStencilState,
   gizmo: Gizmo,
+  tank: Tank,
   texture_drawer: TextureDrawer,
+  profiler: Option<Arc<GpuProfiler>>,
+  /// Writes the frames rendered to `target_texture` while recording
+  recorder: Option<Recorder>,
+  /// Recreated when the size of `target_texture` changes
+  frame_reader: Option<FrameReader>,
 }
 
 pub mod bindings {
@@ -50,6 +69,9 @@ pub mod bindings {
     (4 * std::mem::size_of::<f32>() + 2 * std::mem::size_of::<Matrix4<f32>>()) as u64;
 }
 
+/// Count of the particles simulated by the GUI
+pub const DEFAULT_COUNT: usize = 8192;
+
 #[rustfmt::skip]
 pub const GL_TRANSFORM_TO_WGPU: Matrix4<f32> =
   Matrix4::new(
@@ -61,7 +83,8 @@ pub const GL_TRANSFORM_TO_WGPU: Matrix4<f32> =
 
 /// This structure is responsible for storing WGPU resources for the clear pass
 impl PersistentState {
-  pub fn create_egui(rstate: &RenderState) -> Self {
+  /// Creates the state of the GUI with the particles stored in the `storage` layout
+  pub fn create_egui(rstate: &RenderState, storage: StorageLayout) -> Self {
     let RenderState {
       device,
       target_format: format,
@@ -69,10 +92,16 @@ impl PersistentState {
       ..
     } = rstate;
 
-    Self::create_raw(device, format, queue)
+    Self::create_with(device, format, queue, DEFAULT_COUNT, storage, false)
   }
 
-  fn resize(&mut self, size: egui::Vec2, device: &wgpu::Device, callback: &StateCallback) {
+  fn resize(
+    &mut self,
+    size: egui::Vec2,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    callback: &StateCallback,
+  ) {
     if size.x > 0. && size.y > 0. {
       self.size = size;
       let new_tex_size = wgpu::Extent3d {
@@ -84,6 +113,7 @@ impl PersistentState {
       self.depth_texture.resize(device, new_tex_size);
       self.simulation.resized(
         device,
+        queue,
         size,
         &SimUpdateResources {
           params: &callback.params,
@@ -107,17 +137,157 @@ impl PersistentState {
     &mut self,
     size: egui::Vec2,
     device: &wgpu::Device,
+    queue: &wgpu::Queue,
     callback: &StateCallback,
   ) {
     if size != self.size {
-      self.resize(size, device, callback);
+      self.resize(size, device, queue, callback);
+    }
+  }
+
+  /// `None` if the timestamp queries are not supported
+  pub fn profiler(&self) -> Option<&Arc<GpuProfiler>> {
+    self.profiler.as_ref()
+  }
+
+  /// Starts writing the rendered frames with the `recorder`, replacing the current one
+  pub fn start_recording(&mut self, device: &wgpu::Device, recorder: Recorder) {
+    self.stop_recording(device);
+    log::info!("Recording to {}", recorder.dir().display());
+    self.recorder = Some(recorder);
+  }
+
+  /// Writes the last captured frame and finishes the recording
+  pub fn stop_recording(&mut self, device: &wgpu::Device) {
+    self.write_captured(device);
+    if let Some(recorder) = self.recorder.take() {
+      let frames = recorder.frames();
+      match recorder.finish() {
+        Ok(()) => log::info!("Recorded {frames} frames"),
+        Err(e) => log::error!("Unable to finish the recording: {e}"),
+      }
     }
   }
 
+  pub fn is_recording(&self) -> bool {
+    self.recorder.is_some()
+  }
+
+  /// Records the copy of `target_texture` to be read back after the `encoder` is submitted
+  fn capture(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+    if self.recorder.is_none() {
+      return;
+    }
+    let size = self.target_texture.tex().size();
+    let reader = match &mut self.frame_reader {
+      Some(reader) if reader.size() == size => reader,
+      reader => match FrameReader::new(device, size, self.format) {
+        Ok(new) => reader.insert(new),
+        Err(e) => {
+          log::error!("Unable to capture the frame, stopping the recording: {e}");
+          self.recorder = None;
+          return;
+        }
+      },
+    };
+    reader.copy(encoder, self.target_texture.tex());
+  }
+
+  /// Writes the frame captured by [`Self::capture`], the recording is stopped on errors
+  fn write_captured(&mut self, device: &wgpu::Device) {
+    let (Some(reader), Some(recorder)) = (&mut self.frame_reader, &mut self.recorder) else {
+      return;
+    };
+    let Some(frame) = reader.read(device) else {
+      return;
+    };
+    if let Err(e) = recorder.write(&frame) {
+      log::error!(
+        "Unable to write a frame to {}, stopping the recording: {e}",
+        recorder.dir().display()
+      );
+      self.recorder = None;
+    }
+  }
+
+  pub fn simulation(&self) -> &SphSimulation {
+    &self.simulation
+  }
+
+  pub fn simulation_mut(&mut self) -> &mut SphSimulation {
+    &mut self.simulation
+  }
+
+  fn write_globals(
+    &self,
+    queue: &wgpu::Queue,
+    size: egui::Vec2,
+    time: f32,
+    dt: f32,
+    camera: &Matrix4<f32>,
+  ) {
+    let buf_vec: Vec<u8> = [size.x, size.y, time, dt]
+      .as_bytes_buffer()
+      .iter()
+      .copied()
+      .chain(camera.as_bytes_buffer().to_owned())
+      .chain(self.projection.as_bytes_buffer().to_owned())
+      .collect();
+    queue.write_buffer(&self.global_buf, 0, &buf_vec);
+  }
+
+  /// Advances the simulation without rendering it, e.g. for the headless runs
+  pub fn step(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    params: &SimulationParams,
+    time: f32,
+    dt: f32,
+  ) {
+    self.write_globals(queue, self.size, time, dt, &Matrix4::identity());
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("Simulation step"),
+    });
+    self.simulation.step(
+      device,
+      queue,
+      &SimUpdateResources {
+        params,
+        global_group: &self.global_bind,
+        global_layout: &self.global_layout,
+        depth_stencil: &self.depth_state,
+        dt,
+      },
+      &mut encoder,
+    );
+    queue.submit([encoder.finish()]);
+  }
+
   pub fn create_raw(
     device: &wgpu::Device,
     format: &TextureFormat,
     queue: &wgpu::Queue,
+  ) -> PersistentState {
+    Self::create_with(
+      device,
+      format,
+      queue,
+      DEFAULT_COUNT,
+      Default::default(),
+      false,
+    )
+  }
+
+  /// Creates the state simulating `count` particles stored in the `storage` layout.
+  /// The fluid is not rendered if `headless` is set.
+  pub fn create_with(
+    device: &wgpu::Device,
+    format: &TextureFormat,
+    queue: &wgpu::Queue,
+    count: usize,
+    storage: StorageLayout,
+    headless: bool,
   ) -> PersistentState {
     let global_buf = device.create_buffer(&BufferDescriptor {
       label: None,
@@ -182,7 +352,10 @@ impl PersistentState {
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: *format,
-        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        usage: TextureUsages::RENDER_ATTACHMENT
+          | TextureUsages::TEXTURE_BINDING
+          | TextureUsages::COPY_SRC
+          | TextureUsages::COPY_DST,
         view_formats: vec![*format],
       },
     );
@@ -230,26 +403,34 @@ impl PersistentState {
       (),
     );
 
-    Self {
-      simulation: SphSimulation::init(
-        device,
-        queue,
-        &SimResources {
-          params: &Default::default(),
-          global_group: &global_bind,
-          global_layout: &global_layout,
-          depth_stencil: &depth_stencil,
-        },
-        format,
-        SimInit {
-          count: 8192,
-          size: egui::Vec2 {
-            x: 1200.0,
-            y: 800.0,
-          },
-          depth_state: &depth_stencil,
+    let tank = Tank::new(device, format, &global_layout, &depth_stencil);
+
+    let profiler = GpuProfiler::new(device, queue).map(Arc::new);
+    let mut simulation = SphSimulation::init(
+      device,
+      queue,
+      &SimResources {
+        params: &Default::default(),
+        global_group: &global_bind,
+        global_layout: &global_layout,
+        depth_stencil: &depth_stencil,
+      },
+      format,
+      SimInit {
+        count,
+        size: egui::Vec2 {
+          x: 1200.0,
+          y: 800.0,
         },
-      ),
+        depth_state: &depth_stencil,
+        storage,
+        headless,
+      },
+    );
+    simulation.set_profiler(profiler.clone());
+
+    Self {
+      simulation,
       global_bind,
       global_buf,
       global_layout,
@@ -257,14 +438,24 @@ impl PersistentState {
       format: *format,
       projection: cgmath::ortho(0.0, 200.0, 0.0, 400.0, 0.0, 30.0),
       gizmo,
+      tank,
       target_texture,
       depth_texture,
       depth_state: depth_stencil,
       texture_drawer,
+      profiler,
+      recorder: None,
+      frame_reader: None,
     }
   }
 }
 
+/// Starts or stops writing the rendered frames
+pub enum RecordCommand {
+  Start(Recorder),
+  Stop,
+}
+
 pub struct StateCallback {
   pub dt: f32,
   pub time: f32,
@@ -272,6 +463,15 @@ pub struct StateCallback {
   pub camera: Matrix4<f32>,
   pub size: egui::Vec2,
   pub new_blur: Mutex<Option<Box<dyn Blur + Send + Sync + 'static>>>,
+  pub new_sources: Mutex<Option<(Vec<Emitter>, Vec<Sink>)>>,
+  pub new_material: Mutex<Option<FluidMaterial>>,
+  pub new_environment: Mutex<Option<EnvironmentMap>>,
+  pub layers: SceneLayers,
+  /// Draw the particles instead of the fluid
+  pub particle_view: Option<ParticleViewSettings>,
+  /// Draw the extracted surface mesh instead of the fluid
+  pub surface: Option<SurfaceSettings>,
+  pub record: Mutex<Option<RecordCommand>>,
 }
 
 impl CallbackTrait for StateCallback {
@@ -306,19 +506,47 @@ impl CallbackTrait for StateCallback {
       unreachable!()
     };
     let size = self.size;
-    state.check_resize(self.size, device, self);
+    // Submitted with the previous frame
+    state.write_captured(device);
+    match self.record.lock().take() {
+      Some(RecordCommand::Start(recorder)) => state.start_recording(device, recorder),
+      Some(RecordCommand::Stop) => state.stop_recording(device),
+      None => {}
+    }
+    state.check_resize(self.size, device, queue, self);
 
-    let buf_vec: Vec<u8> = [size.x, size.y, self.time, self.dt]
-      .as_bytes_buffer()
-      .into_iter()
-      .copied()
-      .chain(self.camera.as_bytes_buffer().to_owned())
-      .chain(state.projection.as_bytes_buffer().to_owned())
-      .collect();
-    queue.write_buffer(&state.global_buf, 0, &buf_vec);
+    state.write_globals(queue, size, self.time, self.dt, &self.camera);
+    if let Some(profiler) = &state.profiler {
+      profiler.begin_frame(device);
+    }
     if let Some(b) = self.new_blur.lock().take() {
       state.simulation.set_blur(b, device, queue);
     }
+    if let Some((emitters, sinks)) = self.new_sources.lock().take() {
+      state.simulation.set_sources(emitters, sinks);
+    }
+    if let Some(material) = self.new_material.lock().take() {
+      state.simulation.set_material(material, queue);
+    }
+    if let Some(env) = self.new_environment.lock().take() {
+      if let Err(e) = state.simulation.set_environment(&env, device, queue) {
+        log::error!("Unable to set the environment map: {e}");
+      }
+    }
+    state
+      .simulation
+      .set_particle_view(self.particle_view, queue);
+    state.simulation.set_surface(self.surface);
+    state.tank.update(
+      device,
+      queue,
+      &TankResources {
+        global_group: &state.global_bind,
+        w: self.params.w,
+        layers: self.layers,
+      },
+      encoder,
+    );
 
     state.simulation.update(
       device,
@@ -332,12 +560,15 @@ impl CallbackTrait for StateCallback {
       },
       encoder,
     );
+    if let Some(profiler) = &state.profiler {
+      profiler.resolve(encoder);
+    }
     Vec::new()
   }
 
   fn finish_prepare(
     &self,
-    _device: &wgpu::Device,
+    device: &wgpu::Device,
     _queue: &wgpu::Queue,
     egui_encoder: &mut wgpu::CommandEncoder,
     callback_resources: &mut egui_wgpu::CallbackResources,
@@ -346,21 +577,75 @@ impl CallbackTrait for StateCallback {
     let Some(state) = callback_resources.get_mut::<PersistentState>() else {
       unreachable!()
     };
+    // The scene behind the fluid is drawn first, the merger refracts it.
+    // Without the fluid renderer nothing is drawn at all.
+    let background = state.simulation.background();
+    if let Some(background) = background {
+      let mut pass = egui_encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Background pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+          view: background,
+          resolve_target: None,
+          ops: Operations {
+            load: wgpu::LoadOp::Clear(Color::WHITE),
+            store: wgpu::StoreOp::Store,
+          },
+        })],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+          view: &state.depth_texture,
+          depth_ops: Some(Operations {
+            load: wgpu::LoadOp::Clear(1.0),
+            store: wgpu::StoreOp::Store,
+          }),
+          stencil_ops: None,
+        }),
+        timestamp_writes: None,
+        occlusion_query_set: None,
+      });
+      state.tank.render_into_pass(
+        &mut pass,
+        &TankResources {
+          global_group: &state.global_bind,
+          w: self.params.w,
+          layers: self.layers,
+        },
+      );
+      if self.layers.gizmo {
+        state.gizmo.render_into_pass(
+          &mut pass,
+          &GizmoResources {
+            global_group: &state.global_bind,
+            global_layout: &state.global_layout,
+            depth_stencil: &state.depth_state,
+          },
+        );
+      }
+      drop(pass);
+      egui_encoder.copy_texture_to_texture(
+        background.tex().as_image_copy(),
+        state.target_texture.tex().as_image_copy(),
+        background.tex().size(),
+      );
+    }
     {
+      let (color_load, depth_load) = match background {
+        Some(_) => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+        None => (wgpu::LoadOp::Clear(Color::WHITE), wgpu::LoadOp::Clear(1.0)),
+      };
       let mut pass = egui_encoder.begin_render_pass(&RenderPassDescriptor {
         label: Some("Target pass"),
         color_attachments: &[Some(RenderPassColorAttachment {
           view: &state.target_texture,
           resolve_target: None,
           ops: Operations {
-            load: wgpu::LoadOp::Clear(Color::WHITE),
+            load: color_load,
             store: wgpu::StoreOp::Store,
           },
         })],
         depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
           view: &state.depth_texture,
           depth_ops: Some(Operations {
-            load: wgpu::LoadOp::Clear(1.0),
+            load: depth_load,
             store: wgpu::StoreOp::Store,
           }),
           stencil_ops: None,
@@ -368,14 +653,6 @@ impl CallbackTrait for StateCallback {
         timestamp_writes: None,
         occlusion_query_set: None,
       });
-      // state.gizmo.render_into_pass(
-      //   &mut pass,
-      //   &GizmoResources {
-      //     global_group: &state.global_bind,
-      //     global_layout: &state.global_layout,
-      //     depth_stencil: &state.depth_state,
-      //   },
-      // );
       state.simulation.render_into_pass(
         &mut pass,
         &SimResources {
@@ -386,6 +663,7 @@ impl CallbackTrait for StateCallback {
         },
       );
     }
+    state.capture(device, egui_encoder);
 
     Vec::new()
   }
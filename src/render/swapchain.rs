@@ -45,10 +45,16 @@ impl<T: Clone + AsBuffer> SwapBuffers<T> {
   pub fn cur_buf(&self) -> &Buffer {
     &self.buf[self.cur]
   }
+  /// Copies the current buffer to the old one and exchanges them.
+  /// Not needed if the next pass rewrites the whole current buffer, see [`SwapBuffers::flip`].
   pub fn swap(&mut self, encoder: &mut CommandEncoder) {
     encoder.copy_buffer_to_buffer(self.cur_buf(), 0, self.old().0, 0, self.cur_size());
     self.cur = 1 - self.cur;
   }
+  /// Exchanges the current and the old buffers without copying
+  pub fn flip(&mut self) {
+    self.cur = 1 - self.cur;
+  }
   pub fn cur_size(&self) -> u64 {
     self.buf[self.cur].size()
   }
@@ -1,56 +1,85 @@
-use std::{ops::Deref, sync::Arc};
+use std::{io, ops::Deref, sync::Arc};
 
 use crate::{render::AsBuffer, with};
 
 use wgpu::{
   core::device::queue,
   util::{BufferInitDescriptor, DeviceExt},
-  vertex_attr_array, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+  AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
   BindGroupLayoutDescriptor, BindGroupLayoutEntry, Buffer, Color, DepthBiasState,
-  DepthStencilState, Extent3d, FragmentState, MultisampleState, RenderPassColorAttachment,
-  RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
-  ShaderStages, StencilFaceState, StencilState, TextureFormat, VertexBufferLayout,
+  DepthStencilState, Extent3d, FilterMode, FragmentState, MultisampleState,
+  RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+  RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerDescriptor, ShaderStages,
+  StencilFaceState, StencilState, Texture, TextureFormat, TextureView,
 };
 
 use crate::{
   render::{
+    blur::{Blur, CurvatureFlow, SmootherParams},
+    environment::EnvironmentMap,
+    material::FluidMaterial,
+    profiler::GpuProfiler,
     render_target::{ExternalResources, RenderTarget},
     texture_provider::{TextureProvider, TextureProviderDescriptor},
   },
-  solvers::sph_solver_gpu::Particle,
+  solvers::storage::StorageLayout,
 };
 
 use super::show_texture::{TextureDrawer, TextureDrawerInitRes, TextureDrawerResources};
 
-const PARTICLE_POS_BUFFER_LAYOUT: VertexBufferLayout = VertexBufferLayout {
-  array_stride: std::mem::size_of::<Particle>() as u64,
-  step_mode: wgpu::VertexStepMode::Instance,
-  attributes: &vertex_attr_array![0 => Float32x3, 1 => Float32],
-};
-
 pub struct FluidRenderer {
   spheres_zbuf: TextureProvider,
   zbuf_smoothed: TextureProvider,
   thickness: TextureProvider,
   normals_unsmoothed: TextureProvider,
   normals: TextureProvider,
+  /// Output of the horizontal pass of a separable smoother
+  zbuf_horizontal: TextureProvider,
   norusm_render: RenderPipeline,
   thickness_render: RenderPipeline,
   zbuf_smoother: TextureDrawer,
+  smoother_horizontal: TextureDrawer,
+  smoother_vertical: TextureDrawer,
   merger: TextureDrawer,
   merge_bgl: BindGroupLayout,
   merge_bg: BindGroup,
+  /// [`FluidMaterial::uniform`]
+  material_buf: Buffer,
+  /// The scene behind the fluid, drawn before the fluid and refracted by the merger
+  background: TextureProvider,
+  /// See [`EnvironmentMap`]
+  environment: TextureView,
+  env_sampler: Sampler,
   zbuf_smoother_bg: BindGroup,
+  /// Reads the output of the horizontal pass
+  smoother_vertical_bg: BindGroup,
   zbuf_smoother_bgl: BindGroupLayout,
   kernel_matrix: Vec<f32>,
+  /// The kernel is the horizontal and the vertical 1D kernels, see [`Blur::smoother_kernel`]
+  separable: bool,
+  /// Replaces the kernel smoother if set
+  curvature_flow: Option<CurvatureFlow>,
+  flow_step: TextureDrawer,
+  flow_bgl: BindGroupLayout,
+  /// Read `spheres_zbuf`, `zbuf_smoothed` and `zbuf_horizontal`
+  flow_bgs: [BindGroup; 3],
+  flow_params_buf: Buffer,
+  /// Reconstructs `normals` from `zbuf_smoothed`
+  normals_reconstruction: TextureDrawer,
+  normals_bgl: BindGroupLayout,
+  normals_bg: BindGroup,
   smoothing_kernel_buf: Buffer,
+  smoother_params_buf: Buffer,
+  profiler: Option<Arc<GpuProfiler>>,
 }
 
 pub struct FluidRendererResources<'a> {
   pub global_bg: &'a BindGroup,
   pub params_bg: &'a BindGroup,
   pub pos_buf: &'a wgpu::Buffer,
-  pub count: u32,
+  /// Indirect draw arguments with the count of active particles,
+  /// see [`crate::solvers::emitters::ParticleSources`]
+  pub count_buf: &'a wgpu::Buffer,
 }
 
 pub struct FluidRenderInit<'a> {
@@ -58,7 +87,14 @@ pub struct FluidRenderInit<'a> {
   pub global_layout: &'a BindGroupLayout,
   pub params_layout: &'a BindGroupLayout,
   pub depth_stencil_state: DepthStencilState,
+  /// See [`Blur::smoother_kernel`]
   pub smoother_matrix: Vec<f32>,
+  pub separable: bool,
+  pub smoother_params: SmootherParams,
+  pub curvature_flow: Option<CurvatureFlow>,
+  pub material: FluidMaterial,
+  /// Layout of the particle buffer drawn as the vertex buffer
+  pub storage: StorageLayout,
 }
 
 impl<'a> ExternalResources<'a> for FluidRendererResources<'a> {}
@@ -105,14 +141,17 @@ impl<'a> RenderTarget<'a> for FluidRenderer {
           }),
           stencil_ops: None,
         }),
-        timestamp_writes: None,
+        timestamp_writes: self
+          .profiler
+          .as_ref()
+          .and_then(|p| p.render_writes("Fluid spheres")),
         occlusion_query_set: None,
       });
       pass.set_pipeline(&self.norusm_render);
       pass.set_bind_group(0, resources.global_bg, &[]);
       pass.set_bind_group(1, resources.params_bg, &[]);
       pass.set_vertex_buffer(0, resources.pos_buf.slice(..));
-      pass.draw(0..3, 0..(resources.count));
+      pass.draw_indirect(resources.count_buf, 0);
     }
     {
       let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
@@ -126,51 +165,55 @@ impl<'a> RenderTarget<'a> for FluidRenderer {
           },
         })],
         depth_stencil_attachment: None,
-        timestamp_writes: None,
+        timestamp_writes: self
+          .profiler
+          .as_ref()
+          .and_then(|p| p.render_writes("Fluid thickness")),
         occlusion_query_set: None,
       });
       pass.set_pipeline(&self.thickness_render);
       pass.set_bind_group(0, resources.global_bg, &[]);
       pass.set_bind_group(1, resources.params_bg, &[]);
       pass.set_vertex_buffer(0, resources.pos_buf.slice(..));
-      pass.draw(0..3, 0..(resources.count));
+      pass.draw_indirect(resources.count_buf, 0);
     }
     // Smooth the depth buffer and build normal map
-    {
-      let mut zbuf_smoothing_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-        label: Some("zbuf_smoothing"),
-        color_attachments: &[Some(RenderPassColorAttachment {
-          view: &self.normals,
-          resolve_target: None,
-          ops: wgpu::Operations {
-            load: wgpu::LoadOp::Clear(Color::WHITE),
-            store: wgpu::StoreOp::Store,
-          },
-        })],
-        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-          view: &self.zbuf_smoothed,
-          depth_ops: Some(wgpu::Operations {
-            load: wgpu::LoadOp::Clear(1.0),
-            store: wgpu::StoreOp::Store,
-          }),
-          stencil_ops: None,
-        }),
-        timestamp_writes: None,
-        occlusion_query_set: None,
-      });
-      self.zbuf_smoother.render_into_pass(
-        &mut zbuf_smoothing_pass,
-        &TextureDrawerResources {
-          texture: &self.normals_unsmoothed,
-          bind_groups: &[&self.zbuf_smoother_bg, resources.global_bg],
-        },
+    if let Some(flow) = self.curvature_flow {
+      self.curvature_flow_passes(encoder, flow.iterations, resources.global_bg);
+    } else if self.separable {
+      self.smoothing_pass(
+        encoder,
+        "Fluid smoothing, horizontal",
+        &self.smoother_horizontal,
+        &self.zbuf_smoother_bg,
+        &self.zbuf_horizontal,
+        resources.global_bg,
+      );
+      self.smoothing_pass(
+        encoder,
+        "Fluid smoothing, vertical",
+        &self.smoother_vertical,
+        &self.smoother_vertical_bg,
+        &self.zbuf_smoothed,
+        resources.global_bg,
+      );
+    } else {
+      self.smoothing_pass(
+        encoder,
+        "Fluid smoothing",
+        &self.zbuf_smoother,
+        &self.zbuf_smoother_bg,
+        &self.zbuf_smoothed,
+        resources.global_bg,
       );
     }
+    self.normals_pass(encoder, resources.global_bg);
   }
 
   fn resized(
     &mut self,
     device: &wgpu::Device,
+    _queue: &wgpu::Queue,
     new_size: egui::Vec2,
     _resources: &'a Self::UpdateResources,
     _format: wgpu::TextureFormat,
@@ -185,24 +228,23 @@ impl<'a> RenderTarget<'a> for FluidRenderer {
     self.normals_unsmoothed.resize(device, new_size);
     self.thickness.resize(device, new_size);
     self.zbuf_smoothed.resize(device, new_size);
+    self.zbuf_horizontal.resize(device, new_size);
 
     self.merger.resized(device, &self.normals_unsmoothed);
     self.zbuf_smoother.resized(device, &self.normals_unsmoothed);
-    self.merge_bg = create_merge_bg(
-      device,
-      &self.zbuf_smoothed,
-      &self.normals,
-      &self.thickness,
-      &self.normals_unsmoothed,
-      &self.merge_bgl,
-    );
-    self.zbuf_smoother_bg = create_smoother_bg(
-      device,
-      &self.spheres_zbuf,
-      &self.thickness,
-      &self.zbuf_smoother_bgl,
-      &self.smoothing_kernel_buf,
-    );
+    self
+      .smoother_horizontal
+      .resized(device, &self.normals_unsmoothed);
+    self
+      .smoother_vertical
+      .resized(device, &self.normals_unsmoothed);
+    self.flow_step.resized(device, &self.normals_unsmoothed);
+    self
+      .normals_reconstruction
+      .resized(device, &self.normals_unsmoothed);
+    self.background.resize(device, new_size);
+    self.create_merge_bg(device);
+    self.create_smoother_bgs(device);
   }
 
   fn render_into_pass(&self, pass: &mut wgpu::RenderPass, resources: &'a Self::RenderResources) {
@@ -242,6 +284,8 @@ impl<'a> FluidRenderer {
 
     desc = with!(desc: label = Some("zbuf_smoothed".to_owned()), usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST);
     let zbuf_smoothed = TextureProvider::new(device, desc.clone());
+    desc = with!(desc: label = Some("zbuf_horizontal".to_owned()));
+    let zbuf_horizontal = TextureProvider::new(device, desc.clone());
     desc = with!(desc: label = Some("normals".to_owned()), format = TextureFormat::Rgba16Float, usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT);
     let normals = TextureProvider::new(device, desc.clone());
     desc = with!(desc: label = Some("normals_unsmoothed".to_owned()));
@@ -249,12 +293,18 @@ impl<'a> FluidRenderer {
     desc =
       with!(desc: label = Some("thickness".to_owned()), format = wgpu::TextureFormat::Rgba16Float);
     let thickness = TextureProvider::new(device, desc.clone());
+    desc = with!(desc: label = Some("background".to_owned()), format = *format, usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC);
+    let background = TextureProvider::new(device, desc.clone());
 
     let module = device.create_shader_module(wgpu::include_wgsl!("shaders/fluid/unsmoothed.wgsl"));
     let merge_module =
       device.create_shader_module(wgpu::include_wgsl!("shaders/fluid/merger.wgsl"));
     let smooth_module =
       device.create_shader_module(wgpu::include_wgsl!("shaders/fluid/zbuf-smoother.wgsl"));
+    let flow_module =
+      device.create_shader_module(wgpu::include_wgsl!("shaders/fluid/curvature-flow.wgsl"));
+    let normals_module =
+      device.create_shader_module(wgpu::include_wgsl!("shaders/fluid/normals.wgsl"));
 
     let render_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
       label: None,
@@ -300,15 +350,54 @@ impl<'a> FluidRenderer {
         with!(tex_bgle: binding = 1),       // normals
         with!(tex_bgle: binding = 2),       // normals_unsmoothed
         with!(tex_bgle: binding = 3),       // thickness
+        with!(tex_bgle: binding = 4),       // background
+        with!(tex_bgle: binding = 5),       // environment
+        BindGroupLayoutEntry {
+          binding: 6,
+          visibility: ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+          count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+          binding: 7,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
     });
+    let material_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("fluid material"),
+      contents: init_res.material.uniform().as_bytes_buffer(),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    // Filled by `set_environment`
+    let environment = create_environment(device, 1, 1).create_view(&Default::default());
+    let env_sampler = device.create_sampler(&SamplerDescriptor {
+      label: Some("environment sampler"),
+      // Around the vertical axis
+      address_mode_u: AddressMode::Repeat,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..Default::default()
+    });
     let merge_bg = create_merge_bg(
       device,
-      &zbuf_smoothed,
-      &normals,
-      &thickness,
-      &normals_unsmoothed,
       &merge_bgl,
+      [
+        &zbuf_smoothed,
+        &normals,
+        &normals_unsmoothed,
+        &thickness,
+        &background,
+        &environment,
+      ],
+      &env_sampler,
+      &material_buf,
     );
     let sphere_primitive = wgpu::PrimitiveState {
       topology: wgpu::PrimitiveTopology::TriangleList,
@@ -323,7 +412,7 @@ impl<'a> FluidRenderer {
       module: &module,
       entry_point: Some("vs_main"),
       compilation_options: Default::default(),
-      buffers: &[PARTICLE_POS_BUFFER_LAYOUT],
+      buffers: &[init_res.storage.pos_buffer_layout()],
     };
     let norusm_render = device.create_render_pipeline(&RenderPipelineDescriptor {
       label: Some("norusm"),
@@ -391,6 +480,12 @@ impl<'a> FluidRenderer {
         | wgpu::BufferUsages::COPY_DST,
     });
 
+    let smoother_params_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("smoother params"),
+      contents: init_res.smoother_params.as_bytes_buffer(),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
     let zbuf_smoother_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
       label: Some("zbuf_smoother_bg"),
       entries: &[
@@ -406,6 +501,16 @@ impl<'a> FluidRenderer {
           },
           count: None,
         },
+        wgpu::BindGroupLayoutEntry {
+          binding: 3,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
     });
     let zbuf_smoother_bg = create_smoother_bg(
@@ -414,24 +519,110 @@ impl<'a> FluidRenderer {
       &thickness,
       &zbuf_smoother_bgl,
       &smoothing_kernel_buf,
+      &smoother_params_buf,
     );
-    let zbuf_smoother = TextureDrawer::new(
+    let smoother_vertical_bg = create_smoother_bg(
+      device,
+      &zbuf_horizontal,
+      &thickness,
+      &zbuf_smoother_bgl,
+      &smoothing_kernel_buf,
+      &smoother_params_buf,
+    );
+    let smoother_depth = with!(depth_stencil_state: depth_compare = wgpu::CompareFunction::Always);
+    let create_smoother = |entry_point, bg| {
+      TextureDrawer::new(
+        device,
+        &TextureDrawerResources {
+          texture: &normals_unsmoothed,
+          bind_groups: &[bg],
+        },
+        format,
+        TextureDrawerInitRes {
+          stencil: Some(smoother_depth.clone()),
+          fragment: Some(FragmentState {
+            module: &smooth_module,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            targets: &[],
+          }),
+          layout: &[zbuf_smoother_bgl.clone(), init_res.global_layout.clone()],
+          unclipped_depth: true,
+        },
+      )
+    };
+    let zbuf_smoother = create_smoother("fs_main", &zbuf_smoother_bg);
+    let smoother_horizontal = create_smoother("fs_horizontal", &zbuf_smoother_bg);
+    let smoother_vertical = create_smoother("fs_vertical", &smoother_vertical_bg);
+
+    let flow_params_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("curvature flow params"),
+      contents: flow_params(init_res.curvature_flow).as_bytes_buffer(),
+      usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let flow_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("curvature_flow_bg"),
+      entries: &[
+        with!(tex_bgle: ty = tex_ty_depth),
+        wgpu::BindGroupLayoutEntry {
+          binding: 1,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+    });
+    let flow_bgs = create_flow_bgs(
+      device,
+      [&spheres_zbuf, &zbuf_smoothed, &zbuf_horizontal],
+      &flow_bgl,
+      &flow_params_buf,
+    );
+    let flow_step = TextureDrawer::new(
+      device,
+      &TextureDrawerResources {
+        texture: &normals_unsmoothed,
+        bind_groups: &[&flow_bgs[0]],
+      },
+      format,
+      TextureDrawerInitRes {
+        stencil: Some(smoother_depth.clone()),
+        fragment: Some(FragmentState {
+          module: &flow_module,
+          entry_point: Some("fs_flow"),
+          compilation_options: Default::default(),
+          targets: &[],
+        }),
+        layout: &[flow_bgl.clone(), init_res.global_layout.clone()],
+        unclipped_depth: true,
+      },
+    );
+    let normals_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("normals_bg"),
+      entries: &[with!(tex_bgle: ty = tex_ty_depth)],
+    });
+    let normals_bg = create_normals_bg(device, &zbuf_smoothed, &normals_bgl);
+    let normals_reconstruction = TextureDrawer::new(
       device,
       &TextureDrawerResources {
         texture: &normals_unsmoothed,
-        bind_groups: &[&zbuf_smoother_bg],
+        bind_groups: &[&normals_bg],
       },
       format,
       TextureDrawerInitRes {
-        stencil: Some(with!(depth_stencil_state: depth_compare = wgpu::CompareFunction::Always)),
+        stencil: None,
         fragment: Some(FragmentState {
-          module: &smooth_module,
+          module: &normals_module,
           entry_point: None,
           compilation_options: Default::default(),
           targets: &[Some(normals.color_target())],
         }),
-        layout: &[zbuf_smoother_bgl.clone(), init_res.global_layout.clone()],
-        unclipped_depth: true,
+        layout: &[normals_bgl.clone(), init_res.global_layout.clone()],
+        unclipped_depth: false,
       },
     );
     let merger = TextureDrawer::new(
@@ -468,19 +659,111 @@ impl<'a> FluidRenderer {
       thickness,
       normals_unsmoothed,
       normals,
+      zbuf_horizontal,
       norusm_render,
       thickness_render,
       zbuf_smoother,
+      smoother_horizontal,
+      smoother_vertical,
       merger,
       merge_bgl,
       merge_bg,
+      material_buf,
+      background,
+      environment,
+      env_sampler,
       zbuf_smoother_bg,
+      smoother_vertical_bg,
       zbuf_smoother_bgl,
       smoothing_kernel_buf,
+      smoother_params_buf,
       kernel_matrix: init_res.smoother_matrix,
+      separable: init_res.separable,
+      curvature_flow: init_res.curvature_flow,
+      flow_step,
+      flow_bgl,
+      flow_bgs,
+      flow_params_buf,
+      normals_reconstruction,
+      normals_bgl,
+      normals_bg,
+      profiler: None,
     }
   }
 
+  /// Measures the passes of the renderer with the `profiler`
+  pub fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>) {
+    self.profiler = profiler;
+  }
+
+  /// Uploads the kernel and the filter of the `blur`
+  pub fn set_blur(&mut self, blur: &dyn Blur, device: &wgpu::Device, queue: &wgpu::Queue) {
+    self.separable = blur.is_separable();
+    self.curvature_flow = blur.curvature_flow();
+    queue.write_buffer(
+      &self.flow_params_buf,
+      0,
+      flow_params(self.curvature_flow).as_bytes_buffer(),
+    );
+    self.set_smoother_params(blur.smoother_params(), queue);
+    self.set_kernel(blur.smoother_kernel(), device, queue);
+  }
+
+  /// Changes the filter the kernel is applied with
+  pub fn set_smoother_params(&self, params: SmootherParams, queue: &wgpu::Queue) {
+    queue.write_buffer(&self.smoother_params_buf, 0, params.as_bytes_buffer());
+  }
+
+  pub fn set_material(&self, material: &FluidMaterial, queue: &wgpu::Queue) {
+    queue.write_buffer(&self.material_buf, 0, material.uniform().as_bytes_buffer());
+  }
+
+  /// Replaces the environment map reflected by the fluid
+  /// Keeps the old map if the `env` doesn't fit into a texture
+  pub fn set_environment(
+    &mut self,
+    env: &EnvironmentMap,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) -> io::Result<()> {
+    env.check_size(device.limits().max_texture_dimension_2d)?;
+    let texture = create_environment(device, env.width, env.height);
+    queue.write_texture(
+      texture.as_image_copy(),
+      env.half_floats().as_slice().as_bytes_buffer(),
+      wgpu::TexelCopyBufferLayout {
+        offset: 0,
+        bytes_per_row: Some(4 * size_of::<u16>() as u32 * env.width),
+        rows_per_image: None,
+      },
+      texture.size(),
+    );
+    self.environment = texture.create_view(&Default::default());
+    self.create_merge_bg(device);
+    Ok(())
+  }
+
+  pub fn background(&self) -> &TextureProvider {
+    &self.background
+  }
+
+  fn create_merge_bg(&mut self, device: &wgpu::Device) {
+    self.merge_bg = create_merge_bg(
+      device,
+      &self.merge_bgl,
+      [
+        &self.zbuf_smoothed,
+        &self.normals,
+        &self.normals_unsmoothed,
+        &self.thickness,
+        &self.background,
+        &self.environment,
+      ],
+      &self.env_sampler,
+      &self.material_buf,
+    );
+  }
+
   pub fn set_kernel(&mut self, mat: Vec<f32>, device: &wgpu::Device, queue: &wgpu::Queue) {
     if mat.len() != self.kernel_matrix.len() {
       log::debug!("Set kernel, new len");
@@ -492,13 +775,7 @@ impl<'a> FluidRenderer {
           | wgpu::BufferUsages::STORAGE,
       });
       self.kernel_matrix = mat.clone();
-      self.zbuf_smoother_bg = create_smoother_bg(
-        device,
-        &self.spheres_zbuf,
-        &self.thickness,
-        &self.zbuf_smoother_bgl,
-        &self.smoothing_kernel_buf,
-      );
+      self.create_smoother_bgs(device);
     } else {
       log::debug!("Set kernel, old len");
       self.kernel_matrix = mat.clone();
@@ -509,6 +786,184 @@ impl<'a> FluidRenderer {
       );
     }
   }
+
+  /// Smooths `spheres_zbuf` into `zbuf_smoothed` with the curvature flow,
+  /// ping-ponging with `zbuf_horizontal`
+  fn curvature_flow_passes(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    iterations: u32,
+    global_bg: &BindGroup,
+  ) {
+    let [from_spheres, from_smoothed, from_horizontal] = &self.flow_bgs;
+    for i in 0..iterations {
+      // The last iteration writes `zbuf_smoothed`
+      let to_smoothed = (iterations - i) % 2 == 1;
+      let (source, target) = match (i, to_smoothed) {
+        (0, true) => (from_spheres, &self.zbuf_smoothed),
+        (0, false) => (from_spheres, &self.zbuf_horizontal),
+        (_, true) => (from_horizontal, &self.zbuf_smoothed),
+        (_, false) => (from_smoothed, &self.zbuf_horizontal),
+      };
+      let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("curvature_flow"),
+        color_attachments: &[],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+          view: target,
+          depth_ops: Some(wgpu::Operations {
+            load: wgpu::LoadOp::Clear(1.0),
+            store: wgpu::StoreOp::Store,
+          }),
+          stencil_ops: None,
+        }),
+        // Too many passes to measure each
+        timestamp_writes: None,
+        occlusion_query_set: None,
+      });
+      self.flow_step.render_into_pass(
+        &mut pass,
+        &TextureDrawerResources {
+          texture: &self.normals_unsmoothed,
+          bind_groups: &[source, global_bg],
+        },
+      );
+    }
+  }
+
+  /// Reconstructs `normals` from `zbuf_smoothed`
+  fn normals_pass(&self, encoder: &mut wgpu::CommandEncoder, global_bg: &BindGroup) {
+    let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+      label: Some("normals_reconstruction"),
+      color_attachments: &[Some(RenderPassColorAttachment {
+        view: &self.normals,
+        resolve_target: None,
+        ops: wgpu::Operations {
+          load: wgpu::LoadOp::Clear(Color::WHITE),
+          store: wgpu::StoreOp::Store,
+        },
+      })],
+      depth_stencil_attachment: None,
+      timestamp_writes: self
+        .profiler
+        .as_ref()
+        .and_then(|p| p.render_writes("Fluid normals")),
+      occlusion_query_set: None,
+    });
+    self.normals_reconstruction.render_into_pass(
+      &mut pass,
+      &TextureDrawerResources {
+        texture: &self.normals_unsmoothed,
+        bind_groups: &[&self.normals_bg, global_bg],
+      },
+    );
+  }
+
+  fn create_smoother_bgs(&mut self, device: &wgpu::Device) {
+    self.zbuf_smoother_bg = create_smoother_bg(
+      device,
+      &self.spheres_zbuf,
+      &self.thickness,
+      &self.zbuf_smoother_bgl,
+      &self.smoothing_kernel_buf,
+      &self.smoother_params_buf,
+    );
+    self.smoother_vertical_bg = create_smoother_bg(
+      device,
+      &self.zbuf_horizontal,
+      &self.thickness,
+      &self.zbuf_smoother_bgl,
+      &self.smoothing_kernel_buf,
+      &self.smoother_params_buf,
+    );
+    self.flow_bgs = create_flow_bgs(
+      device,
+      [
+        &self.spheres_zbuf,
+        &self.zbuf_smoothed,
+        &self.zbuf_horizontal,
+      ],
+      &self.flow_bgl,
+      &self.flow_params_buf,
+    );
+    self.normals_bg = create_normals_bg(device, &self.zbuf_smoothed, &self.normals_bgl);
+  }
+
+  /// Runs a `smoother` reading the depth of the `bg`, writing the depth to the `zbuf`
+  fn smoothing_pass(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    label: &'static str,
+    smoother: &TextureDrawer,
+    bg: &BindGroup,
+    zbuf: &TextureProvider,
+    global_bg: &BindGroup,
+  ) {
+    let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+      label: Some(label),
+      color_attachments: &[],
+      depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+        view: zbuf,
+        depth_ops: Some(wgpu::Operations {
+          load: wgpu::LoadOp::Clear(1.0),
+          store: wgpu::StoreOp::Store,
+        }),
+        stencil_ops: None,
+      }),
+      timestamp_writes: self.profiler.as_ref().and_then(|p| p.render_writes(label)),
+      occlusion_query_set: None,
+    });
+    smoother.render_into_pass(
+      &mut pass,
+      &TextureDrawerResources {
+        texture: &self.normals_unsmoothed,
+        bind_groups: &[bg, global_bg],
+      },
+    );
+  }
+}
+
+/// Uniform of the curvature flow shader
+fn flow_params(flow: Option<CurvatureFlow>) -> [f32; 4] {
+  [flow.map_or(0.0, |flow| flow.dt), 0.0, 0.0, 0.0]
+}
+
+fn create_normals_bg(
+  device: &wgpu::Device,
+  zbuf_smoothed: &TextureProvider,
+  normals_bgl: &BindGroupLayout,
+) -> BindGroup {
+  device.create_bind_group(&BindGroupDescriptor {
+    label: Some("normals_bg"),
+    layout: normals_bgl,
+    entries: &[BindGroupEntry {
+      binding: 0,
+      resource: wgpu::BindingResource::TextureView(zbuf_smoothed),
+    }],
+  })
+}
+
+fn create_flow_bgs(
+  device: &wgpu::Device,
+  zbufs: [&TextureProvider; 3],
+  flow_bgl: &BindGroupLayout,
+  params_buf: &Buffer,
+) -> [BindGroup; 3] {
+  zbufs.map(|zbuf| {
+    device.create_bind_group(&BindGroupDescriptor {
+      label: Some("curvature_flow_bg"),
+      layout: flow_bgl,
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(zbuf),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: params_buf.as_entire_binding(),
+        },
+      ],
+    })
+  })
 }
 
 fn create_smoother_bg(
@@ -517,6 +972,7 @@ fn create_smoother_bg(
   thickness: &TextureProvider,
   zbuf_smoother_bgl: &BindGroupLayout,
   kernel_buf: &Buffer,
+  params_buf: &Buffer,
 ) -> BindGroup {
   let zbuf_smoother_bg = device.create_bind_group(&BindGroupDescriptor {
     label: Some("zbuf_smoothed_bg"),
@@ -538,39 +994,60 @@ fn create_smoother_bg(
           size: None,
         }),
       },
+      BindGroupEntry {
+        binding: 3,
+        resource: params_buf.as_entire_binding(),
+      },
     ],
   });
   zbuf_smoother_bg
 }
 
+/// `textures` are bound in the order of the bindings
 fn create_merge_bg(
   device: &wgpu::Device,
-  zbuf_smoothed: &TextureProvider,
-  normals: &TextureProvider,
-  thickness: &TextureProvider,
-  normals_unsmoothed: &TextureProvider,
   merge_bgl: &BindGroupLayout,
+  textures: [&TextureView; 6],
+  env_sampler: &Sampler,
+  material_buf: &Buffer,
 ) -> BindGroup {
+  let mut entries: Vec<BindGroupEntry> = textures
+    .into_iter()
+    .zip(0..)
+    .map(|(view, binding)| BindGroupEntry {
+      binding,
+      resource: wgpu::BindingResource::TextureView(view),
+    })
+    .collect();
+  entries.push(BindGroupEntry {
+    binding: 6,
+    resource: wgpu::BindingResource::Sampler(env_sampler),
+  });
+  entries.push(BindGroupEntry {
+    binding: 7,
+    resource: material_buf.as_entire_binding(),
+  });
   device.create_bind_group(&BindGroupDescriptor {
     label: Some("Merge BG"),
     layout: merge_bgl,
-    entries: &[
-      BindGroupEntry {
-        binding: 0,
-        resource: wgpu::BindingResource::TextureView(zbuf_smoothed),
-      },
-      BindGroupEntry {
-        binding: 1,
-        resource: wgpu::BindingResource::TextureView(normals),
-      },
-      BindGroupEntry {
-        binding: 2,
-        resource: wgpu::BindingResource::TextureView(normals_unsmoothed),
-      },
-      BindGroupEntry {
-        binding: 3,
-        resource: wgpu::BindingResource::TextureView(thickness),
-      },
-    ],
+    entries: &entries,
+  })
+}
+
+/// Empty `Rgba16Float` texture of an environment map
+fn create_environment(device: &wgpu::Device, width: u32, height: u32) -> Texture {
+  device.create_texture(&wgpu::TextureDescriptor {
+    label: Some("environment"),
+    size: Extent3d {
+      width,
+      height,
+      depth_or_array_layers: 1,
+    },
+    mip_level_count: 1,
+    sample_count: 1,
+    dimension: wgpu::TextureDimension::D2,
+    format: TextureFormat::Rgba16Float,
+    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    view_formats: &[],
   })
 }
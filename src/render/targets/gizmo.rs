@@ -12,7 +12,8 @@ use crate::render::{
 
 pub struct Gizmo {
   pipeline: wgpu::RenderPipeline,
-  outline_pipeline: wgpu::RenderPipeline,
+  /// `None` if [`wgpu::Features::POLYGON_MODE_LINE`] is not supported
+  outline_pipeline: Option<wgpu::RenderPipeline>,
   vertex_buf: wgpu::Buffer,
   index_buf: wgpu::Buffer,
 }
@@ -110,46 +111,51 @@ impl<'a> RenderTarget<'a> for Gizmo {
       multiview: None,
       cache: None,
     });
-    let outline_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-      label: Some("Gizmo render pipeline"),
-      layout: Some(&pipeline_layout),
-      vertex: wgpu::VertexState {
-        module: &shader,
-        entry_point: None,
-        compilation_options: Default::default(),
-        buffers: &[wgpu::VertexBufferLayout {
-          array_stride: 3 * std::mem::size_of::<f32>() as u64,
-          step_mode: wgpu::VertexStepMode::Vertex,
-          attributes: &vertex_attr_array![0 => Float32x3],
-        }],
-      },
-      primitive: wgpu::PrimitiveState {
-        topology: wgpu::PrimitiveTopology::TriangleStrip,
-        strip_index_format: None,
-        front_face: wgpu::FrontFace::Ccw,
-        cull_mode: None,
-        unclipped_depth: false,
-        polygon_mode: wgpu::PolygonMode::Line,
-        conservative: false,
-      },
-      depth_stencil: Some(resources.depth_stencil.clone()),
-      multisample: wgpu::MultisampleState {
-        count: 1,
-        mask: !0,
-        alpha_to_coverage_enabled: false,
-      },
-      fragment: Some(wgpu::FragmentState {
-        module: &shader,
-        entry_point: Some("fs_outline"),
-        compilation_options: Default::default(),
-        targets: &[Some(wgpu::ColorTargetState {
-          format: *format,
-          blend: Some(wgpu::BlendState::REPLACE),
-          write_mask: wgpu::ColorWrites::all(),
-        })],
-      }),
-      multiview: None,
-      cache: None,
+    let line_mode = device
+      .features()
+      .contains(wgpu::Features::POLYGON_MODE_LINE);
+    let outline_pipeline = line_mode.then(|| {
+      device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Gizmo render pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+          module: &shader,
+          entry_point: None,
+          compilation_options: Default::default(),
+          buffers: &[wgpu::VertexBufferLayout {
+            array_stride: 3 * std::mem::size_of::<f32>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &vertex_attr_array![0 => Float32x3],
+          }],
+        },
+        primitive: wgpu::PrimitiveState {
+          topology: wgpu::PrimitiveTopology::TriangleStrip,
+          strip_index_format: None,
+          front_face: wgpu::FrontFace::Ccw,
+          cull_mode: None,
+          unclipped_depth: false,
+          polygon_mode: wgpu::PolygonMode::Line,
+          conservative: false,
+        },
+        depth_stencil: Some(resources.depth_stencil.clone()),
+        multisample: wgpu::MultisampleState {
+          count: 1,
+          mask: !0,
+          alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(wgpu::FragmentState {
+          module: &shader,
+          entry_point: Some("fs_outline"),
+          compilation_options: Default::default(),
+          targets: &[Some(wgpu::ColorTargetState {
+            format: *format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::all(),
+          })],
+        }),
+        multiview: None,
+        cache: None,
+      })
     });
 
     Self {
@@ -167,11 +173,13 @@ impl<'a> RenderTarget<'a> for Gizmo {
     pass.set_bind_group(0, resources.global_group, &[]);
     pass.draw_indexed(0..6, 0, 0..3);
 
-    pass.set_pipeline(&self.outline_pipeline);
-    pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
-    pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
-    pass.set_bind_group(0, resources.global_group, &[]);
-    pass.draw_indexed(0..6, 0, 0..3);
+    if let Some(outline_pipeline) = &self.outline_pipeline {
+      pass.set_pipeline(outline_pipeline);
+      pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+      pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
+      pass.set_bind_group(0, resources.global_group, &[]);
+      pass.draw_indexed(0..6, 0, 0..3);
+    }
   }
 
   fn update(
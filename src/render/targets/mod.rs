@@ -1,4 +1,7 @@
 pub mod fluid_renderer;
 pub mod gizmo;
+pub mod particle_view;
 pub mod show_texture;
 pub mod simulation;
+pub mod surface_renderer;
+pub mod tank;
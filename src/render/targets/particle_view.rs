@@ -0,0 +1,409 @@
+use core::slice;
+
+use serde::{Deserialize, Serialize};
+use wgpu::{
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+  DepthStencilState, PipelineLayoutDescriptor, RenderPipeline, ShaderStages,
+};
+
+use crate::{
+  render::{
+    colormap::{Colormap, LUT_SIZE},
+    render_target::{ExternalResources, RenderTarget},
+    swapchain::SwapBuffers,
+    AsBuffer,
+  },
+  solvers::storage::{ParticleData, StorageLayout},
+};
+
+use super::simulation::SimulationParams;
+
+// This constant **must** be kept the same as in `particle-colors.wgsl`
+const WG_SIZE: u32 = 64;
+
+#[cfg(test)]
+mod test {
+  use super::{ParticleAttribute, ParticleViewSettings, ViewParams};
+  use crate::render::targets::simulation::SimulationParams;
+
+  #[test]
+  fn default_ranges() {
+    let params = SimulationParams::default();
+    let [min, max] = ParticleAttribute::Pressure.default_range(&params, 100);
+    assert_eq!(min, -max);
+    assert_eq!(
+      ParticleAttribute::Index.default_range(&params, 100),
+      [0.0, 99.0]
+    );
+    for attribute in ParticleAttribute::ALL {
+      let [min, max] = attribute.default_range(&params, 100);
+      assert!(min < max, "{}", attribute.name());
+    }
+  }
+
+  #[test]
+  fn uniform_layout() {
+    let settings = ParticleViewSettings {
+      attribute: ParticleAttribute::Speed,
+      range: [1.0, 3.0],
+      ..Default::default()
+    };
+    let u = ViewParams::new(&settings);
+    assert_eq!(u.attr, 2);
+    assert_eq!([u.min, u.max], [1.0, 3.0]);
+    assert_eq!(std::mem::size_of::<ViewParams>(), 16 + 16 * 256);
+  }
+}
+
+/// Scalar of the particles mapped to the colours of the [`ParticleView`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParticleAttribute {
+  #[default]
+  Density,
+  /// `k (ρ - ρ₀)`, as computed by the solver
+  Pressure,
+  Speed,
+  /// Magnitude of the forces of the last step
+  Force,
+  /// Position of the particle in the buffer, shows the sort order
+  Index,
+  /// Number of the other particles closer than `h`
+  Neighbours,
+}
+
+impl ParticleAttribute {
+  pub const ALL: [ParticleAttribute; 6] = [
+    ParticleAttribute::Density,
+    ParticleAttribute::Pressure,
+    ParticleAttribute::Speed,
+    ParticleAttribute::Force,
+    ParticleAttribute::Index,
+    ParticleAttribute::Neighbours,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ParticleAttribute::Density => "Density",
+      ParticleAttribute::Pressure => "Pressure",
+      ParticleAttribute::Speed => "Speed",
+      ParticleAttribute::Force => "Force",
+      ParticleAttribute::Index => "Index",
+      ParticleAttribute::Neighbours => "Neighbours",
+    }
+  }
+
+  /// The range showing the usual values, `count` is the capacity of the particle buffers
+  pub fn default_range(self, params: &SimulationParams, count: usize) -> [f32; 2] {
+    match self {
+      ParticleAttribute::Density => [0.0, 2.0 * params.rho0],
+      // 10% of compression either way
+      ParticleAttribute::Pressure => [-0.1 * params.k * params.rho0, 0.1 * params.k * params.rho0],
+      ParticleAttribute::Speed => [0.0, 2.0],
+      ParticleAttribute::Force => [0.0, 50.0],
+      ParticleAttribute::Index => [0.0, count.saturating_sub(1).max(1) as f32],
+      ParticleAttribute::Neighbours => [0.0, 60.0],
+    }
+  }
+}
+
+/// What the [`ParticleView`] shows
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParticleViewSettings {
+  pub attribute: ParticleAttribute,
+  pub colormap: Colormap,
+  /// Values of the attribute mapped to the ends of the colormap, the rest is clamped
+  pub range: [f32; 2],
+  /// Radius of the sprites relative to `h`
+  pub size: f32,
+}
+
+impl Default for ParticleViewSettings {
+  fn default() -> Self {
+    let attribute = ParticleAttribute::default();
+    Self {
+      attribute,
+      colormap: Default::default(),
+      range: attribute.default_range(&Default::default(), 0),
+      size: 0.5,
+    }
+  }
+}
+
+/// The `ViewParams` of the shaders
+#[repr(C)]
+struct ViewParams {
+  attr: u32,
+  min: f32,
+  max: f32,
+  size: f32,
+  lut: [[f32; 4]; LUT_SIZE],
+}
+
+impl ViewParams {
+  fn new(settings: &ParticleViewSettings) -> Self {
+    Self {
+      attr: ParticleAttribute::ALL
+        .iter()
+        .position(|&a| a == settings.attribute)
+        .unwrap() as u32,
+      min: settings.range[0],
+      max: settings.range[1],
+      size: settings.size,
+      lut: settings.colormap.lut(),
+    }
+  }
+}
+
+impl AsBuffer for ViewParams {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<ViewParams>(),
+      )
+    }
+  }
+}
+
+/// Draws the particles as shaded spheres coloured by one of their [`ParticleAttribute`]s,
+/// instead of the fluid surface
+pub struct ParticleView {
+  colorize: ComputePipeline,
+  sprites: RenderPipeline,
+  view_buf: Buffer,
+  /// Colour of every particle, written by `colorize` and read as a vertex buffer
+  colors: Buffer,
+  colorize_bg: BindGroup,
+  sprites_bg: BindGroup,
+  capacity: u32,
+  settings: Option<ParticleViewSettings>,
+}
+
+pub struct ParticleViewResources<'a> {
+  pub global_bg: &'a BindGroup,
+  pub params_bg: &'a BindGroup,
+  pub particles: &'a SwapBuffers<ParticleData>,
+  /// See [`crate::solvers::emitters::ParticleSources::count_buf`]
+  pub count_buf: &'a Buffer,
+}
+
+impl<'a> ExternalResources<'a> for ParticleViewResources<'a> {}
+
+pub struct ParticleViewInit<'a> {
+  pub capacity: u32,
+  pub global_layout: &'a wgpu::BindGroupLayout,
+  pub params_layout: &'a wgpu::BindGroupLayout,
+  pub particle_layout: &'a wgpu::BindGroupLayout,
+  pub count_buf: &'a Buffer,
+  pub depth_stencil: &'a DepthStencilState,
+  pub storage: StorageLayout,
+}
+
+impl ParticleView {
+  pub fn new(device: &wgpu::Device, format: &wgpu::TextureFormat, init: ParticleViewInit) -> Self {
+    let view_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particle view params"),
+      size: std::mem::size_of::<ViewParams>() as u64,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let colors = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particle colors"),
+      size: (init.capacity.max(1) as usize * 4 * std::mem::size_of::<f32>()) as u64,
+      usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
+      mapped_at_creation: false,
+    });
+
+    let entry = |binding, visibility, ty| BindGroupLayoutEntry {
+      binding,
+      visibility,
+      ty: wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let colorize_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Particle colorize layout"),
+      entries: &[
+        entry(0, ShaderStages::COMPUTE, wgpu::BufferBindingType::Uniform),
+        entry(
+          1,
+          ShaderStages::COMPUTE,
+          wgpu::BufferBindingType::Storage { read_only: true },
+        ),
+        entry(
+          2,
+          ShaderStages::COMPUTE,
+          wgpu::BufferBindingType::Storage { read_only: false },
+        ),
+      ],
+    });
+    let colorize_bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Particle colorize"),
+      layout: &colorize_layout,
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: view_buf.as_entire_binding(),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: init.count_buf.as_entire_binding(),
+        },
+        BindGroupEntry {
+          binding: 2,
+          resource: colors.as_entire_binding(),
+        },
+      ],
+    });
+    // The colors can't be bound as a storage buffer while they are the vertex buffer
+    let sprites_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Particle sprites layout"),
+      entries: &[entry(
+        0,
+        ShaderStages::VERTEX_FRAGMENT,
+        wgpu::BufferBindingType::Uniform,
+      )],
+    });
+    let sprites_bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Particle sprites"),
+      layout: &sprites_layout,
+      entries: &[BindGroupEntry {
+        binding: 0,
+        resource: view_buf.as_entire_binding(),
+      }],
+    });
+
+    let module = init.storage.create_shader_module(
+      device,
+      "particle-colors.wgsl",
+      include_str!("shaders/particle-colors.wgsl"),
+    );
+    let colorize = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("ParticleView::colorize"),
+      layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Particle colorize"),
+        bind_group_layouts: &[init.particle_layout, init.params_layout, &colorize_layout],
+        push_constant_ranges: &[],
+      })),
+      module: &module,
+      entry_point: Some("colorize"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+
+    let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/particle-sprites.wgsl"));
+    let color_attributes = wgpu::vertex_attr_array![2 => Float32x4];
+    let sprites = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Particle sprites pipeline"),
+      layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Particle sprites"),
+        bind_group_layouts: &[init.global_layout, init.params_layout, &sprites_layout],
+        push_constant_ranges: &[],
+      })),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        compilation_options: Default::default(),
+        buffers: &[
+          init.storage.pos_buffer_layout(),
+          wgpu::VertexBufferLayout {
+            array_stride: 4 * std::mem::size_of::<f32>() as u64,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &color_attributes,
+          },
+        ],
+      },
+      primitive: wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: None,
+        unclipped_depth: false,
+        polygon_mode: wgpu::PolygonMode::Fill,
+        conservative: false,
+      },
+      depth_stencil: Some(init.depth_stencil.clone()),
+      multisample: wgpu::MultisampleState {
+        count: 1,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        compilation_options: Default::default(),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: *format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::all(),
+        })],
+      }),
+      multiview: None,
+      cache: None,
+    });
+
+    Self {
+      colorize,
+      sprites,
+      view_buf,
+      colors,
+      colorize_bg,
+      sprites_bg,
+      capacity: init.capacity,
+      settings: None,
+    }
+  }
+
+  /// Shows the particles with the `settings`, or the fluid again if `None`
+  pub fn set_settings(&mut self, settings: Option<ParticleViewSettings>, queue: &wgpu::Queue) {
+    if let Some(new) = settings.filter(|&s| self.settings != Some(s)) {
+      queue.write_buffer(&self.view_buf, 0, ViewParams::new(&new).as_bytes_buffer());
+    }
+    self.settings = settings;
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.settings.is_some()
+  }
+}
+
+impl<'a> RenderTarget<'a> for ParticleView {
+  type RenderResources = ParticleViewResources<'a>;
+
+  /// Computes the colours of the particles of the current step
+  fn update(
+    &mut self,
+    _device: &wgpu::Device,
+    _queue: &wgpu::Queue,
+    resources: &'a Self::RenderResources,
+    encoder: &mut wgpu::CommandEncoder,
+  ) {
+    if !self.is_enabled() {
+      return;
+    }
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+      label: Some("ParticleView::colorize"),
+      timestamp_writes: None,
+    });
+    pass.set_pipeline(&self.colorize);
+    pass.set_bind_group(0, resources.particles.cur_group(), &[]);
+    pass.set_bind_group(1, resources.params_bg, &[]);
+    pass.set_bind_group(2, &self.colorize_bg, &[]);
+    pass.dispatch_workgroups(self.capacity.div_ceil(WG_SIZE), 1, 1);
+  }
+
+  fn render_into_pass(&self, pass: &mut wgpu::RenderPass, resources: &'a Self::RenderResources) {
+    pass.set_pipeline(&self.sprites);
+    pass.set_bind_group(0, resources.global_bg, &[]);
+    pass.set_bind_group(1, resources.params_bg, &[]);
+    pass.set_bind_group(2, &self.sprites_bg, &[]);
+    pass.set_vertex_buffer(0, resources.particles.cur_buf().slice(..));
+    pass.set_vertex_buffer(1, self.colors.slice(..));
+    pass.draw_indirect(resources.count_buf, 0);
+  }
+}
@@ -0,0 +1,88 @@
+// Screen-space curvature flow, "Screen Space Fluid Rendering with Curvature Flow"
+// by van der Laan, Green and Sainz
+
+@group(0) @binding(0)
+var normals_unsmoothed: texture_2d<f32>;
+@group(0) @binding(1)
+var smp: sampler;
+
+@group(1) @binding(0)
+var zbuf: texture_depth_2d;
+@group(1) @binding(1)
+var<uniform> flow: FlowParams;
+
+struct FlowParams {
+  dt: f32,
+}
+
+struct Global {
+  size: vec2<f32>,
+  time: f32,
+  dt: f32,
+  camera: mat4x4f,
+  projection: mat4x4f
+}
+
+@group(2) @binding(0)
+var<uniform> g: Global;
+
+struct VOut {
+  @builtin(position) clip_pos: vec4f,
+  @location(0) texcoord: vec4f
+}
+
+// Distance from the camera of a depth buffer value
+fn view_depth(d: f32) -> f32 {
+  let p = g.projection;
+  return (p[3][2] - d*p[3][3]) / (p[2][2] - d*p[2][3]);
+}
+
+// Depth buffer value of a distance from the camera
+fn ndc_depth(dist: f32) -> f32 {
+  let p = g.projection;
+  return (p[3][2] - dist*p[2][2]) / (p[3][3] - dist*p[2][3]);
+}
+
+// Distance from the camera of a pixel, 0 for the background
+fn depth_at(px: vec2i) -> f32 {
+  let last = vec2i(textureDimensions(zbuf)) - 1;
+  let d = textureLoad(zbuf, clamp(px, vec2(0), last), 0);
+  return select(view_depth(d), 0., d == 1.0);
+}
+
+@fragment
+fn fs_flow(in: VOut) -> @builtin(frag_depth) f32 {
+  let px = vec2i(in.clip_pos.xy);
+  let z = depth_at(px);
+  let l = depth_at(px - vec2(1, 0));
+  let r = depth_at(px + vec2(1, 0));
+  let t = depth_at(px - vec2(0, 1));
+  let b = depth_at(px + vec2(0, 1));
+  // The background and the silhouettes are kept in place
+  if (min(z, min(min(l, r), min(t, b))) == 0.) {
+    return textureLoad(zbuf, px, 0);
+  }
+  let lt = depth_at(px - vec2(1, 1));
+  let rb = depth_at(px + vec2(1, 1));
+  let rt = depth_at(px + vec2(1, -1));
+  let lb = depth_at(px + vec2(-1, 1));
+
+  let zx = 0.5 * (r - l);
+  let zy = 0.5 * (b - t);
+  let zxx = r + l - 2. * z;
+  let zyy = b + t - 2. * z;
+  let zxy = select(0.25 * (rb + lt - rt - lb), 0., min(min(lt, rb), min(rt, lb)) == 0.);
+
+  let cx = -2. / (g.size.x * g.projection[0][0]);
+  let cy = -2. / (g.size.y * g.projection[1][1]);
+  let cx2 = cx * cx;
+  let cy2 = cy * cy;
+  let d = cy2 * zx * zx + cx2 * zy * zy + cx2 * cy2 * z * z;
+  let ddx = 2. * (cy2 * zx * zxx + cx2 * zy * zxy + cx2 * cy2 * z * zx);
+  let ddy = 2. * (cy2 * zx * zxy + cx2 * zy * zyy + cx2 * cy2 * z * zy);
+  let ex = 0.5 * zx * ddx - zxx * d;
+  let ey = 0.5 * zy * ddy - zyy * d;
+  // Mean curvature
+  let h = (cy * ex + cx * ey) / (2. * pow(d, 1.5));
+  return ndc_depth(z + flow.dt * h);
+}
@@ -47,53 +47,80 @@ struct FOut {
   @location(0) col: vec4f
 }
 
-const n1: f32 = 1.0;
-const n2: f32 = 1.33;
-fn ffresnel(cos_theta: f32) -> f32 {
-  var r0 = (n1-n2)/(n1+n2);
-  r0 *= r0;
-  return r0 + (1-r0)*pow(cos_theta, 5.);
+// Keep in sync with `FluidMaterial::uniform`
+struct FluidMaterial {
+  absorption: vec3f,
+  ior: f32,
+  specular: vec3f,
+  shininess: f32,
+  light_dir: vec3f,
+  refraction: f32,
+  light_color: vec3f,
+  ambient: vec3f,
+}
+
+// The scene behind the fluid
+@group(1) @binding(4)
+var background: texture_2d<f32>;
+// Equirectangular, see `EnvironmentMap`
+@group(1) @binding(5)
+var environment: texture_2d<f32>;
+@group(1) @binding(6)
+var env_smp: sampler;
+@group(1) @binding(7)
+var<uniform> material: FluidMaterial;
+
+const PI: f32 = 3.14159265;
+
+// Texture coordinates of the environment map in the world space direction `d`
+fn equirect(d: vec3f) -> vec2f {
+  return vec2(atan2(d.z, d.x) / (2. * PI) + 0.5, acos(clamp(d.y, -1., 1.)) / PI);
 }
 
-const SCENE_COLOR: vec3f = vec3f(1.0, 1.0, 1.0);
-const FLUID_COLOR: vec3f = vec3f(0.07, 0.075, 1.0);
-const DIFFUSE_COLOR: vec3f = vec3f(1.0, 1.0, 1.0);
-const SUN = vec4f(0.0, 100.0, 0.0, 1.0);
+// Schlick's approximation of the reflectance
+fn fresnel(cos_theta: f32) -> f32 {
+  var r0 = (1. - material.ior) / (1. + material.ior);
+  r0 *= r0;
+  return r0 + (1. - r0) * pow(1. - cos_theta, 5.);
+}
 
+// Normal in the view space, `normals` has the z axis flipped
 fn get_normal(pos: vec2f) -> vec3f {
-  return normalize(textureSample(normal, smp, pos)).xyz*vec3(1.,1.,1.);
+  let n = textureSample(normal, smp, pos).xyz;
+  return normalize(vec3(n.xy, -n.z));
 }
 
 @fragment
 fn fs_main(in: VOut) -> FOut {
   var o: FOut;
   o.depth = textureSample(zbuf_smoothed, smp, in.texcoord.xy);
-
-  var n = get_normal(in.texcoord.xy);
-  var v = vec3f(0.,0.,-1.);
+  let n = get_normal(in.texcoord.xy);
   let t = textureSample(thickness, smp, in.texcoord.xy).x;
-  let a = mix(FLUID_COLOR, SCENE_COLOR, exp(-t));
-  let b = SCENE_COLOR;
-  let f = ffresnel(dot(n, v));
-  let sun_ = g.projection * (g.camera * SUN);
-  var sun = sun_.xyz / sun_.w;
-
-  var world = vec3(in.texcoord.xy, o.depth);
-  world.y = 1. - world.y;
-  world = vec3(2.,2.,1.)*world - vec3(1.,1.,0.);
-  var ray = normalize(world - sun);
-
-  let specular = pow(dot(n, ray), 5.0);
-  if (t < params.ttr) {
+  if (o.depth == 1.0 || t < params.ttr) {
     discard;
   }
-  let diffuse = dot(n, ray);
-
-  let phong =
-    a * (1 - f)
-  + b * f
-  + vec3f(1.0, 0.0, 0.0) * specular
-  + DIFFUSE_COLOR*diffuse;
-  o.col = vec4(phong, 1.0);
+
+  let v = vec3f(0., 0., 1.);
+  let l = normalize((g.camera * vec4(material.light_dir, 0.)).xyz);
+  let h = normalize(l + v);
+
+  // Beer–Lambert law, the background is seen through the thin fluid
+  let transmittance = exp(-material.absorption * t);
+  // Half-Lambert, the unlit side isn't black
+  let diffuse = material.light_color * (0.5 + 0.5 * dot(n, l));
+  // The thicker the fluid, the more the background is displaced
+  let offset = vec2(n.x, -n.y) * material.refraction * t;
+  let uv = clamp(in.texcoord.xy + offset, vec2(0.), vec2(1.));
+  let behind = textureSampleLevel(background, smp, uv, 0.).rgb;
+  let refracted = behind * transmittance + material.ambient * diffuse * (1. - transmittance);
+  // The camera is rigid, so the inverse of its rotation is the transposition
+  let view_to_world = transpose(mat3x3(g.camera[0].xyz, g.camera[1].xyz, g.camera[2].xyz));
+  let r = view_to_world * reflect(-v, n);
+  let reflected = textureSampleLevel(environment, env_smp, equirect(r), 0.).rgb;
+  let f = fresnel(max(dot(n, v), 0.));
+  let specular = material.specular * material.light_color
+    * pow(max(dot(n, h), 0.), material.shininess);
+
+  o.col = vec4(mix(refracted, reflected, f) + specular, 1.0);
   return o;
-}
\ No newline at end of file
+}
@@ -0,0 +1,76 @@
+// Reconstructs the normals from the smoothed depth, so that they are consistent with it
+
+@group(0) @binding(0)
+var normals_unsmoothed: texture_2d<f32>;
+@group(0) @binding(1)
+var smp: sampler;
+
+@group(1) @binding(0)
+var zbuf: texture_depth_2d;
+
+struct Global {
+  size: vec2<f32>,
+  time: f32,
+  dt: f32,
+  camera: mat4x4f,
+  projection: mat4x4f
+}
+
+@group(2) @binding(0)
+var<uniform> g: Global;
+
+struct VOut {
+  @builtin(position) clip_pos: vec4f,
+  @location(0) texcoord: vec4f
+}
+
+// Distance from the camera of a depth buffer value
+fn view_depth(d: f32) -> f32 {
+  let p = g.projection;
+  return (p[3][2] - d*p[3][3]) / (p[2][2] - d*p[2][3]);
+}
+
+// Distance from the camera of a pixel, 0 for the background
+fn depth_at(px: vec2i) -> f32 {
+  let last = vec2i(textureDimensions(zbuf)) - 1;
+  let d = textureLoad(zbuf, clamp(px, vec2(0), last), 0);
+  return select(view_depth(d), 0., d == 1.0);
+}
+
+// Position in the view space of a pixel at the distance `z` from the camera
+fn unproject(px: vec2i, z: f32) -> vec3f {
+  let p = g.projection;
+  let ndc = vec2(2., -2.) * (vec2f(px) + 0.5) / g.size + vec2(-1., 1.);
+  return vec3((ndc + vec2(p[2][0], p[2][1])) * z / vec2(p[0][0], p[1][1]), -z);
+}
+
+// Difference of the positions of the neighbour pixels along `d`.
+// The one of the smaller depth change is taken, so the silhouettes are not crossed.
+fn difference(px: vec2i, d: vec2i) -> vec3f {
+  let z = depth_at(px);
+  let zf = depth_at(px + d);
+  let zb = depth_at(px - d);
+  // A lone pixel is flat
+  if (zf == 0. && zb == 0.) {
+    return unproject(px + d, z) - unproject(px, z);
+  }
+  let p = unproject(px, z);
+  let forward = unproject(px + d, zf) - p;
+  let backward = p - unproject(px - d, zb);
+  if (zb == 0. || (zf != 0. && abs(forward.z) <= abs(backward.z))) {
+    return forward;
+  }
+  return backward;
+}
+
+@fragment
+fn fs_main(in: VOut) -> @location(0) vec4f {
+  let px = vec2i(in.clip_pos.xy);
+  if (depth_at(px) == 0.) {
+    return vec4(0.);
+  }
+  // The pixels go down, so the normal faces the camera
+  let n = normalize(cross(difference(px, vec2(0, 1)), difference(px, vec2(1, 0))));
+  // The same convention as `normals_unsmoothed`
+  return vec4(n.xy, -n.z, 1.);
+}
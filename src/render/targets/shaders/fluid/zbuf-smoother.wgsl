@@ -10,6 +10,20 @@ var zbuf: texture_depth_2d;
 var thickness: texture_2d<f32>;
 @group(1) @binding(2)
 var<storage> kernel: array<f32>;
+@group(1) @binding(3)
+var<uniform> sp: SmootherParams;
+
+// Must be kept the same as `DepthFilter`
+const FILTER_GAUSSIAN: u32 = 0u;
+const FILTER_BILATERAL: u32 = 1u;
+const FILTER_NARROW_RANGE: u32 = 2u;
+// Never run by this shader, see `curvature-flow.wgsl`
+const FILTER_CURVATURE_FLOW: u32 = 3u;
+
+struct SmootherParams {
+  depth_filter: u32,
+  depth_threshold: f32,
+}
 
 var<private> SIDE: i32;
 var<private> CENTER: vec2i;
@@ -31,9 +45,9 @@ struct VOut {
   @builtin(position) clip_pos: vec4f,
   @location(0) texcoord: vec4f
 }
+// The normals are reconstructed from the smoothed depth, see `normals.wgsl`
 struct FOut {
   @builtin(frag_depth) depth: f32,
-  @location(0) norm: vec4f
 }
 
 var<private> dh: vec2f;
@@ -43,31 +57,113 @@ fn at(i: vec2i) -> f32 {
   return kernel[i.x + i.y*DIM_LEN];
 }
 
+// Distance from the camera of a depth buffer value
+fn view_depth(d: f32) -> f32 {
+  let p = g.projection;
+  return (p[3][2] - d*p[3][3]) / (p[2][2] - d*p[2][3]);
+}
+
+// Depth buffer value of a distance from the camera
+fn ndc_depth(dist: f32) -> f32 {
+  let p = g.projection;
+  return (p[3][2] - dist*p[2][2]) / (p[3][3] - dist*p[2][3]);
+}
+
+// Sums of the samples of the current pixel
+var<private> o: FOut;
+var<private> z0: f32;
+var<private> threshold: f32;
+// Sums of the edge-preserving filters, normalized by the sum of weights
+var<private> dist: f32;
+var<private> weights: f32;
+
+// Starts the sums of the pixel, returns `false` if it's the background
+fn begin(texcoord: vec2f) -> bool {
+  dx = vec2(1./g.size.x, 0.);
+  let dy = vec2(0., 1./g.size.y);
+  dh = dx + dy;
+  o.depth = 0.;
+  dist = 0.;
+  weights = 0.;
+  let center = textureSample(zbuf, smp, texcoord);
+  if (center == 1.0) {
+    // The next passes must see the background too
+    o.depth = 1.0;
+    return false;
+  }
+  z0 = view_depth(center);
+  threshold = max(sp.depth_threshold, 1e-6);
+  return true;
+}
+
+fn add_sample(texcoord: vec2f, k: f32) {
+  let d = textureSample(zbuf, smp, texcoord);
+  if (sp.depth_filter == FILTER_GAUSSIAN) {
+    o.depth += d * k;
+    return;
+  }
+  // The background is skipped
+  let background = d == 1.0;
+  var z = select(view_depth(d), z0, background);
+  var w = select(k, 0., background);
+  if (sp.depth_filter == FILTER_BILATERAL) {
+    let dz = (z - z0) / threshold;
+    w *= exp(-0.5 * dz * dz);
+  } else {
+    w = select(w, 0., z > z0 + threshold);
+    z = max(z, z0 - threshold);
+  }
+  dist += w * z;
+  weights += w;
+}
+
+fn end() -> FOut {
+  if (sp.depth_filter != FILTER_GAUSSIAN) {
+    o.depth = ndc_depth(dist / weights);
+  }
+  return o;
+}
+
 @fragment
 fn fs_main(in: VOut) -> FOut {
   ARRAY_LEN = i32(arrayLength(&kernel));
   DIM_LEN = i32(sqrt(f32(ARRAY_LEN)));
   SIDE = (DIM_LEN-1)/2;
   CENTER = vec2(SIDE, SIDE);
-  var o: FOut;
-  o.depth = 0.;
-  o.norm = vec4(0.);
-  dx = vec2(1./g.size.x, 0.);
-  let dy = vec2(0., 1./g.size.y);
-  dh = dx + dy;
-  if (textureSample(zbuf, smp, in.texcoord.xy) == 1.0) {
+  if (!begin(in.texcoord.xy)) {
     return o;
   }
 
   var px = vec2(-SIDE, -SIDE);
   for (; px.x < SIDE; px.x += 1) {
     for (px.y = -SIDE; px.y < SIDE; px.y += 1) {
-      let pos = vec2f(px);
-      o.depth += textureSample(zbuf, smp, in.texcoord.xy + dh*pos) * at(px+CENTER);
-      o.norm += vec4(textureSample(normals_unsmoothed, smp, in.texcoord.xy + dh*pos).xyzw
-                    //  textureSample(thickness, smp, in.texcoord.xy + dh*pos).x)
-                    ) * at(px+CENTER);
+      add_sample(in.texcoord.xy + dh*vec2f(px), at(px+CENTER));
     };
   }
-  return o;
-}
\ No newline at end of file
+  return end();
+}
+
+// The kernel is the horizontal 1D kernel followed by the vertical one
+fn smooth_1d(texcoord: vec2f, vertical: bool) -> FOut {
+  DIM_LEN = i32(arrayLength(&kernel)) / 2;
+  SIDE = (DIM_LEN-1)/2;
+  if (!begin(texcoord)) {
+    return o;
+  }
+  let step = select(dx, dh - dx, vertical);
+  let offset = select(0, DIM_LEN, vertical);
+  for (var i = -SIDE; i <= SIDE; i += 1) {
+    add_sample(texcoord + step*f32(i), kernel[offset + i + SIDE]);
+  }
+  return end();
+}
+
+@fragment
+fn fs_horizontal(in: VOut) -> FOut {
+  return smooth_1d(in.texcoord.xy, false);
+}
+
+@fragment
+fn fs_vertical(in: VOut) -> FOut {
+  return smooth_1d(in.texcoord.xy, true);
+}
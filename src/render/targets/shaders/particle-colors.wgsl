@@ -0,0 +1,112 @@
+// This constant **must** be kept the same as `render::targets::particle_view::WG_SIZE`
+const WG_SIZE: u32 = 64;
+// This constant **must** be kept the same as `render::colormap::LUT_SIZE`
+const LUT_SIZE: u32 = 256;
+
+// Indices in `ParticleAttribute::ALL`
+const DENSITY: u32 = 0;
+const PRESSURE: u32 = 1;
+const SPEED: u32 = 2;
+const FORCE: u32 = 3;
+const INDEX: u32 = 4;
+const NEIGHBOURS: u32 = 5;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+struct SimParams {
+  k: f32,
+  m0: f32,
+  viscosity: f32,
+  h: f32,
+  rho0: f32,
+  e: f32,
+  w: f32,
+  ttr: f32,
+  dtr: f32
+}
+
+// See `ViewParams`
+struct ViewParams {
+  attr: u32,
+  min: f32,
+  max: f32,
+  size: f32,
+  lut: array<vec4f, LUT_SIZE>,
+}
+
+/// Arguments of the indirect particle draw call, see `solvers::emitters::ParticleSources`
+struct ParticleCount {
+  vertex_count: u32,
+  instance_count: u32,
+  first_vertex: u32,
+  first_instance: u32,
+  alive: u32,
+}
+
+@group(1) @binding(0)
+var<storage, read> params: SimParams;
+
+@group(2) @binding(0)
+var<uniform> view: ViewParams;
+@group(2) @binding(1)
+var<storage, read> count: ParticleCount;
+@group(2) @binding(2)
+var<storage, read_write> colors: array<vec4f>;
+
+// Brute force, like the solver
+fn neighbours(i: u32, pos: vec3f) -> u32 {
+  var n = 0u;
+  for (var j = 0u; j < count.instance_count; j++) {
+    let r = cur_pos(j) - pos;
+    if (j != i && dot(r, r) < params.h * params.h) {
+      n++;
+    }
+  }
+  return n;
+}
+
+fn value_of(i: u32) -> f32 {
+  let p = load_cur(i);
+  switch view.attr {
+    case DENSITY: {
+      return p.density;
+    }
+    case PRESSURE: {
+      return params.k * (p.density - params.rho0);
+    }
+    case SPEED: {
+      return length(p.velocity);
+    }
+    case FORCE: {
+      return length(p.forces);
+    }
+    case INDEX: {
+      return f32(i);
+    }
+    case NEIGHBOURS: {
+      return f32(neighbours(i, p.pos));
+    }
+    default: {
+      return 0.;
+    }
+  }
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn colorize(@builtin(global_invocation_id) id: vec3u) {
+  let i = id.x;
+  // The rest isn't drawn
+  if (i >= count.instance_count) {
+    return;
+  }
+  let value = value_of(i);
+  var t = clamp((value - view.min) / (view.max - view.min), 0., 1.);
+  // NaNs and an empty range
+  if (t != t) {
+    t = 0.;
+  }
+  let x = t * f32(LUT_SIZE - 1);
+  let j = min(u32(x), LUT_SIZE - 2);
+  colors[i] = mix(view.lut[j], view.lut[j + 1], x - f32(j));
+}
@@ -0,0 +1,97 @@
+struct Global {
+  size: vec2<f32>,
+  time: f32,
+  dt: f32,
+  camera: mat4x4f,
+  projection: mat4x4f
+};
+
+struct SimParams {
+  k: f32,
+  m0: f32,
+  viscosity: f32,
+  h: f32,
+  rho0: f32,
+  e: f32,
+  w: f32,
+  ttr: f32,
+  dtr: f32
+}
+
+// See `ViewParams`, the colormap is applied by `particle-colors.wgsl`
+struct ViewParams {
+  attr: u32,
+  min: f32,
+  max: f32,
+  size: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> g: Global;
+
+@group(1) @binding(0)
+var<storage, read> params: SimParams;
+
+@group(2) @binding(0)
+var<uniform> view: ViewParams;
+
+struct Input {
+  @builtin(vertex_index) idx: u32,
+  @location(0) pos: vec3f,
+  @location(1) rho: f32,
+  @location(2) color: vec4f,
+};
+
+struct VOut {
+  @builtin(position) clip_pos: vec4f,
+  @location(0) eye_pos: vec3f,
+  @location(1) center: vec3f,
+  @location(2) color: vec4f,
+}
+
+struct FOut {
+  @location(0) color: vec4f,
+  @builtin(frag_depth) depth: f32,
+}
+
+const PI: f32 = 3.14159265358979;
+// In the view space, towards the upper left behind the camera
+const LIGHT: vec3f = vec3f(-0.4, 0.6, 0.7);
+const AMBIENT: f32 = 0.3;
+
+fn radius() -> f32 {
+  return params.h * view.size;
+}
+
+// A triangle circumscribing the sprite of the sphere
+@vertex
+fn vs_main(in: Input) -> VOut {
+  let angle = f32(in.idx) * 2. * PI / 3.;
+  let d = 2. * radius() * vec2(cos(angle), sin(angle));
+
+  var out: VOut;
+  out.center = (g.camera * vec4(in.pos, 1.)).xyz;
+  out.eye_pos = out.center + vec3(d, 0.);
+  out.clip_pos = g.projection * vec4(out.eye_pos, 1.);
+  out.color = in.color;
+  return out;
+}
+
+@fragment
+fn fs_main(in: VOut) -> FOut {
+  let r = in.eye_pos.xy - in.center.xy;
+  let r2 = dot(r, r) / (radius() * radius());
+  if (r2 >= 1.) {
+    discard;
+  }
+  // The view space looks down -Z
+  let n = vec3(r / radius(), sqrt(1. - r2));
+  let surface = in.center + radius() * n;
+  let clip = g.projection * vec4(surface, 1.);
+
+  var out: FOut;
+  out.depth = clip.z / clip.w;
+  let diffuse = max(dot(n, normalize(LIGHT)), 0.);
+  out.color = vec4((AMBIENT + (1. - AMBIENT) * diffuse) * in.color.rgb, 1.);
+  return out;
+}
@@ -0,0 +1,66 @@
+struct Global {
+  size: vec2<f32>,
+  time: f32,
+  dt: f32,
+  camera: mat4x4f,
+  projection: mat4x4f
+};
+
+// See `FluidMaterial::uniform`
+struct FluidMaterial {
+  absorption: vec3f,
+  ior: f32,
+  specular: vec3f,
+  shininess: f32,
+  light_dir: vec3f,
+  refraction: f32,
+  light_color: vec3f,
+  ambient: vec3f,
+}
+
+@group(0) @binding(0)
+var<uniform> g: Global;
+
+@group(1) @binding(0)
+var<uniform> material: FluidMaterial;
+
+struct Input {
+  @location(0) pos: vec4f,
+  @location(1) normal: vec4f,
+};
+
+struct VOut {
+  @builtin(position) clip_pos: vec4f,
+  @location(0) world: vec3f,
+  @location(1) normal: vec3f,
+}
+
+@vertex
+fn vs_main(in: Input) -> VOut {
+  var out: VOut;
+  out.clip_pos = g.projection * g.camera * vec4(in.pos.xyz, 1.);
+  out.world = in.pos.xyz;
+  out.normal = in.normal.xyz;
+  return out;
+}
+
+// Lit like the opaque fluid: the ambient colour with a half-Lambert diffuse term
+// and the Blinn-Phong highlight with the Schlick Fresnel of the merger
+@fragment
+fn fs_main(in: VOut) -> @location(0) vec4f {
+  // The camera is rigid, so the inverse of its rotation is the transposition
+  let rotation = mat3x3(g.camera[0].xyz, g.camera[1].xyz, g.camera[2].xyz);
+  let eye = -(transpose(rotation) * g.camera[3].xyz);
+  let v = normalize(eye - in.world);
+  let n = normalize(in.normal);
+  let l = normalize(material.light_dir);
+
+  let half_lambert = 0.5 * dot(n, l) + 0.5;
+  let diffuse = material.ambient * material.light_color * half_lambert * half_lambert;
+  let r0 = pow((material.ior - 1.) / (material.ior + 1.), 2.);
+  let fresnel = r0 + (1. - r0) * pow(1. - max(dot(n, v), 0.), 5.);
+  let h = normalize(l + v);
+  let highlight = pow(max(dot(n, h), 0.), material.shininess);
+  let specular = material.specular * material.light_color * highlight;
+  return vec4(diffuse + fresnel * specular, 1.);
+}
@@ -0,0 +1,96 @@
+struct Global {
+  size: vec2<f32>,
+  time: f32,
+  dt: f32,
+  camera: mat4x4f,
+  projection: mat4x4f
+};
+
+@group(0) @binding(0)
+var<uniform> g: Global;
+
+// See `tank_params`
+struct TankParams {
+  w: f32,
+  height: f32,
+  cell: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> tank: TankParams;
+
+struct VOut {
+  @builtin(position) clip_pos: vec4f,
+  @location(0) world: vec3f,
+  @location(1) uv: vec2f,
+}
+
+// Half-width of the ground plane
+const GROUND_EXTENT: f32 = 20.0;
+const LIGHT: vec3f = vec3f(0.8, 0.8, 0.78);
+const DARK: vec3f = vec3f(0.55, 0.55, 0.53);
+const WALL_COLOR: vec4f = vec4f(0.6, 0.7, 0.8, 0.2);
+const EDGE_COLOR: vec4f = vec4f(0.2, 0.25, 0.3, 0.8);
+
+// Corner of the quad drawn as two triangles
+fn corner(i: u32) -> vec2f {
+  var corners = array(
+    vec2f(0., 0.), vec2f(1., 0.), vec2f(0., 1.),
+    vec2f(0., 1.), vec2f(1., 0.), vec2f(1., 1.),
+  );
+  return corners[i % 6];
+}
+
+fn project(world: vec3f, uv: vec2f) -> VOut {
+  var out: VOut;
+  out.clip_pos = g.projection * g.camera * vec4(world, 1.);
+  out.world = world;
+  out.uv = uv;
+  return out;
+}
+
+@vertex
+fn vs_ground(@builtin(vertex_index) i: u32) -> VOut {
+  let uv = corner(i);
+  let xz = mix(vec2(-GROUND_EXTENT), vec2(GROUND_EXTENT), uv);
+  return project(vec3(xz.x, 0., xz.y), uv);
+}
+
+@fragment
+fn fs_ground(in: VOut) -> @location(0) vec4f {
+  let p = in.world.xz / tank.cell;
+  let cells = vec2i(floor(p));
+  let checker = select(LIGHT, DARK, ((cells.x + cells.y) & 1) == 1);
+  // The cells smaller than a pixel average out instead of flickering
+  let fw = fwidth(p);
+  return vec4(mix(checker, 0.5 * (LIGHT + DARK), clamp(max(fw.x, fw.y), 0., 1.)), 1.);
+}
+
+// The four walls, 6 vertices each. Only the ones behind the tank are drawn,
+// the near ones would be composited over the fluid.
+@vertex
+fn vs_walls(@builtin(vertex_index) i: u32) -> VOut {
+  var normals = array(vec3f(-1., 0., 0.), vec3f(1., 0., 0.), vec3f(0., 0., -1.), vec3f(0., 0., 1.));
+  // Facing the inside of the tank
+  let n = normals[i / 6];
+  let tangent = vec3(n.z, 0., -n.x);
+  let uv = corner(i);
+  let world = tank.w * (tangent * (2. * uv.x - 1.) - n) + vec3(0., tank.height * uv.y, 0.);
+
+  // The camera is rigid, so the inverse of its rotation is the transposition
+  let rotation = mat3x3(g.camera[0].xyz, g.camera[1].xyz, g.camera[2].xyz);
+  let eye = -(transpose(rotation) * g.camera[3].xyz);
+  var out = project(world, uv);
+  if (dot(n, eye - world) < 0.) {
+    // Degenerate, so it's clipped
+    out.clip_pos = vec4(0.);
+  }
+  return out;
+}
+
+@fragment
+fn fs_walls(in: VOut) -> @location(0) vec4f {
+  // In pixels
+  let edge = min(in.uv, 1. - in.uv) / fwidth(in.uv);
+  return select(WALL_COLOR, EDGE_COLOR, min(edge.x, edge.y) < 1.5);
+}
@@ -74,6 +74,7 @@ pub struct TextureDrawerInitRes<'a> {
   pub fragment: Option<FragmentState<'a>>,
   /// The first bind group here has `1` index
   pub layout: &'a [BindGroupLayout],
+  /// Ignored if [`wgpu::Features::DEPTH_CLIP_CONTROL`] is not supported
   pub unclipped_depth: bool,
 }
 
@@ -158,7 +159,10 @@ impl TextureDrawer {
         strip_index_format: None,
         front_face: wgpu::FrontFace::Ccw,
         cull_mode: None,
-        unclipped_depth: init_res.unclipped_depth,
+        unclipped_depth: init_res.unclipped_depth
+          && device
+            .features()
+            .contains(wgpu::Features::DEPTH_CLIP_CONTROL),
         polygon_mode: wgpu::PolygonMode::Fill,
         conservative: false,
       },
@@ -182,11 +186,19 @@ impl TextureDrawer {
       cache: None,
     });
 
+    let address_mode = if device
+      .features()
+      .contains(wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER)
+    {
+      AddressMode::ClampToBorder
+    } else {
+      AddressMode::ClampToEdge
+    };
     let sampler = device.create_sampler(&SamplerDescriptor {
       label: None,
-      address_mode_u: AddressMode::ClampToBorder,
-      address_mode_v: AddressMode::ClampToBorder,
-      address_mode_w: AddressMode::ClampToBorder,
+      address_mode_u: address_mode,
+      address_mode_v: address_mode,
+      address_mode_w: address_mode,
       mag_filter: FilterMode::Linear,
       min_filter: FilterMode::Linear,
       mipmap_filter: FilterMode::Nearest,
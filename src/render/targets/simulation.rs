@@ -1,16 +1,56 @@
 use core::{f32, slice};
 use std::cell::Cell;
+use std::io;
+use std::sync::Arc;
 
 use cgmath::{Point3, Vector3, Zero};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::render::profiler::GpuProfiler;
 use crate::render::swapchain::{SwapBuffers, SwapBuffersDescriptor};
 use crate::render::AsBuffer;
+use crate::solvers::emitters::{Emitter, ParticleSources, Sink, DEAD_POS};
+use crate::solvers::sort_keys::{KeyGrid, SortKey};
 use crate::solvers::sph_solver_gpu::Particle;
 use crate::solvers::sph_solver_gpu::{SphSolverGpu, SphSolverGpuRenderResources};
+use crate::solvers::storage::{ParticleData, StorageLayout};
+use crate::solvers::surface::{extract_cpu, Mesh, SurfaceGrid, SurfaceSettings};
+
+#[cfg(test)]
+mod test {
+  use super::SimulationParams;
+  use crate::solvers::sort_keys::SortKey;
+
+  #[test]
+  fn params_get_set() {
+    let mut params = SimulationParams::default();
+    for name in SimulationParams::FIELDS {
+      let value = params.get(name).unwrap();
+      params.set(name, &value).unwrap();
+    }
+    assert_eq!(params, SimulationParams::default());
+
+    params.set("h", "0.05").unwrap();
+    params.set("k", "2e6").unwrap();
+    params.set("m0", "400").unwrap();
+    params.set("paused", "true").unwrap();
+    params.set("sort_key", "Hilbert").unwrap();
+    assert_eq!((params.h, params.k, params.m0), (0.05, 2e6, 400.0));
+    assert!(params.paused);
+    assert_eq!(params.sort_key, SortKey::Hilbert);
+    assert_eq!(params.get("sort_key").unwrap(), "Hilbert");
+
+    assert!(params.set("h", "abc").is_err());
+    assert!(params.set("paused", "1").is_err());
+    assert!(params.set("nope", "1").is_err());
+    assert!(params.get("regen_particles").is_none());
+  }
+}
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SimulationParams {
   pub k: f32,
   pub m0: f32,
@@ -24,7 +64,16 @@ pub struct SimulationParams {
   /// Density threshold
   pub dtr: f32,
   pub paused: bool,
+  #[serde(skip)]
   pub regen_particles: bool,
+  /// Removes all the particles when set
+  #[serde(skip)]
+  pub clear_particles: bool,
+  /// Order of the particles in memory
+  pub sort_key: SortKey,
+  /// Copy the particles to the old buffer before every step.
+  /// The solver rewrites the whole current buffer, so it is only useful for debugging.
+  pub copy_on_swap: bool,
 }
 
 impl Default for SimulationParams {
@@ -41,10 +90,74 @@ impl Default for SimulationParams {
       dtr: 0.0,
       paused: false,
       regen_particles: false,
+      clear_particles: false,
+      sort_key: SortKey::X,
+      copy_on_swap: false,
     }
   }
 }
 
+impl SimulationParams {
+  /// Names of the fields accessible with [`Self::get`] and [`Self::set`]
+  pub const FIELDS: [&'static str; 12] = [
+    "k",
+    "m0",
+    "viscosity",
+    "h",
+    "rho0",
+    "e",
+    "w",
+    "ttr",
+    "dtr",
+    "paused",
+    "sort_key",
+    "copy_on_swap",
+  ];
+
+  /// Value of the field `name` in the RON syntax, `None` if there is no such field
+  pub fn get(&self, name: &str) -> Option<String> {
+    let value = match name {
+      "k" => ron::to_string(&self.k),
+      "m0" => ron::to_string(&self.m0),
+      "viscosity" => ron::to_string(&self.viscosity),
+      "h" => ron::to_string(&self.h),
+      "rho0" => ron::to_string(&self.rho0),
+      "e" => ron::to_string(&self.e),
+      "w" => ron::to_string(&self.w),
+      "ttr" => ron::to_string(&self.ttr),
+      "dtr" => ron::to_string(&self.dtr),
+      "paused" => ron::to_string(&self.paused),
+      "sort_key" => ron::to_string(&self.sort_key),
+      "copy_on_swap" => ron::to_string(&self.copy_on_swap),
+      _ => return None,
+    };
+    Some(value.expect("The parameters are always serializable"))
+  }
+
+  /// Parses the `value` of the field `name` in the RON syntax
+  pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+    fn parse<T: serde::de::DeserializeOwned>(value: &str) -> Result<T, String> {
+      ron::from_str(value).map_err(|e| format!("Invalid value `{value}`: {e}"))
+    }
+    match name {
+      "k" => self.k = parse(value)?,
+      "m0" => self.m0 = parse(value)?,
+      "viscosity" => self.viscosity = parse(value)?,
+      "h" => self.h = parse(value)?,
+      "rho0" => self.rho0 = parse(value)?,
+      "e" => self.e = parse(value)?,
+      "w" => self.w = parse(value)?,
+      "ttr" => self.ttr = parse(value)?,
+      "dtr" => self.dtr = parse(value)?,
+      "paused" => self.paused = parse(value)?,
+      "sort_key" => self.sort_key = parse(value)?,
+      "copy_on_swap" => self.copy_on_swap = parse(value)?,
+      _ => return Err(format!("Unknown parameter `{name}`")),
+    }
+    Ok(())
+  }
+}
+
 impl AsBuffer for SimulationParams {
   fn as_bytes_buffer(&self) -> &[u8] {
     unsafe {
@@ -62,7 +175,14 @@ use wgpu::{BufferUsages, DepthStencilState, ShaderStages};
 use crate::render::render_target::{ExternalResources, RenderTarget};
 
 use super::fluid_renderer::{FluidRenderInit, FluidRenderer, FluidRendererResources};
+use super::particle_view::{
+  ParticleView, ParticleViewInit, ParticleViewResources, ParticleViewSettings,
+};
+use super::surface_renderer::{SurfaceRenderer, SurfaceRendererInit, SurfaceRendererResources};
 use crate::render::blur::{Blur, GaussianBlur};
+use crate::render::environment::EnvironmentMap;
+use crate::render::material::FluidMaterial;
+use crate::render::texture_provider::TextureProvider;
 
 pub struct SimResources<'a> {
   pub params: &'a SimulationParams,
@@ -83,21 +203,38 @@ pub struct SimInit<'a> {
   pub count: usize,
   pub size: egui::Vec2,
   pub depth_state: &'a wgpu::DepthStencilState,
+  /// Layout of the particles in the particle buffers
+  pub storage: StorageLayout,
+  /// Don't create the renderer, the simulation is only stepped
+  pub headless: bool,
 }
 
 impl<'a> ExternalResources<'a> for SimResources<'a> {}
 
 pub struct SphSimulation {
-  pos_buf: Option<SwapBuffers<Vec<Particle>>>,
+  pos_buf: Option<SwapBuffers<ParticleData>>,
   fluid_renderer: Option<FluidRenderer>,
+  /// Replaces the fluid when enabled, created along with `fluid_renderer`
+  particle_view: Option<ParticleView>,
+  /// Replaces the fluid with its extracted surface when enabled, created along with
+  /// `fluid_renderer`
+  surface_renderer: Option<SurfaceRenderer>,
   params_buf: Option<wgpu::Buffer>,
   params_bg: Option<wgpu::BindGroup>,
   height: f32,
   width: f32,
   count: usize,
   solver: Option<SphSolverGpu>,
+  sources: Option<ParticleSources>,
+  emitters: Vec<Emitter>,
+  sinks: Vec<Sink>,
   smoother: Box<dyn Blur + Sync + Send>,
+  material: FluidMaterial,
   params: SimulationParams,
+  storage: StorageLayout,
+  profiler: Option<Arc<GpuProfiler>>,
+  /// `fluid_renderer` is never created
+  headless: bool,
 }
 
 impl<'a> RenderTarget<'a> for SphSimulation {
@@ -107,19 +244,17 @@ impl<'a> RenderTarget<'a> for SphSimulation {
 
   fn init(
     device: &wgpu::Device,
-    _queue: &wgpu::Queue,
+    queue: &wgpu::Queue,
     resources: &'a Self::RenderResources,
     format: &wgpu::TextureFormat,
     init_res: Self::InitResources,
   ) -> Self {
-    Self::create_fully_initialized(
-      init_res.count,
-      device,
-      init_res.size,
-      *format,
-      resources.global_layout,
-      init_res.depth_state,
-    )
+    let mut out =
+      Self::create_fully_initialized(device, queue, *format, resources.global_layout, init_res);
+    out
+      .set_environment(&EnvironmentMap::procedural(), device, queue)
+      .expect("the procedural environment map fits into a texture");
+    out
   }
 
   fn update(
@@ -129,31 +264,48 @@ impl<'a> RenderTarget<'a> for SphSimulation {
     resources: &'a Self::UpdateResources,
     encoder: &mut wgpu::CommandEncoder,
   ) {
-    self.write_buffers(queue, resources.params);
-    if resources.params.regen_particles {
-      self.regenerate_positions(device);
-    }
-    if !resources.params.paused {
-      self.pos_buf.as_mut().unwrap().swap(encoder);
-      self.solver.as_mut().unwrap().update(
+    self.step(device, queue, resources, encoder);
+    if let Some(view) = self.particle_view.as_mut().filter(|v| v.is_enabled()) {
+      view.update(
         device,
         queue,
-        &SphSolverGpuRenderResources {
-          pos: self.pos_buf.as_mut().unwrap(),
+        &ParticleViewResources {
           global_bg: resources.global_group,
-          params_buf: self.params_buf.as_mut().unwrap(),
+          params_bg: self.params_bg.as_ref().unwrap(),
+          particles: self.pos_buf.as_ref().unwrap(),
+          count_buf: self.sources.as_ref().unwrap().count_buf(),
         },
         encoder,
       );
+      return;
+    }
+    if let Some(surface) = self.surface_renderer.as_mut() {
+      if let Some(settings) = surface.settings() {
+        surface.update(
+          device,
+          queue,
+          &SurfaceRendererResources {
+            global_bg: resources.global_group,
+            particles: self.pos_buf.as_ref().unwrap(),
+            count_buf: self.sources.as_ref().unwrap().count_buf(),
+            grid: SurfaceGrid::new(self.params.w, self.params.h, settings.resolution),
+          },
+          encoder,
+        );
+        return;
+      }
     }
-    self.fluid_renderer.as_mut().unwrap().update(
+    let Some(renderer) = self.fluid_renderer.as_mut() else {
+      return;
+    };
+    renderer.update(
       device,
       queue,
       &FluidRendererResources {
         global_bg: resources.global_group,
         params_bg: self.params_bg.as_ref().unwrap(),
         pos_buf: self.pos_buf.as_ref().unwrap().cur_buf(),
-        count: self.count as u32,
+        count_buf: self.sources.as_ref().unwrap().count_buf(),
       },
       encoder,
     );
@@ -162,6 +314,7 @@ impl<'a> RenderTarget<'a> for SphSimulation {
   fn resized(
     &mut self,
     device: &wgpu::Device,
+    queue: &wgpu::Queue,
     new_size: egui::Vec2,
     resources: &'a Self::UpdateResources,
     format: wgpu::TextureFormat,
@@ -170,33 +323,67 @@ impl<'a> RenderTarget<'a> for SphSimulation {
     self.height = new_size.y;
     self.init_pipelines(
       device,
+      queue,
       format,
       resources.global_layout,
       resources.depth_stencil,
       true
     );
     // FIXME: Is it necessary to do? isn't it enough to call `init_pipelines`?
-    self.fluid_renderer.as_mut().unwrap().resized(
+    let Some(renderer) = self.fluid_renderer.as_mut() else {
+      return;
+    };
+    renderer.resized(
       device,
+      queue,
       new_size,
       &FluidRendererResources {
         global_bg: resources.global_group,
         params_bg: self.params_bg.as_ref().unwrap(),
         pos_buf: self.pos_buf.as_ref().unwrap().cur_buf(),
-        count: self.count as u32,
+        count_buf: self.sources.as_ref().unwrap().count_buf(),
       },
       format,
     );
   }
 
   fn render_into_pass(&self, pass: &mut wgpu::RenderPass, resources: &'a Self::RenderResources) {
-    self.fluid_renderer.as_ref().unwrap().render_into_pass(
+    if let Some(view) = self.particle_view.as_ref().filter(|v| v.is_enabled()) {
+      view.render_into_pass(
+        pass,
+        &ParticleViewResources {
+          global_bg: resources.global_group,
+          params_bg: self.params_bg.as_ref().unwrap(),
+          particles: self.pos_buf.as_ref().unwrap(),
+          count_buf: self.sources.as_ref().unwrap().count_buf(),
+        },
+      );
+      return;
+    }
+    if let Some(surface) = self.surface_renderer.as_ref() {
+      if let Some(settings) = surface.settings() {
+        surface.render_into_pass(
+          pass,
+          &SurfaceRendererResources {
+            global_bg: resources.global_group,
+            particles: self.pos_buf.as_ref().unwrap(),
+            count_buf: self.sources.as_ref().unwrap().count_buf(),
+            grid: SurfaceGrid::new(self.params.w, self.params.h, settings.resolution),
+          },
+        );
+        return;
+      }
+    }
+    let Some(renderer) = self.fluid_renderer.as_ref() else {
+      return;
+    };
+    renderer.render_into_pass(
       pass,
       &FluidRendererResources {
         global_bg: resources.global_group,
         params_bg: self.params_bg.as_ref().unwrap(),
         pos_buf: self.pos_buf.as_ref().unwrap().cur_buf(),
-        count: self.count as u32,
+        count_buf: self.sources.as_ref().unwrap().count_buf(),
       },
     );
   }
@@ -204,13 +391,19 @@ impl<'a> RenderTarget<'a> for SphSimulation {
 
 impl SphSimulation {
   fn create_fully_initialized(
-    count: usize,
     device: &wgpu::Device,
-    size: egui::Vec2,
+    queue: &wgpu::Queue,
     format: wgpu::TextureFormat,
     global_layout: &wgpu::BindGroupLayout,
-    depth: &DepthStencilState,
+    init_res: SimInit,
   ) -> Self {
+    let SimInit {
+      count,
+      size,
+      depth_state: depth,
+      storage,
+      headless,
+    } = init_res;
     let mut particles: Vec<Particle> = vec![Default::default(); count];
     let mut rng = rand::rng();
     let width = size.x;
@@ -225,20 +418,81 @@ impl SphSimulation {
       // Initialized in `init_pipelines`
       pos_buf: None,
       fluid_renderer: None,
+      particle_view: None,
+      surface_renderer: None,
       params_buf: None,
       params_bg: None,
       height,
       width,
       count,
       solver: None,
+      sources: None,
+      emitters: Vec::new(),
+      sinks: Vec::new(),
       smoother: Box::new(GaussianBlur::default()),
+      material: FluidMaterial::default(),
       params: Default::default(),
+      storage,
+      profiler: None,
+      headless,
     };
-    out.init_pipelines(device, format, global_layout, depth, false);
+    out.init_pipelines(device, queue, format, global_layout, depth, false);
     out.regenerate_positions(device);
     out
   }
 
+  /// Advances the simulation by `resources.dt` without rendering it
+  pub fn step(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    resources: &SimUpdateResources,
+    encoder: &mut wgpu::CommandEncoder,
+  ) {
+    self.write_buffers(queue, resources.params);
+    if resources.params.regen_particles {
+      self.regenerate_positions(device);
+      self
+        .sources
+        .as_mut()
+        .unwrap()
+        .reset(queue, self.count as u32);
+    }
+    if resources.params.clear_particles {
+      self.clear_positions(device);
+      self.sources.as_mut().unwrap().reset(queue, 0);
+    }
+    if !resources.params.paused {
+      self.sources.as_mut().unwrap().update(
+        queue,
+        encoder,
+        self.pos_buf.as_mut().unwrap(),
+        resources.dt,
+      );
+      let pos_buf = self.pos_buf.as_mut().unwrap();
+      if resources.params.copy_on_swap {
+        pos_buf.swap(encoder);
+      } else {
+        pos_buf.flip();
+      }
+      self.solver.as_mut().unwrap().update(
+        device,
+        queue,
+        &SphSolverGpuRenderResources {
+          pos: self.pos_buf.as_mut().unwrap(),
+          global_bg: resources.global_group,
+          params_buf: self.params_buf.as_mut().unwrap(),
+        },
+        encoder,
+      );
+      self
+        .solver
+        .as_ref()
+        .unwrap()
+        .sort(encoder, self.pos_buf.as_mut().unwrap());
+    }
+  }
+
   fn regenerate_positions(&mut self, device: &wgpu::Device) {
     let a = (self.count as f32).cbrt() * self.params.h;
     let len = (self.count as f32).cbrt() as usize;
@@ -263,12 +517,25 @@ impl SphSimulation {
 
       p.velocity = Vector3::zero();
     });
-    self.pos_buf.as_mut().unwrap().reset(parts, device);
+    self
+      .pos_buf
+      .as_mut()
+      .unwrap()
+      .reset(ParticleData::new(self.storage, &parts), device);
+  }
+
+  fn clear_positions(&mut self, device: &wgpu::Device) {
+    let dead = with!(Particle::default() => pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS));
+    self.pos_buf.as_mut().unwrap().reset(
+      ParticleData::new(self.storage, &vec![dead; self.count]),
+      device,
+    );
   }
 
   fn init_pipelines(
     &mut self,
     device: &wgpu::Device,
+    queue: &wgpu::Queue,
     format: wgpu::TextureFormat,
     global_layout: &wgpu::BindGroupLayout,
     depth_stencil: &DepthStencilState,
@@ -308,37 +575,103 @@ impl SphSimulation {
     });
 
     let pos_buf = SwapBuffers::init_with(
-      vec![Default::default(); self.count],
+      ParticleData::new(self.storage, &vec![Default::default(); self.count]),
       device,
       SwapBuffersDescriptor {
         usage: BufferUsages::VERTEX
           | BufferUsages::COPY_DST
           | BufferUsages::COPY_SRC
           | BufferUsages::STORAGE,
-        visibility: ShaderStages::all(),
+        visibility: ShaderStages::COMPUTE,
         ty: wgpu::BufferBindingType::Storage { read_only: false },
         has_dynamic_offset: false,
       },
     );
 
-    let solver = SphSolverGpu::new(device, (self.count, global_layout, &params_buf, &pos_buf));
-    let fluid_renderer = FluidRenderer::new(
+    let mut sources = ParticleSources::new(
+      device,
+      self.count as u32,
+      pos_buf.cur_layout(),
+      self.storage,
+    );
+    sources.set_sources(&self.emitters, &self.sinks);
+    let mut solver = SphSolverGpu::new(
       device,
-      &format,
-      FluidRenderInit {
-        size: egui::Vec2::new(self.width, self.height),
+      queue,
+      (
+        self.count,
         global_layout,
-        params_layout: &params_layout,
-        depth_stencil_state: depth_stencil.clone(),
-        smoother_matrix: self.smoother.full_kernel(),
-      },
+        &params_buf,
+        &pos_buf,
+        sources.count_buf(),
+        self.storage,
+      ),
     );
+    let fluid_renderer = (!self.headless).then(|| {
+      let mut fluid_renderer = FluidRenderer::new(
+        device,
+        &format,
+        FluidRenderInit {
+          size: egui::Vec2::new(self.width, self.height),
+          global_layout,
+          params_layout: &params_layout,
+          depth_stencil_state: depth_stencil.clone(),
+          smoother_matrix: self.smoother.smoother_kernel(),
+          separable: self.smoother.is_separable(),
+          curvature_flow: self.smoother.curvature_flow(),
+          smoother_params: self.smoother.smoother_params(),
+          material: self.material,
+          storage: self.storage,
+        },
+      );
+      fluid_renderer.set_profiler(self.profiler.clone());
+      fluid_renderer
+    });
+
+    let particle_view = (!self.headless).then(|| {
+      ParticleView::new(
+        device,
+        &format,
+        ParticleViewInit {
+          capacity: self.count as u32,
+          global_layout,
+          params_layout: &params_layout,
+          particle_layout: pos_buf.cur_layout(),
+          count_buf: sources.count_buf(),
+          depth_stencil,
+          storage: self.storage,
+        },
+      )
+    });
+
+    let surface_renderer = (!self.headless).then(|| {
+      let mut renderer = SurfaceRenderer::new(
+        device,
+        &format,
+        SurfaceRendererInit {
+          capacity: self.count as u32,
+          global_layout,
+          particle_layout: pos_buf.cur_layout(),
+          count_buf: sources.count_buf(),
+          depth_stencil,
+          material: self.material,
+          storage: self.storage,
+        },
+      );
+      renderer.set_settings(self.surface_renderer.as_ref().and_then(|s| s.settings()));
+      renderer
+    });
 
-    self.fluid_renderer = Some(fluid_renderer);
+    solver.set_profiler(self.profiler.clone());
+
+    self.fluid_renderer = fluid_renderer;
+    self.particle_view = particle_view;
+    self.surface_renderer = surface_renderer;
     self.pos_buf = Some(pos_buf);
     self.params_bg = Some(params_bg);
     self.params_buf = Some(params_buf);
     self.solver = Some(solver);
+    self.sources = Some(sources);
     if !resize {
       self.regenerate_positions(device);
     }
@@ -350,6 +683,16 @@ impl SphSimulation {
       0,
       params.as_bytes_buffer(),
     );
+    // The cells of the size of the kernel support, covering the tank
+    let grid = KeyGrid {
+      origin: Point3::new(-params.w, 0.0, -params.w),
+      cell_size: params.h,
+    };
+    self
+      .solver
+      .as_ref()
+      .unwrap()
+      .set_sort_key(queue, params.sort_key, &grid);
     self.params = params.clone();
   }
 
@@ -359,11 +702,153 @@ impl SphSimulation {
     device: &wgpu::Device,
     queue: &wgpu::Queue,
   ) {
+    if let Some(renderer) = self.fluid_renderer.as_mut() {
+      renderer.set_blur(&*blur, device, queue);
+    }
+    self.smoother = blur;
+  }
+
+  pub fn set_material(&mut self, material: FluidMaterial, queue: &wgpu::Queue) {
+    if let Some(renderer) = self.fluid_renderer.as_ref() {
+      renderer.set_material(&material, queue);
+    }
+    if let Some(surface) = self.surface_renderer.as_ref() {
+      surface.set_material(&material, queue);
+    }
+    self.material = material;
+  }
+
+  pub fn set_environment(
+    &mut self,
+    env: &EnvironmentMap,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+  ) -> io::Result<()> {
+    match self.fluid_renderer.as_mut() {
+      Some(renderer) => renderer.set_environment(env, device, queue),
+      None => Ok(()),
+    }
+  }
+
+  /// Texture the scene behind the fluid is drawn into, `None` if the fluid isn't rendered
+  pub fn background(&self) -> Option<&TextureProvider> {
+    self.fluid_renderer.as_ref().map(FluidRenderer::background)
+  }
+
+  /// Draws the particles coloured by an attribute instead of the fluid, or the fluid if `None`
+  pub fn set_particle_view(&mut self, settings: Option<ParticleViewSettings>, queue: &wgpu::Queue) {
+    if let Some(view) = self.particle_view.as_mut() {
+      view.set_settings(settings, queue);
+    }
+  }
+
+  /// Draws the mesh of the surface extracted with the `settings` instead of the fluid, or
+  /// the fluid if `None`
+  pub fn set_surface(&mut self, settings: Option<SurfaceSettings>) {
+    if let Some(surface) = self.surface_renderer.as_mut() {
+      surface.set_settings(settings);
+    }
+  }
+
+  /// Extracts the mesh of the fluid surface, the triangles share their vertices.
+  /// The mesh drawn on the GPU is reused if it was extracted with the same `settings`,
+  /// otherwise the particles are read back and polygonized on the CPU.
+  pub fn extract_surface(
+    &self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    settings: &SurfaceSettings,
+  ) -> Mesh {
+    let gpu = self
+      .surface_renderer
+      .as_ref()
+      .filter(|s| s.settings() == Some(*settings))
+      .and_then(|s| s.read_mesh(device, queue));
+    if let Some(mesh) = gpu {
+      return mesh.welded();
+    }
+    let grid = SurfaceGrid::new(self.params.w, self.params.h, settings.resolution);
+    extract_cpu(&self.read_particles(device, queue), &grid, settings).welded()
+  }
+
+  /// Replaces the particle emitters and sinks
+  pub fn set_sources(&mut self, emitters: Vec<Emitter>, sinks: Vec<Sink>) {
+    if let Some(sources) = self.sources.as_mut() {
+      sources.set_sources(&emitters, &sinks);
+    }
+    self.emitters = emitters;
+    self.sinks = sinks;
+  }
+
+  /// Measures the GPU passes of the solver and the renderer with the `profiler`
+  pub fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>) {
+    if let Some(solver) = self.solver.as_mut() {
+      solver.set_profiler(profiler.clone());
+    }
+    if let Some(renderer) = self.fluid_renderer.as_mut() {
+      renderer.set_profiler(profiler.clone());
+    }
+    self.profiler = profiler;
+  }
+
+  /// Capacity of the particle buffers
+  pub fn count(&self) -> usize {
+    self.count
+  }
+
+  pub fn storage(&self) -> StorageLayout {
+    self.storage
+  }
+
+  /// Reads the particles back from the GPU, waiting for the submitted work to finish.
+  /// The dead particles are included.
+  pub fn read_particles(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Particle> {
+    let src = self.pos_buf.as_ref().unwrap().cur_buf();
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particles readback"),
+      size: src.size(),
+      usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+      label: Some("Particles readback"),
+    });
+    encoder.copy_buffer_to_buffer(src, 0, &staging, 0, src.size());
+    queue.submit([encoder.finish()]);
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |r| {
+      r.expect("Unable to map the particles")
+    });
+    device.poll(wgpu::Maintain::Wait);
+    let particles = ParticleData::decode(self.storage, &slice.get_mapped_range());
+    staging.unmap();
+    particles
+  }
+
+  /// Replaces the particles, at most [`Self::count`] of them are kept.
+  /// The particles at [`DEAD_POS`] are dead.
+  pub fn load_particles(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particles: &[Particle],
+  ) {
+    let dead = with!(Particle::default() => pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS));
+    // The active particles go first, see `ParticleSources`
+    let mut parts: Vec<Particle> = particles
+      .iter()
+      .filter(|p| p.pos.x != DEAD_POS)
+      .take(self.count)
+      .cloned()
+      .collect();
+    let alive = parts.len();
+    parts.resize(self.count, dead);
     self
-      .fluid_renderer
+      .pos_buf
       .as_mut()
       .unwrap()
-      .set_kernel(blur.full_kernel(), device, queue);
-    self.smoother = blur;
+      .reset(ParticleData::new(self.storage, &parts), device);
+    self.sources.as_mut().unwrap().reset(queue, alive as u32);
   }
 }
@@ -0,0 +1,201 @@
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, DepthStencilState, PipelineLayoutDescriptor, RenderPipeline, ShaderStages,
+};
+
+use crate::{
+  render::{
+    material::FluidMaterial,
+    render_target::{ExternalResources, RenderTarget},
+    swapchain::SwapBuffers,
+    AsBuffer,
+  },
+  solvers::{
+    storage::{ParticleData, StorageLayout},
+    surface::{Mesh, SurfaceExtractor, SurfaceGrid, SurfaceSettings, GPU_VERTEX_SIZE},
+  },
+};
+
+/// Draws the mesh of the fluid surface extracted by a [`SurfaceExtractor`] instead of
+/// the screen-space fluid
+pub struct SurfaceRenderer {
+  extractor: SurfaceExtractor,
+  pipeline: RenderPipeline,
+  /// [`FluidMaterial::uniform`]
+  material_buf: Buffer,
+  material_bg: BindGroup,
+  settings: Option<SurfaceSettings>,
+}
+
+pub struct SurfaceRendererResources<'a> {
+  pub global_bg: &'a BindGroup,
+  pub particles: &'a SwapBuffers<ParticleData>,
+  /// See [`crate::solvers::emitters::ParticleSources::count_buf`]
+  pub count_buf: &'a Buffer,
+  pub grid: SurfaceGrid,
+}
+
+impl<'a> ExternalResources<'a> for SurfaceRendererResources<'a> {}
+
+pub struct SurfaceRendererInit<'a> {
+  pub capacity: u32,
+  pub global_layout: &'a wgpu::BindGroupLayout,
+  pub particle_layout: &'a wgpu::BindGroupLayout,
+  pub count_buf: &'a Buffer,
+  pub depth_stencil: &'a DepthStencilState,
+  pub material: FluidMaterial,
+  pub storage: StorageLayout,
+}
+
+impl SurfaceRenderer {
+  pub fn new(
+    device: &wgpu::Device,
+    format: &wgpu::TextureFormat,
+    init: SurfaceRendererInit,
+  ) -> Self {
+    let extractor = SurfaceExtractor::new(
+      device,
+      init.capacity,
+      init.particle_layout,
+      init.count_buf,
+      init.storage,
+      Default::default(),
+    );
+    let material_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("Surface material"),
+      contents: init.material.uniform().as_bytes_buffer(),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let material_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Surface material layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      }],
+    });
+    let material_bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Surface material"),
+      layout: &material_layout,
+      entries: &[BindGroupEntry {
+        binding: 0,
+        resource: material_buf.as_entire_binding(),
+      }],
+    });
+
+    let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/surface.wgsl"));
+    let attributes = wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Surface pipeline"),
+      layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Surface"),
+        bind_group_layouts: &[init.global_layout, &material_layout],
+        push_constant_ranges: &[],
+      })),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_main"),
+        compilation_options: Default::default(),
+        buffers: &[wgpu::VertexBufferLayout {
+          array_stride: GPU_VERTEX_SIZE,
+          step_mode: wgpu::VertexStepMode::Vertex,
+          attributes: &attributes,
+        }],
+      },
+      primitive: wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: None,
+        unclipped_depth: false,
+        polygon_mode: wgpu::PolygonMode::Fill,
+        conservative: false,
+      },
+      depth_stencil: Some(init.depth_stencil.clone()),
+      multisample: wgpu::MultisampleState {
+        count: 1,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_main"),
+        compilation_options: Default::default(),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: *format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::all(),
+        })],
+      }),
+      multiview: None,
+      cache: None,
+    });
+
+    Self {
+      extractor,
+      pipeline,
+      material_buf,
+      material_bg,
+      settings: None,
+    }
+  }
+
+  /// Extracts and draws the surface with the `settings`, or the fluid again if `None`
+  pub fn set_settings(&mut self, settings: Option<SurfaceSettings>) {
+    self.settings = settings;
+  }
+
+  pub fn settings(&self) -> Option<SurfaceSettings> {
+    self.settings
+  }
+
+  pub fn set_material(&self, material: &FluidMaterial, queue: &wgpu::Queue) {
+    queue.write_buffer(&self.material_buf, 0, material.uniform().as_bytes_buffer());
+  }
+
+  /// The mesh of the last update, `None` if it didn't fit on the GPU, see
+  /// [`SurfaceExtractor::read_mesh`]
+  pub fn read_mesh(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Mesh> {
+    self.extractor.read_mesh(device, queue)
+  }
+}
+
+impl<'a> RenderTarget<'a> for SurfaceRenderer {
+  type RenderResources = SurfaceRendererResources<'a>;
+
+  /// Extracts the surface of the particles of the current step
+  fn update(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    resources: &'a Self::RenderResources,
+    encoder: &mut wgpu::CommandEncoder,
+  ) {
+    let Some(settings) = self.settings else {
+      return;
+    };
+    self.extractor.extract(
+      device,
+      queue,
+      encoder,
+      resources.particles,
+      resources.count_buf,
+      &resources.grid,
+      &settings,
+    );
+  }
+
+  fn render_into_pass(&self, pass: &mut wgpu::RenderPass, resources: &'a Self::RenderResources) {
+    pass.set_pipeline(&self.pipeline);
+    pass.set_bind_group(0, resources.global_bg, &[]);
+    pass.set_bind_group(1, &self.material_bg, &[]);
+    pass.set_vertex_buffer(0, self.extractor.vertex_buf().slice(..));
+    pass.draw_indirect(self.extractor.args_buf(), 0);
+  }
+}
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, DepthStencilState, RenderPipeline, ShaderStages,
+};
+
+use crate::render::{
+  render_target::{ExternalResources, RenderTarget},
+  AsBuffer,
+};
+
+/// Parts of the scene drawn behind the fluid
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneLayers {
+  /// Checkerboard at the floor of the domain
+  pub ground: bool,
+  /// The far walls of the tank
+  pub walls: bool,
+  /// The axes, see [`super::gizmo::Gizmo`]
+  pub gizmo: bool,
+}
+
+impl Default for SceneLayers {
+  fn default() -> Self {
+    Self {
+      ground: true,
+      walls: true,
+      gizmo: false,
+    }
+  }
+}
+
+/// Draws the ground plane and the walls of the domain the particles are kept in
+pub struct Tank {
+  ground: RenderPipeline,
+  walls: RenderPipeline,
+  params_buf: Buffer,
+  params_bg: BindGroup,
+  /// [`crate::render::targets::simulation::SimulationParams::w`] in `params_buf`
+  w: f32,
+}
+
+pub struct TankResources<'a> {
+  pub global_group: &'a BindGroup,
+  /// Half-width of the tank
+  pub w: f32,
+  pub layers: SceneLayers,
+}
+
+impl<'a> ExternalResources<'a> for TankResources<'a> {}
+
+/// The `TankParams` of the shader: the half-width, the height of the walls and the size of the
+/// ground cells. The walls are as high as the tank is wide, the ceiling of the solver is far above.
+fn tank_params(w: f32) -> [f32; 4] {
+  [w, 2.0 * w, 0.5 * w, 0.0]
+}
+
+impl Tank {
+  pub fn new(
+    device: &wgpu::Device,
+    format: &wgpu::TextureFormat,
+    global_layout: &wgpu::BindGroupLayout,
+    depth_stencil: &DepthStencilState,
+  ) -> Self {
+    let w = 0.0;
+    let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("Tank params"),
+      contents: tank_params(w).as_bytes_buffer(),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let params_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Tank params layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      }],
+    });
+    let params_bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Tank params"),
+      layout: &params_layout,
+      entries: &[BindGroupEntry {
+        binding: 0,
+        resource: params_buf.as_entire_binding(),
+      }],
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+      label: Some("Tank pipeline layout"),
+      bind_group_layouts: &[global_layout, &params_layout],
+      push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/tank.wgsl"));
+    let ground_desc = wgpu::RenderPipelineDescriptor {
+      label: Some("Ground pipeline"),
+      layout: Some(&layout),
+      vertex: wgpu::VertexState {
+        module: &shader,
+        entry_point: Some("vs_ground"),
+        compilation_options: Default::default(),
+        buffers: &[],
+      },
+      primitive: wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: None,
+        unclipped_depth: false,
+        polygon_mode: wgpu::PolygonMode::Fill,
+        conservative: false,
+      },
+      depth_stencil: Some(depth_stencil.clone()),
+      multisample: wgpu::MultisampleState {
+        count: 1,
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+      },
+      fragment: Some(wgpu::FragmentState {
+        module: &shader,
+        entry_point: Some("fs_ground"),
+        compilation_options: Default::default(),
+        targets: &[Some(wgpu::ColorTargetState {
+          format: *format,
+          blend: Some(wgpu::BlendState::REPLACE),
+          write_mask: wgpu::ColorWrites::all(),
+        })],
+      }),
+      multiview: None,
+      cache: None,
+    };
+    let ground = device.create_render_pipeline(&ground_desc);
+    // Translucent, so the ground behind them stays visible
+    let wall_targets = [Some(wgpu::ColorTargetState {
+      format: *format,
+      blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+      write_mask: wgpu::ColorWrites::all(),
+    })];
+    let walls = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+      label: Some("Walls pipeline"),
+      vertex: wgpu::VertexState {
+        entry_point: Some("vs_walls"),
+        ..ground_desc.vertex.clone()
+      },
+      depth_stencil: Some(with!(depth_stencil.clone() => depth_write_enabled = false)),
+      fragment: Some(wgpu::FragmentState {
+        entry_point: Some("fs_walls"),
+        targets: &wall_targets,
+        ..ground_desc.fragment.clone().unwrap()
+      }),
+      ..ground_desc.clone()
+    });
+
+    Self {
+      ground,
+      walls,
+      params_buf,
+      params_bg,
+      w,
+    }
+  }
+}
+
+impl<'a> RenderTarget<'a> for Tank {
+  type RenderResources = TankResources<'a>;
+
+  fn update(
+    &mut self,
+    _device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    resources: &'a Self::RenderResources,
+    _encoder: &mut wgpu::CommandEncoder,
+  ) {
+    if resources.w != self.w {
+      self.w = resources.w;
+      queue.write_buffer(&self.params_buf, 0, tank_params(self.w).as_bytes_buffer());
+    }
+  }
+
+  fn render_into_pass(&self, pass: &mut wgpu::RenderPass, resources: &'a Self::RenderResources) {
+    pass.set_bind_group(0, resources.global_group, &[]);
+    pass.set_bind_group(1, &self.params_bg, &[]);
+    if resources.layers.ground {
+      pass.set_pipeline(&self.ground);
+      pass.draw(0..6, 0..1);
+    }
+    if resources.layers.walls {
+      pass.set_pipeline(&self.walls);
+      pass.draw(0..24, 0..1);
+    }
+  }
+}
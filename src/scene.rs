@@ -0,0 +1,115 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  render::targets::simulation::SimulationParams,
+  solvers::{
+    emitters::{Emitter, Sink},
+    storage::StorageLayout,
+  },
+};
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use super::Scene;
+  use crate::solvers::{emitters::Emitter, sort_keys::SortKey, storage::StorageLayout};
+
+  #[test]
+  fn ron_roundtrip() {
+    let mut scene = Scene {
+      count: 1024,
+      storage: StorageLayout::ConcatenatedArrays,
+      ..Default::default()
+    };
+    scene.params.h = 0.05;
+    scene.params.sort_key = SortKey::Hilbert;
+    scene.emitters.push(Emitter::Nozzle {
+      pos: Point3::new(0.0, 1.0, 0.0),
+      dir: Vector3::new(1.0, 0.0, 0.0),
+      radius: 0.1,
+      rate: 500.0,
+      speed: 2.0,
+    });
+    let parsed = Scene::from_ron(&scene.to_ron().unwrap()).unwrap();
+    assert_eq!(parsed, scene);
+  }
+
+  #[test]
+  fn missing_fields_are_default() {
+    let scene = Scene::from_ron("(count: 100, params: (h: 0.1))").unwrap();
+    assert_eq!(scene.count, 100);
+    assert_eq!(scene.params.h, 0.1);
+    assert_eq!(scene.params.rho0, Scene::default().params.rho0);
+    assert!(scene.emitters.is_empty());
+  }
+}
+
+/// Initial setup of a simulation, stored in the RON format
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+  /// Capacity of the particle buffers
+  pub count: usize,
+  pub storage: StorageLayout,
+  pub params: SimulationParams,
+  pub emitters: Vec<Emitter>,
+  pub sinks: Vec<Sink>,
+}
+
+impl Default for Scene {
+  fn default() -> Self {
+    Self {
+      count: 8192,
+      storage: Default::default(),
+      params: Default::default(),
+      emitters: Vec::new(),
+      sinks: Vec::new(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+  Io(io::Error),
+  Parse(ron::de::SpannedError),
+  Serialize(ron::Error),
+}
+
+impl fmt::Display for SceneError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SceneError::Io(e) => write!(f, "{e}"),
+      SceneError::Parse(e) => write!(f, "Invalid scene: {e}"),
+      SceneError::Serialize(e) => write!(f, "Unable to serialize the scene: {e}"),
+    }
+  }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+  fn from(e: io::Error) -> Self {
+    SceneError::Io(e)
+  }
+}
+
+impl Scene {
+  pub fn from_ron(s: &str) -> Result<Self, SceneError> {
+    ron::from_str(s).map_err(SceneError::Parse)
+  }
+
+  pub fn to_ron(&self) -> Result<String, SceneError> {
+    ron::ser::to_string_pretty(self, Default::default()).map_err(SceneError::Serialize)
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+    Self::from_ron(&fs::read_to_string(path)?)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+    Ok(fs::write(path, self.to_ron()?)?)
+  }
+}
@@ -4,25 +4,33 @@ struct Params {
   k: u32,
   tq: u32
 }
-struct Particle {
-  pos: vec3<f32>,
-  density: f32,
-  velocity: vec3<f32>,
-  forces: vec3<f32>,
-}
-
-@group(0) @binding(0)
-var<storage, read_write> cur_particles: array<Particle>;
-@group(0) @binding(1)
-var<storage, read_write> old_particles: array<Particle>;
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
 
 var<push_constant> p: Params;
 
+override DESCENDING: bool = false;
+
+/// Returns `true` if `a` goes before `b` in the ascending order.
+/// The function is replaced with the one returning `SortOptions::comparator` by the sorter,
+/// its declaration must be kept the same as `LESS_DECL` there.
+fn less(a: Particle, b: Particle) -> bool {
+  return a.pos.x < b.pos.x;
+}
+
+fn out_of_order(l: Particle, r: Particle) -> bool {
+  if DESCENDING {
+    return less(l, r);
+  }
+  return less(r, l);
+}
+
 fn global_cas(l: u32, r: u32) {
-  if cur_particles[l].pos.x > cur_particles[r].pos.x {
-    let buf = cur_particles[l];
-    cur_particles[l] = cur_particles[r];
-    cur_particles[r] = buf;
+  let a = load_cur(l);
+  let b = load_cur(r);
+  if out_of_order(a, b) {
+    store_cur(l, b);
+    store_cur(r, a);
   }
 }
 
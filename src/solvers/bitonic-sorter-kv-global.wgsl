@@ -0,0 +1,61 @@
+const WG_SIZE: u32 = 64u;
+
+struct Params {
+  k: u32,
+  tq: u32
+}
+
+/// `x` is the key, `y` is the index of the particle
+@group(0) @binding(0)
+var<storage, read_write> pairs: array<vec2<u32>>;
+
+var<push_constant> p: Params;
+
+// This constant **must** be kept the same as `DEAD_KEY` in `sort-keys.wgsl`
+const DEAD_KEY: u32 = 0xffffffffu;
+override DESCENDING: bool = false;
+override STABLE: bool = false;
+
+/// Returns `true` if the pair `r` goes before the pair `l`
+fn out_of_order(l: vec2<u32>, r: vec2<u32>) -> bool {
+  if l.x == r.x {
+    return STABLE && l.y > r.y;
+  }
+  // Dead particles stay at the end in both orders
+  if DESCENDING && l.x != DEAD_KEY && r.x != DEAD_KEY {
+    return l.x < r.x;
+  }
+  return l.x > r.x;
+}
+
+fn global_cas(l: u32, r: u32) {
+  if out_of_order(pairs[l], pairs[r]) {
+    let buf = pairs[l];
+    pairs[l] = pairs[r];
+    pairs[r] = buf;
+  }
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn flip_global(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let j = gii.x;
+  let flh = 1u << (p.k - p.tq);
+  // block num = j / (ops per block) = j / (height/2) = 2j / height
+  let flb = 2u * j / flh;
+  // operation number
+  let lo = j % (flh/2);
+  // offset of block
+  let go = flh * flb;
+  global_cas(go + lo, go + flh - lo - 1);
+}
+
+// Performs one 'stage' of disperse in global memory
+@compute @workgroup_size(WG_SIZE)
+fn disperse_global(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let i = gii.x;
+  let dbh = 1u << p.tq;
+  let dib = 2u * i / dbh;
+  let go = dib * dbh;
+  let jj = i % (dbh/2);
+  global_cas(go + jj, go + jj + dbh / 2);
+}
@@ -0,0 +1,96 @@
+// Replaced with the local pass size supported by the device, see `SortMode::local_pass_size`
+const WG_SIZE: u32 = 512;
+const LOCAL_ARRAY_LEN: u32 = WG_SIZE * 2;
+
+/// `x` is the key, `y` is the index of the particle
+var<workgroup> local: array<vec2<u32>, LOCAL_ARRAY_LEN>;
+
+@group(0) @binding(0)
+var<storage, read_write> pairs: array<vec2<u32>>;
+
+// This constant **must** be kept the same as `DEAD_KEY` in `sort-keys.wgsl`
+const DEAD_KEY: u32 = 0xffffffffu;
+override DESCENDING: bool = false;
+override STABLE: bool = false;
+
+/// Returns `true` if the pair `r` goes before the pair `l`
+fn out_of_order(l: vec2<u32>, r: vec2<u32>) -> bool {
+  if l.x == r.x {
+    return STABLE && l.y > r.y;
+  }
+  // Dead particles stay at the end in both orders
+  if DESCENDING && l.x != DEAD_KEY && r.x != DEAD_KEY {
+    return l.x < r.x;
+  }
+  return l.x > r.x;
+}
+
+fn local_cas(l: u32, r: u32) {
+  if out_of_order(local[l], local[r]) {
+    let buf = local[l];
+    local[l] = local[r];
+    local[r] = buf;
+  }
+}
+
+/// q = log(height)
+/// i = the number of operator
+fn do_disperse_local_stage(q: u32, i: u32) {
+  // 2^q is the height of disperse block in the stage
+  let dbh = 1u << q;
+  let dib = 2u * i / dbh;
+  let go = dib * dbh;
+  let jj = i % (dbh/2);
+  local_cas(go + jj, go + jj + dbh / 2);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn flip_local(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>) {
+  let j = lii.x;
+  local[2*j] = pairs[LOCAL_ARRAY_LEN*wid.x + 2*j];
+  local[2*j+1] = pairs[LOCAL_ARRAY_LEN*wid.x + 2*j + 1];
+  workgroupBarrier();
+
+  let k = countTrailingZeros(LOCAL_ARRAY_LEN);
+  for (var _t: u32 = 0; _t <= k-1; _t += 1u) {
+    let t = k-1 - _t;
+    // height of a flip block
+    let flh = 1u << (k - t);
+    let flb = 2u * j / flh;
+    let lo = j % (flh/2);
+    let go = flh * flb;
+    local_cas(go + lo, go + flh - lo - 1);
+    workgroupBarrier();
+
+    for (var _q: u32 = 1; _q <= k - t; _q += 1u) {
+      let q = k - t - _q;
+      do_disperse_local_stage(q, j);
+      workgroupBarrier();
+    }
+  }
+  pairs[LOCAL_ARRAY_LEN*wid.x + 2*j] = local[2*j];
+  pairs[LOCAL_ARRAY_LEN*wid.x + 2*j + 1] = local[2*j+1];
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn disperse_local(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let j = lii.x;
+  local[2*j] = pairs[LOCAL_ARRAY_LEN*wid.x + 2*j];
+  local[2*j+1] = pairs[LOCAL_ARRAY_LEN*wid.x + 2*j + 1];
+  workgroupBarrier();
+
+  let k = countTrailingZeros(LOCAL_ARRAY_LEN);
+  for (var _q: u32 = 0; _q <= k; _q += 1u) {
+    let q = k - _q;
+    do_disperse_local_stage(q, j);
+    workgroupBarrier();
+  }
+
+  pairs[LOCAL_ARRAY_LEN*wid.x + 2*j] = local[2*j];
+  pairs[LOCAL_ARRAY_LEN*wid.x + 2*j + 1] = local[2*j+1];
+}
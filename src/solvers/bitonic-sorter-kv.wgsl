@@ -0,0 +1,17 @@
+// Permutation passes of the key/value sort mode.
+// The keys are computed by `sort-keys.wgsl`
+const WG_SIZE: u32 = 64u;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+/// `x` is the key, `y` is the index of the particle
+@group(1) @binding(0)
+var<storage, read_write> pairs: array<vec2<u32>>;
+
+// Writes the sorted particles to the old buffer, which the sorter flips to afterwards
+@compute @workgroup_size(WG_SIZE)
+fn gather(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let i = gii.x;
+  store_old(i, load_cur(pairs[i].y));
+}
@@ -1,22 +1,30 @@
+// Replaced with the local pass size supported by the device, see `SortMode::local_pass_size`
 const WG_SIZE: u32 = 512;
 const LOCAL_ARRAY_LEN: u32 = WG_SIZE * 2;
 
-struct Particle {
-  pos: vec3<f32>,
-  density: f32,
-  velocity: vec3<f32>,
-  forces: vec3<f32>,
-}
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
 
 var<workgroup> local: array<Particle, LOCAL_ARRAY_LEN>;
 
-@group(0) @binding(0)
-var<storage, read_write> cur_particles: array<Particle>;
-@group(0) @binding(1)
-var<storage, read_write> old_particles: array<Particle>;
+override DESCENDING: bool = false;
+
+/// Returns `true` if `a` goes before `b` in the ascending order.
+/// The function is replaced with the one returning `SortOptions::comparator` by the sorter,
+/// its declaration must be kept the same as `LESS_DECL` there.
+fn less(a: Particle, b: Particle) -> bool {
+  return a.pos.x < b.pos.x;
+}
+
+fn out_of_order(l: Particle, r: Particle) -> bool {
+  if DESCENDING {
+    return less(l, r);
+  }
+  return less(r, l);
+}
 
 fn local_cas(l: u32, r: u32) {
-  if local[l].pos.x > local[r].pos.x {
+  if out_of_order(local[l], local[r]) {
     let buf = local[l];
     local[l] = local[r];
     local[r] = buf;
@@ -41,8 +49,8 @@ fn flip_local(
   @builtin(workgroup_id) wid: vec3<u32>) {
   let i = gii.x;
   let j = lii.x;
-  local[2*j] = cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j];
-  local[2*j+1] = cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j + 1];
+  local[2*j] = load_cur(LOCAL_ARRAY_LEN*wid.x + 2*j);
+  local[2*j+1] = load_cur(LOCAL_ARRAY_LEN*wid.x + 2*j + 1);
   workgroupBarrier();
   
   let n = LOCAL_ARRAY_LEN;
@@ -67,8 +75,8 @@ fn flip_local(
       workgroupBarrier();
     }
   }
-  cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j] = local[2*j];
-  cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j + 1] = local[2*j+1];
+  store_cur(LOCAL_ARRAY_LEN*wid.x + 2*j, local[2*j]);
+  store_cur(LOCAL_ARRAY_LEN*wid.x + 2*j + 1, local[2*j+1]);
 }
 
 @compute @workgroup_size(WG_SIZE)
@@ -77,8 +85,8 @@ fn disperse_local(
   @builtin(workgroup_id) wid: vec3<u32>
 ) {
   let j = lii.x;
-  local[2*j] = cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j];
-  local[2*j+1] = cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j + 1];
+  local[2*j] = load_cur(LOCAL_ARRAY_LEN*wid.x + 2*j);
+  local[2*j+1] = load_cur(LOCAL_ARRAY_LEN*wid.x + 2*j + 1);
   workgroupBarrier();
 
   let k = countTrailingZeros(LOCAL_ARRAY_LEN);
@@ -88,6 +96,6 @@ fn disperse_local(
     workgroupBarrier();
   }
 
-  cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j] = local[2*j];
-  cur_particles[LOCAL_ARRAY_LEN*wid.x + 2*j + 1] = local[2*j+1];
+  store_cur(LOCAL_ARRAY_LEN*wid.x + 2*j, local[2*j]);
+  store_cur(LOCAL_ARRAY_LEN*wid.x + 2*j + 1, local[2*j+1]);
 }
\ No newline at end of file
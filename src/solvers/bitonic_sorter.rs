@@ -1,19 +1,37 @@
+use std::{collections::HashMap, sync::Arc};
+
 use wgpu::{
-  ComputePassDescriptor, ComputePipelineDescriptor, PipelineLayoutDescriptor, PushConstantRange,
-  ShaderStages,
+  BindGroupLayoutDescriptor, BindGroupLayoutEntry, ComputePassDescriptor,
+  ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderStages,
+};
+
+use crate::render::{profiler::GpuProfiler, swapchain::SwapBuffers};
+
+use super::{
+  pass_params::{PassParams, PreparedParams},
+  sort_keys::{KeyGrid, KeyPass, SortKey},
+  sph_solver_gpu::Particle,
+  storage::{ParticleData, StorageLayout},
+  GpuSorter,
 };
 
-/// Count of workgroups in a local pass that sorts subarrays of length `2*LOCAL_PASS_SIZE`
+/// Maximal workgroup size of a local pass that sorts subarrays of length `2*LOCAL_PASS_SIZE`
 /// and performs disperse using local memory optimizations.
+/// The size actually used is chosen by [`SortMode::local_pass_size`].
 pub const LOCAL_PASS_SIZE: u32 = 512;
 pub const LOCAL_ARRAY_SIZE: u32 = 2 * LOCAL_PASS_SIZE;
-const LOG_LOCAL_ARRAY_SIZE: u32 = LOCAL_ARRAY_SIZE.trailing_zeros();
 pub const GLOBAL_PASS_SIZE: u32 = 64;
+/// Declaration of the workgroup size in the local pass shaders
+const LOCAL_WG_SIZE_DECL: &str = "const WG_SIZE: u32 = 512;";
+/// Workgroup size of the permutation passes of [`SortMode::KeyValue`].
+/// This constant **must** be kept the same as `WG_SIZE` in `bitonic-sorter-kv.wgsl`.
+const KV_PASS_SIZE: u32 = 64;
 
 #[cfg(test)]
 mod test {
   use core::slice;
 
+  use cgmath::Point3;
   use rand::distr::Distribution;
   use wgpu::{
     BufferUsages, ComputePassDescriptor, Features, InstanceDescriptor, Limits,
@@ -22,7 +40,13 @@ mod test {
 
   use crate::{
     render::swapchain::{SwapBuffers, SwapBuffersDescriptor},
-    solvers::{bitonic_sorter::ParticleBitonicSorter, sph_solver_gpu::Particle},
+    solvers::{
+      bitonic_sorter::{with_comparator, ParticleBitonicSorter, SortMode, SortOptions, SortOrder},
+      emitters::DEAD_POS,
+      sph_solver_gpu::Particle,
+      storage::{ParticleData, StorageLayout},
+      GpuSorter,
+    },
   };
 
   async fn setup_wgpu() -> Result<(wgpu::Device, wgpu::Queue), ()> {
@@ -64,10 +88,10 @@ mod test {
     let (mut buf, obuf) = particle_gpu(array, &device).await;
 
     buf.write(queue);
-    let sorter = ParticleBitonicSorter::new(&device, buf.cur_layout());
+    let sorter = ParticleBitonicSorter::new(&device, queue, buf.cur_layout());
     let mut encoder =
       device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    sorter.sort(&mut encoder, buf.cur_group(), 1024);
+    sorter.sort(&mut encoder, &mut buf, 1024);
     let cmd = encoder.finish();
     let mut e = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
     e.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * 1024);
@@ -104,7 +128,7 @@ mod test {
     let (mut buf, obuf) = particle_gpu(array, &device).await;
 
     buf.write(queue);
-    let sorter = ParticleBitonicSorter::new(&device, buf.cur_layout());
+    let sorter = ParticleBitonicSorter::new(&device, queue, buf.cur_layout());
     let mut encoder =
       device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
     let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
@@ -165,10 +189,10 @@ mod test {
     let (mut buf, obuf) = particle_gpu(array, &device).await;
 
     buf.write(queue);
-    let sorter = ParticleBitonicSorter::new(&device, buf.cur_layout());
+    let sorter = ParticleBitonicSorter::new(&device, queue, buf.cur_layout());
     let mut encoder =
       device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    sorter.sort(&mut encoder, buf.cur_group(), COUNT as u32);
+    sorter.sort(&mut encoder, &mut buf, COUNT as u32);
     let cmd = encoder.finish();
     let mut e = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
     e.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * COUNT as u64);
@@ -197,12 +221,164 @@ mod test {
     Ok(())
   }
 
+  #[tokio::test]
+  async fn gpu_bitonic_sort_key_value_16384() -> Result<(), ()> {
+    const COUNT: usize = 16384;
+    let (device, ref mut queue) = setup_wgpu().await?;
+    let mut array = particle_array(COUNT, -100., 100.).await;
+    // Tag every particle to check that the result is a permutation of the input
+    for (i, p) in array.iter_mut().enumerate() {
+      p.density = i as f32;
+    }
+    let (mut buf, obuf) = particle_gpu(array, &device).await;
+
+    buf.write(queue);
+    let sorter = ParticleBitonicSorter::with_mode(
+      &device,
+      queue,
+      buf.cur_layout(),
+      SortMode::KeyValue,
+      COUNT as u32,
+    );
+    let mut encoder =
+      device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    sorter.sort(&mut encoder, &mut buf, COUNT as u32);
+    encoder.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * COUNT as u64);
+    queue.submit([encoder.finish()]);
+
+    obuf
+      .slice(..)
+      .map_async(wgpu::MapMode::Read, |a| a.unwrap());
+    device.poll(wgpu::MaintainBase::Wait);
+    let v = unsafe {
+      slice::from_raw_parts::<Particle>(obuf.slice(..).get_mapped_range().as_ptr().cast(), COUNT)
+        .to_vec()
+    };
+    obuf.unmap();
+
+    if let Err(fail) = is_sorted(&v) {
+      panic!("The array is not sorted. First element out of order has index {fail}");
+    }
+    let mut tags: Vec<usize> = v.iter().map(|p| p.density as usize).collect();
+    tags.sort();
+    assert!(
+      tags.into_iter().eq(0..COUNT),
+      "The result is not a permutation of the input"
+    );
+    Ok(())
+  }
+
+  /// Sorts `array` on the GPU and returns the result
+  async fn sort_with(
+    mode: SortMode,
+    options: SortOptions,
+    array: Vec<Particle>,
+  ) -> Result<Vec<Particle>, ()> {
+    let count = array.len();
+    let (device, ref mut queue) = setup_wgpu().await?;
+    let (mut buf, obuf) = particle_gpu(array, &device).await;
+    buf.write(queue);
+    let sorter = ParticleBitonicSorter::with_options(
+      &device,
+      queue,
+      buf.cur_layout(),
+      mode,
+      count as u32,
+      &options,
+    );
+    let mut encoder =
+      device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    sorter.sort(&mut encoder, &mut buf, count as u32);
+    encoder.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * count as u64);
+    queue.submit([encoder.finish()]);
+
+    obuf
+      .slice(..)
+      .map_async(wgpu::MapMode::Read, |a| a.unwrap());
+    device.poll(wgpu::MaintainBase::Wait);
+    let v = unsafe {
+      slice::from_raw_parts::<Particle>(obuf.slice(..).get_mapped_range().as_ptr().cast(), count)
+        .to_vec()
+    };
+    obuf.unmap();
+    Ok(v)
+  }
+
+  #[tokio::test]
+  async fn gpu_bitonic_sort_descending_stable() -> Result<(), ()> {
+    const COUNT: usize = 4096;
+    const DEAD: usize = 100;
+    let mut array = particle_array(COUNT, 0., 8.).await;
+    for (i, p) in array.iter_mut().enumerate() {
+      // Plenty of equal keys
+      p.pos.x = p.pos.x.floor();
+      p.density = i as f32;
+      if i % (COUNT / DEAD) == 0 {
+        p.pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS);
+      }
+    }
+    let dead = array.iter().filter(|p| p.pos.x == DEAD_POS).count();
+    let options = SortOptions {
+      order: SortOrder::Descending,
+      stable: true,
+      ..Default::default()
+    };
+    let v = sort_with(SortMode::KeyValue, options, array).await?;
+
+    let (alive, tail) = v.split_at(COUNT - dead);
+    assert!(
+      tail.iter().all(|p| p.pos.x == DEAD_POS),
+      "Dead particles must be placed at the end"
+    );
+    for (i, w) in alive.windows(2).enumerate() {
+      assert!(
+        w[0].pos.x > w[1].pos.x || (w[0].pos.x == w[1].pos.x && w[0].density < w[1].density),
+        "Elements {i} and {} are out of order: {:?} {:?}",
+        i + 1,
+        (w[0].pos.x, w[0].density),
+        (w[1].pos.x, w[1].density)
+      );
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_bitonic_sort_custom_comparator() -> Result<(), ()> {
+    const COUNT: usize = 4096;
+    let mut array = particle_array(COUNT, -100., 100.).await;
+    for p in array.iter_mut() {
+      p.density = p.pos.x * p.pos.x;
+    }
+    let options = SortOptions {
+      comparator: Some("a.density < b.density".to_owned()),
+      ..Default::default()
+    };
+    let v = sort_with(SortMode::Particles, options, array).await?;
+    assert!(
+      v.is_sorted_by(|a, b| a.density <= b.density),
+      "The array is not sorted by density"
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn comparator_replaced() {
+    for source in [
+      include_str!("bitonic-sorter-local.wgsl"),
+      include_str!("bitonic-sorter-global.wgsl"),
+    ] {
+      let patched = with_comparator(source, "a.density < b.density");
+      assert!(patched.contains("return a.density < b.density;"));
+      assert!(!patched.contains("a.pos.x < b.pos.x"));
+    }
+  }
+
   async fn particle_gpu(
     array: Vec<Particle>,
     device: &wgpu::Device,
-  ) -> (SwapBuffers<Vec<Particle>>, wgpu::Buffer) {
+  ) -> (SwapBuffers<ParticleData>, wgpu::Buffer) {
     let buf = SwapBuffers::init_with(
-      array.clone(),
+      ParticleData::new(StorageLayout::ArrayOfStructs, &array),
       device,
       SwapBuffersDescriptor {
         usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
@@ -232,22 +408,229 @@ mod test {
   }
 }
 
+/// The data the bitonic network is applied to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortMode {
+  /// Compare-and-swap whole particles. Requires `2*local_pass_size` particles of workgroup memory.
+  Particles,
+  /// Sort `(key, index)` pairs and permute the particles once afterwards.
+  /// The particles are gathered into the old buffer, which becomes the current one.
+  #[default]
+  KeyValue,
+}
+
+impl SortMode {
+  /// Workgroup memory required by the local passes of `local_pass_size` invocations, in bytes
+  pub const fn workgroup_storage_size(self, local_pass_size: u32) -> u32 {
+    let element = match self {
+      SortMode::Particles => size_of::<Particle>(),
+      SortMode::KeyValue => size_of::<[u32; 2]>(),
+    };
+    2 * local_pass_size * element as u32
+  }
+
+  /// The largest power of 2 up to [`LOCAL_PASS_SIZE`] that the `limits` allow as the
+  /// workgroup size of the local passes. It is never less than [`GLOBAL_PASS_SIZE`].
+  pub fn local_pass_size(self, limits: &wgpu::Limits) -> u32 {
+    let mut size = LOCAL_PASS_SIZE;
+    while size > GLOBAL_PASS_SIZE
+      && (size > limits.max_compute_invocations_per_workgroup
+        || size > limits.max_compute_workgroup_size_x
+        || self.workgroup_storage_size(size) > limits.max_compute_workgroup_storage_size)
+    {
+      size /= 2;
+    }
+    size
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+  #[default]
+  Ascending,
+  /// In the [`SortMode::KeyValue`] mode the dead particles are still placed at the end
+  Descending,
+}
+
+/// Parameters of the comparison of the particles
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SortOptions {
+  pub order: SortOrder,
+  /// Keep the particles with equal keys in their original order.
+  /// Only supported in the [`SortMode::KeyValue`] mode.
+  pub stable: bool,
+  /// WGSL expression that is `true` if the particle `a` goes before the particle `b` in the
+  /// ascending order, e.g. `a.density < b.density`. Only supported in the [`SortMode::Particles`]
+  /// mode, the [`SortKey`] selects the order of the [`SortMode::KeyValue`] one.
+  pub comparator: Option<String>,
+  /// Layout of the sorted particle buffers
+  pub storage: StorageLayout,
+}
+
+/// The comparator of the [`SortMode::Particles`] shaders if [`SortOptions::comparator`] isn't set
+const DEFAULT_COMPARATOR: &str = "a.pos.x < b.pos.x";
+/// Declaration of the comparison function in the [`SortMode::Particles`] shaders,
+/// replaced by [`with_comparator`]
+const LESS_DECL: &str =
+  "fn less(a: Particle, b: Particle) -> bool {\n  return a.pos.x < b.pos.x;\n}";
+
+/// Replaces the comparison function of the `source` with the one returning `comparator`
+fn with_comparator(source: &str, comparator: &str) -> String {
+  assert_eq!(
+    source.matches(LESS_DECL).count(),
+    1,
+    "the shader must declare the comparison function exactly once as `{LESS_DECL}`"
+  );
+  source.replace(
+    LESS_DECL,
+    &format!("fn less(a: Particle, b: Particle) -> bool {{\n  return {comparator};\n}}"),
+  )
+}
+
+/// Resources of the [`SortMode::KeyValue`] mode
+struct KeyValueStage {
+  keys: KeyPass,
+  gather: wgpu::ComputePipeline,
+  pairs_bg: wgpu::BindGroup,
+  capacity: u32,
+}
+
 pub struct ParticleBitonicSorter {
   flip_local: wgpu::ComputePipeline,
   disperse_local: wgpu::ComputePipeline,
   flip_global: wgpu::ComputePipeline,
   disperse_global: wgpu::ComputePipeline,
+  key_value: Option<KeyValueStage>,
+  profiler: Option<Arc<GpuProfiler>>,
+  /// Parameters of the global passes
+  params: PassParams,
+  /// Length of the subarrays sorted by the local passes
+  local_array_size: u32,
 }
 
 impl ParticleBitonicSorter {
+  /// Creates a sorter in the [`SortMode::Particles`] mode
   pub fn new(
     device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+  ) -> ParticleBitonicSorter {
+    Self::with_mode(device, queue, particle_layout, SortMode::Particles, 0)
+  }
+
+  /// Creates a sorter able to sort up to `capacity` particles.
+  /// `capacity` is ignored in the [`SortMode::Particles`] mode.
+  pub fn with_mode(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+    mode: SortMode,
+    capacity: u32,
+  ) -> ParticleBitonicSorter {
+    Self::with_options(
+      device,
+      queue,
+      particle_layout,
+      mode,
+      capacity,
+      &SortOptions::default(),
+    )
+  }
+
+  /// Creates a sorter comparing the particles according to `options`.
+  /// Panics if the options are not supported in the `mode`.
+  pub fn with_options(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
     particle_layout: &wgpu::BindGroupLayout,
+    mode: SortMode,
+    capacity: u32,
+    options: &SortOptions,
   ) -> ParticleBitonicSorter {
-    let module = &device.create_shader_module(wgpu::include_wgsl!("bitonic-sorter-local.wgsl"));
+    let mut constants = HashMap::from([(
+      "DESCENDING".to_owned(),
+      (options.order == SortOrder::Descending) as u32 as f64,
+    )]);
+    let local_size = mode.local_pass_size(&device.limits());
+    let local_source = |source: &str| {
+      source.replace(
+        LOCAL_WG_SIZE_DECL,
+        &format!("const WG_SIZE: u32 = {local_size};"),
+      )
+    };
+    let params = PassParams::new(device, queue, 8, 1);
+    let (local_module, global_module, network_layout) = match mode {
+      SortMode::Particles => {
+        assert!(
+          !options.stable,
+          "stable sorting is not supported in the `SortMode::Particles` mode"
+        );
+        let comparator = options.comparator.as_deref().unwrap_or(DEFAULT_COMPARATOR);
+        let module = |label, source: &str| {
+          options
+            .storage
+            .create_shader_module(device, label, &with_comparator(source, comparator))
+        };
+        (
+          module(
+            "bitonic-sorter-local.wgsl",
+            &local_source(include_str!("bitonic-sorter-local.wgsl")),
+          ),
+          module(
+            "bitonic-sorter-global.wgsl",
+            &params.patch(include_str!("bitonic-sorter-global.wgsl")),
+          ),
+          particle_layout.clone(),
+        )
+      }
+      SortMode::KeyValue => {
+        assert!(
+          options.comparator.is_none(),
+          "custom comparators are not supported in the `SortMode::KeyValue` mode"
+        );
+        constants.insert("STABLE".to_owned(), options.stable as u32 as f64);
+        let module = |label, source: String| {
+          device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+          })
+        };
+        (
+          module(
+            "bitonic-sorter-kv-local.wgsl",
+            local_source(include_str!("bitonic-sorter-kv-local.wgsl")),
+          ),
+          module(
+            "bitonic-sorter-kv-global.wgsl",
+            params
+              .patch(include_str!("bitonic-sorter-kv-global.wgsl"))
+              .into_owned(),
+          ),
+          device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("BitonicSorter pairs"),
+            entries: &[BindGroupLayoutEntry {
+              binding: 0,
+              visibility: ShaderStages::COMPUTE,
+              ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+              },
+              count: None,
+            }],
+          }),
+        )
+      }
+    };
+    let compilation_options = wgpu::PipelineCompilationOptions {
+      constants: &constants,
+      ..Default::default()
+    };
+
+    let module = &local_module;
     let local_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
       label: Some("BitonicSorter_local"),
-      bind_group_layouts: &[particle_layout],
+      bind_group_layouts: &[&network_layout],
       push_constant_ranges: &[],
     });
     let layout = Some(&local_layout);
@@ -256,7 +639,7 @@ impl ParticleBitonicSorter {
       layout,
       module,
       entry_point: Some("flip_local"),
-      compilation_options: Default::default(),
+      compilation_options: compilation_options.clone(),
       cache: None,
     });
     let disperse_local = device.create_compute_pipeline(&ComputePipelineDescriptor {
@@ -264,18 +647,17 @@ impl ParticleBitonicSorter {
       layout,
       module,
       entry_point: Some("disperse_local"),
-      compilation_options: Default::default(),
+      compilation_options: compilation_options.clone(),
       cache: None,
     });
 
-    let module = &device.create_shader_module(wgpu::include_wgsl!("bitonic-sorter-global.wgsl"));
+    let module = &global_module;
+    let mut bind_group_layouts = vec![&network_layout];
+    bind_group_layouts.extend(params.layout());
     let global_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
       label: Some("BitonicSorter_global"),
-      bind_group_layouts: &[particle_layout],
-      push_constant_ranges: &[PushConstantRange {
-        stages: ShaderStages::COMPUTE,
-        range: 0..8,
-      }],
+      bind_group_layouts: &bind_group_layouts,
+      push_constant_ranges: &params.push_constant_ranges(),
     });
     let layout = Some(&global_layout);
     let flip_global = device.create_compute_pipeline(&ComputePipelineDescriptor {
@@ -283,7 +665,7 @@ impl ParticleBitonicSorter {
       layout,
       module,
       entry_point: Some("flip_global"),
-      compilation_options: Default::default(),
+      compilation_options: compilation_options.clone(),
       cache: None,
     });
     let disperse_global = device.create_compute_pipeline(&ComputePipelineDescriptor {
@@ -291,15 +673,80 @@ impl ParticleBitonicSorter {
       layout,
       module,
       entry_point: Some("disperse_global"),
-      compilation_options: Default::default(),
+      compilation_options: compilation_options.clone(),
       cache: None,
     });
 
+    let key_value = match mode {
+      SortMode::Particles => None,
+      SortMode::KeyValue => Some(Self::create_key_value_stage(
+        device,
+        queue,
+        particle_layout,
+        &network_layout,
+        capacity,
+        options.storage,
+      )),
+    };
+
     ParticleBitonicSorter {
       flip_local,
       disperse_local,
       flip_global,
       disperse_global,
+      key_value,
+      profiler: None,
+      params,
+      local_array_size: 2 * local_size,
+    }
+  }
+
+  fn create_key_value_stage(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+    pairs_layout: &wgpu::BindGroupLayout,
+    capacity: u32,
+    storage: StorageLayout,
+  ) -> KeyValueStage {
+    let pairs_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("BitonicSorter pairs"),
+      size: capacity.max(1) as u64 * size_of::<[u32; 2]>() as u64,
+      usage: wgpu::BufferUsages::STORAGE,
+      mapped_at_creation: false,
+    });
+    let pairs_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("BitonicSorter pairs"),
+      layout: pairs_layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: pairs_buf.as_entire_binding(),
+      }],
+    });
+    let module = &storage.create_shader_module(
+      device,
+      "bitonic-sorter-kv.wgsl",
+      include_str!("bitonic-sorter-kv.wgsl"),
+    );
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("BitonicSorter_kv"),
+      bind_group_layouts: &[particle_layout, pairs_layout],
+      push_constant_ranges: &[],
+    });
+    let layout = Some(&layout);
+    let gather = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("BitonicSorter::gather"),
+      layout,
+      module,
+      entry_point: Some("gather"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+    KeyValueStage {
+      keys: KeyPass::new(device, queue, particle_layout, &pairs_buf, storage),
+      gather,
+      pairs_bg,
+      capacity,
     }
   }
 
@@ -326,55 +773,112 @@ impl ParticleBitonicSorter {
     pass.dispatch_workgroups(count_groups, 1, 1);
   }
 
+  /// `params` holds `[k, x]` at the index `x`
   #[inline(always)]
   fn full_disperse_global(
     &self,
     pass: &mut wgpu::ComputePass,
     particles: &wgpu::BindGroup,
+    params: &PreparedParams,
     t: u32,
     k: u32,
   ) {
+    let log_local_array_size = self.local_array_size.trailing_zeros();
     pass.set_pipeline(&self.disperse_global);
     pass.set_bind_group(0, particles, &[]);
     // FIXME: if sort ever fails, remove `+1`
-    for q in ((LOG_LOCAL_ARRAY_SIZE + 1)..=(k - t)).rev() {
-      pass.set_push_constants(0, &Self::pack(k, q));
+    for q in ((log_local_array_size + 1)..=(k - t)).rev() {
+      params.set(pass, q as usize);
       pass.dispatch_workgroups((1 << (k - 1)) / GLOBAL_PASS_SIZE, 1, 1);
     }
-    self.disperse_local(pass, particles, 1 << (k - LOG_LOCAL_ARRAY_SIZE));
-  }
-  fn pack(a: u32, b: u32) -> [u8; 8] {
-    unsafe { std::mem::transmute([a, b]) }
+    self.disperse_local(pass, particles, 1 << (k - log_local_array_size));
   }
   #[inline(always)]
   fn single_flip_global(
     &self,
     pass: &mut wgpu::ComputePass,
     particles: &wgpu::BindGroup,
+    params: &PreparedParams,
     t: u32,
     k: u32,
   ) {
     debug_assert!(k >= GLOBAL_PASS_SIZE.trailing_zeros());
     pass.set_pipeline(&self.flip_global);
-    pass.set_push_constants(0, &Self::pack(k, t));
+    params.set(pass, t as usize);
     pass.set_bind_group(0, particles, &[]);
     pass.dispatch_workgroups((1 << (k - 1)) / GLOBAL_PASS_SIZE, 1, 1);
   }
-  pub fn sort(&self, encoder: &mut wgpu::CommandEncoder, particles: &wgpu::BindGroup, count: u32) {
+  /// Runs the whole bitonic network over the elements bound to the group `0`
+  fn sort_network(&self, pass: &mut wgpu::ComputePass, elements: &wgpu::BindGroup, count: u32) {
+    let k = count.trailing_zeros();
+    let log_local_array_size = self.local_array_size.trailing_zeros();
+    let params = self.params.prepare((0..=k).map(|x| [k, x]).collect());
+    self.sort_local(pass, elements, count >> log_local_array_size);
+    for t in (0..=(k - log_local_array_size)).rev() {
+      self.single_flip_global(pass, elements, &params, t, k);
+      self.full_disperse_global(pass, elements, &params, t, k);
+    }
+  }
+}
+
+impl GpuSorter for ParticleBitonicSorter {
+  fn sort(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    particles: &mut SwapBuffers<ParticleData>,
+    count: u32,
+  ) {
     assert!(
-      count >= LOCAL_ARRAY_SIZE && count.count_ones() == 1,
-      "`count` must be a power of 2 greater or equal {LOCAL_ARRAY_SIZE}, got {count}"
+      self.supports(count),
+      "`count` must be a power of 2 greater or equal {} fitting the capacity, got {count}",
+      self.local_array_size
     );
-    let pass = &mut encoder.begin_compute_pass(&ComputePassDescriptor {
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
       label: Some("BitonicSort::sort(full)"),
-      timestamp_writes: None,
+      timestamp_writes: self
+        .profiler
+        .as_ref()
+        .and_then(|p| p.compute_writes("Bitonic sort")),
     });
 
-    let k = count.trailing_zeros();
-    self.sort_local(pass, particles, count >> LOG_LOCAL_ARRAY_SIZE);
-    for t in (0..=(k - LOG_LOCAL_ARRAY_SIZE)).rev() {
-      self.single_flip_global(pass, particles, t, k);
-      self.full_disperse_global(pass, particles, t, k);
+    let Some(kv) = &self.key_value else {
+      self.sort_network(&mut pass, particles.cur_group(), count);
+      return;
+    };
+    let groups = count / KV_PASS_SIZE;
+    kv.keys.dispatch(&mut pass, particles.cur_group(), count);
+
+    self.sort_network(&mut pass, &kv.pairs_bg, count);
+
+    pass.set_pipeline(&kv.gather);
+    pass.set_bind_group(0, particles.cur_group(), &[]);
+    pass.set_bind_group(1, &kv.pairs_bg, &[]);
+    pass.dispatch_workgroups(groups, 1, 1);
+    drop(pass);
+    particles.flip();
+  }
+
+  fn set_key(&self, queue: &wgpu::Queue, key: SortKey, grid: &KeyGrid) {
+    match &self.key_value {
+      Some(kv) => kv.keys.set_key(queue, key, grid),
+      None => assert_eq!(
+        key,
+        SortKey::X,
+        "only the x coordinate is supported in the `SortMode::Particles` mode"
+      ),
     }
   }
+
+  fn supports(&self, count: u32) -> bool {
+    count >= self.local_array_size
+      && count.count_ones() == 1
+      && self
+        .key_value
+        .as_ref()
+        .is_none_or(|kv| count <= kv.capacity)
+  }
+
+  fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>) {
+    self.profiler = profiler;
+  }
 }
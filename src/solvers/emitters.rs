@@ -0,0 +1,516 @@
+use core::slice;
+use std::sync::{
+  atomic::{AtomicU8, Ordering},
+  Arc,
+};
+
+use cgmath::{InnerSpace, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+  PipelineLayoutDescriptor, ShaderStages,
+};
+
+use crate::render::{swapchain::SwapBuffers, AsBuffer};
+
+use super::storage::{ParticleData, StorageLayout};
+
+// These constants **must** be kept the same as in the emitter shader.
+pub const EMITTER_WG_SIZE: u32 = 64;
+pub const MAX_EMITTERS: usize = 16;
+pub const MAX_SINKS: usize = 16;
+/// Coordinate of every component of the position of a dead particle.
+/// Dead particles are therefore moved to the end of the array by the sorter.
+pub const DEAD_POS: f32 = 1e30;
+
+/// Offset of the compaction counter in the particle count buffer
+const ALIVE_OFFSET: u64 = 4 * std::mem::size_of::<u32>() as u64;
+/// Offset of the active particle count in the particle count buffer
+const ACTIVE_OFFSET: u64 = std::mem::size_of::<u32>() as u64;
+
+const MAP_PENDING: u8 = 0;
+const MAP_OK: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use super::{Emitter, SourceSchedule};
+
+  fn nozzle(rate: f32) -> Emitter {
+    Emitter::Nozzle {
+      pos: Point3::new(0., 1., 0.),
+      dir: Vector3::new(0., -1., 0.),
+      radius: 0.05,
+      rate,
+      speed: 1.0,
+    }
+  }
+
+  #[test]
+  fn fractional_rates_accumulate() {
+    let mut schedule = SourceSchedule::default();
+    let emitters = [nozzle(10.0)];
+    let spawned: u32 = (0..100)
+      .map(|_| schedule.advance(&emitters, 0.01, u32::MAX)[0])
+      .sum();
+    assert_eq!(spawned, 10);
+  }
+
+  #[test]
+  fn spawns_are_limited_by_free_slots() {
+    let mut schedule = SourceSchedule::default();
+    let emitters = [nozzle(1000.0), nozzle(1000.0)];
+    let spawns = schedule.advance(&emitters, 1.0, 1500);
+    assert_eq!(spawns, vec![1000, 500]);
+  }
+}
+
+/// A volume that produces particles at a constant rate.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Emitter {
+  /// Emits particles from a disc of radius `radius` centered at `pos`
+  /// in the direction `dir` with the speed `speed`.
+  Nozzle {
+    pos: Point3<f32>,
+    dir: Vector3<f32>,
+    radius: f32,
+    /// Particles per second
+    rate: f32,
+    speed: f32,
+  },
+  /// Emits particles at random points of an axis-aligned box.
+  Box {
+    min: Point3<f32>,
+    max: Point3<f32>,
+    /// Particles per second
+    rate: f32,
+    velocity: Vector3<f32>,
+  },
+}
+
+impl Emitter {
+  pub fn rate(&self) -> f32 {
+    match *self {
+      Emitter::Nozzle { rate, .. } | Emitter::Box { rate, .. } => rate,
+    }
+  }
+}
+
+/// A volume that removes the particles entering it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Sink {
+  Box { min: Point3<f32>, max: Point3<f32> },
+  Sphere { center: Point3<f32>, radius: f32 },
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuEmitter {
+  origin: [f32; 3],
+  kind: u32,
+  extent: [f32; 3],
+  radius: f32,
+  velocity: [f32; 3],
+  first: u32,
+  spawn: u32,
+  _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuSink {
+  min: [f32; 3],
+  kind: u32,
+  max: [f32; 3],
+  radius: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SourceParams {
+  n_emitters: u32,
+  n_sinks: u32,
+  seed: u32,
+  total_spawn: u32,
+  dt: f32,
+  _padding: [u32; 3],
+  emitters: [GpuEmitter; MAX_EMITTERS],
+  sinks: [GpuSink; MAX_SINKS],
+}
+
+impl AsBuffer for SourceParams {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<SourceParams>(),
+      )
+    }
+  }
+}
+
+impl From<&Emitter> for GpuEmitter {
+  fn from(e: &Emitter) -> Self {
+    match *e {
+      Emitter::Nozzle {
+        pos,
+        dir,
+        radius,
+        speed,
+        ..
+      } => {
+        let dir = if dir.magnitude2() > 0. {
+          dir.normalize()
+        } else {
+          -Vector3::unit_y()
+        };
+        Self {
+          origin: pos.into(),
+          kind: 0,
+          extent: dir.into(),
+          radius,
+          velocity: (dir * speed).into(),
+          ..Default::default()
+        }
+      }
+      Emitter::Box {
+        min, max, velocity, ..
+      } => Self {
+        origin: min.into(),
+        kind: 1,
+        extent: max.into(),
+        velocity: velocity.into(),
+        ..Default::default()
+      },
+    }
+  }
+}
+
+impl From<&Sink> for GpuSink {
+  fn from(s: &Sink) -> Self {
+    match *s {
+      Sink::Box { min, max } => Self {
+        min: min.into(),
+        kind: 0,
+        max: max.into(),
+        radius: 0.,
+      },
+      Sink::Sphere { center, radius } => Self {
+        min: center.into(),
+        kind: 1,
+        max: center.into(),
+        radius,
+      },
+    }
+  }
+}
+
+/// Converts the emission rates into the integer number of particles spawned every step.
+#[derive(Default)]
+struct SourceSchedule {
+  accum: Vec<f32>,
+}
+
+impl SourceSchedule {
+  /// Returns the number of particles spawned by every emitter.
+  /// The total count never exceeds `free`.
+  fn advance(&mut self, emitters: &[Emitter], dt: f32, free: u32) -> Vec<u32> {
+    self.accum.resize(emitters.len(), 0.);
+    let mut free = free;
+    emitters
+      .iter()
+      .zip(self.accum.iter_mut())
+      .map(|(e, acc)| {
+        *acc += e.rate().max(0.) * dt;
+        let n = (acc.floor() as u32).min(free);
+        *acc -= acc.floor();
+        free -= n;
+        n
+      })
+      .collect()
+  }
+}
+
+/// Stage of reading the active particle count back from the GPU
+enum Readback {
+  Idle,
+  /// The count is copied by a command buffer that is not submitted yet
+  Copied,
+  Mapping(Arc<AtomicU8>),
+}
+
+/// Spawns and removes particles.
+///
+/// The particles with indices less than the active particle count are alive,
+/// the rest are dead and placed at [`DEAD_POS`]. The count is stored on the GPU
+/// in a buffer that also serves as indirect draw arguments for the particle renderers:
+/// ```wgsl
+/// struct ParticleCount {
+///   vertex_count: u32,
+///   instance_count: u32,
+///   first_vertex: u32,
+///   first_instance: u32,
+///   alive: atomic<u32>,
+/// }
+/// ```
+pub struct ParticleSources {
+  compact: ComputePipeline,
+  emit: ComputePipeline,
+  params_buf: Buffer,
+  count_buf: Buffer,
+  bg: BindGroup,
+  emitters: Vec<Emitter>,
+  sinks: Vec<Sink>,
+  schedule: SourceSchedule,
+  capacity: u32,
+  step: u32,
+  /// Upper bound of the active particle count, the emitters only fill the slots above it.
+  /// It is read back from the GPU a few steps late, the particles spawned since then are added.
+  alive: u32,
+  /// Spawned since the count in `alive_buf` was copied, `None` if the copy is outdated
+  spawned_since_copy: Option<u32>,
+  alive_buf: Buffer,
+  readback: Readback,
+}
+
+impl ParticleSources {
+  pub fn new(
+    device: &wgpu::Device,
+    capacity: u32,
+    particle_layout: &wgpu::BindGroupLayout,
+    storage: StorageLayout,
+  ) -> Self {
+    let module =
+      storage.create_shader_module(device, "emitters.wgsl", include_str!("emitters.wgsl"));
+    let params_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particle sources params"),
+      size: std::mem::size_of::<SourceParams>() as u64,
+      usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let count_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("Particle count"),
+      contents: Self::count_args(capacity).as_bytes_buffer(),
+      usage: BufferUsages::STORAGE
+        | BufferUsages::INDIRECT
+        | BufferUsages::COPY_DST
+        | BufferUsages::COPY_SRC,
+    });
+    let alive_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Particle count readback"),
+      size: std::mem::size_of::<u32>() as u64,
+      usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let storage = |binding, read_only| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Particle sources BG layout"),
+      entries: &[storage(0, true), storage(1, false)],
+    });
+    let bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Particle sources BG"),
+      layout: &bg_layout,
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: params_buf.as_entire_binding(),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: count_buf.as_entire_binding(),
+        },
+      ],
+    });
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("Particle sources"),
+      bind_group_layouts: &[particle_layout, &bg_layout],
+      push_constant_ranges: &[],
+    });
+    let compact = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("ParticleSources::compact"),
+      layout: Some(&layout),
+      module: &module,
+      entry_point: Some("compact"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+    let emit = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("ParticleSources::emit"),
+      layout: Some(&layout),
+      module: &module,
+      entry_point: Some("emit"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+    Self {
+      compact,
+      emit,
+      params_buf,
+      count_buf,
+      bg,
+      emitters: Vec::new(),
+      sinks: Vec::new(),
+      schedule: Default::default(),
+      capacity,
+      step: 0,
+      alive: capacity,
+      spawned_since_copy: None,
+      alive_buf,
+      readback: Readback::Idle,
+    }
+  }
+
+  fn count_args(active: u32) -> [u32; 5] {
+    [3, active, 0, 0, 0]
+  }
+
+  /// The buffer with the active particle count, see [`ParticleSources`]
+  pub fn count_buf(&self) -> &Buffer {
+    &self.count_buf
+  }
+
+  /// Returns `true` if the stage has something to do
+  pub fn is_active(&self) -> bool {
+    !self.emitters.is_empty() || !self.sinks.is_empty()
+  }
+
+  /// Overwrites the active particle count
+  pub fn reset(&mut self, queue: &wgpu::Queue, active: u32) {
+    self.alive = active.min(self.capacity);
+    self.spawned_since_copy = None;
+    queue.write_buffer(
+      &self.count_buf,
+      0,
+      Self::count_args(active.min(self.capacity)).as_bytes_buffer(),
+    );
+  }
+
+  pub fn set_sources(&mut self, emitters: &[Emitter], sinks: &[Sink]) {
+    if emitters.len() > MAX_EMITTERS || sinks.len() > MAX_SINKS {
+      log::warn!(
+        "Too many particle sources: {} emitters and {} sinks, only {MAX_EMITTERS} and {MAX_SINKS} are used",
+        emitters.len(),
+        sinks.len()
+      );
+    }
+    self.emitters = emitters.iter().take(MAX_EMITTERS).copied().collect();
+    self.sinks = sinks.iter().take(MAX_SINKS).copied().collect();
+  }
+
+  fn params(&mut self, dt: f32) -> SourceParams {
+    let spawns = self
+      .schedule
+      .advance(&self.emitters, dt, self.capacity - self.alive);
+    let mut params = SourceParams {
+      n_emitters: self.emitters.len() as u32,
+      n_sinks: self.sinks.len() as u32,
+      seed: self.step,
+      total_spawn: 0,
+      dt,
+      _padding: [0; 3],
+      emitters: [Default::default(); MAX_EMITTERS],
+      sinks: [Default::default(); MAX_SINKS],
+    };
+    for (i, (e, n)) in self.emitters.iter().zip(spawns).enumerate() {
+      params.emitters[i] = GpuEmitter {
+        first: params.total_spawn,
+        spawn: n,
+        ..GpuEmitter::from(e)
+      };
+      params.total_spawn += n;
+    }
+    self.alive += params.total_spawn;
+    if let Some(spawned) = self.spawned_since_copy.as_mut() {
+      *spawned += params.total_spawn;
+    }
+    for (i, s) in self.sinks.iter().enumerate() {
+      params.sinks[i] = s.into();
+    }
+    params
+  }
+
+  /// Removes the particles inside the sinks, compacts the rest and spawns new ones.
+  ///
+  /// The result is written to the old buffer of `particles`, which becomes the current one afterwards.
+  pub fn update(
+    &mut self,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    particles: &mut SwapBuffers<ParticleData>,
+    dt: f32,
+  ) {
+    if !self.is_active() {
+      return;
+    }
+    self.step = self.step.wrapping_add(1);
+    self.poll_alive();
+    let params = self.params(dt);
+    queue.write_buffer(&self.params_buf, 0, params.as_bytes_buffer());
+    encoder.clear_buffer(&self.count_buf, ALIVE_OFFSET, Some(4));
+    {
+      let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+        label: Some("ParticleSources::update"),
+        timestamp_writes: None,
+      });
+      pass.set_bind_group(0, particles.cur_group(), &[]);
+      pass.set_bind_group(1, &self.bg, &[]);
+      pass.set_pipeline(&self.compact);
+      pass.dispatch_workgroups(self.capacity.div_ceil(EMITTER_WG_SIZE), 1, 1);
+      pass.set_pipeline(&self.emit);
+      pass.dispatch_workgroups(self.capacity.div_ceil(EMITTER_WG_SIZE), 1, 1);
+    }
+    if matches!(self.readback, Readback::Idle) {
+      encoder.copy_buffer_to_buffer(&self.count_buf, ACTIVE_OFFSET, &self.alive_buf, 0, 4);
+      self.readback = Readback::Copied;
+      self.spawned_since_copy = Some(0);
+    }
+    particles.flip();
+  }
+
+  /// Advances the readback of the active particle count copied by a previous update
+  fn poll_alive(&mut self) {
+    match std::mem::replace(&mut self.readback, Readback::Idle) {
+      Readback::Idle => {}
+      // Submitted since then, so the buffer may be mapped
+      Readback::Copied => {
+        let status = Arc::new(AtomicU8::new(MAP_PENDING));
+        let callback_status = status.clone();
+        self
+          .alive_buf
+          .slice(..)
+          .map_async(wgpu::MapMode::Read, move |r| {
+            let s = if r.is_ok() { MAP_OK } else { MAP_FAILED };
+            callback_status.store(s, Ordering::Release);
+          });
+        self.readback = Readback::Mapping(status);
+      }
+      Readback::Mapping(status) => match status.load(Ordering::Acquire) {
+        MAP_PENDING => self.readback = Readback::Mapping(status),
+        MAP_OK => {
+          let copied = {
+            let view = self.alive_buf.slice(..).get_mapped_range();
+            u32::from_ne_bytes(view[..4].try_into().unwrap())
+          };
+          self.alive_buf.unmap();
+          if let Some(spawned) = self.spawned_since_copy {
+            self.alive = copied.saturating_add(spawned).min(self.capacity);
+          }
+        }
+        _ => log::warn!("Unable to read back the active particle count"),
+      },
+    }
+  }
+}
@@ -0,0 +1,161 @@
+// This constant **must** be kept the same as `solvers::emitters::EMITTER_WG_SIZE`
+const WG_SIZE: u32 = 64;
+// These constants **must** be kept the same as `solvers::emitters::{MAX_EMITTERS, MAX_SINKS}`
+const MAX_EMITTERS: u32 = 16;
+const MAX_SINKS: u32 = 16;
+// This constant **must** be kept the same as `solvers::emitters::DEAD_POS`
+const DEAD_POS: f32 = 1e30;
+
+const EMITTER_NOZZLE: u32 = 0;
+const EMITTER_BOX: u32 = 1;
+const SINK_BOX: u32 = 0;
+const SINK_SPHERE: u32 = 1;
+
+const TAU: f32 = 6.28318530717958;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+struct Emitter {
+  origin: vec3f,
+  kind: u32,
+  /// Normalized direction of a nozzle or the max corner of a box
+  extent: vec3f,
+  radius: f32,
+  velocity: vec3f,
+  /// Index of the first particle spawned by the emitter among the spawned this step
+  first: u32,
+  spawn: u32,
+}
+
+struct Sink {
+  /// Min corner of a box or the center of a sphere
+  min: vec3f,
+  kind: u32,
+  max: vec3f,
+  radius: f32,
+}
+
+struct SourceParams {
+  n_emitters: u32,
+  n_sinks: u32,
+  seed: u32,
+  total_spawn: u32,
+  dt: f32,
+  emitters: array<Emitter, MAX_EMITTERS>,
+  sinks: array<Sink, MAX_SINKS>,
+}
+
+/// Arguments of the indirect particle draw call followed by the counter used for compaction.
+struct ParticleCount {
+  vertex_count: u32,
+  instance_count: u32,
+  first_vertex: u32,
+  first_instance: u32,
+  alive: atomic<u32>,
+}
+
+@group(1) @binding(0)
+var<storage, read> params: SourceParams;
+@group(1) @binding(1)
+var<storage, read_write> count: ParticleCount;
+
+fn pcg(v: u32) -> u32 {
+  let state = v * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+fn rand(seed: ptr<function, u32>) -> f32 {
+  *seed = pcg(*seed);
+  return f32(*seed) / 4294967295.0;
+}
+
+fn in_sink(p: vec3f) -> bool {
+  for (var i: u32 = 0; i < params.n_sinks; i += 1u) {
+    let s = params.sinks[i];
+    if s.kind == SINK_BOX {
+      if all(s.min <= p) && all(p <= s.max) {
+        return true;
+      }
+    } else if s.kind == SINK_SPHERE {
+      if distance(p, s.min) <= s.radius {
+        return true;
+      }
+    }
+  }
+  return false;
+}
+
+fn spawn(e: Emitter, seed: ptr<function, u32>) -> Particle {
+  var p: Particle;
+  p.density = 1.;
+  p.forces = vec3f(0.);
+  p.velocity = e.velocity;
+  if e.kind == EMITTER_NOZZLE {
+    let dir = e.extent;
+    var helper = vec3f(1., 0., 0.);
+    if abs(dir.x) > 0.9 {
+      helper = vec3f(0., 1., 0.);
+    }
+    let t = normalize(cross(dir, helper));
+    let b = cross(dir, t);
+    let r = e.radius * sqrt(rand(seed));
+    let phi = TAU * rand(seed);
+    // Spread the particles along the jet so that they do not spawn on top of each other
+    let along = e.velocity * params.dt * rand(seed);
+    p.pos = e.origin + r * (cos(phi) * t + sin(phi) * b) + along;
+  } else {
+    let u = vec3f(rand(seed), rand(seed), rand(seed));
+    p.pos = mix(e.origin, e.extent, u);
+  }
+  return p;
+}
+
+// Moves the active particles outside of the sinks from `cur_particles` to the
+// beginning of `old_particles`.
+@compute @workgroup_size(WG_SIZE)
+fn compact(@builtin(global_invocation_id) idx: vec3u) {
+  let i = idx.x;
+  if i >= count.instance_count {
+    return;
+  }
+  let p = load_cur(i);
+  if in_sink(p.pos) {
+    return;
+  }
+  let slot = atomicAdd(&count.alive, 1u);
+  store_old(slot, p);
+}
+
+// Fills the tail of `old_particles` with the newly spawned and dead particles
+// and updates the active particle count.
+@compute @workgroup_size(WG_SIZE)
+fn emit(@builtin(global_invocation_id) idx: vec3u) {
+  let i = idx.x;
+  let capacity = particle_capacity();
+  let alive = atomicLoad(&count.alive);
+  if i == 0u {
+    count.instance_count = min(alive + params.total_spawn, capacity);
+  }
+  if i < alive || i >= capacity {
+    return;
+  }
+  let k = i - alive;
+  var p: Particle;
+  p.pos = vec3f(DEAD_POS);
+  p.density = 1.;
+  p.velocity = vec3f(0.);
+  p.forces = vec3f(0.);
+  if k < params.total_spawn {
+    var seed = pcg(i ^ pcg(params.seed));
+    for (var e: u32 = 0; e < params.n_emitters; e += 1u) {
+      let em = params.emitters[e];
+      if k >= em.first && k < em.first + em.spawn {
+        p = spawn(em, &seed);
+        break;
+      }
+    }
+  }
+  store_old(i, p);
+}
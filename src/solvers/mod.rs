@@ -1,2 +1,34 @@
 pub mod bitonic_sorter;
+pub mod emitters;
+mod pass_params;
+pub mod primitives;
+pub mod radix_sorter;
+pub mod sort_keys;
 pub mod sph_solver_gpu;
+pub mod storage;
+pub mod surface;
+
+use std::sync::Arc;
+
+use sort_keys::{KeyGrid, SortKey};
+use storage::ParticleData;
+
+use crate::render::{profiler::GpuProfiler, swapchain::SwapBuffers};
+
+/// A GPU algorithm ordering the particles by a [`SortKey`], the x coordinate by default
+pub trait GpuSorter: Send + Sync {
+  /// Sorts the first `count` particles of the current buffer of `particles`.
+  /// The sorted particles may be written to the old buffer, which becomes the current one then.
+  fn sort(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    particles: &mut SwapBuffers<ParticleData>,
+    count: u32,
+  );
+  /// Selects the key used by the subsequent sorts
+  fn set_key(&self, queue: &wgpu::Queue, key: SortKey, grid: &KeyGrid);
+  /// Returns `true` if the sorter is able to sort `count` particles
+  fn supports(&self, count: u32) -> bool;
+  /// Measures the subsequent sorts with the `profiler`
+  fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>);
+}
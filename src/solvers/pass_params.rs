@@ -0,0 +1,184 @@
+use std::{borrow::Cow, num::NonZero};
+
+use egui::mutex::Mutex;
+use wgpu::{
+  BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, Buffer,
+  BufferUsages, PushConstantRange, ShaderStages,
+};
+
+/// Small parameters changing between the dispatches of a compute pass.
+///
+/// They are passed as push constants if [`wgpu::Features::PUSH_CONSTANTS`] is enabled
+/// and as a uniform buffer with dynamic offsets otherwise. The shaders declare them as
+/// `var<push_constant>`, the declaration is replaced by [`PassParams::patch`] in the latter case.
+pub(crate) struct PassParams {
+  device: wgpu::Device,
+  /// Writes the uniform buffer
+  queue: wgpu::Queue,
+  /// Size of the parameters of a dispatch, in bytes
+  size: u32,
+  /// Group of the uniform buffer, the one after the groups of the pass
+  group: u32,
+  /// `None` if the push constants are used
+  uniform: Option<UniformParams>,
+}
+
+struct UniformParams {
+  layout: BindGroupLayout,
+  /// Distance between the parameters of the dispatches in the buffer
+  stride: u32,
+  /// Reused by the subsequent passes, recreated only when more dispatches are prepared
+  buffer: Mutex<Option<UniformBuffer>>,
+}
+
+struct UniformBuffer {
+  buf: Buffer,
+  bg: BindGroup,
+  /// Count of the dispatches the buffer has room for
+  capacity: usize,
+  /// Parameters written by the last [`PassParams::prepare`]
+  written: Vec<[u32; 2]>,
+}
+
+/// Parameters of all the dispatches of a pass
+pub(crate) struct PreparedParams<'a> {
+  params: &'a PassParams,
+  entries: Vec<[u32; 2]>,
+  bg: Option<BindGroup>,
+}
+
+impl PassParams {
+  /// `size` is at most 8 bytes, `group` is the count of the other groups of the pipelines
+  pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, size: u32, group: u32) -> Self {
+    assert!(size as usize <= size_of::<[u32; 2]>());
+    let uniform =
+      (!device.features().contains(wgpu::Features::PUSH_CONSTANTS)).then(|| UniformParams {
+        layout: device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+          label: Some("Pass params"),
+          entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: true,
+              min_binding_size: NonZero::new(size as u64),
+            },
+            count: None,
+          }],
+        }),
+        stride: size.max(device.limits().min_uniform_buffer_offset_alignment),
+        buffer: Mutex::new(None),
+      });
+    Self {
+      device: device.clone(),
+      queue: queue.clone(),
+      size,
+      group,
+      uniform,
+    }
+  }
+
+  /// Layout to append to the bind group layouts of the pipelines
+  pub(crate) fn layout(&self) -> Option<&BindGroupLayout> {
+    self.uniform.as_ref().map(|u| &u.layout)
+  }
+
+  pub(crate) fn push_constant_ranges(&self) -> Vec<PushConstantRange> {
+    match self.uniform {
+      Some(_) => Vec::new(),
+      None => vec![PushConstantRange {
+        stages: ShaderStages::COMPUTE,
+        range: 0..self.size,
+      }],
+    }
+  }
+
+  /// Replaces the push constant declaration of the `source` with the uniform binding if needed
+  pub(crate) fn patch<'a>(&self, source: &'a str) -> Cow<'a, str> {
+    match self.uniform {
+      Some(_) => source
+        .replace(
+          "var<push_constant>",
+          &format!("@group({}) @binding(0)\nvar<uniform>", self.group),
+        )
+        .into(),
+      None => source.into(),
+    }
+  }
+
+  /// Uploads the parameters of the dispatches, the `i`-th of them is selected by [`PreparedParams::set`].
+  ///
+  /// The uniform buffer is shared by the prepared parameters and written on the next submission,
+  /// so the commands of a submission must not use parameters prepared with different `entries`.
+  pub(crate) fn prepare(&self, entries: Vec<[u32; 2]>) -> PreparedParams<'_> {
+    let bg = self.uniform.as_ref().map(|u| {
+      let mut buffer = u.buffer.lock();
+      if buffer.as_ref().is_none_or(|b| b.capacity < entries.len()) {
+        *buffer = Some(self.create_buffer(u, entries.len().next_power_of_two()));
+      }
+      let buffer = buffer.as_mut().unwrap();
+      if buffer.written != entries {
+        let mut contents = vec![0u8; u.stride as usize * entries.len().max(1)];
+        for (i, e) in entries.iter().enumerate() {
+          let at = i * u.stride as usize;
+          contents[at..at + 4].copy_from_slice(&e[0].to_ne_bytes());
+          contents[at + 4..at + 8].copy_from_slice(&e[1].to_ne_bytes());
+        }
+        self.queue.write_buffer(&buffer.buf, 0, &contents);
+        buffer.written.clone_from(&entries);
+      }
+      buffer.bg.clone()
+    });
+    PreparedParams {
+      params: self,
+      entries,
+      bg,
+    }
+  }
+
+  /// Creates a uniform buffer with room for the parameters of `capacity` dispatches
+  fn create_buffer(&self, u: &UniformParams, capacity: usize) -> UniformBuffer {
+    let buf = self.device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Pass params"),
+      size: u.stride as u64 * capacity.max(1) as u64,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let bg = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+      label: Some("Pass params"),
+      layout: &u.layout,
+      entries: &[wgpu::BindGroupEntry {
+        binding: 0,
+        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+          buffer: &buf,
+          offset: 0,
+          size: NonZero::new(self.size as u64),
+        }),
+      }],
+    });
+    UniformBuffer {
+      buf,
+      bg,
+      capacity,
+      written: Vec::new(),
+    }
+  }
+}
+
+impl PreparedParams<'_> {
+  /// Passes the `i`-th parameters to the subsequent dispatches
+  pub(crate) fn set(&self, pass: &mut wgpu::ComputePass, i: usize) {
+    match (&self.bg, &self.params.uniform) {
+      (Some(bg), Some(u)) => {
+        pass.set_bind_group(self.params.group, bg, &[i as u32 * u.stride]);
+      }
+      _ => {
+        let [a, b] = self.entries[i];
+        let mut bytes = [0u8; size_of::<[u32; 2]>()];
+        bytes[..4].copy_from_slice(&a.to_ne_bytes());
+        bytes[4..].copy_from_slice(&b.to_ne_bytes());
+        pass.set_push_constants(0, &bytes[..self.params.size as usize]);
+      }
+    }
+  }
+}
@@ -0,0 +1,646 @@
+use core::slice;
+use std::{collections::HashMap, ops::Range};
+
+use egui::mutex::Mutex;
+
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+  BindGroupLayoutEntry, Buffer, BufferUsages, ComputePassDescriptor, ComputePipeline,
+  ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderStages,
+};
+
+use crate::render::AsBuffer;
+
+/// Count of elements processed by a single workgroup, also the max count of histogram bins
+pub const TILE_SIZE: u32 = 256;
+// These constants **must** be kept the same as in the primitives shader.
+const MODE_EXCLUSIVE: u32 = 1;
+const MODE_FLAGS: u32 = 2;
+/// The cached bind groups are dropped past this count, in case the operands keep changing
+const MAX_CACHED_BIND_GROUPS: usize = 256;
+
+#[cfg(test)]
+mod test {
+  use rand::Rng;
+  use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BufferUsages, InstanceDescriptor, RequestAdapterOptions,
+  };
+
+  use super::{ParallelPrimitives, ReduceOp, ScanKind};
+
+  const LENGTHS: [usize; 7] = [1, 5, 255, 256, 257, 10_000, 70_001];
+
+  async fn setup_wgpu() -> Result<(wgpu::Device, wgpu::Queue), ()> {
+    let instance = wgpu::Instance::new(&InstanceDescriptor {
+      backends: wgpu::Backends::PRIMARY,
+      ..Default::default()
+    });
+    let adapter = instance
+      .request_adapter(&RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+      })
+      .await
+      .expect("Unable to request an adapter");
+    adapter
+      .request_device(&Default::default(), None)
+      .await
+      .map_err(|_| ())
+  }
+
+  fn upload(device: &wgpu::Device, data: &[u32]) -> wgpu::Buffer {
+    device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("input"),
+      // Empty buffers can not be bound
+      contents: if data.is_empty() {
+        &[0; 4]
+      } else {
+        unsafe { core::slice::from_raw_parts(data.as_ptr().cast(), size_of_val(data)) }
+      },
+      usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+    })
+  }
+
+  fn zeroed(device: &wgpu::Device, len: usize) -> wgpu::Buffer {
+    upload(device, &vec![0; len])
+  }
+
+  /// Runs the recorded commands and reads the first `len` words of `buf`
+  fn run(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mut encoder: wgpu::CommandEncoder,
+    buf: &wgpu::Buffer,
+    len: usize,
+  ) -> Vec<u32> {
+    let size = 4 * len as u64;
+    let obuf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("outbuf"),
+      size,
+      usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    });
+    encoder.copy_buffer_to_buffer(buf, 0, &obuf, 0, size);
+    queue.submit([encoder.finish()]);
+    obuf
+      .slice(..)
+      .map_async(wgpu::MapMode::Read, |a| a.unwrap());
+    device.poll(wgpu::MaintainBase::Wait);
+    let out = unsafe {
+      core::slice::from_raw_parts::<u32>(obuf.slice(..).get_mapped_range().as_ptr().cast(), len)
+        .to_vec()
+    };
+    obuf.unmap();
+    out
+  }
+
+  fn encoder(device: &wgpu::Device) -> wgpu::CommandEncoder {
+    device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None })
+  }
+
+  fn random_floats(len: usize) -> Vec<f32> {
+    let mut rng = rand::rng();
+    (0..len).map(|_| rng.random_range(-100.0..100.0)).collect()
+  }
+
+  #[tokio::test]
+  async fn gpu_scan_like_cpu() -> Result<(), ()> {
+    let (device, queue) = setup_wgpu().await?;
+    let primitives = ParallelPrimitives::new(&device, *LENGTHS.iter().max().unwrap() as u32);
+    let mut rng = rand::rng();
+    for len in LENGTHS {
+      let data: Vec<u32> = (0..len).map(|_| rng.random_range(0..100)).collect();
+      let input = upload(&device, &data);
+      for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let output = zeroed(&device, len);
+        let mut enc = encoder(&device);
+        primitives.scan(&mut enc, &input, &output, len as u32, kind);
+        let got = run(&device, &queue, enc, &output, len);
+        let mut sum = 0;
+        let expected: Vec<u32> = data
+          .iter()
+          .map(|v| {
+            sum += v;
+            match kind {
+              ScanKind::Inclusive => sum,
+              ScanKind::Exclusive => sum - v,
+            }
+          })
+          .collect();
+        assert_eq!(got, expected, "{kind:?} scan of {len} elements");
+      }
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_reduce_like_cpu() -> Result<(), ()> {
+    let (device, queue) = setup_wgpu().await?;
+    let primitives = ParallelPrimitives::new(&device, *LENGTHS.iter().max().unwrap() as u32);
+    for len in LENGTHS {
+      let data = random_floats(len);
+      let input = upload(
+        &device,
+        &data.iter().map(|f| f.to_bits()).collect::<Vec<_>>(),
+      );
+      for op in [ReduceOp::Min, ReduceOp::Max, ReduceOp::Sum] {
+        let output = zeroed(&device, 1);
+        let mut enc = encoder(&device);
+        primitives.reduce(&mut enc, &input, &output, len as u32, op);
+        let got = f32::from_bits(run(&device, &queue, enc, &output, 1)[0]);
+        let expected = match op {
+          ReduceOp::Min => data.iter().copied().fold(f32::INFINITY, f32::min),
+          ReduceOp::Max => data.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+          ReduceOp::Sum => data.iter().map(|&v| v as f64).sum::<f64>() as f32,
+        };
+        // The summation order differs from the CPU one
+        let tolerance = match op {
+          ReduceOp::Sum => 1e-3 * len as f32,
+          _ => 0.0,
+        };
+        assert!(
+          (got - expected).abs() <= tolerance,
+          "{op:?} of {len} elements: expected {expected}, got {got}"
+        );
+      }
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_compact_like_cpu() -> Result<(), ()> {
+    let (device, queue) = setup_wgpu().await?;
+    let primitives = ParallelPrimitives::new(&device, *LENGTHS.iter().max().unwrap() as u32);
+    let mut rng = rand::rng();
+    for len in [0].into_iter().chain(LENGTHS) {
+      let data: Vec<u32> = (0..len as u32).collect();
+      let flags: Vec<u32> = (0..len).map(|_| rng.random_range(0..3)).collect();
+      let (input, flags_buf) = (upload(&device, &data), upload(&device, &flags));
+      let output = zeroed(&device, len.max(1));
+      let count = upload(&device, &[u32::MAX]);
+      let mut enc = encoder(&device);
+      primitives.compact(&mut enc, &input, &flags_buf, &output, &count, len as u32);
+      let expected: Vec<u32> = data
+        .iter()
+        .zip(&flags)
+        .filter(|(_, &f)| f != 0)
+        .map(|(&v, _)| v)
+        .collect();
+      let got_count = run(&device, &queue, enc, &count, 1)[0] as usize;
+      assert_eq!(got_count, expected.len(), "count of {len} elements");
+      let got = run(&device, &queue, encoder(&device), &output, len.max(1))[..got_count].to_vec();
+      assert_eq!(got, expected, "compaction of {len} elements");
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_histogram_like_cpu() -> Result<(), ()> {
+    let (device, queue) = setup_wgpu().await?;
+    let primitives = ParallelPrimitives::new(&device, *LENGTHS.iter().max().unwrap() as u32);
+    for len in LENGTHS {
+      // Some values fall outside of the range
+      let data = random_floats(len);
+      let input = upload(
+        &device,
+        &data.iter().map(|f| f.to_bits()).collect::<Vec<_>>(),
+      );
+      for n_bins in [1, 7, 256] {
+        let range = -50.0..50.0;
+        let bins = upload(&device, &vec![u32::MAX; n_bins]);
+        let mut enc = encoder(&device);
+        primitives.histogram(
+          &mut enc,
+          &input,
+          &bins,
+          len as u32,
+          range.clone(),
+          n_bins as u32,
+        );
+        let got = run(&device, &queue, enc, &bins, n_bins);
+        let mut expected = vec![0; n_bins];
+        for &v in data.iter().filter(|v| range.contains(v)) {
+          let t = (v - range.start) / (range.end - range.start);
+          expected[((t * n_bins as f32) as usize).min(n_bins - 1)] += 1;
+        }
+        assert_eq!(got, expected, "{n_bins} bins of {len} elements");
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanKind {
+  /// The i-th output is the sum of the elements up to the i-th inclusive
+  Inclusive,
+  /// The i-th output is the sum of the elements before the i-th
+  Exclusive,
+}
+
+/// Associative operation of [`ParallelPrimitives::reduce`].
+/// This enum **must** be kept the same as the `OP_*` constants of the primitives shader.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReduceOp {
+  Min = 0,
+  Max = 1,
+  Sum = 2,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Params {
+  len: u32,
+  mode: u32,
+  lo: f32,
+  hi: f32,
+}
+
+impl AsBuffer for Params {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<Params>(),
+      )
+    }
+  }
+}
+
+impl Params {
+  fn key(&self) -> [u32; 4] {
+    [self.len, self.mode, self.lo.to_bits(), self.hi.to_bits()]
+  }
+}
+
+/// Buffers bound to a dispatch, unused ones are replaced with a dummy buffer
+#[derive(Default)]
+struct Operands<'a> {
+  a: Option<&'a Buffer>,
+  b: Option<&'a Buffer>,
+  c: Option<&'a Buffer>,
+  d: Option<&'a Buffer>,
+  bins: Option<&'a Buffer>,
+}
+
+/// Uniform buffers of the distinct parameters and the bind groups of the distinct operands.
+/// They are never written to after the creation, so any number of dispatches may be recorded
+/// before the submission.
+#[derive(Default)]
+struct BindGroupCache {
+  params: HashMap<[u32; 4], Buffer>,
+  bind_groups: HashMap<([u32; 4], [Buffer; 5]), wgpu::BindGroup>,
+}
+
+/// Workgroup-tiled scan, reduction, stream compaction and histogram over buffers of 32-bit words.
+///
+/// The operations are recorded into a command encoder and may be mixed with other passes.
+/// Input and output buffers must be distinct `STORAGE` buffers. The intermediate results are kept
+/// in the scratch buffers of the struct, so the operations must not be applied to more than
+/// `capacity` elements.
+pub struct ParallelPrimitives {
+  scan_tiles: ComputePipeline,
+  add_offsets: ComputePipeline,
+  reduce_tiles: ComputePipeline,
+  scatter_kept: ComputePipeline,
+  histogram: ComputePipeline,
+  layout: BindGroupLayout,
+  /// Tile totals and their scans for every level of the hierarchical scan
+  levels: Vec<(Buffer, Buffer)>,
+  /// Scanned flags of the compaction
+  positions: Buffer,
+  dummy: Buffer,
+  capacity: u32,
+  /// Used to create the bind groups of the dispatches
+  device: wgpu::Device,
+  cache: Mutex<BindGroupCache>,
+}
+
+impl ParallelPrimitives {
+  pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+    let storage = |binding| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Primitives BG layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        storage(1),
+        storage(2),
+        storage(3),
+        storage(4),
+        storage(5),
+      ],
+    });
+    let module = &device.create_shader_module(wgpu::include_wgsl!("primitives.wgsl"));
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("Primitives"),
+      bind_group_layouts: &[&layout],
+      push_constant_ranges: &[],
+    });
+    let pipeline = |entry_point: &str| {
+      device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(&format!("Primitives::{entry_point}")),
+        layout: Some(&pipeline_layout),
+        module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+      })
+    };
+    let scratch = |label, len: u32| {
+      device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: len.max(1) as u64 * size_of::<u32>() as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+      })
+    };
+
+    let mut levels = vec![];
+    let mut tiles = capacity.div_ceil(TILE_SIZE);
+    while tiles > 1 {
+      levels.push((
+        scratch("Primitives tile totals", tiles),
+        scratch("Primitives tile offsets", tiles),
+      ));
+      tiles = tiles.div_ceil(TILE_SIZE);
+    }
+
+    Self {
+      scan_tiles: pipeline("scan_tiles"),
+      add_offsets: pipeline("add_offsets"),
+      reduce_tiles: pipeline("reduce_tiles"),
+      scatter_kept: pipeline("scatter_kept"),
+      histogram: pipeline("histogram"),
+      layout,
+      levels,
+      positions: scratch("Primitives positions", capacity),
+      dummy: scratch("Primitives dummy", 1),
+      capacity,
+      device: device.clone(),
+      cache: Mutex::new(BindGroupCache::default()),
+    }
+  }
+
+  /// Writes the prefix sums of the first `len` words of `input` to `output`.
+  pub fn scan(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    input: &Buffer,
+    output: &Buffer,
+    len: u32,
+    kind: ScanKind,
+  ) {
+    self.check_len(len);
+    let mode = match kind {
+      ScanKind::Inclusive => 0,
+      ScanKind::Exclusive => MODE_EXCLUSIVE,
+    };
+    let pass = &mut Self::begin_pass(encoder, "Primitives::scan");
+    self.scan_level(pass, input, output, len, mode, 0);
+  }
+
+  /// Reduces the first `len` floats of `input` to the first word of `output`.
+  pub fn reduce(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    input: &Buffer,
+    output: &Buffer,
+    len: u32,
+    op: ReduceOp,
+  ) {
+    assert!(len > 0, "can not reduce an empty array");
+    self.check_len(len);
+    let pass = &mut Self::begin_pass(encoder, "Primitives::reduce");
+    let (mut src, mut len) = (input, len);
+    for level in 0.. {
+      let tiles = len.div_ceil(TILE_SIZE);
+      let dst = if tiles == 1 {
+        output
+      } else {
+        &self.levels[level].0
+      };
+      let operands = Operands {
+        a: Some(src),
+        b: Some(dst),
+        ..Default::default()
+      };
+      self.dispatch(pass, &self.reduce_tiles, len, op as u32, operands, tiles);
+      if tiles == 1 {
+        break;
+      }
+      (src, len) = (dst, tiles);
+    }
+  }
+
+  /// Copies the first `len` words of `input` with non-zero `flags` to the beginning of `output`
+  /// preserving their order. The count of the copied words is written to the first word of `count`.
+  pub fn compact(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    input: &Buffer,
+    flags: &Buffer,
+    output: &Buffer,
+    count: &Buffer,
+    len: u32,
+  ) {
+    self.check_len(len);
+    let pass = &mut Self::begin_pass(encoder, "Primitives::compact");
+    self.scan_level(pass, flags, &self.positions, len, MODE_FLAGS, 0);
+    let operands = Operands {
+      a: Some(input),
+      b: Some(output),
+      c: Some(&self.positions),
+      d: Some(count),
+      ..Default::default()
+    };
+    let groups = len.div_ceil(TILE_SIZE).max(1);
+    self.dispatch(pass, &self.scatter_kept, len, 0, operands, groups);
+  }
+
+  /// Counts the first `len` floats of `input` falling into each of `n_bins` equal subranges of `range`.
+  /// The counts are written to `bins`, which must also be a `COPY_DST` buffer.
+  /// Values outside of `range` are ignored.
+  pub fn histogram(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    input: &Buffer,
+    bins: &Buffer,
+    len: u32,
+    range: Range<f32>,
+    n_bins: u32,
+  ) {
+    assert!(
+      (1..=TILE_SIZE).contains(&n_bins),
+      "the count of bins must be in 1..={TILE_SIZE}, got {n_bins}"
+    );
+    self.check_len(len);
+    encoder.clear_buffer(bins, 0, Some(n_bins as u64 * size_of::<u32>() as u64));
+    if len == 0 {
+      return;
+    }
+    let pass = &mut Self::begin_pass(encoder, "Primitives::histogram");
+    let params = Params {
+      len,
+      mode: n_bins,
+      lo: range.start,
+      hi: range.end,
+    };
+    let operands = Operands {
+      a: Some(input),
+      bins: Some(bins),
+      ..Default::default()
+    };
+    let bg = self.bind_group(&params, operands);
+    pass.set_pipeline(&self.histogram);
+    pass.set_bind_group(0, &bg, &[]);
+    pass.dispatch_workgroups(len.div_ceil(TILE_SIZE), 1, 1);
+  }
+
+  fn check_len(&self, len: u32) {
+    assert!(
+      len <= self.capacity,
+      "`len` must not exceed the capacity {}, got {len}",
+      self.capacity
+    );
+  }
+
+  fn begin_pass<'e>(encoder: &'e mut wgpu::CommandEncoder, label: &str) -> wgpu::ComputePass<'e> {
+    encoder.begin_compute_pass(&ComputePassDescriptor {
+      label: Some(label),
+      timestamp_writes: None,
+    })
+  }
+
+  /// Scans `src` into `dst` using the scratch buffers starting from `level`
+  fn scan_level(
+    &self,
+    pass: &mut wgpu::ComputePass,
+    src: &Buffer,
+    dst: &Buffer,
+    len: u32,
+    mode: u32,
+    level: usize,
+  ) {
+    if len == 0 {
+      return;
+    }
+    let tiles = len.div_ceil(TILE_SIZE);
+    if tiles == 1 {
+      let operands = Operands {
+        a: Some(src),
+        b: Some(dst),
+        ..Default::default()
+      };
+      self.dispatch(pass, &self.scan_tiles, len, mode, operands, 1);
+      return;
+    }
+    let (totals, offsets) = &self.levels[level];
+    let operands = Operands {
+      a: Some(src),
+      b: Some(dst),
+      c: Some(totals),
+      ..Default::default()
+    };
+    self.dispatch(pass, &self.scan_tiles, len, mode, operands, tiles);
+    self.scan_level(pass, totals, offsets, tiles, MODE_EXCLUSIVE, level + 1);
+    let operands = Operands {
+      a: Some(offsets),
+      b: Some(dst),
+      ..Default::default()
+    };
+    self.dispatch(pass, &self.add_offsets, len, 0, operands, tiles);
+  }
+
+  fn dispatch(
+    &self,
+    pass: &mut wgpu::ComputePass,
+    pipeline: &ComputePipeline,
+    len: u32,
+    mode: u32,
+    operands: Operands,
+    groups: u32,
+  ) {
+    let params = Params {
+      len,
+      mode,
+      lo: 0.0,
+      hi: 0.0,
+    };
+    let bg = self.bind_group(&params, operands);
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, &bg, &[]);
+    pass.dispatch_workgroups(groups, 1, 1);
+  }
+
+  /// Returns the bind group of a dispatch, creating it on the first use of the `params`
+  /// with the `operands`. See [`BindGroupCache`].
+  fn bind_group(&self, params: &Params, operands: Operands) -> wgpu::BindGroup {
+    let buffers = [
+      operands.a,
+      operands.b,
+      operands.c,
+      operands.d,
+      operands.bins,
+    ]
+    .map(|buf| buf.unwrap_or(&self.dummy).clone());
+    let key = (params.key(), buffers);
+    let mut cache = self.cache.lock();
+    if let Some(bg) = cache.bind_groups.get(&key) {
+      return bg.clone();
+    }
+    if cache.bind_groups.len() >= MAX_CACHED_BIND_GROUPS {
+      log::debug!("Primitives bind group cache is full, clearing it");
+      *cache = BindGroupCache::default();
+    }
+    let params_buf = cache
+      .params
+      .entry(key.0)
+      .or_insert_with(|| {
+        self.device.create_buffer_init(&BufferInitDescriptor {
+          label: Some("Primitives params"),
+          contents: params.as_bytes_buffer(),
+          usage: BufferUsages::UNIFORM,
+        })
+      })
+      .clone();
+    let mut entries = vec![BindGroupEntry {
+      binding: 0,
+      resource: params_buf.as_entire_binding(),
+    }];
+    for (binding, buf) in (1..).zip(&key.1) {
+      entries.push(BindGroupEntry {
+        binding,
+        resource: buf.as_entire_binding(),
+      });
+    }
+    let bg = self.device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Primitives BG"),
+      layout: &self.layout,
+      entries: &entries,
+    });
+    cache.bind_groups.insert(key, bg.clone());
+    bg
+  }
+}
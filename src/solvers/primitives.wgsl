@@ -0,0 +1,188 @@
+// Workgroup-tiled parallel primitives over arrays of 32-bit words.
+// Every workgroup processes a tile of `TILE_SIZE` elements, one per thread.
+// Floating point values are stored as their bits.
+
+// This constant **must** be kept the same as `solvers::primitives::TILE_SIZE`
+const TILE_SIZE: u32 = 256u;
+
+// These constants **must** be kept the same as the ones in `solvers::primitives`
+const MODE_EXCLUSIVE: u32 = 1u;
+const MODE_FLAGS: u32 = 2u;
+const OP_MIN: u32 = 0u;
+const OP_MAX: u32 = 1u;
+const OP_SUM: u32 = 2u;
+
+struct Params {
+  len: u32,
+  /// Scan mode bits, reduction operation or count of histogram bins
+  mode: u32,
+  /// Range of the histogram
+  lo: f32,
+  hi: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> p: Params;
+@group(0) @binding(1)
+var<storage, read_write> a: array<u32>;
+@group(0) @binding(2)
+var<storage, read_write> b: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> c: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> d: array<u32>;
+@group(0) @binding(5)
+var<storage, read_write> bins: array<atomic<u32>>;
+
+var<workgroup> tile: array<u32, TILE_SIZE>;
+var<workgroup> ftile: array<f32, TILE_SIZE>;
+var<workgroup> local_bins: array<atomic<u32>, TILE_SIZE>;
+
+/// Inclusive Hillis-Steele scan of `tile`. Must be called from uniform control flow.
+fn scan_tile(j: u32) {
+  for (var off: u32 = 1u; off < TILE_SIZE; off <<= 1u) {
+    var t = 0u;
+    if j >= off {
+      t = tile[j - off];
+    }
+    workgroupBarrier();
+    tile[j] += t;
+    workgroupBarrier();
+  }
+}
+
+// Scans every tile of `a` into `b` and writes the totals of the tiles to `c`
+@compute @workgroup_size(TILE_SIZE)
+fn scan_tiles(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let j = lii.x;
+  let i = wid.x * TILE_SIZE + j;
+  var v = 0u;
+  if i < p.len {
+    v = a[i];
+    if (p.mode & MODE_FLAGS) != 0u {
+      v = min(v, 1u);
+    }
+  }
+  tile[j] = v;
+  workgroupBarrier();
+  scan_tile(j);
+  if i < p.len {
+    if (p.mode & MODE_EXCLUSIVE) != 0u {
+      b[i] = tile[j] - v;
+    } else {
+      b[i] = tile[j];
+    }
+  }
+  if j == TILE_SIZE - 1u {
+    c[wid.x] = tile[j];
+  }
+}
+
+// Adds the scanned tile totals `a` to the elements of `b`
+@compute @workgroup_size(TILE_SIZE)
+fn add_offsets(
+  @builtin(global_invocation_id) gii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let i = gii.x;
+  if i < p.len {
+    b[i] += a[wid.x];
+  }
+}
+
+fn combine(op: u32, x: f32, y: f32) -> f32 {
+  switch op {
+    case OP_MIN: {
+      return min(x, y);
+    }
+    case OP_MAX: {
+      return max(x, y);
+    }
+    default: {
+      return x + y;
+    }
+  }
+}
+
+// Reduces every tile of `a` to an element of `b`
+@compute @workgroup_size(TILE_SIZE)
+fn reduce_tiles(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let j = lii.x;
+  let i = wid.x * TILE_SIZE + j;
+  // The first element of the tile is always present and does not change a min or max
+  var v = bitcast<f32>(a[wid.x * TILE_SIZE]);
+  if i < p.len {
+    v = bitcast<f32>(a[i]);
+  } else if p.mode == OP_SUM {
+    v = 0.;
+  }
+  ftile[j] = v;
+  workgroupBarrier();
+  for (var off: u32 = TILE_SIZE / 2u; off > 0u; off >>= 1u) {
+    if j < off {
+      ftile[j] = combine(p.mode, ftile[j], ftile[j + off]);
+    }
+    workgroupBarrier();
+  }
+  if j == 0u {
+    b[wid.x] = bitcast<u32>(ftile[0]);
+  }
+}
+
+// Moves the elements of `a` to `b` using the inclusive scan of the flags `c`
+// and writes the count of the kept elements to `d`
+@compute @workgroup_size(TILE_SIZE)
+fn scatter_kept(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let i = gii.x;
+  if i == 0u {
+    var count = 0u;
+    if p.len > 0u {
+      count = c[p.len - 1u];
+    }
+    d[0] = count;
+  }
+  if i >= p.len {
+    return;
+  }
+  var prev = 0u;
+  if i > 0u {
+    prev = c[i - 1u];
+  }
+  if c[i] != prev {
+    b[prev] = a[i];
+  }
+}
+
+// Counts the values of `a` falling into `p.mode` equal bins of `[p.lo, p.hi)`
+@compute @workgroup_size(TILE_SIZE)
+fn histogram(
+  @builtin(global_invocation_id) gii: vec3<u32>,
+  @builtin(local_invocation_id) lii: vec3<u32>
+) {
+  let i = gii.x;
+  let j = lii.x;
+  let n_bins = p.mode;
+  atomicStore(&local_bins[j], 0u);
+  workgroupBarrier();
+  if i < p.len {
+    let t = (bitcast<f32>(a[i]) - p.lo) / (p.hi - p.lo);
+    // NaN fails both comparisons
+    if t >= 0. && t < 1. {
+      let bin = min(u32(t * f32(n_bins)), n_bins - 1u);
+      atomicAdd(&local_bins[bin], 1u);
+    }
+  }
+  workgroupBarrier();
+  if j < n_bins {
+    let n = atomicLoad(&local_bins[j]);
+    if n > 0u {
+      atomicAdd(&bins[j], n);
+    }
+  }
+}
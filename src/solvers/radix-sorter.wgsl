@@ -0,0 +1,160 @@
+// LSD radix sort of (key, index) pairs by 8 bit digits.
+// The pairs are computed by `sort-keys.wgsl`.
+// Every digit is processed by three passes:
+// 1. `histogram` counts the digits of every tile of `TILE_SIZE` elements;
+// 2. `scan` turns the counts into the offsets of the tiles' digits in the output;
+// 3. `scatter` sorts every tile locally and moves the elements to the output.
+
+// These constants **must** be kept the same as `solvers::radix_sorter::{TILE_SIZE, KEY_PASS_SIZE}`
+const TILE_SIZE: u32 = 256u;
+const KEY_WG_SIZE: u32 = 64u;
+// Must be equal to `TILE_SIZE`, every thread of a tile is responsible for one bin
+const RADIX: u32 = 256u;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+struct Params {
+  count: u32,
+  shift: u32,
+}
+
+/// `x` is the key, `y` is the index of the particle
+@group(1) @binding(0)
+var<storage, read_write> src: array<vec2<u32>>;
+@group(1) @binding(1)
+var<storage, read_write> dst: array<vec2<u32>>;
+/// Digit counts, `hist[digit * num_tiles() + tile]`
+@group(1) @binding(2)
+var<storage, read_write> hist: array<u32>;
+
+var<push_constant> p: Params;
+
+var<workgroup> local_hist: array<atomic<u32>, RADIX>;
+var<workgroup> scan_buf: array<u32, TILE_SIZE>;
+var<workgroup> tile: array<vec2<u32>, TILE_SIZE>;
+var<workgroup> tile_digit: array<u32, TILE_SIZE>;
+var<workgroup> digit_start: array<u32, RADIX>;
+var<workgroup> carry: u32;
+
+fn num_tiles() -> u32 {
+  return (p.count + TILE_SIZE - 1u) / TILE_SIZE;
+}
+
+/// Inclusive Hillis-Steele scan of `scan_buf`. Must be called from uniform control flow.
+fn scan_local(j: u32) {
+  for (var off: u32 = 1u; off < TILE_SIZE; off <<= 1u) {
+    var t = 0u;
+    if j >= off {
+      t = scan_buf[j - off];
+    }
+    workgroupBarrier();
+    scan_buf[j] += t;
+    workgroupBarrier();
+  }
+}
+
+@compute @workgroup_size(TILE_SIZE)
+fn histogram(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let j = lii.x;
+  atomicStore(&local_hist[j], 0u);
+  workgroupBarrier();
+  let i = wid.x * TILE_SIZE + j;
+  if i < p.count {
+    let d = (src[i].x >> p.shift) & 0xffu;
+    atomicAdd(&local_hist[d], 1u);
+  }
+  workgroupBarrier();
+  hist[j * num_tiles() + wid.x] = atomicLoad(&local_hist[j]);
+}
+
+// Exclusive scan of the whole `hist` by a single workgroup
+@compute @workgroup_size(TILE_SIZE)
+fn scan(@builtin(local_invocation_id) lii: vec3<u32>) {
+  let j = lii.x;
+  let n = RADIX * num_tiles();
+  if j == 0u {
+    carry = 0u;
+  }
+  workgroupBarrier();
+  for (var base: u32 = 0u; base < n; base += TILE_SIZE) {
+    let i = base + j;
+    var v = 0u;
+    if i < n {
+      v = hist[i];
+    }
+    scan_buf[j] = v;
+    workgroupBarrier();
+    scan_local(j);
+    let c = carry;
+    if i < n {
+      hist[i] = c + scan_buf[j] - v;
+    }
+    workgroupBarrier();
+    if j == TILE_SIZE - 1u {
+      carry = c + scan_buf[j];
+    }
+    workgroupBarrier();
+  }
+}
+
+@compute @workgroup_size(TILE_SIZE)
+fn scatter(
+  @builtin(local_invocation_id) lii: vec3<u32>,
+  @builtin(workgroup_id) wid: vec3<u32>
+) {
+  let j = lii.x;
+  let i = wid.x * TILE_SIZE + j;
+  // The elements past the end get the 9th bit set and are moved to the end of the tile
+  var d = RADIX;
+  var e = vec2(0u);
+  if i < p.count {
+    e = src[i];
+    d = (e.x >> p.shift) & 0xffu;
+  }
+  tile[j] = e;
+  tile_digit[j] = d;
+
+  // Stable sort of the tile by the digit with 1-bit splits
+  for (var b: u32 = 0u; b < 9u; b += 1u) {
+    workgroupBarrier();
+    let cur_e = tile[j];
+    let cur_d = tile_digit[j];
+    let bit = (cur_d >> b) & 1u;
+    scan_buf[j] = 1u - bit;
+    workgroupBarrier();
+    scan_local(j);
+    let zeros = scan_buf[j];
+    let total_zeros = scan_buf[TILE_SIZE - 1u];
+    var new_pos = zeros - 1u;
+    if bit == 1u {
+      new_pos = total_zeros + j - zeros;
+    }
+    workgroupBarrier();
+    tile[new_pos] = cur_e;
+    tile_digit[new_pos] = cur_d;
+  }
+  workgroupBarrier();
+
+  let sd = tile_digit[j];
+  if sd < RADIX && (j == 0u || tile_digit[j - 1u] != sd) {
+    digit_start[sd] = j;
+  }
+  workgroupBarrier();
+  if sd < RADIX {
+    dst[hist[sd * num_tiles() + wid.x] + j - digit_start[sd]] = tile[j];
+  }
+}
+
+// Writes the sorted particles to the old buffer, which the sorter flips to afterwards
+@compute @workgroup_size(KEY_WG_SIZE)
+fn gather(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let i = gii.x;
+  if i >= p.count {
+    return;
+  }
+  store_old(i, load_cur(src[i].y));
+}
@@ -0,0 +1,427 @@
+use std::sync::Arc;
+
+use wgpu::{
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  BufferUsages, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+  PipelineLayoutDescriptor, ShaderStages,
+};
+
+use crate::render::{profiler::GpuProfiler, swapchain::SwapBuffers};
+
+use super::{
+  pass_params::PassParams,
+  sort_keys::{KeyGrid, KeyPass, SortKey},
+  storage::{ParticleData, StorageLayout},
+  GpuSorter,
+};
+
+/// Count of elements sorted locally by a workgroup of the scatter pass
+pub const TILE_SIZE: u32 = 256;
+/// Workgroup size of the permutation passes
+pub const KEY_PASS_SIZE: u32 = 64;
+const RADIX_BITS: u32 = 8;
+const RADIX: u32 = 1 << RADIX_BITS;
+
+/// LSD radix sort of the particles by a [`SortKey`].
+///
+/// The `(key, index)` pairs are sorted
+/// by 8-bit digits and the particles are permuted once at the end into the old buffer,
+/// which becomes the current one. The particles past the sorted count are not carried over.
+/// Unlike [`super::bitonic_sorter::ParticleBitonicSorter`] it is stable and accepts any count of particles.
+pub struct ParticleRadixSorter {
+  keys: KeyPass,
+  histogram: ComputePipeline,
+  scan: ComputePipeline,
+  scatter: ComputePipeline,
+  gather: ComputePipeline,
+  /// Sorts from the first pairs buffer to the second one
+  forward_bg: BindGroup,
+  /// Sorts from the second pairs buffer to the first one
+  backward_bg: BindGroup,
+  capacity: u32,
+  profiler: Option<Arc<GpuProfiler>>,
+  /// The count of the particles and the shift of the digit
+  params: PassParams,
+}
+
+impl ParticleRadixSorter {
+  /// Creates a sorter able to sort up to `capacity` particles
+  pub fn new(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+    capacity: u32,
+  ) -> ParticleRadixSorter {
+    Self::with_storage(
+      device,
+      queue,
+      particle_layout,
+      capacity,
+      StorageLayout::default(),
+    )
+  }
+
+  /// Creates a sorter of the particle buffers in the `storage` layout
+  pub fn with_storage(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+    capacity: u32,
+    storage: StorageLayout,
+  ) -> ParticleRadixSorter {
+    let pairs_size = capacity.max(1) as u64 * size_of::<[u32; 2]>() as u64;
+    let pairs = [0, 1].map(|_| {
+      device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("RadixSorter pairs"),
+        size: pairs_size,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+      })
+    });
+    let hist = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("RadixSorter histogram"),
+      size: (RADIX * capacity.max(1).div_ceil(TILE_SIZE)) as u64 * size_of::<u32>() as u64,
+      usage: BufferUsages::STORAGE,
+      mapped_at_creation: false,
+    });
+    let storage_entry = |binding| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let pairs_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("RadixSorter pairs"),
+      entries: &[storage_entry(0), storage_entry(1), storage_entry(2)],
+    });
+    let create_bg = |src: &wgpu::Buffer, dst: &wgpu::Buffer| {
+      device.create_bind_group(&BindGroupDescriptor {
+        label: Some("RadixSorter pairs"),
+        layout: &pairs_layout,
+        entries: &[
+          BindGroupEntry {
+            binding: 0,
+            resource: src.as_entire_binding(),
+          },
+          BindGroupEntry {
+            binding: 1,
+            resource: dst.as_entire_binding(),
+          },
+          BindGroupEntry {
+            binding: 2,
+            resource: hist.as_entire_binding(),
+          },
+        ],
+      })
+    };
+    let forward_bg = create_bg(&pairs[0], &pairs[1]);
+    let backward_bg = create_bg(&pairs[1], &pairs[0]);
+
+    let params = PassParams::new(device, queue, 8, 2);
+    let module = &storage.create_shader_module(
+      device,
+      "radix-sorter.wgsl",
+      &params.patch(include_str!("radix-sorter.wgsl")),
+    );
+    let mut bind_group_layouts = vec![particle_layout, &pairs_layout];
+    bind_group_layouts.extend(params.layout());
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("RadixSorter"),
+      bind_group_layouts: &bind_group_layouts,
+      push_constant_ranges: &params.push_constant_ranges(),
+    });
+    let pipeline = |entry_point: &str| {
+      device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(&format!("RadixSorter::{entry_point}")),
+        layout: Some(&layout),
+        module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+      })
+    };
+
+    ParticleRadixSorter {
+      keys: KeyPass::new(device, queue, particle_layout, &pairs[0], storage),
+      histogram: pipeline("histogram"),
+      scan: pipeline("scan"),
+      scatter: pipeline("scatter"),
+      gather: pipeline("gather"),
+      forward_bg,
+      backward_bg,
+      capacity,
+      profiler: None,
+      params,
+    }
+  }
+}
+
+impl GpuSorter for ParticleRadixSorter {
+  fn sort(
+    &self,
+    encoder: &mut wgpu::CommandEncoder,
+    particles: &mut SwapBuffers<ParticleData>,
+    count: u32,
+  ) {
+    assert!(
+      self.supports(count),
+      "`count` must not exceed the capacity of the sorter {}, got {count}",
+      self.capacity
+    );
+    if count == 0 {
+      return;
+    }
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+      label: Some("RadixSort::sort"),
+      timestamp_writes: self
+        .profiler
+        .as_ref()
+        .and_then(|p| p.compute_writes("Radix sort")),
+    });
+    let key_groups = count.div_ceil(KEY_PASS_SIZE);
+    let tiles = count.div_ceil(TILE_SIZE);
+
+    self.keys.dispatch(&mut pass, particles.cur_group(), count);
+
+    let shifts = (0..u32::BITS).step_by(RADIX_BITS as usize);
+    let params = self
+      .params
+      .prepare(shifts.map(|shift| [count, shift]).collect());
+    // An even number of digits, so the result ends up in the first pairs buffer
+    for digit in 0..(u32::BITS / RADIX_BITS) as usize {
+      let bg = if digit % 2 == 0 {
+        &self.forward_bg
+      } else {
+        &self.backward_bg
+      };
+      pass.set_bind_group(1, bg, &[]);
+      params.set(&mut pass, digit);
+      pass.set_pipeline(&self.histogram);
+      pass.dispatch_workgroups(tiles, 1, 1);
+      pass.set_pipeline(&self.scan);
+      pass.dispatch_workgroups(1, 1, 1);
+      pass.set_pipeline(&self.scatter);
+      pass.dispatch_workgroups(tiles, 1, 1);
+    }
+
+    pass.set_bind_group(1, &self.forward_bg, &[]);
+    pass.set_pipeline(&self.gather);
+    pass.dispatch_workgroups(key_groups, 1, 1);
+    drop(pass);
+    particles.flip();
+  }
+
+  fn set_key(&self, queue: &wgpu::Queue, key: SortKey, grid: &KeyGrid) {
+    self.keys.set_key(queue, key, grid);
+  }
+
+  fn supports(&self, count: u32) -> bool {
+    count <= self.capacity
+  }
+
+  fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>) {
+    self.profiler = profiler;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use core::slice;
+
+  use rand::{distr::Distribution, seq::SliceRandom};
+  use wgpu::{
+    BufferUsages, Features, InstanceDescriptor, Limits, RequestAdapterOptions, ShaderStages,
+  };
+
+  use crate::{
+    render::swapchain::{SwapBuffers, SwapBuffersDescriptor},
+    solvers::{
+      bitonic_sorter::{ParticleBitonicSorter, SortMode},
+      radix_sorter::ParticleRadixSorter,
+      sph_solver_gpu::Particle,
+      storage::{ParticleData, StorageLayout},
+      GpuSorter,
+    },
+  };
+
+  async fn setup_wgpu() -> Result<(wgpu::Device, wgpu::Queue), ()> {
+    let instance = wgpu::Instance::new(&InstanceDescriptor {
+      backends: wgpu::Backends::PRIMARY,
+      ..Default::default()
+    });
+    let adapter = instance
+      .request_adapter(&RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+      })
+      .await
+      .expect("Unable to request an adapter");
+    adapter
+      .request_device(
+        &wgpu::DeviceDescriptor {
+          required_limits: Limits {
+            max_compute_invocations_per_workgroup: 512,
+            max_compute_workgroup_size_x: 512,
+            max_push_constant_size: 8,
+            ..Default::default()
+          },
+          required_features: Features::PUSH_CONSTANTS,
+          ..Default::default()
+        },
+        None,
+      )
+      .await
+      .map_err(|_| ())
+  }
+
+  #[derive(Clone, Copy, Debug)]
+  enum Input {
+    Random,
+    Sorted,
+    Reversed,
+    Duplicates,
+  }
+
+  fn keys(input: Input, count: usize) -> Vec<f32> {
+    let mut rng = rand::rng();
+    let d = rand::distr::Uniform::new_inclusive(-1000.0f32, 1000.0).unwrap();
+    let mut v: Vec<f32> = (0..count).map(|_| d.sample(&mut rng)).collect();
+    match input {
+      Input::Random => (),
+      Input::Sorted => v.sort_by(f32::total_cmp),
+      Input::Reversed => v.sort_by(|a, b| b.total_cmp(a)),
+      Input::Duplicates => {
+        let values = [-3.5, 0.0, 1.0, 42.0];
+        v = (0..count).map(|i| values[i % values.len()]).collect();
+        v.shuffle(&mut rng);
+      }
+    }
+    v
+  }
+
+  /// Sorts the particles with the given x coordinates on the GPU and returns the resulting coordinates
+  fn gpu_sort(
+    device: &wgpu::Device,
+    queue: &mut wgpu::Queue,
+    sorter: &dyn GpuSorter,
+    buf: &mut SwapBuffers<ParticleData>,
+    xs: &[f32],
+  ) -> Vec<f32> {
+    let count = xs.len();
+    let mut particles = vec![Particle::default(); count];
+    for (p, &x) in particles.iter_mut().zip(xs) {
+      p.pos.x = x;
+    }
+    buf.reset(
+      ParticleData::new(StorageLayout::ArrayOfStructs, &particles),
+      device,
+    );
+    let obuf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("outbuf"),
+      size: 48 * count as u64,
+      usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    });
+    let mut encoder =
+      device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    sorter.sort(&mut encoder, buf, count as u32);
+    encoder.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * count as u64);
+    queue.submit([encoder.finish()]);
+
+    obuf
+      .slice(..)
+      .map_async(wgpu::MapMode::Read, |a| a.unwrap());
+    device.poll(wgpu::MaintainBase::Wait);
+    let out = unsafe {
+      slice::from_raw_parts::<Particle>(obuf.slice(..).get_mapped_range().as_ptr().cast(), count)
+        .iter()
+        .map(|p| p.pos.x)
+        .collect()
+    };
+    obuf.unmap();
+    out
+  }
+
+  async fn compare_with_cpu(
+    create: impl Fn(&wgpu::Device, &wgpu::Queue, &wgpu::BindGroupLayout, u32) -> Box<dyn GpuSorter>,
+    count: usize,
+  ) -> Result<(), ()> {
+    let (device, ref mut queue) = setup_wgpu().await?;
+    let mut buf = SwapBuffers::init_with(
+      ParticleData::new(
+        StorageLayout::ArrayOfStructs,
+        &vec![Particle::default(); count],
+      ),
+      &device,
+      SwapBuffersDescriptor {
+        usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        visibility: ShaderStages::COMPUTE,
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+      },
+    );
+    let sorter = create(&device, queue, buf.cur_layout(), count as u32);
+    for input in [
+      Input::Random,
+      Input::Sorted,
+      Input::Reversed,
+      Input::Duplicates,
+    ] {
+      let xs = keys(input, count);
+      let mut expected = xs.clone();
+      expected.sort_by(f32::total_cmp);
+      let got = gpu_sort(&device, queue, sorter.as_ref(), &mut buf, &xs);
+      if let Some(i) = (0..count).find(|&i| got[i] != expected[i]) {
+        panic!(
+          "{input:?} input of {count} elements: mismatch at index {i}, expected {} got {}",
+          expected[i], got[i]
+        );
+      }
+    }
+    Ok(())
+  }
+
+  fn radix(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    count: u32,
+  ) -> Box<dyn GpuSorter> {
+    Box::new(ParticleRadixSorter::new(device, queue, layout, count))
+  }
+
+  fn bitonic(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    count: u32,
+  ) -> Box<dyn GpuSorter> {
+    Box::new(ParticleBitonicSorter::with_mode(
+      device,
+      queue,
+      layout,
+      SortMode::KeyValue,
+      count,
+    ))
+  }
+
+  #[tokio::test]
+  async fn gpu_radix_sort_4096() -> Result<(), ()> {
+    compare_with_cpu(radix, 4096).await
+  }
+
+  #[tokio::test]
+  async fn gpu_radix_sort_not_power_of_two() -> Result<(), ()> {
+    compare_with_cpu(radix, 5003).await
+  }
+
+  #[tokio::test]
+  async fn gpu_bitonic_sort_4096_like_cpu() -> Result<(), ()> {
+    compare_with_cpu(bitonic, 4096).await
+  }
+}
@@ -0,0 +1,127 @@
+// Computes the `(key, index)` pairs sorted by the key/value sorters
+// This constant **must** be kept the same as `solvers::sort_keys::KEY_PASS_SIZE`
+const WG_SIZE: u32 = 64u;
+// This constant **must** be kept the same as `solvers::emitters::DEAD_POS`
+const DEAD_POS: f32 = 1e30;
+// This constant **must** be kept the same as `solvers::sort_keys::GRID_BITS`
+const GRID_BITS: u32 = 10u;
+const GRID_MAX: u32 = (1u << GRID_BITS) - 1u;
+// Greater than any key of an alive particle
+const DEAD_KEY: u32 = 0xffffffffu;
+
+// These constants **must** be kept the same as the discriminants of `solvers::sort_keys::SortKey`
+const KEY_X: u32 = 0u;
+const KEY_CELL: u32 = 1u;
+const KEY_MORTON: u32 = 2u;
+const KEY_HILBERT: u32 = 3u;
+const KEY_Y: u32 = 4u;
+const KEY_Z: u32 = 5u;
+const KEY_DENSITY: u32 = 6u;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+struct KeyParams {
+  origin: vec3<f32>,
+  cell_size: f32,
+  kind: u32,
+}
+
+/// `x` is the key, `y` is the index of the particle
+@group(1) @binding(0)
+var<storage, read_write> pairs: array<vec2<u32>>;
+@group(1) @binding(1)
+var<uniform> key: KeyParams;
+
+var<push_constant> count: u32;
+
+/// Maps a float to an unsigned integer preserving the order
+fn float_key(f: f32) -> u32 {
+  let b = bitcast<u32>(f);
+  if (b & 0x80000000u) != 0u {
+    return ~b;
+  }
+  return b | 0x80000000u;
+}
+
+/// Coordinates of the grid cell containing `p`, clamped to the grid
+fn cell(p: vec3<f32>) -> vec3<u32> {
+  let c = floor((p - key.origin) / key.cell_size);
+  return vec3<u32>(clamp(c, vec3(0.), vec3(f32(GRID_MAX))));
+}
+
+/// Inserts two zero bits between every bit of the lower `GRID_BITS` bits of `v`
+fn spread(v: u32) -> u32 {
+  var x = v & GRID_MAX;
+  x = (x | (x << 16u)) & 0x030000ffu;
+  x = (x | (x << 8u)) & 0x0300f00fu;
+  x = (x | (x << 4u)) & 0x030c30c3u;
+  x = (x | (x << 2u)) & 0x09249249u;
+  return x;
+}
+
+fn morton(c: vec3<u32>) -> u32 {
+  return (spread(c.x) << 2u) | (spread(c.y) << 1u) | spread(c.z);
+}
+
+/// Index of the cell along the 3D Hilbert curve (Skilling's transpose algorithm)
+fn hilbert(c: vec3<u32>) -> u32 {
+  var x = array<u32, 3>(c.x, c.y, c.z);
+  // Inverse undo of the excess work
+  for (var q: u32 = 1u << (GRID_BITS - 1u); q > 1u; q >>= 1u) {
+    let p = q - 1u;
+    for (var i: u32 = 0u; i < 3u; i += 1u) {
+      if (x[i] & q) != 0u {
+        x[0] ^= p;
+      } else {
+        let t = (x[0] ^ x[i]) & p;
+        x[0] ^= t;
+        x[i] ^= t;
+      }
+    }
+  }
+  // Gray encode
+  x[1] ^= x[0];
+  x[2] ^= x[1];
+  var t = 0u;
+  for (var q: u32 = 1u << (GRID_BITS - 1u); q > 1u; q >>= 1u) {
+    if (x[2] & q) != 0u {
+      t ^= q - 1u;
+    }
+  }
+  x[0] ^= t;
+  x[1] ^= t;
+  x[2] ^= t;
+  return morton(vec3(x[0], x[1], x[2]));
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn make_keys(@builtin(global_invocation_id) gii: vec3<u32>) {
+  let i = gii.x;
+  if i >= count {
+    return;
+  }
+  let p = cur_pos(i);
+  var k: u32;
+  if any(p >= vec3(DEAD_POS)) {
+    k = DEAD_KEY;
+  } else if key.kind == KEY_X {
+    k = float_key(p.x);
+  } else if key.kind == KEY_Y {
+    k = float_key(p.y);
+  } else if key.kind == KEY_Z {
+    k = float_key(p.z);
+  } else if key.kind == KEY_DENSITY {
+    k = float_key(cur_density(i));
+  } else {
+    let c = cell(p);
+    if key.kind == KEY_CELL {
+      k = (c.z << (2u * GRID_BITS)) | (c.y << GRID_BITS) | c.x;
+    } else if key.kind == KEY_MORTON {
+      k = morton(c);
+    } else {
+      k = hilbert(c);
+    }
+  }
+  pairs[i] = vec2(k, i);
+}
@@ -0,0 +1,389 @@
+use core::slice;
+
+use cgmath::Point3;
+use serde::{Deserialize, Serialize};
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor,
+  ShaderStages,
+};
+
+use crate::render::AsBuffer;
+
+use super::{pass_params::PassParams, storage::StorageLayout};
+
+/// Workgroup size of the key pass.
+/// This constant **must** be kept the same as `WG_SIZE` in `sort-keys.wgsl`.
+pub const KEY_PASS_SIZE: u32 = 64;
+/// Count of bits of every cell coordinate of [`KeyGrid`]
+pub const GRID_BITS: u32 = 10;
+
+#[cfg(test)]
+mod test {
+  use core::slice;
+
+  use cgmath::{Point3, Vector3};
+  use rand::seq::SliceRandom;
+  use wgpu::{
+    BufferUsages, Features, InstanceDescriptor, Limits, RequestAdapterOptions, ShaderStages,
+  };
+
+  use crate::{
+    render::swapchain::{SwapBuffers, SwapBuffersDescriptor},
+    solvers::{
+      emitters::DEAD_POS,
+      radix_sorter::ParticleRadixSorter,
+      sph_solver_gpu::Particle,
+      storage::{ParticleData, StorageLayout},
+      GpuSorter,
+    },
+  };
+
+  use super::{KeyGrid, SortKey};
+
+  const SIDE: u32 = 8;
+  const DEAD: usize = 17;
+
+  async fn setup_wgpu() -> Result<(wgpu::Device, wgpu::Queue), ()> {
+    let instance = wgpu::Instance::new(&InstanceDescriptor {
+      backends: wgpu::Backends::PRIMARY,
+      ..Default::default()
+    });
+    let adapter = instance
+      .request_adapter(&RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+      })
+      .await
+      .expect("Unable to request an adapter");
+    adapter
+      .request_device(
+        &wgpu::DeviceDescriptor {
+          required_limits: Limits {
+            max_push_constant_size: 8,
+            ..Default::default()
+          },
+          required_features: Features::PUSH_CONSTANTS,
+          ..Default::default()
+        },
+        None,
+      )
+      .await
+      .map_err(|_| ())
+  }
+
+  /// Sorts one particle in the center of every cell of a `SIDE`³ block at the origin of the grid
+  /// followed by some dead particles and returns the cells in the resulting order
+  async fn sorted_cells(key: SortKey) -> Result<Vec<[i32; 3]>, ()> {
+    let (device, queue) = setup_wgpu().await?;
+    let grid = KeyGrid {
+      origin: Point3::new(-2.0, 0.0, -2.0),
+      cell_size: 0.5,
+    };
+    let mut dead = Particle::default();
+    dead.pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS);
+    let mut particles = vec![dead; DEAD];
+    for i in 0..SIDE.pow(3) {
+      let c =
+        [i % SIDE, i / SIDE % SIDE, i / SIDE / SIDE].map(|c| (c as f32 + 0.5) * grid.cell_size);
+      let mut p = Particle::default();
+      p.pos = grid.origin + Vector3::from(c);
+      particles.push(p);
+    }
+    particles.shuffle(&mut rand::rng());
+    let count = particles.len();
+
+    let mut buf = SwapBuffers::init_with(
+      ParticleData::new(StorageLayout::ArrayOfStructs, &particles),
+      &device,
+      SwapBuffersDescriptor {
+        usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        visibility: ShaderStages::COMPUTE,
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+      },
+    );
+    let sorter = ParticleRadixSorter::new(&device, &queue, buf.cur_layout(), count as u32);
+    sorter.set_key(&queue, key, &grid);
+    let obuf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("outbuf"),
+      size: 48 * count as u64,
+      usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    });
+    let mut encoder =
+      device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    sorter.sort(&mut encoder, &mut buf, count as u32);
+    encoder.copy_buffer_to_buffer(buf.cur_buf(), 0, &obuf, 0, 48 * count as u64);
+    queue.submit([encoder.finish()]);
+
+    obuf
+      .slice(..)
+      .map_async(wgpu::MapMode::Read, |a| a.unwrap());
+    device.poll(wgpu::MaintainBase::Wait);
+    let out: Vec<Particle> = unsafe {
+      slice::from_raw_parts::<Particle>(obuf.slice(..).get_mapped_range().as_ptr().cast(), count)
+        .to_vec()
+    };
+    obuf.unmap();
+
+    let (alive, dead) = out.split_at(count - DEAD);
+    assert!(
+      dead.iter().all(|p| p.pos.x == DEAD_POS),
+      "{key:?}: dead particles must be moved to the end"
+    );
+    Ok(
+      alive
+        .iter()
+        .map(|p| {
+          let c = (p.pos - grid.origin) / grid.cell_size;
+          [c.x, c.y, c.z].map(|c| c.floor() as i32)
+        })
+        .collect(),
+    )
+  }
+
+  #[tokio::test]
+  async fn gpu_key_x() -> Result<(), ()> {
+    let cells = sorted_cells(SortKey::X).await?;
+    assert!(cells.is_sorted_by_key(|c| c[0]));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_key_cell() -> Result<(), ()> {
+    let cells = sorted_cells(SortKey::Cell).await?;
+    assert!(cells.is_sorted_by_key(|c| [c[2], c[1], c[0]]));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_key_morton() -> Result<(), ()> {
+    // Every 8 consecutive cells of the Z-order curve form a 2x2x2 cube
+    for cube in sorted_cells(SortKey::Morton).await?.chunks(8) {
+      for axis in 0..3 {
+        let min = cube.iter().map(|c| c[axis]).min().unwrap();
+        let max = cube.iter().map(|c| c[axis]).max().unwrap();
+        assert!(min % 2 == 0 && max == min + 1, "{cube:?} is not a cube");
+      }
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn gpu_key_hilbert() -> Result<(), ()> {
+    // Consecutive cells of the Hilbert curve are always face neighbours
+    for pair in sorted_cells(SortKey::Hilbert).await?.windows(2) {
+      let dist: i32 = (0..3).map(|i| (pair[0][i] - pair[1][i]).abs()).sum();
+      assert_eq!(
+        dist, 1,
+        "{:?} and {:?} are not neighbours",
+        pair[0], pair[1]
+      );
+    }
+    Ok(())
+  }
+}
+
+/// The value the particles are ordered by
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+  /// The x coordinate of the position
+  #[default]
+  X = 0,
+  /// Linear index of the [`KeyGrid`] cell, the x coordinate varies fastest
+  Cell = 1,
+  /// Index of the [`KeyGrid`] cell along the Z-order curve
+  Morton = 2,
+  /// Index of the [`KeyGrid`] cell along the Hilbert curve.
+  /// Slower to compute than [`SortKey::Morton`], but never jumps between distant cells.
+  Hilbert = 3,
+  /// The y coordinate of the position
+  Y = 4,
+  /// The z coordinate of the position
+  Z = 5,
+  Density = 6,
+}
+
+impl SortKey {
+  pub const ALL: [SortKey; 4] = [SortKey::X, SortKey::Cell, SortKey::Morton, SortKey::Hilbert];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      SortKey::X => "x",
+      SortKey::Y => "y",
+      SortKey::Z => "z",
+      SortKey::Density => "Density",
+      SortKey::Cell => "Cell index",
+      SortKey::Morton => "Morton",
+      SortKey::Hilbert => "Hilbert",
+    }
+  }
+}
+
+/// Uniform grid of `2^GRID_BITS` cells along every axis used by the cell based keys.
+/// Positions outside of the grid are clamped to its border cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyGrid {
+  /// Min corner of the grid
+  pub origin: Point3<f32>,
+  pub cell_size: f32,
+}
+
+impl Default for KeyGrid {
+  fn default() -> Self {
+    Self {
+      origin: Point3::new(0.0, 0.0, 0.0),
+      cell_size: 1.0,
+    }
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KeyParams {
+  origin: [f32; 3],
+  cell_size: f32,
+  kind: u32,
+  _padding: [u32; 3],
+}
+
+impl KeyParams {
+  fn new(key: SortKey, grid: &KeyGrid) -> Self {
+    Self {
+      origin: grid.origin.into(),
+      cell_size: grid.cell_size,
+      kind: key as u32,
+      _padding: [0; 3],
+    }
+  }
+}
+
+impl AsBuffer for KeyParams {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<KeyParams>(),
+      )
+    }
+  }
+}
+
+/// Fills a buffer of `(key, index)` pairs from the particles of the current buffer.
+/// Dead particles get the greatest key, so that they are moved to the end.
+pub(crate) struct KeyPass {
+  pipeline: ComputePipeline,
+  bg: BindGroup,
+  params_buf: Buffer,
+  /// Count of the particles
+  count: PassParams,
+}
+
+impl KeyPass {
+  /// Creates a pass computing [`SortKey::X`] keys into `pairs`
+  pub(crate) fn new(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    particle_layout: &wgpu::BindGroupLayout,
+    pairs: &Buffer,
+    storage: StorageLayout,
+  ) -> Self {
+    let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("Sort key params"),
+      contents: KeyParams::new(SortKey::default(), &KeyGrid::default()).as_bytes_buffer(),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Sort keys BG layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
+      ],
+    });
+    let bg = device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Sort keys BG"),
+      layout: &bg_layout,
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: pairs.as_entire_binding(),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: params_buf.as_entire_binding(),
+        },
+      ],
+    });
+    let count = PassParams::new(device, queue, 4, 2);
+    let module = &storage.create_shader_module(
+      device,
+      "sort-keys.wgsl",
+      &count.patch(include_str!("sort-keys.wgsl")),
+    );
+    let mut bind_group_layouts = vec![particle_layout, &bg_layout];
+    bind_group_layouts.extend(count.layout());
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("Sort keys"),
+      bind_group_layouts: &bind_group_layouts,
+      push_constant_ranges: &count.push_constant_ranges(),
+    });
+    let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+      label: Some("Sort keys::make_keys"),
+      layout: Some(&layout),
+      module,
+      entry_point: Some("make_keys"),
+      compilation_options: Default::default(),
+      cache: None,
+    });
+    Self {
+      pipeline,
+      bg,
+      params_buf,
+      count,
+    }
+  }
+
+  pub(crate) fn set_key(&self, queue: &wgpu::Queue, key: SortKey, grid: &KeyGrid) {
+    queue.write_buffer(
+      &self.params_buf,
+      0,
+      KeyParams::new(key, grid).as_bytes_buffer(),
+    );
+  }
+
+  /// Writes the pairs of the first `count` particles. The group `1` has to be rebound afterwards.
+  pub(crate) fn dispatch(
+    &self,
+    pass: &mut wgpu::ComputePass,
+    particles: &wgpu::BindGroup,
+    count: u32,
+  ) {
+    pass.set_pipeline(&self.pipeline);
+    pass.set_bind_group(0, particles, &[]);
+    pass.set_bind_group(1, &self.bg, &[]);
+    self.count.prepare(vec![[count, 0]]).set(pass, 0);
+    pass.dispatch_workgroups(count.div_ceil(KEY_PASS_SIZE), 1, 1);
+  }
+}
@@ -1,9 +1,9 @@
-struct Particle {
-  pos: vec3<f32>,
-  density: f32,
-  velocity: vec3<f32>,
-  forces: vec3<f32>,
-}
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+// This constant **must** be kept the same as `solvers::sph_solver_gpu::CEILING`
+const CEILING: f32 = 10.0;
+
 struct Global {
   size: vec2<f32>,
   time: f32,
@@ -22,16 +22,20 @@ struct SimParams {
   ttr: f32,
   dtr: f32,
 }
-
-@group(0) @binding(0)
-var<storage, read_write> cur_particles: array<Particle>;
-@group(0) @binding(1)
-var<storage, read_write> old_particles: array<Particle>;
+/// See `solvers::emitters::ParticleSources`
+struct ParticleCount {
+  vertex_count: u32,
+  instance_count: u32,
+  first_vertex: u32,
+  first_instance: u32,
+}
 
 @group(1) @binding(0)
 var<storage, read_write> pressure: array<f32>;
 @group(1) @binding(1)
 var<storage, read> params: SimParams;
+@group(1) @binding(2)
+var<storage, read> count: ParticleCount;
 
 @group(2) @binding(0)
 var<uniform> g: Global;
@@ -69,15 +73,15 @@ fn laplacian_viscosity(r: f32, h: f32) -> f32 {
   }
 }
 
-fn mpless(l: Particle, r: Particle) -> bool {
-  return l.pos.x < r.pos.x;
+fn mpless(l: vec3f, r: vec3f) -> bool {
+  return l.x < r.x;
 }
 
 fn intrp_density(at: vec3<f32>) -> f32 {
   var sum: f32 = 0.0;
-  let els = arrayLength(&old_particles);
+  let els = count.instance_count;
   for (var i: u32 = 0; i < els; i += u32(1)) {
-    sum += spiky(distance(at, old_particles[i].pos), params.h);
+    sum += spiky(distance(at, old_pos(i)), params.h);
   }
   sum *= params.m0;
   return sum;
@@ -89,9 +93,17 @@ const NA: f32 = 7;
 @compute @workgroup_size(WG_SIZE)
 fn density_pressure(@builtin(global_invocation_id) idx: vec3u) {
   let num = idx.x;
+  // The first pass rewrites every particle of the current buffer from the old one,
+  // so the buffers do not have to be copied between the steps
+  var particle = load_old(num);
+  if num >= count.instance_count {
+    store_cur(num, particle);
+    return;
+  }
   // Density
-  let rho = intrp_density(old_particles[num].pos);
-  cur_particles[num].density = rho;
+  let rho = intrp_density(particle.pos);
+  particle.density = rho;
+  store_cur(num, particle);
   // Pressure
   // var p = 1 / (NA*params.k) * (pow((cur_particles[num].density)/params.rho0, NA) - 1);
   var p = params.k * (rho - params.rho0);
@@ -105,39 +117,44 @@ fn density_pressure(@builtin(global_invocation_id) idx: vec3u) {
 @compute @workgroup_size(WG_SIZE)
 fn pressure_forces(@builtin(global_invocation_id) idx: vec3u) {
   let i = idx.x;
-  let els = arrayLength(&pressure);
-  cur_particles[i].forces = vec3f(0.);
+  let els = count.instance_count;
+  if i >= els {
+    return;
+  }
+  var particle = load_cur(i);
+  let pos = old_pos(i);
 
-  var probe = old_particles[i];
-  probe.pos.x -= params.h;
+  var probe = pos;
+  probe.x -= params.h;
   var l = i;
-  while l > 0 && !mpless(old_particles[l], probe) {
+  while l > 0 && !mpless(old_pos(l), probe) {
     l -= 1u;
   }
-  probe.pos.x += params.h * 2;
+  probe.x += params.h * 2;
   var r = i;
-  while r < els - 1 && !mpless(probe, old_particles[r]) {
+  while r < els - 1 && !mpless(probe, old_pos(r)) {
     r += 1u;
   }
   var f_visc = vec3f(0.);
-  cur_particles[i].forces = vec3(0.);
+  particle.forces = vec3(0.);
   for (var j: u32 = 0; j < els; j += 1u) {
     if (i == j) {
       continue;
     }
     // pressure
-    cur_particles[i].forces -= (pressure[i]/cur_particles[i].density/cur_particles[i].density
-                              + pressure[j]/cur_particles[j].density/cur_particles[j].density)
-                            * grad_spiky(old_particles[i].pos - old_particles[j].pos, params.h);
+    particle.forces -= (pressure[i]/particle.density/particle.density
+                       + pressure[j]/cur_density(j)/cur_density(j))
+                     * grad_spiky(pos - old_pos(j), params.h);
   }
   // NaN
-  if length(cur_particles[i].forces) != length(cur_particles[i].forces) {
-    cur_particles[i].forces = vec3f(0.);
+  if length(particle.forces) != length(particle.forces) {
+    particle.forces = vec3f(0.);
   }
-  cur_particles[i].forces *= cur_particles[i].density * params.m0/params.rho0;
+  particle.forces *= particle.density * params.m0/params.rho0;
   // External forces
-  cur_particles[i].velocity += g.dt/params.m0 * (vec3f(0., -30.0, 0.) + f_visc);
-  // cur_particles[i].forces += 20.0*cross(vec3(0.,1.,0.), old_particles[i].pos);
+  particle.velocity += g.dt/params.m0 * (vec3f(0., -30.0, 0.) + f_visc);
+  // particle.forces += 20.0*cross(vec3(0.,1.,0.), pos);
+  store_cur(i, particle);
 }
 
 fn project_on(a: vec3f, direction: vec3f) -> vec3f {
@@ -147,22 +164,25 @@ fn project_on(a: vec3f, direction: vec3f) -> vec3f {
 @compute @workgroup_size(WG_SIZE)
 fn integrate_forces(@builtin(global_invocation_id) idx: vec3u) {
   let i = idx.x;
-  let els = arrayLength(&pressure);
+  if i >= count.instance_count {
+    return;
+  }
+  var particle = load_cur(i);
   var a: vec3f = vec3f(0.0);
-  if cur_particles[i].density == cur_particles[i].density {
-    a = g.dt * cur_particles[i].forces / cur_particles[i].density;
+  if particle.density == particle.density {
+    a = g.dt * particle.forces / particle.density;
   }
-  cur_particles[i].velocity += a;
-  // cur_particles[i].velocity += g.dt * cur_particles[i].forces/params.m0;
+  particle.velocity += a;
+  // particle.velocity += g.dt * particle.forces/params.m0;
   
-  cur_particles[i].pos += g.dt * cur_particles[i].velocity;
+  particle.pos += g.dt * particle.velocity;
 
-  // let a = cur_particles[i].forces / params.m0;
-  // cur_particles[i].pos += g.dt * cur_particles[i].velocity + 0.5 * a * g.dt * g.dt;
+  // let a = particle.forces / params.m0;
+  // particle.pos += g.dt * particle.velocity + 0.5 * a * g.dt * g.dt;
 
   // Out of bounds check
-  var p = cur_particles[i].pos;
-  var v = cur_particles[i].velocity;
+  var p = particle.pos;
+  var v = particle.velocity;
   let e = params.e;
   let w = params.w;
   if abs(p.z) > w {
@@ -177,10 +197,11 @@ fn integrate_forces(@builtin(global_invocation_id) idx: vec3u) {
     p.y = 0.;
     v.y = -e * v.y;
   }
-  if p.y > 10. {
-    p.y = 10.;
+  if p.y > CEILING {
+    p.y = CEILING;
     v.y = -e * v.y;
   }
-  cur_particles[i].pos = p;
-  cur_particles[i].velocity = v;
+  particle.pos = p;
+  particle.velocity = v;
+  store_cur(i, particle);
 }
\ No newline at end of file
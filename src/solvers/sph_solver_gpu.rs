@@ -1,4 +1,4 @@
-use std::slice;
+use std::{slice, sync::Arc};
 
 use cgmath::{EuclideanSpace, Point3, Vector3, Zero};
 use wgpu::{
@@ -8,14 +8,23 @@ use wgpu::{
 };
 
 use crate::render::{
+  profiler::GpuProfiler,
   render_target::{ExternalResources, RenderTarget},
   swapchain::SwapBuffers,
   AsBuffer,
 };
 
-use super::bitonic_sorter::ParticleBitonicSorter;
+use super::{
+  bitonic_sorter::{ParticleBitonicSorter, SortMode, SortOptions},
+  sort_keys::{KeyGrid, SortKey},
+  storage::{ParticleData, StorageLayout},
+  GpuSorter,
+};
 // This constant **must** be kept the same as `WG_SIZE` in the solver shader.
 pub const SOLVER_WG_SIZE: u32 = 16;
+/// Height the particles bounce off.
+/// This constant **must** be kept the same as `CEILING` in the solver shader.
+pub const CEILING: f32 = 10.0;
 
 #[derive(Clone, Debug)]
 pub struct Particle {
@@ -63,12 +72,13 @@ pub struct SphSolverGpu {
   integrate_forces: ComputePipeline,
   pressure_buf: Buffer,
   pressure_bg: BindGroup,
-  sorter: ParticleBitonicSorter,
+  sorter: Box<dyn GpuSorter>,
   count: u32,
+  profiler: Option<Arc<GpuProfiler>>,
 }
 
 pub struct SphSolverGpuRenderResources<'a> {
-  pub pos: &'a SwapBuffers<Vec<Particle>>,
+  pub pos: &'a SwapBuffers<ParticleData>,
   pub global_bg: &'a wgpu::BindGroup,
   pub params_buf: &'a wgpu::Buffer,
 }
@@ -81,7 +91,9 @@ impl<'a> RenderTarget<'a> for SphSolverGpu {
     usize,
     &'a BindGroupLayout,
     &'a wgpu::Buffer,
-    &'a SwapBuffers<Vec<Particle>>,
+    &'a SwapBuffers<ParticleData>,
+    &'a wgpu::Buffer,
+    StorageLayout,
   );
   type UpdateResources = Self::RenderResources;
 
@@ -92,24 +104,20 @@ impl<'a> RenderTarget<'a> for SphSolverGpu {
     resources: &'a Self::UpdateResources,
     encoder: &mut wgpu::CommandEncoder,
   ) {
-    {
+    // A pass per stage, so that every stage is timed separately by the profiler
+    let stages = [
+      (&self.density_pressure, "SPH density & pressure"),
+      (&self.pressure_forces, "SPH pressure forces"),
+      (&self.integrate_forces, "SPH integration"),
+    ];
+    for (pipeline, label) in stages {
       let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-        label: Some("SPH Solver compute pass"),
-        timestamp_writes: None,
+        label: Some(label),
+        timestamp_writes: self.profiler.as_ref().and_then(|p| p.compute_writes(label)),
       });
-      self.setup_groups_for_compute(&self.density_pressure, resources, &mut pass);
-      pass.dispatch_workgroups(self.count / SOLVER_WG_SIZE, 1, 1);
-
-      self.setup_groups_for_compute(&self.pressure_forces, resources, &mut pass);
-      pass.dispatch_workgroups(self.count / SOLVER_WG_SIZE, 1, 1);
-
-      self.setup_groups_for_compute(&self.integrate_forces, resources, &mut pass);
+      self.setup_groups_for_compute(pipeline, resources, &mut pass);
       pass.dispatch_workgroups(self.count / SOLVER_WG_SIZE, 1, 1);
     }
-
-    self
-      .sorter
-      .sort(encoder, resources.pos.cur_group(), self.count);
   }
 
   fn render_into_pass(&self, _pass: &mut wgpu::RenderPass, _resources: &'a Self::RenderResources) {
@@ -131,6 +139,7 @@ impl SphSolverGpu {
   }
   pub fn new<'a>(
     device: &wgpu::Device,
+    queue: &wgpu::Queue,
     init_res: <SphSolverGpu as RenderTarget>::InitResources,
   ) -> Self {
     let pressure_buf = device.create_buffer(&BufferDescriptor {
@@ -139,7 +148,10 @@ impl SphSolverGpu {
       usage: wgpu::BufferUsages::STORAGE,
       mapped_at_creation: false,
     });
-    let module = device.create_shader_module(wgpu::include_wgsl!("sph-solver.wgsl"));
+    let module =
+      init_res
+        .5
+        .create_shader_module(device, "sph-solver.wgsl", include_str!("sph-solver.wgsl"));
     let bg_layout_1 = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
       label: None,
       entries: &[
@@ -163,6 +175,16 @@ impl SphSolverGpu {
           },
           count: None,
         },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::COMPUTE,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
     });
     let pressure_bg = device.create_bind_group(&BindGroupDescriptor {
@@ -177,6 +199,10 @@ impl SphSolverGpu {
           binding: 1,
           resource: init_res.2.as_entire_binding(),
         },
+        BindGroupEntry {
+          binding: 2,
+          resource: init_res.4.as_entire_binding(),
+        },
       ],
     });
 
@@ -209,7 +235,17 @@ impl SphSolverGpu {
       compilation_options: Default::default(),
       cache: None,
     });
-    let sorter = ParticleBitonicSorter::new(device, init_res.3.cur_layout());
+    let sorter = Box::new(ParticleBitonicSorter::with_options(
+      device,
+      queue,
+      init_res.3.cur_layout(),
+      SortMode::KeyValue,
+      init_res.0 as u32,
+      &SortOptions {
+        storage: init_res.5,
+        ..Default::default()
+      },
+    ));
     Self {
       density_pressure,
       pressure_forces,
@@ -218,6 +254,23 @@ impl SphSolverGpu {
       pressure_bg,
       count: init_res.0 as u32,
       sorter,
+      profiler: None,
     }
   }
+
+  /// Orders the particles for the next step, the sorter may flip the buffers of `pos`
+  pub fn sort(&self, encoder: &mut wgpu::CommandEncoder, pos: &mut SwapBuffers<ParticleData>) {
+    self.sorter.sort(encoder, pos, self.count);
+  }
+
+  /// Measures the passes of the solver and of its sorter with the `profiler`
+  pub fn set_profiler(&mut self, profiler: Option<Arc<GpuProfiler>>) {
+    self.sorter.set_profiler(profiler.clone());
+    self.profiler = profiler;
+  }
+
+  /// Changes the order the particles are kept in between the steps
+  pub fn set_sort_key(&self, queue: &wgpu::Queue, key: SortKey, grid: &KeyGrid) {
+    self.sorter.set_key(queue, key, grid);
+  }
 }
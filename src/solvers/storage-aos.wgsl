@@ -0,0 +1,46 @@
+// Array of structs particle storage, see `solvers::storage::StorageLayout`.
+// Prepended to the shaders accessing the particles.
+
+struct Particle {
+  pos: vec3<f32>,
+  density: f32,
+  velocity: vec3<f32>,
+  forces: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> cur_particles: array<Particle>;
+@group(0) @binding(1)
+var<storage, read_write> old_particles: array<Particle>;
+
+fn particle_capacity() -> u32 {
+  return arrayLength(&cur_particles);
+}
+
+fn load_cur(i: u32) -> Particle {
+  return cur_particles[i];
+}
+
+fn load_old(i: u32) -> Particle {
+  return old_particles[i];
+}
+
+fn store_cur(i: u32, particle: Particle) {
+  cur_particles[i] = particle;
+}
+
+fn store_old(i: u32, particle: Particle) {
+  old_particles[i] = particle;
+}
+
+fn cur_pos(i: u32) -> vec3<f32> {
+  return cur_particles[i].pos;
+}
+
+fn old_pos(i: u32) -> vec3<f32> {
+  return old_particles[i].pos;
+}
+
+fn cur_density(i: u32) -> f32 {
+  return cur_particles[i].density;
+}
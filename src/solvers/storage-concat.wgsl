@@ -0,0 +1,58 @@
+// Concatenated arrays particle storage, see `solvers::storage::StorageLayout`.
+// Prepended to the shaders accessing the particles.
+// Every buffer holds the arrays of `vec4(pos, density)`, `vec4(velocity, 0)` and `vec4(forces, 0)`
+// of `particle_capacity()` elements one after another.
+
+struct Particle {
+  pos: vec3<f32>,
+  density: f32,
+  velocity: vec3<f32>,
+  forces: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> cur_particles: array<vec4<f32>>;
+@group(0) @binding(1)
+var<storage, read_write> old_particles: array<vec4<f32>>;
+
+fn particle_capacity() -> u32 {
+  return arrayLength(&cur_particles) / 3u;
+}
+
+fn load_cur(i: u32) -> Particle {
+  let n = particle_capacity();
+  let pd = cur_particles[i];
+  return Particle(pd.xyz, pd.w, cur_particles[n + i].xyz, cur_particles[2u * n + i].xyz);
+}
+
+fn load_old(i: u32) -> Particle {
+  let n = particle_capacity();
+  let pd = old_particles[i];
+  return Particle(pd.xyz, pd.w, old_particles[n + i].xyz, old_particles[2u * n + i].xyz);
+}
+
+fn store_cur(i: u32, particle: Particle) {
+  let n = particle_capacity();
+  cur_particles[i] = vec4(particle.pos, particle.density);
+  cur_particles[n + i] = vec4(particle.velocity, 0.);
+  cur_particles[2u * n + i] = vec4(particle.forces, 0.);
+}
+
+fn store_old(i: u32, particle: Particle) {
+  let n = particle_capacity();
+  old_particles[i] = vec4(particle.pos, particle.density);
+  old_particles[n + i] = vec4(particle.velocity, 0.);
+  old_particles[2u * n + i] = vec4(particle.forces, 0.);
+}
+
+fn cur_pos(i: u32) -> vec3<f32> {
+  return cur_particles[i].xyz;
+}
+
+fn old_pos(i: u32) -> vec3<f32> {
+  return old_particles[i].xyz;
+}
+
+fn cur_density(i: u32) -> f32 {
+  return cur_particles[i].w;
+}
@@ -0,0 +1,229 @@
+use std::slice;
+
+use cgmath::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use wgpu::{vertex_attr_array, ShaderModule, VertexBufferLayout};
+
+use crate::render::AsBuffer;
+
+use super::sph_solver_gpu::Particle;
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use super::{ParticleData, StorageLayout};
+  use crate::{render::AsBuffer, solvers::sph_solver_gpu::Particle};
+
+  fn floats(data: &ParticleData) -> Vec<f32> {
+    data
+      .as_bytes_buffer()
+      .chunks_exact(4)
+      .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+      .collect()
+  }
+
+  #[test]
+  fn concatenated_layout() {
+    let particles: Vec<Particle> = (0..3)
+      .map(|i| {
+        let mut p = Particle::default();
+        p.pos = Point3::new(i as f32, 10.0 + i as f32, 20.0 + i as f32);
+        p.density = 30.0 + i as f32;
+        p.velocity = Vector3::new(40.0 + i as f32, 50.0 + i as f32, 60.0 + i as f32);
+        p
+      })
+      .collect();
+    let data = ParticleData::new(StorageLayout::ConcatenatedArrays, &particles);
+    assert_eq!(
+      data.as_bytes_buffer().len(),
+      size_of::<Particle>() * particles.len()
+    );
+    let f = floats(&data);
+    for i in 0..3 {
+      let x = i as f32;
+      assert_eq!(f[4 * i..4 * i + 4], [x, 10.0 + x, 20.0 + x, 30.0 + x]);
+      assert_eq!(
+        f[12 + 4 * i..12 + 4 * i + 4],
+        [40.0 + x, 50.0 + x, 60.0 + x, 0.0]
+      );
+      assert_eq!(f[24 + 4 * i..24 + 4 * i + 4], [0.0; 4]);
+    }
+  }
+
+  #[test]
+  fn aos_layout() {
+    let mut p = Particle::default();
+    p.pos = Point3::new(1.0, 2.0, 3.0);
+    p.density = 4.0;
+    p.velocity = Vector3::new(5.0, 6.0, 7.0);
+    let data = ParticleData::new(StorageLayout::ArrayOfStructs, &[p.clone(), p]);
+    assert_eq!(data.as_bytes_buffer().len(), 2 * size_of::<Particle>());
+    let f = floats(&data);
+    assert_eq!(f[..7], [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+    assert_eq!(f[12..19], [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+  }
+
+  #[test]
+  fn decode_roundtrip() {
+    let particles: Vec<Particle> = (0..5)
+      .map(|i| {
+        let mut p = Particle::default();
+        p.pos = Point3::new(i as f32, -(i as f32), 2.0 * i as f32);
+        p.density = 100.0 * i as f32;
+        p.velocity = Vector3::new(0.5, i as f32, -1.0);
+        p
+      })
+      .collect();
+    for layout in [
+      StorageLayout::ArrayOfStructs,
+      StorageLayout::ConcatenatedArrays,
+    ] {
+      let data = ParticleData::new(layout, &particles);
+      let decoded = ParticleData::decode(layout, data.as_bytes_buffer());
+      assert_eq!(decoded.len(), particles.len());
+      for (a, b) in decoded.iter().zip(&particles) {
+        assert_eq!(
+          (a.pos, a.density, a.velocity),
+          (b.pos, b.density, b.velocity)
+        );
+      }
+    }
+  }
+}
+
+/// Memory layout of the particles in the particle buffers.
+///
+/// The shaders access the particles through the accessors of a prelude
+/// (`storage-aos.wgsl` or `storage-concat.wgsl`) prepended to them, see [`StorageLayout::create_shader_module`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageLayout {
+  /// The buffer is an array of [`Particle`]
+  #[default]
+  ArrayOfStructs,
+  /// The buffer holds the arrays of `vec4(pos, density)`, `vec4(velocity, 0)` and `vec4(forces, 0)`
+  /// one after another. The passes reading only the positions touch a third of the memory.
+  ///
+  /// The arrays share the buffer and its binding, they aren't separate buffers of the fields:
+  /// every pass still binds the whole particle storage and the swaps copy all of the fields.
+  #[serde(alias = "StructOfArrays")]
+  ConcatenatedArrays,
+}
+
+impl StorageLayout {
+  pub const ALL: [StorageLayout; 2] = [
+    StorageLayout::ArrayOfStructs,
+    StorageLayout::ConcatenatedArrays,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      StorageLayout::ArrayOfStructs => "Array of structs",
+      StorageLayout::ConcatenatedArrays => "Concatenated arrays",
+    }
+  }
+
+  fn prelude(self) -> &'static str {
+    match self {
+      StorageLayout::ArrayOfStructs => include_str!("storage-aos.wgsl"),
+      StorageLayout::ConcatenatedArrays => include_str!("storage-concat.wgsl"),
+    }
+  }
+
+  /// Creates a shader module from the `source` accessing the particles bound to the group 0
+  pub fn create_shader_module(
+    self,
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+  ) -> ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+      label: Some(label),
+      source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", self.prelude(), source).into()),
+    })
+  }
+
+  /// Layout of the particle buffer used as a vertex buffer with the position and the density
+  pub fn pos_buffer_layout(self) -> VertexBufferLayout<'static> {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = vertex_attr_array![0 => Float32x3, 1 => Float32];
+    let array_stride = match self {
+      StorageLayout::ArrayOfStructs => size_of::<Particle>(),
+      StorageLayout::ConcatenatedArrays => 4 * size_of::<f32>(),
+    };
+    VertexBufferLayout {
+      array_stride: array_stride as u64,
+      step_mode: wgpu::VertexStepMode::Instance,
+      attributes: &ATTRIBUTES,
+    }
+  }
+}
+
+/// Contents of a particle buffer in a [`StorageLayout`]
+#[derive(Clone)]
+pub struct ParticleData {
+  bytes: Vec<u8>,
+}
+
+impl ParticleData {
+  pub fn new(layout: StorageLayout, particles: &[Particle]) -> Self {
+    let bytes = match layout {
+      StorageLayout::ArrayOfStructs => particles.as_bytes_buffer().to_vec(),
+      StorageLayout::ConcatenatedArrays => {
+        let mut v: Vec<[f32; 4]> = Vec::with_capacity(3 * particles.len());
+        v.extend(
+          particles
+            .iter()
+            .map(|p| [p.pos.x, p.pos.y, p.pos.z, p.density]),
+        );
+        v.extend(
+          particles
+            .iter()
+            .map(|p| [p.velocity.x, p.velocity.y, p.velocity.z, 0.0]),
+        );
+        // The forces are recomputed on every step of the solver
+        v.extend(particles.iter().map(|_| [0.0; 4]));
+        unsafe { slice::from_raw_parts(v.as_ptr().cast(), size_of_val(v.as_slice())) }.to_vec()
+      }
+    };
+    Self { bytes }
+  }
+
+  /// Reads the particles back from the contents of a particle buffer in the `layout`.
+  /// The forces are not restored.
+  pub fn decode(layout: StorageLayout, bytes: &[u8]) -> Vec<Particle> {
+    let floats: Vec<f32> = bytes
+      .chunks_exact(size_of::<f32>())
+      .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+      .collect();
+    let particle = |pos_density: &[f32], velocity: &[f32]| {
+      let mut p = Particle::default();
+      p.pos = Point3::new(pos_density[0], pos_density[1], pos_density[2]);
+      p.density = pos_density[3];
+      p.velocity = Vector3::new(velocity[0], velocity[1], velocity[2]);
+      p
+    };
+    match layout {
+      StorageLayout::ArrayOfStructs => floats
+        .chunks_exact(size_of::<Particle>() / size_of::<f32>())
+        .map(|f| particle(&f[0..4], &f[4..7]))
+        .collect(),
+      StorageLayout::ConcatenatedArrays => {
+        let count = floats.len() / 12;
+        (0..count)
+          .map(|i| {
+            particle(
+              &floats[4 * i..4 * i + 4],
+              &floats[4 * (count + i)..4 * (count + i) + 3],
+            )
+          })
+          .collect()
+      }
+    }
+  }
+}
+
+impl AsBuffer for ParticleData {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    &self.bytes
+  }
+}
@@ -0,0 +1,785 @@
+use core::slice;
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use wgpu::{
+  util::{BufferInitDescriptor, DeviceExt},
+  BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+  Buffer, BufferUsages, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+  PipelineLayoutDescriptor, ShaderStages,
+};
+
+use crate::render::{swapchain::SwapBuffers, AsBuffer};
+
+use super::{
+  emitters::DEAD_POS,
+  sph_solver_gpu::{Particle, CEILING},
+  storage::{ParticleData, StorageLayout},
+};
+
+/// Workgroup size of the splatting pass.
+/// This constant **must** be kept the same as `WG_SIZE` in `surface.wgsl`.
+pub const SPLAT_WG_SIZE: u32 = 64;
+/// Side of the cubic workgroups of the polygonization pass.
+/// This constant **must** be kept the same as `CELLS_WG_SIDE` in `surface.wgsl`.
+pub const CELLS_WG_SIDE: u32 = 4;
+/// Entries of a row of [`triangle_table`], the edges of at most 5 triangles and the -1 terminator.
+/// This constant **must** be kept the same as `TABLE_ROW` in `surface.wgsl`.
+pub const TABLE_ROW: usize = 16;
+/// About the largest count of the points of a [`SurfaceGrid`], 64 MiB of the field on the GPU
+pub const MAX_GRID_POINTS: u32 = 1 << 24;
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+
+  use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+
+  use super::{
+    edge_corners, extract_cpu, triangle_table, SurfaceGrid, SurfaceSettings, MAX_GRID_POINTS,
+    TABLE_ROW,
+  };
+  use crate::solvers::{
+    emitters::DEAD_POS,
+    sph_solver_gpu::{Particle, CEILING},
+  };
+
+  #[test]
+  fn table_loops_are_closed() {
+    let table = triangle_table();
+    assert_eq!(table[0][0], -1);
+    assert_eq!(table[255][0], -1);
+    for (config, row) in table.iter().enumerate() {
+      let len = row.iter().position(|&e| e == -1).unwrap();
+      assert_eq!(len % 3, 0, "{config:#x}");
+      // Every crossed edge of the cell is a vertex of the surface
+      let crossed = (0..12)
+        .filter(|&e| {
+          let (c0, c1) = edge_corners(e);
+          (config >> c0 & 1) != (config >> c1 & 1)
+        })
+        .count();
+      let mut used: Vec<_> = row[..len].to_vec();
+      used.sort();
+      used.dedup();
+      assert_eq!(used.len(), crossed, "{config:#x}");
+    }
+    // The table is symmetric to the inversion up to the orientation and the ambiguous faces
+    assert_eq!(
+      table[1].iter().position(|&e| e == -1),
+      table[254].iter().position(|&e| e == -1)
+    );
+    assert!(table.iter().all(|row| row[TABLE_ROW - 1] == -1));
+  }
+
+  /// A ball of particles on a lattice
+  fn ball(center: Point3<f32>, radius: f32, spacing: f32) -> Vec<Particle> {
+    let n = (radius / spacing).ceil() as i32;
+    let mut particles = Vec::new();
+    for x in -n..=n {
+      for y in -n..=n {
+        for z in -n..=n {
+          let offset = Vector3::new(x as f32, y as f32, z as f32) * spacing;
+          if offset.magnitude() <= radius {
+            let mut p = Particle::default();
+            p.pos = center + offset;
+            particles.push(p);
+          }
+        }
+      }
+    }
+    let mut dead = Particle::default();
+    dead.pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS);
+    particles.push(dead);
+    particles
+  }
+
+  #[test]
+  fn closed_ball() {
+    let center = Point3::new(0.0, 0.2, 0.0);
+    let particles = ball(center, 0.15, 0.02);
+    let grid = SurfaceGrid::new(0.2, 0.04, 48);
+    let mesh = extract_cpu(&particles, &grid, &SurfaceSettings::default()).welded();
+    assert!(mesh.triangles() > 100);
+
+    // Every edge is shared by two triangles going along it in the opposite directions
+    let mut edges = HashMap::new();
+    for t in mesh.indices.chunks_exact(3) {
+      for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+        *edges.entry((a, b)).or_insert(0) += 1;
+      }
+    }
+    assert!(edges
+      .iter()
+      .all(|(&(a, b), &n)| n == 1 && edges.get(&(b, a)) == Some(&1)));
+
+    // Counter-clockwise seen from the outside, the normals point away from the fluid
+    for t in mesh.indices.chunks_exact(3) {
+      let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vector3::from(mesh.positions[i as usize]));
+      let out = (a + b + c) / 3.0 - center.to_vec();
+      assert!((b - a).cross(c - a).dot(out) > 0.0);
+      assert!(Vector3::from(mesh.normals[t[0] as usize]).dot(out) > 0.0);
+    }
+    let radii = mesh
+      .positions
+      .iter()
+      .map(|&p| (Point3::from(p) - center).magnitude());
+    assert!(radii.clone().all(|r| r > 0.14 && r < 0.2));
+  }
+
+  #[test]
+  fn grid_reaches_ceiling() {
+    for resolution in [16, 64, 256] {
+      let grid = SurfaceGrid::new(0.2, 0.04, resolution);
+      let top = grid.origin[1] + (grid.dims[1] - 1) as f32 * grid.cell;
+      assert!(top >= CEILING + 0.04);
+      // The rounding up adds at most a couple of points per axis
+      let points: u32 = grid.dims.map(|d| d - 2).iter().product();
+      assert!(points <= MAX_GRID_POINTS);
+    }
+  }
+
+  #[test]
+  fn empty_without_particles() {
+    let grid = SurfaceGrid::new(0.2, 0.04, 16);
+    let mesh = extract_cpu(&[], &grid, &SurfaceSettings::default());
+    assert_eq!(mesh.triangles(), 0);
+  }
+}
+
+/// What the surface is extracted with
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurfaceSettings {
+  /// Cells of the grid along the width of the tank
+  pub resolution: u32,
+  /// The level of the field bounding the fluid, a lone particle reaches 1
+  pub iso: f32,
+  /// Capacity of the mesh built on the GPU, the larger ones are extracted on the CPU
+  pub max_triangles: u32,
+}
+
+impl Default for SurfaceSettings {
+  fn default() -> Self {
+    Self {
+      resolution: 64,
+      iso: 0.5,
+      max_triangles: 1 << 18,
+    }
+  }
+}
+
+/// Points the particles are splatted onto, covering the tank of the half-width `w`
+/// up to the ceiling of the solver, see [`CEILING`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceGrid {
+  pub origin: [f32; 3],
+  pub cell: f32,
+  /// Points along every axis
+  pub dims: [u32; 3],
+  /// The kernel support, the particles are splatted onto the points closer than `h`
+  pub h: f32,
+}
+
+impl SurfaceGrid {
+  /// The grid is padded by `h`, so the surface is closed at the walls, the floor and the ceiling.
+  /// The cells are larger than `width / resolution` if the grid would have more than
+  /// [`MAX_GRID_POINTS`] points otherwise.
+  pub fn new(w: f32, h: f32, resolution: u32) -> Self {
+    let width = 2.0 * (w + h);
+    let height = CEILING + 2.0 * h;
+    let cell = (width / resolution.max(1) as f32)
+      .max((width * width * height / MAX_GRID_POINTS as f32).cbrt());
+    let points = |extent: f32| (extent / cell).ceil() as u32 + 1;
+    Self {
+      origin: [-w - h, -h, -w - h],
+      cell,
+      dims: [points(width), points(height), points(width)],
+      h,
+    }
+  }
+
+  fn len(&self) -> usize {
+    self.dims.iter().map(|&d| d as usize).product()
+  }
+
+  fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+    let [dx, dy, _] = self.dims.map(|d| d as usize);
+    x + dx * (y + dy * z)
+  }
+
+  fn point(&self, p: [usize; 3]) -> Vector3<f32> {
+    Vector3::from(self.origin) + Vector3::new(p[0] as f32, p[1] as f32, p[2] as f32) * self.cell
+  }
+}
+
+/// Triangle mesh of the fluid surface
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+  pub positions: Vec<[f32; 3]>,
+  /// Pointing away from the fluid
+  pub normals: Vec<[f32; 3]>,
+  /// Three per counter-clockwise triangle
+  pub indices: Vec<u32>,
+}
+
+impl Mesh {
+  pub fn triangles(&self) -> usize {
+    self.indices.len() / 3
+  }
+
+  /// Merges the vertices at the same positions, the triangles of the neighbouring cells share them
+  pub fn welded(&self) -> Mesh {
+    let mut mesh = Mesh::default();
+    let mut welded = HashMap::new();
+    for &i in &self.indices {
+      let p = self.positions[i as usize];
+      let j = *welded.entry(p.map(f32::to_bits)).or_insert_with(|| {
+        mesh.positions.push(p);
+        mesh.normals.push(self.normals[i as usize]);
+        mesh.positions.len() as u32 - 1
+      });
+      mesh.indices.push(j);
+    }
+    // The triangles collapsed by the welding
+    let indices = mesh
+      .indices
+      .chunks_exact(3)
+      .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+      .flatten()
+      .copied()
+      .collect();
+    mesh.indices = indices;
+    mesh
+  }
+}
+
+/// The corners of the edge `e` of a cell, the lower one first.
+/// The bits 0, 1 and 2 of a corner are its x, y and z offsets,
+/// the edges `4 * axis..4 * axis + 4` go along the `axis`.
+fn edge_corners(e: usize) -> (usize, usize) {
+  let axis = e / 4;
+  let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+  let c0 = (e & 1) << a | (e >> 1 & 1) << b;
+  (c0, c0 | 1 << axis)
+}
+
+fn edge_between(c0: usize, c1: usize) -> usize {
+  let axis = (c0 ^ c1).trailing_zeros() as usize;
+  let lo = c0.min(c1);
+  let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+  4 * axis + (lo >> a & 1) + 2 * (lo >> b & 1)
+}
+
+fn corner_pos(c: usize) -> Vector3<f32> {
+  Vector3::new((c & 1) as f32, (c >> 1 & 1) as f32, (c >> 2 & 1) as f32)
+}
+
+/// Polygons cutting the corners of the `config` inside the fluid off a cell
+fn triangulate(config: usize) -> Vec<usize> {
+  let inside = |c: usize| config >> c & 1 == 1;
+  let mid = |e: usize| {
+    let (c0, c1) = edge_corners(e);
+    (corner_pos(c0) + corner_pos(c1)) / 2.0
+  };
+  // The surface crosses every face of the cell along the segments.
+  // They go counter-clockwise around the fluid seen from the outside of the cell.
+  let mut next = [None; 12];
+  for axis in 0..3 {
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    for side in 0..2 {
+      let base = side << axis;
+      let face = [base, base | 1 << a, base | 1 << a | 1 << b, base | 1 << b];
+      let mut outward = Vector3::new(0.0, 0.0, 0.0);
+      outward[axis] = if side == 1 { 1.0 } else { -1.0 };
+      let crossed: Vec<usize> = (0..4)
+        .filter(|&i| inside(face[i]) != inside(face[(i + 1) % 4]))
+        .collect();
+      let edge = |i: usize| edge_between(face[i % 4], face[(i + 1) % 4]);
+      // The segments with a point on the side of the fluid
+      let segments = match crossed.len() {
+        2 => {
+          let inner: Vec<_> = face.iter().filter(|&&c| inside(c)).collect();
+          let center =
+            inner.iter().map(|&&c| corner_pos(c)).sum::<Vector3<f32>>() / inner.len() as f32;
+          vec![(edge(crossed[0]), edge(crossed[1]), center)]
+        }
+        // Ambiguous, the corners inside are kept apart. It only depends on the face,
+        // so the neighbouring cell sharing it agrees.
+        4 => (0..4)
+          .filter(|&i| inside(face[i]))
+          .map(|i| (edge(i + 3), edge(i), corner_pos(face[i])))
+          .collect(),
+        _ => Vec::new(),
+      };
+      for (from, to, fluid) in segments {
+        let d = mid(to) - mid(from);
+        if d.cross(outward).dot(fluid - (mid(from) + mid(to)) / 2.0) > 0.0 {
+          next[from] = Some(to);
+        } else {
+          next[to] = Some(from);
+        }
+      }
+    }
+  }
+
+  let mut edges = Vec::new();
+  let mut visited = [false; 12];
+  for start in 0..12 {
+    if visited[start] || next[start].is_none() {
+      continue;
+    }
+    let mut polygon = vec![start];
+    visited[start] = true;
+    let mut e = next[start].unwrap();
+    while e != start {
+      polygon.push(e);
+      visited[e] = true;
+      e = next[e].unwrap();
+    }
+    for i in 1..polygon.len() - 1 {
+      edges.extend([polygon[0], polygon[i], polygon[i + 1]]);
+    }
+  }
+  edges
+}
+
+/// The edges of the triangles of every configuration of the corners inside the fluid,
+/// terminated by -1. The bit `c` of a configuration is the corner `c`, see [`edge_corners`].
+pub fn triangle_table() -> Vec<[i32; TABLE_ROW]> {
+  (0..256)
+    .map(|config| {
+      let mut row = [-1; TABLE_ROW];
+      for (r, e) in row.iter_mut().zip(triangulate(config)) {
+        *r = e as i32;
+      }
+      row
+    })
+    .collect()
+}
+
+/// Adds the kernel of the particles closer than `h` to the points of the `grid`
+fn splat(particles: &[Particle], grid: &SurfaceGrid) -> Vec<f32> {
+  let mut field = vec![0.0; grid.len()];
+  let h2 = grid.h * grid.h;
+  for p in particles.iter().filter(|p| p.pos.x != DEAD_POS) {
+    let pos = Vector3::new(p.pos.x, p.pos.y, p.pos.z);
+    // The points closer than `h` along the axis
+    let range = |axis: usize| {
+      let lo = ((pos[axis] - grid.h - grid.origin[axis]) / grid.cell).ceil();
+      let hi = ((pos[axis] + grid.h - grid.origin[axis]) / grid.cell).floor();
+      let (lo, hi) = (lo.max(0.0), hi.min(grid.dims[axis] as f32 - 1.0));
+      (lo <= hi).then_some(lo as usize..=hi as usize)
+    };
+    let (Some(xs), Some(ys), Some(zs)) = (range(0), range(1), range(2)) else {
+      continue;
+    };
+    for z in zs {
+      for y in ys.clone() {
+        for x in xs.clone() {
+          let r = grid.point([x, y, z]) - pos;
+          let q = 1.0 - r.magnitude2() / h2;
+          if q > 0.0 {
+            field[grid.index([x, y, z])] += q * q * q;
+          }
+        }
+      }
+    }
+  }
+  field
+}
+
+/// Splats the alive `particles` onto the `grid` and polygonizes the field with marching cubes.
+/// The triangles don't share the vertices, see [`Mesh::welded`].
+pub fn extract_cpu(particles: &[Particle], grid: &SurfaceGrid, settings: &SurfaceSettings) -> Mesh {
+  let field = splat(particles, grid);
+  let table = triangle_table();
+  let [dx, dy, dz] = grid.dims.map(|d| d as usize);
+  let value = |x: usize, y: usize, z: usize| field[grid.index([x, y, z])];
+  // Points away from the fluid
+  let normal = |[x, y, z]: [usize; 3]| {
+    let d = |lo: f32, hi: f32| lo - hi;
+    Vector3::new(
+      d(
+        value(x.saturating_sub(1), y, z),
+        value((x + 1).min(dx - 1), y, z),
+      ),
+      d(
+        value(x, y.saturating_sub(1), z),
+        value(x, (y + 1).min(dy - 1), z),
+      ),
+      d(
+        value(x, y, z.saturating_sub(1)),
+        value(x, y, (z + 1).min(dz - 1)),
+      ),
+    )
+  };
+  let slabs: Vec<Mesh> = (0..dz.saturating_sub(1))
+    .into_par_iter()
+    .map(|z| {
+      let mut mesh = Mesh::default();
+      for y in 0..dy - 1 {
+        for x in 0..dx - 1 {
+          let point = |c: usize| [x + (c & 1), y + (c >> 1 & 1), z + (c >> 2 & 1)];
+          let config = (0..8)
+            .filter(|&c| {
+              let [x, y, z] = point(c);
+              value(x, y, z) > settings.iso
+            })
+            .fold(0, |config, c| config | 1 << c);
+          for &e in table[config].iter().take_while(|&&e| e != -1) {
+            let (c0, c1) = edge_corners(e as usize);
+            let (p0, p1) = (point(c0), point(c1));
+            let (v0, v1) = (value(p0[0], p0[1], p0[2]), value(p1[0], p1[1], p1[2]));
+            let t = (settings.iso - v0) / (v1 - v0);
+            let pos = grid.point(p0) + (grid.point(p1) - grid.point(p0)) * t;
+            let n = normal(p0) + (normal(p1) - normal(p0)) * t;
+            let n = if n.magnitude2() > 0.0 {
+              n.normalize()
+            } else {
+              Vector3::unit_y()
+            };
+            mesh.indices.push(mesh.positions.len() as u32);
+            mesh.positions.push(pos.into());
+            mesh.normals.push(n.into());
+          }
+        }
+      }
+      mesh
+    })
+    .collect();
+  let mut mesh = Mesh::default();
+  for slab in slabs {
+    let offset = mesh.positions.len() as u32;
+    mesh.positions.extend(slab.positions);
+    mesh.normals.extend(slab.normals);
+    mesh.indices.extend(slab.indices.iter().map(|i| i + offset));
+  }
+  mesh
+}
+
+/// The `SurfaceParams` of `surface.wgsl`
+#[repr(C)]
+struct SurfaceParams {
+  origin: [f32; 3],
+  cell: f32,
+  dims: [u32; 3],
+  h: f32,
+  iso: f32,
+  /// Vertices of the mesh
+  capacity: u32,
+  _padding: [u32; 2],
+}
+
+impl AsBuffer for SurfaceParams {
+  fn as_bytes_buffer(&self) -> &[u8] {
+    unsafe {
+      slice::from_raw_parts(
+        std::ptr::from_ref(self).cast(),
+        std::mem::size_of::<SurfaceParams>(),
+      )
+    }
+  }
+}
+
+/// The vertex of the mesh built on the GPU, `vec4f`s for the storage buffer alignment
+pub const GPU_VERTEX_SIZE: u64 = 8 * std::mem::size_of::<f32>() as u64;
+/// Offset of the count of the vertices that didn't fit into the capacity in the
+/// [`SurfaceExtractor::args_buf`], after the draw arguments and the count of the written ones
+const DROPPED_OFFSET: u64 = 5 * std::mem::size_of::<u32>() as u64;
+
+/// Builds the mesh of the fluid surface on the GPU like [`extract_cpu`].
+///
+/// The vertices of the triangles are written to [`Self::vertex_buf`] as
+/// `pos: vec4f, normal: vec4f`, their count to the indirect draw arguments in [`Self::args_buf`]
+/// followed by the counts of the written vertices and of the ones that didn't fit.
+pub struct SurfaceExtractor {
+  splat: ComputePipeline,
+  polygonize: ComputePipeline,
+  finish: ComputePipeline,
+  params_buf: Buffer,
+  /// Fixed-point `u32` per point of the grid, reallocated when the grid grows
+  field_buf: Buffer,
+  vertex_buf: Buffer,
+  args_buf: Buffer,
+  table_buf: Buffer,
+  bg_layout: wgpu::BindGroupLayout,
+  bg: BindGroup,
+  /// Capacity of the particle buffers
+  particles: u32,
+  /// The grid and the settings of the last [`Self::extract`]
+  grid: Option<SurfaceGrid>,
+  settings: SurfaceSettings,
+}
+
+impl SurfaceExtractor {
+  pub fn new(
+    device: &wgpu::Device,
+    particles: u32,
+    particle_layout: &wgpu::BindGroupLayout,
+    count_buf: &Buffer,
+    storage: StorageLayout,
+    settings: SurfaceSettings,
+  ) -> Self {
+    let module = storage.create_shader_module(device, "surface.wgsl", include_str!("surface.wgsl"));
+    let params_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Surface params"),
+      size: std::mem::size_of::<SurfaceParams>() as u64,
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let table_buf = device.create_buffer_init(&BufferInitDescriptor {
+      label: Some("Marching cubes table"),
+      contents: triangle_table().as_flattened().as_bytes_buffer(),
+      usage: BufferUsages::STORAGE,
+    });
+    let args_buf = device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Surface draw args"),
+      size: DROPPED_OFFSET + std::mem::size_of::<u32>() as u64,
+      usage: BufferUsages::STORAGE
+        | BufferUsages::INDIRECT
+        | BufferUsages::COPY_SRC
+        | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    });
+    let entry = |binding, ty| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let storage = |read_only| wgpu::BufferBindingType::Storage { read_only };
+    let bg_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("Surface BG layout"),
+      entries: &[
+        entry(0, wgpu::BufferBindingType::Uniform),
+        entry(1, storage(true)),
+        entry(2, storage(true)),
+        entry(3, storage(false)),
+        entry(4, storage(false)),
+        entry(5, storage(false)),
+      ],
+    });
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      label: Some("Surface extraction"),
+      bind_group_layouts: &[particle_layout, &bg_layout],
+      push_constant_ranges: &[],
+    });
+    let pipeline = |entry_point| {
+      device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&layout),
+        module: &module,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+      })
+    };
+    let (splat, polygonize, finish) = (
+      pipeline("splat"),
+      pipeline("polygonize"),
+      pipeline("finish"),
+    );
+
+    let field_buf = Self::create_field(device, 1);
+    let vertex_buf = Self::create_vertices(device, &settings);
+    let bg = Self::create_bg(
+      device,
+      &bg_layout,
+      [
+        &params_buf,
+        count_buf,
+        &table_buf,
+        &field_buf,
+        &vertex_buf,
+        &args_buf,
+      ],
+    );
+    Self {
+      splat,
+      polygonize,
+      finish,
+      params_buf,
+      field_buf,
+      vertex_buf,
+      args_buf,
+      table_buf,
+      bg_layout,
+      bg,
+      particles,
+      grid: None,
+      settings,
+    }
+  }
+
+  fn create_field(device: &wgpu::Device, points: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Surface field"),
+      size: (points.max(1) * std::mem::size_of::<u32>()) as u64,
+      usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+      mapped_at_creation: false,
+    })
+  }
+
+  fn create_vertices(device: &wgpu::Device, settings: &SurfaceSettings) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+      label: Some("Surface vertices"),
+      size: 3 * u64::from(settings.max_triangles.max(1)) * GPU_VERTEX_SIZE,
+      usage: BufferUsages::STORAGE | BufferUsages::VERTEX | BufferUsages::COPY_SRC,
+      mapped_at_creation: false,
+    })
+  }
+
+  /// `buffers` are the params, the particle count, the table, the field, the vertices and the args
+  fn create_bg(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffers: [&Buffer; 6],
+  ) -> BindGroup {
+    let entries: Vec<_> = buffers
+      .iter()
+      .enumerate()
+      .map(|(binding, buf)| BindGroupEntry {
+        binding: binding as u32,
+        resource: buf.as_entire_binding(),
+      })
+      .collect();
+    device.create_bind_group(&BindGroupDescriptor {
+      label: Some("Surface BG"),
+      layout,
+      entries: &entries,
+    })
+  }
+
+  pub fn vertex_buf(&self) -> &Buffer {
+    &self.vertex_buf
+  }
+
+  /// The `DrawIndirectArgs` of the mesh
+  pub fn args_buf(&self) -> &Buffer {
+    &self.args_buf
+  }
+
+  /// Records the extraction of the surface of the current particles onto the `grid`.
+  /// The buffers are reallocated if the grid or the capacity grow.
+  #[allow(clippy::too_many_arguments)]
+  pub fn extract(
+    &mut self,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    particles: &SwapBuffers<ParticleData>,
+    count_buf: &Buffer,
+    grid: &SurfaceGrid,
+    settings: &SurfaceSettings,
+  ) {
+    let field_size = (grid.len() * std::mem::size_of::<u32>()) as u64;
+    let resized =
+      field_size > self.field_buf.size() || settings.max_triangles > self.settings.max_triangles;
+    if field_size > self.field_buf.size() {
+      self.field_buf = Self::create_field(device, grid.len());
+    }
+    if settings.max_triangles > self.settings.max_triangles {
+      self.vertex_buf = Self::create_vertices(device, settings);
+    }
+    if resized {
+      self.bg = Self::create_bg(
+        device,
+        &self.bg_layout,
+        [
+          &self.params_buf,
+          count_buf,
+          &self.table_buf,
+          &self.field_buf,
+          &self.vertex_buf,
+          &self.args_buf,
+        ],
+      );
+    }
+    if self.grid != Some(*grid) || self.settings != *settings {
+      let capacity = (self.vertex_buf.size() / GPU_VERTEX_SIZE) as u32;
+      let params = SurfaceParams {
+        origin: grid.origin,
+        cell: grid.cell,
+        dims: grid.dims,
+        h: grid.h,
+        iso: settings.iso,
+        capacity: capacity.min(3 * settings.max_triangles),
+        _padding: [0; 2],
+      };
+      queue.write_buffer(&self.params_buf, 0, params.as_bytes_buffer());
+      self.grid = Some(*grid);
+      self.settings = *settings;
+    }
+
+    encoder.clear_buffer(&self.field_buf, 0, Some(field_size));
+    encoder.clear_buffer(&self.args_buf, 0, None);
+    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+      label: Some("SurfaceExtractor::extract"),
+      timestamp_writes: None,
+    });
+    pass.set_bind_group(0, particles.cur_group(), &[]);
+    pass.set_bind_group(1, &self.bg, &[]);
+    pass.set_pipeline(&self.splat);
+    pass.dispatch_workgroups(self.particles.div_ceil(SPLAT_WG_SIZE), 1, 1);
+    pass.set_pipeline(&self.polygonize);
+    let [x, y, z] = grid.dims.map(|d| (d - 1).div_ceil(CELLS_WG_SIDE));
+    pass.dispatch_workgroups(x, y, z);
+    pass.set_pipeline(&self.finish);
+    pass.dispatch_workgroups(1, 1, 1);
+  }
+
+  /// Reads back the mesh of the last [`Self::extract`], waiting for the submitted work to finish.
+  /// `None` if the mesh didn't fit into the capacity.
+  pub fn read_mesh(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Mesh> {
+    let read = |src: &Buffer, size: u64| {
+      let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Surface readback"),
+        size: size.max(4),
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+      });
+      let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Surface readback"),
+      });
+      encoder.copy_buffer_to_buffer(src, 0, &staging, 0, size);
+      queue.submit([encoder.finish()]);
+      let slice = staging.slice(..);
+      slice.map_async(wgpu::MapMode::Read, |r| {
+        r.expect("Unable to map the surface")
+      });
+      device.poll(wgpu::Maintain::Wait);
+      let words: Vec<u32> = slice
+        .get_mapped_range()
+        .chunks_exact(4)
+        .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+        .collect();
+      staging.unmap();
+      words
+    };
+    let args = read(&self.args_buf, self.args_buf.size());
+    let (count, dropped) = (args[0], args[(DROPPED_OFFSET / 4) as usize]);
+    if dropped > 0 {
+      log::warn!(
+        "The surface has {} vertices, only {count} fit on the GPU",
+        count + dropped
+      );
+      return None;
+    }
+    let floats = read(&self.vertex_buf, u64::from(count) * GPU_VERTEX_SIZE);
+    let mut mesh = Mesh::default();
+    for v in floats.chunks_exact(8).take(count as usize) {
+      let v = v.iter().map(|&w| f32::from_bits(w)).collect::<Vec<_>>();
+      mesh.indices.push(mesh.positions.len() as u32);
+      mesh.positions.push([v[0], v[1], v[2]]);
+      mesh.normals.push([v[4], v[5], v[6]]);
+    }
+    Some(mesh)
+  }
+}
@@ -0,0 +1,181 @@
+// These constants **must** be kept the same as `solvers::surface::{SPLAT_WG_SIZE, CELLS_WG_SIDE, TABLE_ROW}`
+const WG_SIZE: u32 = 64;
+const CELLS_WG_SIDE: u32 = 4;
+const TABLE_ROW: u32 = 16;
+// The field is accumulated in the fixed point, there are no float atomics
+const SCALE: f32 = 4096.0;
+
+// `Particle` and the accessors of the particles bound to the group 0 are defined by
+// the storage prelude, see `solvers::storage::StorageLayout`
+
+// See `SurfaceGrid` and `SurfaceSettings`
+struct SurfaceParams {
+  origin: vec3f,
+  cell: f32,
+  dims: vec3u,
+  h: f32,
+  iso: f32,
+  // Vertices fitting into `vertices`
+  capacity: u32,
+}
+
+/// Arguments of the indirect particle draw call, see `solvers::emitters::ParticleSources`
+struct ParticleCount {
+  vertex_count: u32,
+  instance_count: u32,
+  first_vertex: u32,
+  first_instance: u32,
+  alive: u32,
+}
+
+struct Vertex {
+  pos: vec4f,
+  normal: vec4f,
+}
+
+/// Arguments of the indirect mesh draw call followed by the counts of the vertices written
+/// and of the ones that didn't fit
+struct DrawArgs {
+  vertex_count: u32,
+  instance_count: u32,
+  first_vertex: u32,
+  first_instance: u32,
+  written: atomic<u32>,
+  dropped: atomic<u32>,
+}
+
+@group(1) @binding(0)
+var<uniform> surface: SurfaceParams;
+@group(1) @binding(1)
+var<storage, read> count: ParticleCount;
+// See `triangle_table`
+@group(1) @binding(2)
+var<storage, read> table: array<i32>;
+@group(1) @binding(3)
+var<storage, read_write> field: array<atomic<u32>>;
+@group(1) @binding(4)
+var<storage, read_write> vertices: array<Vertex>;
+@group(1) @binding(5)
+var<storage, read_write> args: DrawArgs;
+
+fn point_index(p: vec3u) -> u32 {
+  return p.x + surface.dims.x * (p.y + surface.dims.y * p.z);
+}
+
+fn point_pos(p: vec3u) -> vec3f {
+  return surface.origin + vec3f(p) * surface.cell;
+}
+
+fn field_at(p: vec3u) -> f32 {
+  return f32(atomicLoad(&field[point_index(p)])) / SCALE;
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn splat(@builtin(global_invocation_id) id: vec3u) {
+  let i = id.x;
+  if (i >= count.instance_count) {
+    return;
+  }
+  let pos = cur_pos(i);
+  // The points closer than `h`
+  let lo = max(ceil((pos - surface.h - surface.origin) / surface.cell), vec3(0.0));
+  let hi = min(floor((pos + surface.h - surface.origin) / surface.cell), vec3f(surface.dims) - 1.0);
+  // Outside of the grid, like the dead particles, or NaN
+  if (any(lo > hi) || any(pos != pos)) {
+    return;
+  }
+  let h2 = surface.h * surface.h;
+  for (var z = u32(lo.z); z <= u32(hi.z); z++) {
+    for (var y = u32(lo.y); y <= u32(hi.y); y++) {
+      for (var x = u32(lo.x); x <= u32(hi.x); x++) {
+        let p = vec3(x, y, z);
+        let r = point_pos(p) - pos;
+        let q = 1.0 - dot(r, r) / h2;
+        if (q > 0.0) {
+          atomicAdd(&field[point_index(p)], u32(q * q * q * SCALE + 0.5));
+        }
+      }
+    }
+  }
+}
+
+// Points away from the fluid, not normalized
+fn gradient_out(p: vec3u) -> vec3f {
+  let lo = select(p - 1u, p, p == vec3(0u));
+  let hi = min(p + 1u, surface.dims - 1u);
+  return vec3(
+    field_at(vec3(lo.x, p.y, p.z)) - field_at(vec3(hi.x, p.y, p.z)),
+    field_at(vec3(p.x, lo.y, p.z)) - field_at(vec3(p.x, hi.y, p.z)),
+    field_at(vec3(p.x, p.y, lo.z)) - field_at(vec3(p.x, p.y, hi.z)),
+  );
+}
+
+// The bits 0, 1 and 2 are the x, y and z offsets
+fn corner(c: u32) -> vec3u {
+  return vec3(c & 1u, (c >> 1u) & 1u, (c >> 2u) & 1u);
+}
+
+// The lower corner first, see `edge_corners`
+fn edge_corners(e: u32) -> vec2u {
+  let axis = e / 4u;
+  let a = (axis + 1u) % 3u;
+  let b = (axis + 2u) % 3u;
+  let c0 = ((e & 1u) << a) | (((e >> 1u) & 1u) << b);
+  return vec2(c0, c0 | (1u << axis));
+}
+
+@compute @workgroup_size(CELLS_WG_SIDE, CELLS_WG_SIDE, CELLS_WG_SIDE)
+fn polygonize(@builtin(global_invocation_id) cell: vec3u) {
+  if (any(cell + 1u >= surface.dims)) {
+    return;
+  }
+  var config = 0u;
+  for (var c = 0u; c < 8u; c++) {
+    if (field_at(cell + corner(c)) > surface.iso) {
+      config |= 1u << c;
+    }
+  }
+  let row = config * TABLE_ROW;
+  var n = 0u;
+  while (n < TABLE_ROW && table[row + n] != -1) {
+    n++;
+  }
+  if (n == 0u) {
+    return;
+  }
+  // Only the cells fitting into the capacity are reserved,
+  // so the vertices before `written` are always written
+  var first = atomicLoad(&args.written);
+  loop {
+    if (first + n > surface.capacity) {
+      atomicAdd(&args.dropped, n);
+      return;
+    }
+    let reserved = atomicCompareExchangeWeak(&args.written, first, first + n);
+    if (reserved.exchanged) {
+      break;
+    }
+    first = reserved.old_value;
+  }
+  for (var k = 0u; k < n; k++) {
+    let ends = edge_corners(u32(table[row + k]));
+    let p0 = cell + corner(ends.x);
+    let p1 = cell + corner(ends.y);
+    let v0 = field_at(p0);
+    let t = (surface.iso - v0) / (field_at(p1) - v0);
+    let g = mix(gradient_out(p0), gradient_out(p1), t);
+    var normal = vec3(0.0, 1.0, 0.0);
+    if (dot(g, g) > 0.0) {
+      normal = normalize(g);
+    }
+    let pos = point_pos(p0) + (point_pos(p1) - point_pos(p0)) * t;
+    vertices[first + k] = Vertex(vec4(pos, 1.0), vec4(normal, 0.0));
+  }
+}
+
+// Draws the written vertices
+@compute @workgroup_size(1)
+fn finish() {
+  args.vertex_count = atomicLoad(&args.written);
+  args.instance_count = 1u;
+}
@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use cgmath::InnerSpace;
+use serde::Serialize;
+
+use crate::solvers::{emitters::DEAD_POS, sph_solver_gpu::Particle};
+
+pub const CSV_HEADER: &str =
+  "step,time,alive,mean_density,max_density,max_speed,kinetic_energy,non_finite";
+
+#[cfg(test)]
+mod test {
+  use crate::solvers::{emitters::DEAD_POS, sph_solver_gpu::Particle};
+  use cgmath::{Point3, Vector3};
+
+  use super::Stats;
+
+  #[test]
+  fn dead_and_non_finite() {
+    let mut particles = vec![Particle::default(); 4];
+    particles[0].density = 1000.0;
+    particles[0].velocity = Vector3::new(3.0, 4.0, 0.0);
+    particles[1].density = 3000.0;
+    particles[2].pos = Point3::new(DEAD_POS, DEAD_POS, DEAD_POS);
+    particles[3].velocity.y = f32::NAN;
+    let stats = Stats::compute(&particles, 2.0);
+    assert_eq!(stats.alive, 3);
+    assert_eq!(stats.non_finite, 1);
+    assert_eq!(stats.max_density, 3000.0);
+    assert_eq!(stats.max_speed, 5.0);
+    assert_eq!(stats.kinetic_energy, 25.0);
+  }
+}
+
+/// Aggregates of the particles written to the statistics CSV
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+  pub alive: usize,
+  pub mean_density: f32,
+  pub max_density: f32,
+  pub max_speed: f32,
+  pub kinetic_energy: f32,
+  /// Count of the alive particles with a NaN or an infinite position, density or velocity.
+  /// They are not included in the other values.
+  pub non_finite: usize,
+}
+
+impl Stats {
+  /// `m0` is the mass of a particle
+  pub fn compute(particles: &[Particle], m0: f32) -> Self {
+    let mut stats = Stats::default();
+    let mut density_sum = 0.0;
+    for p in particles.iter().filter(|p| p.pos.x != DEAD_POS) {
+      stats.alive += 1;
+      let finite = [p.pos.x, p.pos.y, p.pos.z, p.density]
+        .into_iter()
+        .chain([p.velocity.x, p.velocity.y, p.velocity.z])
+        .all(f32::is_finite);
+      if !finite {
+        stats.non_finite += 1;
+        continue;
+      }
+      let speed2 = p.velocity.magnitude2();
+      density_sum += p.density;
+      stats.max_density = stats.max_density.max(p.density);
+      stats.max_speed = stats.max_speed.max(speed2.sqrt());
+      stats.kinetic_energy += 0.5 * m0 * speed2;
+    }
+    let finite = stats.alive - stats.non_finite;
+    if finite > 0 {
+      stats.mean_density = density_sum / finite as f32;
+    }
+    stats
+  }
+
+  /// Writes a line of the CSV with the [`CSV_HEADER`]
+  pub fn write_csv(&self, w: &mut impl Write, step: u64, time: f32) -> io::Result<()> {
+    writeln!(
+      w,
+      "{step},{time},{},{},{},{},{},{}",
+      self.alive,
+      self.mean_density,
+      self.max_density,
+      self.max_speed,
+      self.kinetic_energy,
+      self.non_finite
+    )
+  }
+}